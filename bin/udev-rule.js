@@ -0,0 +1,93 @@
+#!/usr/bin/env node
+const { usb } = require('../dist');
+
+const usage = `Usage: usb-udev-rule --vid <id> [--pid <id>] [--serial <serial>] [--mode <mode>] [--group <group> | --uaccess] [--file-name] [--json]
+
+Prints a udev rule granting access to a USB device. If a matching device is attached,
+the rule is checked against its sysfs attributes. Exits with 1 if the current user
+would not have access once the rule is applied, or if the attached device does not match.`;
+
+const parseId = value => {
+    const id = Number.parseInt(value, 16);
+    if (Number.isNaN(id) || id < 0 || id > 0xffff) {
+        throw new Error(`invalid id ${value}`);
+    }
+    return id;
+};
+
+const parseArgs = argv => {
+    const args = {};
+    for (let i = 0; i < argv.length; i++) {
+        const flag = argv[i];
+        const value = () => {
+            if (i + 1 >= argv.length) {
+                throw new Error(`${flag} requires a value`);
+            }
+            return argv[++i];
+        };
+
+        switch (flag) {
+            case '--vid': args.vendorId = parseId(value()); break;
+            case '--pid': args.productId = parseId(value()); break;
+            case '--serial': args.serialNumber = value(); break;
+            case '--mode': args.mode = value(); break;
+            case '--group': args.group = value(); break;
+            case '--uaccess': args.uaccess = true; break;
+            case '--file-name': args.fileName = true; break;
+            case '--json': args.json = true; break;
+            case '--help': args.help = true; break;
+            default: throw new Error(`unknown option ${flag}`);
+        }
+    }
+    return args;
+};
+
+const main = async () => {
+    let args;
+    try {
+        args = parseArgs(process.argv.slice(2));
+        if (!args.help && args.vendorId === undefined) {
+            throw new Error('--vid is required');
+        }
+    } catch (error) {
+        console.error(`${error.message}\n\n${usage}`);
+        return 2;
+    }
+
+    if (args.help) {
+        console.log(usage);
+        return 0;
+    }
+
+    const options = { mode: args.mode, group: args.group, uaccess: args.uaccess };
+    const devices = await usb.getDevices();
+    const device = devices.find(device =>
+        device.vendorId === args.vendorId
+        && (args.productId === undefined || device.productId === args.productId)
+        && (args.serialNumber === undefined || device.serialNumber === args.serialNumber)
+    );
+
+    const rule = device && args.productId !== undefined
+        ? await device.udevRule({ ...options, serialNumber: args.serialNumber !== undefined })
+        : await usb.udevRule({ vendorId: args.vendorId, productId: args.productId, serialNumber: args.serialNumber }, options);
+
+    if (args.json) {
+        console.log(JSON.stringify(rule, null, 4));
+    } else if (args.fileName) {
+        console.log(rule.fileName);
+    } else {
+        console.log(rule.rule);
+        if (rule.sysfsPath) {
+            console.error(`# checked against ${rule.sysfsPath}`);
+        }
+        rule.mismatches.forEach(mismatch => console.error(`# mismatch: ${mismatch}`));
+        console.error(`# ${rule.explanation}`);
+    }
+
+    return rule.hasAccess && rule.matchesDevice !== false ? 0 : 1;
+};
+
+main().then(code => process.exit(code), error => {
+    console.error(error.message);
+    process.exit(2);
+});
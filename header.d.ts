@@ -1,5 +1,97 @@
 export type USBSpeed = 'low' | 'full' | 'high' | 'super' | 'superPlus';
 
+export type SerialParity = 'none' | 'odd' | 'even' | 'mark' | 'space';
+
+export type SerialFlowControl = 'none' | 'hardware';
+
+export type MidiMessageType = 'noteOff' | 'noteOn' | 'polyPressure' | 'controlChange' | 'programChange' | 'channelPressure' | 'pitchBend'
+    | 'sysex' | 'timeCode' | 'songPosition' | 'songSelect' | 'tuneRequest'
+    | 'clock' | 'start' | 'continue' | 'stop' | 'activeSensing' | 'reset' | 'other';
+
+/**
+ * Web Serial style streams over a port's bulk endpoints
+ */
+export declare interface CdcAcmPort {
+    /**
+     * Data received from the device. Reading starts when the stream is first accessed, and a new stream is returned after it ends
+     */
+    readonly readable: ReadableStream<Uint8Array>;
+
+    /**
+     * Data to send to the device
+     */
+    readonly writable: WritableStream<Uint8Array>;
+}
+
+/**
+ * Web Serial style streams over a vendor bridge's bulk endpoints, with any chip framing removed
+ */
+export declare interface UsbSerialPort {
+    /**
+     * Data received from the device. Reading starts when the stream is first accessed, and a new stream is returned after it ends
+     */
+    readonly readable: ReadableStream<Uint8Array>;
+
+    /**
+     * Data to send to the device
+     */
+    readonly writable: WritableStream<Uint8Array>;
+}
+
+/**
+ * Streams over the accessory interface's bulk endpoints
+ */
+export declare interface AccessoryConnection {
+    /**
+     * Data received from the phone. Reading starts when the stream is first accessed, and a new stream is returned after it ends
+     */
+    readonly readable: ReadableStream<Uint8Array>;
+
+    /**
+     * Data to send to the phone
+     */
+    readonly writable: WritableStream<Uint8Array>;
+}
+
+/**
+ * Streams over an ADB service stream
+ */
+export declare interface AdbStream {
+    /**
+     * Data from the service. Reading starts when the stream is first accessed, and the stream ends when the service closes it
+     */
+    readonly readable: ReadableStream<Uint8Array>;
+
+    /**
+     * Data to send to the service
+     */
+    readonly writable: WritableStream<Uint8Array>;
+}
+
+export interface UsbSequenceOptions {
+    /**
+     * Sequences waiting for the device run highest priority first (default `0`)
+     */
+    priority?: number;
+
+    /**
+     * Maximum time (in milliseconds) to wait for the device before rejecting
+     */
+    timeout?: number;
+}
+
+/**
+ * Ordered access to a device for the duration of a `sequence()` callback.
+ * Operations run in the order they are called, even when not awaited in between, and other sequences
+ * wait until this one ends. Transfers made directly on the device are not queued and may run in between.
+ */
+export declare interface UsbSequence {
+    controlTransferIn(setup: USBControlTransferParameters, length: number, timeout?: number): Promise<USBInTransferResult>;
+    controlTransferOut(setup: USBControlTransferParameters, data?: BufferSource, timeout?: number): Promise<USBOutTransferResult>;
+    transferIn(endpointNumber: number, length: number, timeout?: number): Promise<USBInTransferResult>;
+    transferOut(endpointNumber: number, data: BufferSource, timeout?: number): Promise<USBOutTransferResult>;
+}
+
 export declare interface UsbDevice extends USBDevice {
     /**
      * The USB bus the device is connected to (e.g. `1-1`, `2-3.4`, etc.)
@@ -32,4 +124,29 @@ export declare interface UsbDevice extends USBDevice {
      * @param interfaceNumber 
      */
     attachKernelDriver(interfaceNumber: number): Promise<void>;
+
+    /**
+     * Generates a udev rule granting access to this device and validates it against the device's sysfs attributes (Linux only)
+     * @param options
+     */
+    udevRule(options?: UdevRuleOptions): Promise<UdevRule>;
+
+    /**
+     * Runs the callback once no other sequence holds the device. Transfers issued through the
+     * sequence are performed natively in order, and other sequences wait until the callback settles.
+     * Transfers made directly on the device don't wait for sequences
+     * @param callback
+     * @param options
+     */
+    sequence<T>(callback: (sequence: UsbSequence) => Promise<T>, options?: UsbSequenceOptions): Promise<T>;
+
+    /**
+     * Performs an IN transfer into the supplied buffer, with at most its length read. The data arrives in a
+     * pooled transfer buffer and is copied across, as nusb only transfers into memory it allocated itself.
+     * The result's `data` is a view over the supplied buffer, which must not be touched until the promise settles
+     * @param endpointNumber
+     * @param buffer
+     * @param timeout
+     */
+    transferInto(endpointNumber: number, buffer: BufferSource, timeout?: number): Promise<USBInTransferResult>;
 }
@@ -0,0 +1,531 @@
+mod auth;
+mod protocol;
+mod sync;
+
+use crate::endpoint_stream::InStream;
+use crate::transfer_pool::pooled_transfer_out;
+use crate::webusb_device::{run_blocking, SharedDevice, UsbDevice};
+use auth::AdbKey;
+use napi::{
+    bindgen_prelude::*, threadsafe_function::ThreadsafeFunction,
+    threadsafe_function::ThreadsafeFunctionCallMode,
+};
+use napi_derive::napi;
+use nusb::{
+    descriptors::{ConfigurationDescriptor, TransferType},
+    transfer::{Direction, Out},
+};
+use protocol::{AdbCore, AdbTransport};
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime},
+};
+use sync::{SyncClient, SyncStat};
+
+const CLASS_VENDOR: u8 = 0xff;
+const SUBCLASS_ADB: u8 = 0x42;
+const PROTOCOL_ADB: u8 = 0x01;
+
+const WRITE_TIMEOUT: Duration = Duration::from_millis(5000);
+const DEFAULT_ACCEPT_TIMEOUT: u32 = 60000;
+const DEFAULT_KEY_NAME: &str = "node-usb";
+const READ_TRANSFERS: usize = 4;
+const READ_TRANSFER_LENGTH: usize = 64 * 1024;
+
+/// Regular file with `rw-r--r--`, for `push()`
+const DEFAULT_PUSH_MODE: u32 = 0o100644;
+
+type DataCallback = ThreadsafeFunction<Uint8Array, (), Uint8Array, napi::Status, false>;
+type EndCallback = ThreadsafeFunction<Option<String>, (), Option<String>, napi::Status, false>;
+
+#[napi(object)]
+pub struct AdbOptions {
+    /// The ADB interface to use, defaults to the first found
+    pub interfaceNumber: Option<u8>,
+    /// Detach kernel drivers while the interface is open, defaults to `true`
+    pub detachKernelDriver: Option<bool>,
+    /// The host's 2048-bit RSA key as PKCS#8 or PKCS#1 PEM (e.g. `~/.android/adbkey`).
+    /// A new key is generated when left out, which the device asks the user to accept
+    pub privateKey: Option<String>,
+    /// Name shown on the device when asking to accept the key, defaults to `node-usb`
+    pub keyName: Option<String>,
+    /// Maximum time (in milliseconds) to wait for the user to accept the key, defaults to 60 seconds
+    pub timeout: Option<u32>,
+}
+
+#[napi(object)]
+pub struct AdbFileStat {
+    /// Unix mode bits, 0 when the path doesn't exist
+    #[napi(writable = false)]
+    pub mode: u32,
+    #[napi(writable = false)]
+    pub size: u32,
+    /// Modification time in seconds since the epoch
+    #[napi(writable = false)]
+    pub mtime: u32,
+}
+
+#[napi(object)]
+pub struct AdbDirectoryEntry {
+    #[napi(writable = false)]
+    pub name: String,
+    #[napi(writable = false)]
+    pub mode: u32,
+    #[napi(writable = false)]
+    pub size: u32,
+    #[napi(writable = false)]
+    pub mtime: u32,
+}
+
+impl From<SyncStat> for AdbFileStat {
+    fn from(stat: SyncStat) -> Self {
+        Self {
+            mode: stat.mode,
+            size: stat.size,
+            mtime: stat.mtime,
+        }
+    }
+}
+
+/// Writes each message part as its own bulk transfer, as adbd reads them.
+struct UsbTransport {
+    shared: Arc<SharedDevice>,
+    bulk_out: u8,
+}
+
+impl AdbTransport for UsbTransport {
+    fn write(&self, data: &[u8]) -> std::result::Result<(), String> {
+        let mut endpoint = self
+            .shared
+            .get_endpoint::<Out>(self.bulk_out)
+            .ok_or("invalid state")?;
+        let mut result = pooled_transfer_out(&self.shared, &mut endpoint, data, WRITE_TIMEOUT)
+            .map(|_| ())
+            .map_err(|e| format!("{e:?}"));
+        // A payload filling its last packet needs a zero length packet to end the transfer
+        if result.is_ok() && data.len().is_multiple_of(endpoint.max_packet_size()) {
+            result = pooled_transfer_out(&self.shared, &mut endpoint, &[], WRITE_TIMEOUT)
+                .map(|_| ())
+                .map_err(|e| format!("{e:?}"));
+        }
+        self.shared.put_endpoint(endpoint);
+        result
+    }
+}
+
+struct AdbLayout {
+    interface: u8,
+    bulk_in: u8,
+    bulk_out: u8,
+}
+
+fn find_adb_layout(
+    config: &ConfigurationDescriptor,
+    interface_number: Option<u8>,
+) -> std::result::Result<AdbLayout, String> {
+    config
+        .interface_alt_settings()
+        .filter(|alt| {
+            alt.alternate_setting() == 0
+                && alt.class() == CLASS_VENDOR
+                && alt.subclass() == SUBCLASS_ADB
+                && alt.protocol() == PROTOCOL_ADB
+        })
+        .filter(|alt| interface_number.is_none_or(|n| alt.interface_number() == n))
+        .find_map(|alt| {
+            let endpoint = |direction| {
+                alt.endpoints()
+                    .find(|ep| {
+                        ep.direction() == direction && ep.transfer_type() == TransferType::Bulk
+                    })
+                    .map(|ep| ep.address() & 0x7f)
+            };
+            Some(AdbLayout {
+                interface: alt.interface_number(),
+                bulk_in: endpoint(Direction::In)?,
+                bulk_out: endpoint(Direction::Out)?,
+            })
+        })
+        .ok_or_else(|| "no ADB interface found".to_string())
+}
+
+/// An ADB connection to an Android device, without an adb server.
+#[napi]
+pub struct AdbConnection {
+    shared: Arc<SharedDevice>,
+    core: Arc<AdbCore>,
+    key: Arc<AdbKey>,
+    key_name: String,
+    reader: Mutex<Option<InStream>>,
+
+    #[napi(writable = false)]
+    pub interfaceNumber: u8,
+    /// The device's CNXN banner, e.g. `device::ro.product.name=...;`
+    #[napi(writable = false)]
+    pub banner: String,
+    #[napi(writable = false)]
+    pub version: u32,
+    /// Largest payload per message, agreed with the device
+    #[napi(writable = false)]
+    pub maxPayload: u32,
+}
+
+impl AdbConnection {
+    fn reader(&self) -> MutexGuard<'_, Option<InStream>> {
+        self.reader
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    async fn open(&self, method: &'static str, service: String) -> Result<u32> {
+        let core = self.core.clone();
+        run_blocking(move || {
+            core.open(&service)
+                .map_err(|e| format!("{method} error: {e}"))
+        })
+        .await
+    }
+}
+
+#[napi]
+impl AdbConnection {
+    /// The public key in adbd's format, as kept in `~/.android/adbkey.pub`.
+    #[napi(getter)]
+    pub fn publicKey(&self) -> String {
+        self.key.public_key(&self.key_name)
+    }
+
+    /// The private key as PKCS#8 PEM, to keep a generated key for next time.
+    #[napi(getter)]
+    pub fn privateKey(&self) -> Result<String> {
+        self.key
+            .to_pem()
+            .map_err(|e| napi::Error::from_reason(format!("privateKey error: {e}")))
+    }
+
+    /// Opens a stream to a service such as `shell:ls`, `tcp:5555` or `reverse:...`.
+    #[napi]
+    pub async fn openStream(&self, service: String) -> Result<AdbStream> {
+        let id = self.open("openStream", service.clone()).await?;
+        Ok(AdbStream {
+            core: self.core.clone(),
+            id,
+            service,
+        })
+    }
+
+    /// Runs a command, or an interactive shell without one, on a `shell:` stream.
+    #[napi]
+    pub async fn shell(&self, command: Option<String>) -> Result<AdbStream> {
+        let service = format!("shell:{}", command.unwrap_or_default());
+        let id = self.open("shell", service.clone()).await?;
+        Ok(AdbStream {
+            core: self.core.clone(),
+            id,
+            service,
+        })
+    }
+
+    /// Opens the file sync service.
+    #[napi]
+    pub async fn openSync(&self) -> Result<AdbSync> {
+        let id = self.open("openSync", "sync:".to_string()).await?;
+        Ok(AdbSync {
+            core: self.core.clone(),
+            id,
+        })
+    }
+
+    /// Closes every stream and releases the interface, reattaching any kernel driver.
+    #[napi]
+    pub async fn close(&self) -> Result<()> {
+        let core = self.core.clone();
+        run_blocking(move || {
+            core.close_all();
+            Ok(())
+        })
+        .await?;
+        let reader = self.reader().take();
+        if let Some(reader) = reader {
+            reader.finish().await;
+        }
+        self.core.fail("connection closed");
+
+        self.shared
+            .release_interface("close", self.interfaceNumber)
+            .await
+    }
+}
+
+/// A stream to an ADB service, with `readable` and `writable` streams added in JavaScript.
+#[napi]
+pub struct AdbStream {
+    core: Arc<AdbCore>,
+    id: u32,
+
+    #[napi(writable = false)]
+    pub service: String,
+}
+
+#[napi]
+impl AdbStream {
+    #[napi(js_name = "nativeWrite")]
+    pub async fn write(&self, data: Uint8Array, timeout: u32) -> Result<u32> {
+        let core = self.core.clone();
+        let id = self.id;
+        run_blocking(move || {
+            core.write(id, &data, Duration::from_millis(timeout as u64))
+                .map(|_| data.len() as u32)
+                .map_err(|e| format!("write error: {e}"))
+        })
+        .await
+    }
+
+    #[napi(js_name = "nativeStartReading")]
+    pub fn startReading(
+        &self,
+        _bufferSize: Option<u32>,
+        onData: DataCallback,
+        onEnd: EndCallback,
+    ) -> Result<()> {
+        self.core
+            .start_reading(
+                self.id,
+                Box::new(move |data| {
+                    onData.call(
+                        Uint8Array::new(data),
+                        ThreadsafeFunctionCallMode::NonBlocking,
+                    );
+                }),
+                Box::new(move |error| {
+                    onEnd.call(error, ThreadsafeFunctionCallMode::NonBlocking);
+                }),
+            )
+            .map_err(|e| napi::Error::from_reason(format!("startReading error: {e}")))
+    }
+
+    #[napi]
+    pub fn stopReading(&self) {
+        self.core.stop_reading(self.id);
+    }
+
+    /// Holds back acknowledging the stream's data until `resumeReading()`, so the device stops
+    /// sending on it.
+    #[napi]
+    pub fn pauseReading(&self) {
+        self.core.pause_reading(self.id);
+    }
+
+    #[napi]
+    pub async fn resumeReading(&self) -> Result<()> {
+        let core = self.core.clone();
+        let id = self.id;
+        run_blocking(move || {
+            core.resume_reading(id)
+                .map_err(|e| format!("resumeReading error: {e}"))
+        })
+        .await
+    }
+
+    #[napi]
+    pub async fn close(&self) -> Result<()> {
+        let core = self.core.clone();
+        let id = self.id;
+        run_blocking(move || core.close(id).map_err(|e| format!("close error: {e}"))).await
+    }
+}
+
+/// The `sync:` service, for files on the device.
+#[napi]
+pub struct AdbSync {
+    core: Arc<AdbCore>,
+    id: u32,
+}
+
+impl AdbSync {
+    async fn run<T, F>(&self, method: &'static str, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&SyncClient) -> std::result::Result<T, String> + Send + 'static,
+    {
+        let core = self.core.clone();
+        let id = self.id;
+        run_blocking(move || {
+            f(&SyncClient { core: &core, id }).map_err(|e| format!("{method} error: {e}"))
+        })
+        .await
+    }
+}
+
+#[napi]
+impl AdbSync {
+    #[napi]
+    pub async fn stat(&self, path: String) -> Result<AdbFileStat> {
+        self.run("stat", move |sync| sync.stat(&path).map(AdbFileStat::from))
+            .await
+    }
+
+    #[napi]
+    pub async fn list(&self, path: String) -> Result<Vec<AdbDirectoryEntry>> {
+        let entries = self.run("list", move |sync| sync.list(&path)).await?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| AdbDirectoryEntry {
+                name: entry.name,
+                mode: entry.stat.mode,
+                size: entry.stat.size,
+                mtime: entry.stat.mtime,
+            })
+            .collect())
+    }
+
+    #[napi]
+    pub async fn pull(&self, path: String) -> Result<Uint8Array> {
+        let data = self.run("pull", move |sync| sync.pull(&path)).await?;
+        Ok(Uint8Array::new(data))
+    }
+
+    /// Writes a file, by default a regular `rw-r--r--` one modified now.
+    #[napi]
+    pub async fn push(
+        &self,
+        path: String,
+        data: Uint8Array,
+        mode: Option<u32>,
+        mtime: Option<u32>,
+    ) -> Result<()> {
+        let mtime = mtime.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs() as u32)
+                .unwrap_or(0)
+        });
+        self.run("push", move |sync| {
+            sync.push(&path, mode.unwrap_or(DEFAULT_PUSH_MODE), mtime, &data)
+        })
+        .await
+    }
+
+    #[napi]
+    pub async fn close(&self) -> Result<()> {
+        self.run("close", |sync| {
+            // Best effort, the stream is closed regardless
+            let _ = sync.quit();
+            sync.core.close(sync.id)
+        })
+        .await
+    }
+}
+
+#[napi]
+impl UsbDevice {
+    /// Claims the ADB interface (class `0xFF`, subclass `0x42`, protocol `0x01`), then connects
+    /// and authenticates with the host key.
+    #[napi]
+    pub async fn openAdb(&self, options: Option<AdbOptions>) -> Result<AdbConnection> {
+        let options = options.unwrap_or(AdbOptions {
+            interfaceNumber: None,
+            detachKernelDriver: None,
+            privateKey: None,
+            keyName: None,
+            timeout: None,
+        });
+
+        let shared = self.shared().clone();
+        let device = shared
+            .opened_device()
+            .ok_or_else(|| napi::Error::from_reason("openAdb error: invalid state"))?;
+        let interface_number = options.interfaceNumber;
+        let layout = run_blocking(move || {
+            let config = device
+                .active_configuration()
+                .map_err(|e| format!("openAdb error: {e}"))?;
+            find_adb_layout(&config, interface_number).map_err(|e| format!("openAdb error: {e}"))
+        })
+        .await?;
+
+        let private_key = options.privateKey;
+        let key = run_blocking(move || {
+            match private_key {
+                Some(pem) => AdbKey::from_pem(&pem),
+                None => AdbKey::generate(),
+            }
+            .map_err(|e| format!("openAdb error: {e}"))
+        })
+        .await?;
+        let key = Arc::new(key);
+
+        let detach = options.detachKernelDriver.unwrap_or(true);
+        shared
+            .claim_interface("openAdb", layout.interface, detach)
+            .await?;
+
+        let core = Arc::new(AdbCore::new(Box::new(UsbTransport {
+            shared: shared.clone(),
+            bulk_out: layout.bulk_out,
+        })));
+        let endpoint = shared
+            .get_endpoint::<nusb::transfer::In>(layout.bulk_in)
+            .ok_or_else(|| napi::Error::from_reason("openAdb error: invalid state"))?;
+        let reader = {
+            let feed_core = core.clone();
+            let end_core = core.clone();
+            InStream::start(
+                endpoint,
+                READ_TRANSFER_LENGTH,
+                READ_TRANSFERS,
+                move |data| {
+                    let replies = feed_core.feed(data);
+                    if !replies.is_empty() {
+                        let core = feed_core.clone();
+                        tokio::task::spawn_blocking(move || {
+                            for reply in replies {
+                                // A failed reply surfaces through the stream's next operation
+                                let _ = core.send(&reply);
+                            }
+                        });
+                    }
+                },
+                move |error| {
+                    end_core.fail(&error.unwrap_or_else(|| "connection closed".to_string()));
+                },
+            )
+        };
+
+        let key_name = options
+            .keyName
+            .unwrap_or_else(|| DEFAULT_KEY_NAME.to_string());
+        let accept_timeout =
+            Duration::from_millis(options.timeout.unwrap_or(DEFAULT_ACCEPT_TIMEOUT) as u64);
+        let connected = {
+            let core = core.clone();
+            let key = key.clone();
+            let key_name = key_name.clone();
+            run_blocking(move || {
+                core.connect(&key, &key_name, accept_timeout)
+                    .map_err(|e| format!("openAdb error: {e}"))
+            })
+            .await
+        };
+        let connected = match connected {
+            Ok(connected) => connected,
+            Err(e) => {
+                drop(reader);
+                let _ = shared.release_interface("openAdb", layout.interface).await;
+                return Err(e);
+            }
+        };
+
+        Ok(AdbConnection {
+            shared,
+            core,
+            key,
+            key_name,
+            reader: Mutex::new(Some(reader)),
+            interfaceNumber: layout.interface,
+            banner: connected.banner,
+            version: connected.version,
+            maxPayload: connected.max_payload,
+        })
+    }
+}
@@ -0,0 +1,168 @@
+use rsa::{
+    pkcs1::DecodeRsaPrivateKey,
+    pkcs8::{DecodePrivateKey, EncodePrivateKey, LineEnding},
+    traits::PublicKeyParts,
+    BigUint, Pkcs1v15Sign, RsaPrivateKey,
+};
+
+/// ADB's public key format only holds 2048-bit keys.
+const KEY_BITS: usize = 2048;
+const KEY_WORDS: usize = KEY_BITS / 32;
+const KEY_BYTES: usize = KEY_BITS / 8;
+
+/// Length of the AUTH token, which is signed as if it were a SHA-1 digest
+const TOKEN_LENGTH: usize = 20;
+/// DER DigestInfo header for a SHA-1 digest
+const SHA1_DIGEST_INFO: [u8; 15] = [
+    0x30, 0x21, 0x30, 0x09, 0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a, 0x05, 0x00, 0x04, 0x14,
+];
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for n in 0..4 {
+            if n <= chunk.len() {
+                out.push(BASE64[(bits >> (18 - 6 * n) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Little-endian bytes of `value`, zero padded to `len`.
+fn padded_le(value: &BigUint, len: usize) -> Vec<u8> {
+    let mut bytes = value.to_bytes_le();
+    bytes.resize(len, 0);
+    bytes
+}
+
+/// The host's RSA key, as kept in `~/.android/adbkey`.
+pub(super) struct AdbKey {
+    key: RsaPrivateKey,
+}
+
+impl AdbKey {
+    pub(super) fn generate() -> std::result::Result<Self, String> {
+        let key = RsaPrivateKey::new(&mut rand::thread_rng(), KEY_BITS)
+            .map_err(|e| format!("key generation failed: {e}"))?;
+        Ok(Self { key })
+    }
+
+    /// Reads a PKCS#8 or PKCS#1 PEM key.
+    pub(super) fn from_pem(pem: &str) -> std::result::Result<Self, String> {
+        let key = RsaPrivateKey::from_pkcs8_pem(pem)
+            .or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem))
+            .map_err(|e| format!("invalid private key: {e}"))?;
+        if key.size() != KEY_BYTES {
+            return Err(format!("private key must be {KEY_BITS} bits"));
+        }
+        Ok(Self { key })
+    }
+
+    /// PKCS#8 PEM, the format adb itself writes.
+    pub(super) fn to_pem(&self) -> std::result::Result<String, String> {
+        self.key
+            .to_pkcs8_pem(LineEnding::LF)
+            .map(|pem| pem.to_string())
+            .map_err(|e| format!("key encoding failed: {e}"))
+    }
+
+    /// Signs an AUTH token, which adbd checks as a PKCS#1 v1.5 SHA-1 signature.
+    pub(super) fn sign(&self, token: &[u8]) -> std::result::Result<Vec<u8>, String> {
+        if token.len() != TOKEN_LENGTH {
+            return Err(format!("invalid AUTH token length {}", token.len()));
+        }
+        let mut digest_info = SHA1_DIGEST_INFO.to_vec();
+        digest_info.extend_from_slice(token);
+        self.key
+            .sign(Pkcs1v15Sign::new_unprefixed(), &digest_info)
+            .map_err(|e| format!("signing failed: {e}"))
+    }
+
+    /// The public key in adbd's format: the modulus with the Montgomery constants its verifier
+    /// uses, base64 encoded and followed by a name shown on the device.
+    pub(super) fn public_key(&self, name: &str) -> String {
+        let n = self.key.n();
+        let n_bytes = padded_le(n, KEY_BYTES);
+
+        // -1 / n mod 2^32, by Newton's method on the lowest word
+        let n0 = u32::from_le_bytes([n_bytes[0], n_bytes[1], n_bytes[2], n_bytes[3]]);
+        let mut inverse = n0;
+        for _ in 0..5 {
+            inverse = inverse.wrapping_mul(2u32.wrapping_sub(n0.wrapping_mul(inverse)));
+        }
+        let rr = (BigUint::from(1u32) << (2 * KEY_BITS)) % n;
+        let mut exponent = padded_le(self.key.e(), 4);
+        exponent.truncate(4);
+
+        let mut encoded = Vec::with_capacity(12 + 2 * KEY_BYTES);
+        encoded.extend_from_slice(&(KEY_WORDS as u32).to_le_bytes());
+        encoded.extend_from_slice(&inverse.wrapping_neg().to_le_bytes());
+        encoded.extend_from_slice(&n_bytes);
+        encoded.extend_from_slice(&padded_le(&rr, KEY_BYTES));
+        encoded.extend_from_slice(&exponent);
+        format!("{} {name}", base64(&encoded))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::RsaPublicKey;
+
+    // A throwaway key, with its public half as `adb` would write it to `adbkey.pub`
+    const TEST_KEY: &str = include_str!("test_key.pem");
+    const TEST_KEY_PUB: &str = include_str!("test_key.pub");
+
+    #[test]
+    fn base64_matches_rfc_4648() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foob"), "Zm9vYg==");
+        assert_eq!(base64(b"fooba"), "Zm9vYmE=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64(&[0xfb, 0xff]), "+/8=");
+    }
+
+    #[test]
+    fn public_key_matches_adb_format() {
+        let key = AdbKey::from_pem(TEST_KEY).unwrap();
+        assert_eq!(key.public_key("test@host"), TEST_KEY_PUB.trim_end());
+    }
+
+    #[test]
+    fn signs_tokens_as_sha1_digests() {
+        let key = AdbKey::from_pem(TEST_KEY).unwrap();
+        let token: Vec<u8> = (0..TOKEN_LENGTH as u8).collect();
+        let signature = key.sign(&token).unwrap();
+        assert_eq!(signature.len(), KEY_BYTES);
+
+        let mut digest_info = SHA1_DIGEST_INFO.to_vec();
+        digest_info.extend_from_slice(&token);
+        RsaPublicKey::from(&key.key)
+            .verify(Pkcs1v15Sign::new_unprefixed(), &digest_info, &signature)
+            .unwrap();
+
+        assert!(key.sign(&token[..19]).is_err());
+    }
+
+    #[test]
+    fn round_trips_pem() {
+        let key = AdbKey::from_pem(TEST_KEY).unwrap();
+        let again = AdbKey::from_pem(&key.to_pem().unwrap()).unwrap();
+        assert_eq!(again.public_key("a"), key.public_key("a"));
+        assert!(AdbKey::from_pem("not a key").is_err());
+    }
+}
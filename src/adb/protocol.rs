@@ -0,0 +1,746 @@
+use super::auth::AdbKey;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+pub(super) const A_CNXN: u32 = 0x4e58_4e43;
+pub(super) const A_AUTH: u32 = 0x4854_5541;
+pub(super) const A_OPEN: u32 = 0x4e45_504f;
+pub(super) const A_OKAY: u32 = 0x5941_4b4f;
+pub(super) const A_CLSE: u32 = 0x4553_4c43;
+pub(super) const A_WRTE: u32 = 0x4554_5257;
+
+const AUTH_TOKEN: u32 = 1;
+const AUTH_SIGNATURE: u32 = 2;
+const AUTH_RSAPUBLICKEY: u32 = 3;
+
+/// Version with checksums optional, though they are still sent for older devices
+const A_VERSION: u32 = 0x0100_0001;
+const MAX_PAYLOAD: u32 = 256 * 1024;
+const HEADER_LENGTH: usize = 24;
+
+pub(super) const RESPONSE_TIMEOUT: Duration = Duration::from_millis(10000);
+
+pub(super) struct Message {
+    pub(super) command: u32,
+    pub(super) arg0: u32,
+    pub(super) arg1: u32,
+    pub(super) data: Vec<u8>,
+}
+
+impl Message {
+    pub(super) fn new(command: u32, arg0: u32, arg1: u32, data: Vec<u8>) -> Self {
+        Self {
+            command,
+            arg0,
+            arg1,
+            data,
+        }
+    }
+
+    fn header(&self) -> [u8; HEADER_LENGTH] {
+        let checksum = self
+            .data
+            .iter()
+            .fold(0u32, |sum, byte| sum.wrapping_add(*byte as u32));
+        let mut header = [0; HEADER_LENGTH];
+        for (n, field) in [
+            self.command,
+            self.arg0,
+            self.arg1,
+            self.data.len() as u32,
+            checksum,
+            !self.command,
+        ]
+        .iter()
+        .enumerate()
+        {
+            header[n * 4..n * 4 + 4].copy_from_slice(&field.to_le_bytes());
+        }
+        header
+    }
+}
+
+/// Carries bytes to the device. Bytes from the device are handed to `AdbCore::feed()`, so the
+/// protocol runs the same over USB or against a simulated device.
+pub(super) trait AdbTransport: Send + Sync {
+    fn write(&self, data: &[u8]) -> std::result::Result<(), String>;
+}
+
+pub(super) type StreamData = Box<dyn FnMut(Vec<u8>) + Send>;
+pub(super) type StreamEnd = Box<dyn FnOnce(Option<String>) + Send>;
+
+#[derive(Default)]
+struct StreamState {
+    /// Set by the OKAY answering OPEN
+    remote_id: Option<u32>,
+    /// The device acknowledged the last WRTE, so another may be sent
+    write_ready: bool,
+    closed: bool,
+    /// Data arriving while nothing is reading
+    received: VecDeque<u8>,
+    /// Set while the reader can't keep up, holding back the OKAY for the last WRTE
+    paused: bool,
+    /// The remote ID to acknowledge the held back WRTE to on resuming
+    unacknowledged: Option<u32>,
+    on_data: Option<StreamData>,
+    on_end: Option<StreamEnd>,
+}
+
+#[derive(Default)]
+struct CoreState {
+    buffer: Vec<u8>,
+    /// CNXN and AUTH messages, until connected
+    handshake: VecDeque<Message>,
+    streams: HashMap<u32, StreamState>,
+    next_id: u32,
+    max_payload: usize,
+    /// Why the connection ended
+    error: Option<String>,
+}
+
+pub(super) struct Connected {
+    pub(super) version: u32,
+    pub(super) max_payload: u32,
+    pub(super) banner: String,
+}
+
+/// ADB connection state shared by its streams: incoming messages are matched to streams by
+/// local ID, and writes wait for the device's OKAY before the next WRTE.
+pub(super) struct AdbCore {
+    transport: Box<dyn AdbTransport>,
+    send_lock: Mutex<()>,
+    state: Mutex<CoreState>,
+    changed: Condvar,
+}
+
+impl AdbCore {
+    pub(super) fn new(transport: Box<dyn AdbTransport>) -> Self {
+        Self {
+            transport,
+            send_lock: Mutex::new(()),
+            state: Mutex::new(CoreState {
+                next_id: 1,
+                max_payload: MAX_PAYLOAD as usize,
+                ..Default::default()
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, CoreState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub(super) fn send(&self, message: &Message) -> std::result::Result<(), String> {
+        let _guard = self
+            .send_lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        self.transport.write(&message.header())?;
+        if !message.data.is_empty() {
+            self.transport.write(&message.data)?;
+        }
+        Ok(())
+    }
+
+    /// Waits until `ready` returns a result or the connection ends.
+    fn wait<T, F>(&self, timeout: Duration, mut ready: F) -> std::result::Result<T, String>
+    where
+        F: FnMut(&mut CoreState) -> Option<std::result::Result<T, String>>,
+    {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state();
+        loop {
+            if let Some(result) = ready(&mut state) {
+                return result;
+            }
+            if let Some(error) = &state.error {
+                return Err(error.clone());
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err("timed out".to_string());
+            }
+            state = self
+                .changed
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+        }
+    }
+
+    /// Takes bytes read from the device and returns the replies to send, which the caller
+    /// sends from a thread that may block.
+    pub(super) fn feed(&self, data: &[u8]) -> Vec<Message> {
+        let mut replies = Vec::new();
+        let mut state = self.state();
+        state.buffer.extend_from_slice(data);
+
+        while state.buffer.len() >= HEADER_LENGTH {
+            let field = |n: usize| {
+                let bytes = &state.buffer[n * 4..n * 4 + 4];
+                u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+            };
+            let (command, arg0, arg1, length, magic) =
+                (field(0), field(1), field(2), field(3) as usize, field(5));
+            if magic != !command || length > MAX_PAYLOAD as usize * 4 {
+                drop(state);
+                self.fail("invalid message from device");
+                return Vec::new();
+            }
+            if state.buffer.len() < HEADER_LENGTH + length {
+                break;
+            }
+            let data = state.buffer[HEADER_LENGTH..HEADER_LENGTH + length].to_vec();
+            state.buffer.drain(..HEADER_LENGTH + length);
+            Self::handle(
+                &mut state,
+                Message::new(command, arg0, arg1, data),
+                &mut replies,
+            );
+        }
+
+        self.changed.notify_all();
+        replies
+    }
+
+    fn handle(state: &mut CoreState, message: Message, replies: &mut Vec<Message>) {
+        let local_id = message.arg1;
+        let remote_id = message.arg0;
+        match message.command {
+            A_CNXN | A_AUTH => state.handshake.push_back(message),
+            A_OKAY => {
+                if let Some(stream) = state.streams.get_mut(&local_id) {
+                    stream.remote_id.get_or_insert(remote_id);
+                    stream.write_ready = true;
+                }
+            }
+            A_WRTE => match state.streams.get_mut(&local_id) {
+                Some(stream) if !stream.closed => {
+                    match stream.on_data.as_mut() {
+                        Some(on_data) => on_data(message.data),
+                        None => stream.received.extend(message.data),
+                    }
+                    if stream.paused {
+                        stream.unacknowledged = Some(remote_id);
+                    } else {
+                        replies.push(Message::new(A_OKAY, local_id, remote_id, Vec::new()));
+                    }
+                }
+                _ => replies.push(Message::new(A_CLSE, 0, remote_id, Vec::new())),
+            },
+            A_CLSE => {
+                if let Some(stream) = state.streams.get_mut(&local_id) {
+                    if !stream.closed && stream.remote_id.is_some() {
+                        replies.push(Message::new(A_CLSE, local_id, remote_id, Vec::new()));
+                    }
+                    stream.closed = true;
+                    if let Some(on_end) = stream.on_end.take() {
+                        on_end(None);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Ends the connection, ending every stream with the error.
+    pub(super) fn fail(&self, error: &str) {
+        let mut state = self.state();
+        state.error.get_or_insert_with(|| error.to_string());
+        for stream in state.streams.values_mut() {
+            stream.closed = true;
+            if let Some(on_end) = stream.on_end.take() {
+                on_end(Some(error.to_string()));
+            }
+        }
+        self.changed.notify_all();
+    }
+
+    /// CNXN, then AUTH: the token is signed, and if the device doesn't know the key it is sent
+    /// for the user to accept, which is waited for up to `accept_timeout`.
+    pub(super) fn connect(
+        &self,
+        key: &AdbKey,
+        key_name: &str,
+        accept_timeout: Duration,
+    ) -> std::result::Result<Connected, String> {
+        self.send(&Message::new(
+            A_CNXN,
+            A_VERSION,
+            MAX_PAYLOAD,
+            b"host::\0".to_vec(),
+        ))?;
+
+        let mut signed = false;
+        let mut key_sent = false;
+        loop {
+            let timeout = if key_sent {
+                accept_timeout
+            } else {
+                RESPONSE_TIMEOUT
+            };
+            let message = self
+                .wait(timeout, |state| state.handshake.pop_front().map(Ok))
+                .map_err(|e| match key_sent {
+                    true => format!("device didn't accept the key: {e}"),
+                    false => e,
+                })?;
+
+            match message.command {
+                A_CNXN => {
+                    let max_payload = message.arg1.min(MAX_PAYLOAD);
+                    self.state().max_payload = max_payload as usize;
+                    let banner = String::from_utf8_lossy(&message.data)
+                        .trim_end_matches('\0')
+                        .to_string();
+                    return Ok(Connected {
+                        version: message.arg0,
+                        max_payload,
+                        banner,
+                    });
+                }
+                A_AUTH if message.arg0 == AUTH_TOKEN => {
+                    if !signed {
+                        let signature = key.sign(&message.data)?;
+                        self.send(&Message::new(A_AUTH, AUTH_SIGNATURE, 0, signature))?;
+                        signed = true;
+                    } else if !key_sent {
+                        let mut public_key = key.public_key(key_name).into_bytes();
+                        public_key.push(0);
+                        self.send(&Message::new(A_AUTH, AUTH_RSAPUBLICKEY, 0, public_key))?;
+                        key_sent = true;
+                    } else {
+                        return Err("device rejected the key".to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Opens a service such as `shell:ls` and returns the stream's local ID.
+    pub(super) fn open(&self, service: &str) -> std::result::Result<u32, String> {
+        let id = {
+            let mut state = self.state();
+            let id = state.next_id;
+            state.next_id = state.next_id.wrapping_add(1).max(1);
+            state.streams.insert(id, StreamState::default());
+            id
+        };
+
+        let mut destination = service.as_bytes().to_vec();
+        destination.push(0);
+        let opened = self
+            .send(&Message::new(A_OPEN, id, 0, destination))
+            .and_then(|_| {
+                self.wait(RESPONSE_TIMEOUT, |state| match state.streams.get(&id) {
+                    Some(stream) if stream.remote_id.is_some() => Some(Ok(())),
+                    Some(stream) if !stream.closed => None,
+                    _ => Some(Err(format!("device refused {service}"))),
+                })
+            });
+        if let Err(e) = opened {
+            self.state().streams.remove(&id);
+            return Err(e);
+        }
+        Ok(id)
+    }
+
+    /// Sends data in `max_payload` pieces, each once the previous one is acknowledged.
+    pub(super) fn write(
+        &self,
+        id: u32,
+        data: &[u8],
+        timeout: Duration,
+    ) -> std::result::Result<(), String> {
+        let max_payload = self.state().max_payload;
+        for chunk in data.chunks(max_payload) {
+            let remote_id = self.wait(timeout, |state| match state.streams.get_mut(&id) {
+                Some(stream) if stream.closed => Some(Err("stream closed".to_string())),
+                Some(stream) if stream.write_ready => {
+                    stream.write_ready = false;
+                    stream.remote_id.map(Ok)
+                }
+                Some(_) => None,
+                None => Some(Err("stream closed".to_string())),
+            })?;
+            self.send(&Message::new(A_WRTE, id, remote_id, chunk.to_vec()))?;
+        }
+        Ok(())
+    }
+
+    /// Reads exactly `length` bytes that arrived while nothing was reading the stream.
+    pub(super) fn read(&self, id: u32, length: usize) -> std::result::Result<Vec<u8>, String> {
+        self.wait(RESPONSE_TIMEOUT, |state| match state.streams.get_mut(&id) {
+            Some(stream) if stream.received.len() >= length => {
+                Some(Ok(stream.received.drain(..length).collect()))
+            }
+            Some(stream) if !stream.closed => None,
+            _ => Some(Err("stream closed".to_string())),
+        })
+    }
+
+    /// Hands the stream's data to `on_data` from now on, starting with anything already received.
+    pub(super) fn start_reading(
+        &self,
+        id: u32,
+        mut on_data: StreamData,
+        on_end: StreamEnd,
+    ) -> std::result::Result<(), String> {
+        let mut state = self.state();
+        let error = state.error.clone();
+        let stream = state.streams.get_mut(&id).ok_or("stream closed")?;
+        if stream.on_data.is_some() {
+            return Err("already reading".to_string());
+        }
+        if !stream.received.is_empty() {
+            on_data(stream.received.drain(..).collect());
+        }
+        if stream.closed {
+            on_end(error);
+            return Ok(());
+        }
+        stream.on_data = Some(on_data);
+        stream.on_end = Some(on_end);
+        Ok(())
+    }
+
+    pub(super) fn stop_reading(&self, id: u32) {
+        let on_end = self.state().streams.get_mut(&id).and_then(|stream| {
+            stream.on_data = None;
+            stream.on_end.take()
+        });
+        if let Some(on_end) = on_end {
+            on_end(None);
+        }
+    }
+
+    /// Stops acknowledging the stream's data, so the device holds on to the rest until resumed.
+    pub(super) fn pause_reading(&self, id: u32) {
+        if let Some(stream) = self.state().streams.get_mut(&id) {
+            stream.paused = true;
+        }
+    }
+
+    pub(super) fn resume_reading(&self, id: u32) -> std::result::Result<(), String> {
+        let unacknowledged = self.state().streams.get_mut(&id).and_then(|stream| {
+            stream.paused = false;
+            stream.unacknowledged.take()
+        });
+        match unacknowledged {
+            Some(remote_id) => self.send(&Message::new(A_OKAY, id, remote_id, Vec::new())),
+            None => Ok(()),
+        }
+    }
+
+    /// Closes a stream from the host side.
+    pub(super) fn close(&self, id: u32) -> std::result::Result<(), String> {
+        let Some(mut stream) = self.state().streams.remove(&id) else {
+            return Ok(());
+        };
+        if let Some(on_end) = stream.on_end.take() {
+            on_end(None);
+        }
+        match stream.remote_id {
+            Some(remote_id) if !stream.closed => {
+                self.send(&Message::new(A_CLSE, id, remote_id, Vec::new()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub(super) fn close_all(&self) {
+        let ids = self.state().streams.keys().copied().collect::<Vec<_>>();
+        for id in ids {
+            // Best effort, the connection is going away
+            let _ = self.close(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, thread};
+
+    const TEST_KEY: &str = include_str!("test_key.pem");
+    const TEST_KEY_PUB: &str = include_str!("test_key.pub");
+
+    /// Keeps everything the host sends, for the test to split back into messages.
+    #[derive(Clone, Default)]
+    struct FakeTransport(Arc<Mutex<Vec<u8>>>);
+
+    impl AdbTransport for FakeTransport {
+        fn write(&self, data: &[u8]) -> std::result::Result<(), String> {
+            self.0.lock().unwrap().extend_from_slice(data);
+            Ok(())
+        }
+    }
+
+    impl FakeTransport {
+        fn sent(&self) -> Vec<Message> {
+            let bytes = self.0.lock().unwrap();
+            let mut messages = Vec::new();
+            let mut rest = &bytes[..];
+            while rest.len() >= HEADER_LENGTH {
+                let field =
+                    |n: usize| u32::from_le_bytes(rest[n * 4..n * 4 + 4].try_into().unwrap());
+                let length = field(3) as usize;
+                assert_eq!(field(5), !field(0), "bad magic");
+                let data = rest[HEADER_LENGTH..HEADER_LENGTH + length].to_vec();
+                messages.push(Message::new(field(0), field(1), field(2), data));
+                rest = &rest[HEADER_LENGTH + length..];
+            }
+            messages
+        }
+
+        /// Waits for the host to have sent `count` messages, from another thread.
+        fn wait_for(&self, count: usize) -> Vec<Message> {
+            for _ in 0..1000 {
+                let sent = self.sent();
+                if sent.len() >= count {
+                    return sent;
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+            panic!("host sent {} messages, expected {count}", self.sent().len());
+        }
+    }
+
+    fn bytes(message: &Message) -> Vec<u8> {
+        let mut bytes = message.header().to_vec();
+        bytes.extend_from_slice(&message.data);
+        bytes
+    }
+
+    fn core() -> (Arc<AdbCore>, FakeTransport) {
+        let transport = FakeTransport::default();
+        (
+            Arc::new(AdbCore::new(Box::new(transport.clone()))),
+            transport,
+        )
+    }
+
+    fn connect(core: &AdbCore, max_payload: u32) -> Connected {
+        let key = AdbKey::from_pem(TEST_KEY).unwrap();
+        core.feed(&bytes(&Message::new(
+            A_CNXN,
+            A_VERSION,
+            max_payload,
+            b"device::ro.product.name=test;\0".to_vec(),
+        )));
+        core.connect(&key, "test@host", Duration::from_millis(100))
+            .unwrap()
+    }
+
+    /// Opens a stream the device answers as remote ID 7.
+    fn open(core: &Arc<AdbCore>, transport: &FakeTransport) -> u32 {
+        let sent = transport.sent().len();
+        let opening = {
+            let core = core.clone();
+            thread::spawn(move || core.open("shell:ls"))
+        };
+        let open = transport.wait_for(sent + 1).pop().unwrap();
+        assert_eq!(
+            (open.command, open.arg1, &open.data[..]),
+            (A_OPEN, 0, &b"shell:ls\0"[..])
+        );
+        core.feed(&bytes(&Message::new(A_OKAY, 7, open.arg0, Vec::new())));
+        let id = opening.join().unwrap().unwrap();
+        assert_eq!(id, open.arg0);
+        id
+    }
+
+    #[test]
+    fn connects_without_auth() {
+        let (core, transport) = core();
+        let connected = connect(&core, 4096);
+        assert_eq!(connected.version, A_VERSION);
+        assert_eq!(connected.max_payload, 4096);
+        assert_eq!(connected.banner, "device::ro.product.name=test;");
+
+        let sent = transport.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(
+            (sent[0].command, sent[0].arg0, sent[0].arg1),
+            (A_CNXN, A_VERSION, MAX_PAYLOAD)
+        );
+        assert_eq!(sent[0].data, b"host::\0");
+        // Checksummed for older devices
+        assert_eq!(
+            u32::from_le_bytes(bytes(&sent[0])[16..20].try_into().unwrap()),
+            b"host::".iter().map(|b| *b as u32).sum::<u32>()
+        );
+    }
+
+    #[test]
+    fn signs_then_sends_the_public_key() {
+        let (core, transport) = core();
+        let token = Message::new(A_AUTH, AUTH_TOKEN, 0, (0..20).collect());
+        core.feed(&bytes(&token));
+        core.feed(&bytes(&token));
+        let connected = connect(&core, MAX_PAYLOAD);
+        assert_eq!(connected.max_payload, MAX_PAYLOAD);
+
+        let sent = transport.sent();
+        let commands: Vec<_> = sent.iter().map(|m| (m.command, m.arg0)).collect();
+        assert_eq!(
+            commands,
+            [
+                (A_CNXN, A_VERSION),
+                (A_AUTH, AUTH_SIGNATURE),
+                (A_AUTH, AUTH_RSAPUBLICKEY)
+            ]
+        );
+        let key = AdbKey::from_pem(TEST_KEY).unwrap();
+        assert_eq!(sent[1].data, key.sign(&token.data).unwrap());
+        assert_eq!(
+            sent[2].data,
+            format!("{}\0", TEST_KEY_PUB.trim_end()).into_bytes()
+        );
+    }
+
+    #[test]
+    fn rejected_key_fails_the_connection() {
+        let (core, _) = core();
+        let key = AdbKey::from_pem(TEST_KEY).unwrap();
+        for _ in 0..3 {
+            core.feed(&bytes(&Message::new(A_AUTH, AUTH_TOKEN, 0, vec![0; 20])));
+        }
+        let error = core
+            .connect(&key, "test@host", Duration::from_millis(100))
+            .err();
+        assert_eq!(error.as_deref(), Some("device rejected the key"));
+    }
+
+    #[test]
+    fn writes_wait_for_okay() {
+        let (core, transport) = core();
+        connect(&core, 4);
+        let id = open(&core, &transport);
+
+        let writing = {
+            let core = core.clone();
+            thread::spawn(move || core.write(id, b"hello", Duration::from_secs(1)))
+        };
+        // Only the first piece goes out until the device acknowledges it
+        let sent = transport.wait_for(3);
+        assert_eq!(
+            (sent[2].command, sent[2].arg0, sent[2].arg1),
+            (A_WRTE, id, 7)
+        );
+        assert_eq!(sent[2].data, b"hell");
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(transport.sent().len(), 3);
+
+        core.feed(&bytes(&Message::new(A_OKAY, 7, id, Vec::new())));
+        writing.join().unwrap().unwrap();
+        let sent = transport.sent();
+        assert_eq!(sent[3].data, b"o");
+    }
+
+    #[test]
+    fn acknowledges_data_unless_paused() {
+        let (core, transport) = core();
+        connect(&core, MAX_PAYLOAD);
+        let id = open(&core, &transport);
+
+        let replies = core.feed(&bytes(&Message::new(A_WRTE, 7, id, b"hi".to_vec())));
+        assert_eq!(replies.len(), 1);
+        assert_eq!(
+            (replies[0].command, replies[0].arg0, replies[0].arg1),
+            (A_OKAY, id, 7)
+        );
+        assert_eq!(core.read(id, 2).unwrap(), b"hi");
+
+        core.pause_reading(id);
+        let replies = core.feed(&bytes(&Message::new(A_WRTE, 7, id, b"!".to_vec())));
+        assert!(replies.is_empty());
+        let sent = transport.sent().len();
+        core.resume_reading(id).unwrap();
+        let okay = transport.sent().pop().unwrap();
+        assert_eq!(transport.sent().len(), sent + 1);
+        assert_eq!((okay.command, okay.arg0, okay.arg1), (A_OKAY, id, 7));
+        assert_eq!(core.read(id, 1).unwrap(), b"!");
+    }
+
+    #[test]
+    fn device_close_ends_the_stream() {
+        let (core, transport) = core();
+        connect(&core, MAX_PAYLOAD);
+        let id = open(&core, &transport);
+
+        let ended = Arc::new(Mutex::new(None));
+        let on_end = ended.clone();
+        core.start_reading(
+            id,
+            Box::new(|_| {}),
+            Box::new(move |error| *on_end.lock().unwrap() = Some(error)),
+        )
+        .unwrap();
+
+        let replies = core.feed(&bytes(&Message::new(A_CLSE, 7, id, Vec::new())));
+        assert_eq!(replies.len(), 1);
+        assert_eq!(
+            (replies[0].command, replies[0].arg0, replies[0].arg1),
+            (A_CLSE, id, 7)
+        );
+        assert_eq!(*ended.lock().unwrap(), Some(None));
+        assert_eq!(
+            core.write(id, b"x", Duration::from_millis(100))
+                .err()
+                .as_deref(),
+            Some("stream closed")
+        );
+
+        // Data for a stream that is gone is refused
+        let replies = core.feed(&bytes(&Message::new(A_WRTE, 7, 99, b"x".to_vec())));
+        assert_eq!(
+            (replies[0].command, replies[0].arg0, replies[0].arg1),
+            (A_CLSE, 0, 7)
+        );
+    }
+
+    #[test]
+    fn partial_messages_wait_for_the_rest() {
+        let (core, transport) = core();
+        connect(&core, MAX_PAYLOAD);
+        let id = open(&core, &transport);
+
+        let message = bytes(&Message::new(A_WRTE, 7, id, b"split".to_vec()));
+        assert!(core.feed(&message[..10]).is_empty());
+        assert!(core.feed(&message[10..26]).is_empty());
+        assert_eq!(core.feed(&message[26..]).len(), 1);
+        assert_eq!(core.read(id, 5).unwrap(), b"split");
+    }
+
+    #[test]
+    fn bad_magic_fails_the_connection() {
+        let (core, _) = core();
+        let mut message = bytes(&Message::new(A_OKAY, 1, 1, Vec::new()));
+        message[20] ^= 0xff;
+        assert!(core.feed(&message).is_empty());
+        assert_eq!(
+            core.open("shell:").err().as_deref(),
+            Some("invalid message from device")
+        );
+    }
+
+    #[test]
+    fn oversized_length_fails_the_connection() {
+        let (core, _) = core();
+        let mut message = Message::new(A_WRTE, 1, 1, Vec::new()).header();
+        message[12..16].copy_from_slice(&(MAX_PAYLOAD * 4 + 1).to_le_bytes());
+        assert!(core.feed(&message).is_empty());
+        assert_eq!(
+            core.open("shell:").err().as_deref(),
+            Some("invalid message from device")
+        );
+    }
+}
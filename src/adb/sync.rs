@@ -0,0 +1,143 @@
+use super::protocol::{AdbCore, RESPONSE_TIMEOUT};
+
+/// Largest DATA chunk the sync service takes
+const SYNC_DATA_MAX: usize = 64 * 1024;
+
+pub(super) struct SyncStat {
+    pub(super) mode: u32,
+    pub(super) size: u32,
+    pub(super) mtime: u32,
+}
+
+pub(super) struct SyncEntry {
+    pub(super) name: String,
+    pub(super) stat: SyncStat,
+}
+
+/// Requests on a `sync:` stream, which carries length-prefixed records tagged with four letters.
+pub(super) struct SyncClient<'a> {
+    pub(super) core: &'a AdbCore,
+    pub(super) id: u32,
+}
+
+impl SyncClient<'_> {
+    fn request(&self, tag: &[u8; 4], payload: &[u8]) -> std::result::Result<(), String> {
+        let mut request = Vec::with_capacity(8 + payload.len());
+        request.extend_from_slice(tag);
+        request.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        request.extend_from_slice(payload);
+        self.core.write(self.id, &request, RESPONSE_TIMEOUT)
+    }
+
+    fn read_u32(&self) -> std::result::Result<u32, String> {
+        let bytes = self.core.read(self.id, 4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn read_tag(&self) -> std::result::Result<[u8; 4], String> {
+        let bytes = self.core.read(self.id, 4)?;
+        Ok([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    fn read_stat(&self) -> std::result::Result<SyncStat, String> {
+        Ok(SyncStat {
+            mode: self.read_u32()?,
+            size: self.read_u32()?,
+            mtime: self.read_u32()?,
+        })
+    }
+
+    /// Reads the message following a FAIL.
+    fn failure(&self) -> String {
+        match self
+            .read_u32()
+            .and_then(|length| self.core.read(self.id, length as usize))
+        {
+            Ok(message) => String::from_utf8_lossy(&message).to_string(),
+            Err(e) => e,
+        }
+    }
+
+    fn unexpected(&self, tag: [u8; 4]) -> String {
+        match &tag {
+            b"FAIL" => self.failure(),
+            tag => format!("unexpected {} response", String::from_utf8_lossy(tag)),
+        }
+    }
+
+    /// STAT, with every field 0 when the path doesn't exist.
+    pub(super) fn stat(&self, path: &str) -> std::result::Result<SyncStat, String> {
+        self.request(b"STAT", path.as_bytes())?;
+        match self.read_tag()? {
+            tag if &tag == b"STAT" => self.read_stat(),
+            tag => Err(self.unexpected(tag)),
+        }
+    }
+
+    pub(super) fn list(&self, path: &str) -> std::result::Result<Vec<SyncEntry>, String> {
+        self.request(b"LIST", path.as_bytes())?;
+        let mut entries = Vec::new();
+        loop {
+            match &self.read_tag()? {
+                b"DENT" => {
+                    let stat = self.read_stat()?;
+                    let length = self.read_u32()?;
+                    let name = self.core.read(self.id, length as usize)?;
+                    entries.push(SyncEntry {
+                        name: String::from_utf8_lossy(&name).to_string(),
+                        stat,
+                    });
+                }
+                b"DONE" => {
+                    // DONE carries an empty entry
+                    self.core.read(self.id, 16)?;
+                    return Ok(entries);
+                }
+                tag => return Err(self.unexpected(*tag)),
+            }
+        }
+    }
+
+    pub(super) fn pull(&self, path: &str) -> std::result::Result<Vec<u8>, String> {
+        self.request(b"RECV", path.as_bytes())?;
+        let mut data = Vec::new();
+        loop {
+            match &self.read_tag()? {
+                b"DATA" => {
+                    let length = self.read_u32()?;
+                    data.extend(self.core.read(self.id, length as usize)?);
+                }
+                b"DONE" => {
+                    self.read_u32()?;
+                    return Ok(data);
+                }
+                tag => return Err(self.unexpected(*tag)),
+            }
+        }
+    }
+
+    pub(super) fn push(
+        &self,
+        path: &str,
+        mode: u32,
+        mtime: u32,
+        data: &[u8],
+    ) -> std::result::Result<(), String> {
+        self.request(b"SEND", format!("{path},{mode}").as_bytes())?;
+        for chunk in data.chunks(SYNC_DATA_MAX) {
+            self.request(b"DATA", chunk)?;
+        }
+        let mut done = b"DONE".to_vec();
+        done.extend_from_slice(&mtime.to_le_bytes());
+        self.core.write(self.id, &done, RESPONSE_TIMEOUT)?;
+
+        match self.read_tag()? {
+            tag if &tag == b"OKAY" => self.read_u32().map(|_| ()),
+            tag => Err(self.unexpected(tag)),
+        }
+    }
+
+    pub(super) fn quit(&self) -> std::result::Result<(), String> {
+        self.request(b"QUIT", &[])
+    }
+}
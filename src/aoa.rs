@@ -0,0 +1,430 @@
+use crate::endpoint_stream::{InStream, StreamSlot};
+use crate::transfer_pool::pooled_transfer_out;
+use crate::webusb_device::{run_blocking, ControlTarget, SharedDevice, UsbDevice};
+use crate::DeviceWait;
+use napi::{
+    bindgen_prelude::*, threadsafe_function::ThreadsafeFunction,
+    threadsafe_function::ThreadsafeFunctionCallMode,
+};
+use napi_derive::napi;
+use nusb::{
+    descriptors::{ConfigurationDescriptor, TransferType},
+    transfer::{ControlIn, ControlOut, ControlType, Direction, Recipient},
+};
+use std::{sync::Arc, time::Duration};
+
+const GOOGLE_VENDOR_ID: u16 = 0x18d1;
+/// Accessory, accessory + ADB, audio, audio + ADB, accessory + audio and accessory + audio + ADB
+const ACCESSORY_PRODUCT_IDS: std::ops::RangeInclusive<u16> = 0x2d00..=0x2d05;
+
+const ACCESSORY_GET_PROTOCOL: u8 = 51;
+const ACCESSORY_SEND_STRING: u8 = 52;
+const ACCESSORY_START: u8 = 53;
+const ACCESSORY_REGISTER_HID: u8 = 54;
+const ACCESSORY_UNREGISTER_HID: u8 = 55;
+const ACCESSORY_SET_HID_REPORT_DESC: u8 = 56;
+const ACCESSORY_SEND_HID_EVENT: u8 = 57;
+const ACCESSORY_SET_AUDIO_MODE: u8 = 58;
+
+/// 2 channel, 16-bit PCM at 44100 Hz, the only mode AOA 2.0 defines
+const AUDIO_MODE_PCM: u16 = 1;
+
+const CLASS_VENDOR: u8 = 0xff;
+/// Subclass of the accessory interface, ADB's is `0x42`
+const SUBCLASS_ACCESSORY: u8 = 0xff;
+
+const CONTROL_TIMEOUT: Duration = Duration::from_millis(1000);
+const DEFAULT_START_WAIT: u32 = 10000;
+const READ_TRANSFERS: usize = 4;
+const DEFAULT_BUFFER_SIZE: u32 = 16384;
+
+type DataCallback = ThreadsafeFunction<Uint8Array, (), Uint8Array, napi::Status, false>;
+type EndCallback = ThreadsafeFunction<Option<String>, (), Option<String>, napi::Status, false>;
+
+/// Identifies the accessory to the phone, which picks an app to handle it from these.
+#[napi(object)]
+pub struct AccessoryInfo {
+    pub manufacturer: String,
+    pub model: String,
+    pub description: Option<String>,
+    pub version: Option<String>,
+    /// Where to get the app when none is installed
+    pub uri: Option<String>,
+    pub serial: Option<String>,
+    /// Route the phone's audio to the accessory as 16-bit stereo PCM at 44.1 kHz (AOA 2.0)
+    pub audio: Option<bool>,
+    /// Maximum time (in milliseconds) to wait for the phone to re-enumerate, defaults to 10 seconds
+    pub timeout: Option<u32>,
+}
+
+#[napi(object)]
+pub struct AccessoryOptions {
+    /// Detach kernel drivers while the interface is open, defaults to `true`
+    pub detachKernelDriver: Option<bool>,
+}
+
+fn is_accessory(vendor_id: u16, product_id: u16) -> bool {
+    vendor_id == GOOGLE_VENDOR_ID && ACCESSORY_PRODUCT_IDS.contains(&product_id)
+}
+
+fn accessory_target(shared: &SharedDevice) -> std::result::Result<ControlTarget, String> {
+    shared
+        .control_target(Recipient::Device, 0)
+        .ok_or_else(|| "invalid state".to_string())
+}
+
+fn accessory_out(
+    target: &ControlTarget,
+    request: u8,
+    value: u16,
+    index: u16,
+    data: &[u8],
+) -> std::result::Result<(), String> {
+    target
+        .control_out_blocking(
+            ControlOut {
+                control_type: ControlType::Vendor,
+                recipient: Recipient::Device,
+                request,
+                value,
+                index,
+                data,
+            },
+            CONTROL_TIMEOUT,
+        )
+        .map_err(|e| format!("request {request} failed: {e}"))
+}
+
+/// AOA protocol version, 0 when the phone doesn't support accessory mode.
+fn get_protocol(target: &ControlTarget) -> std::result::Result<u16, String> {
+    let data = target
+        .control_in_blocking(
+            ControlIn {
+                control_type: ControlType::Vendor,
+                recipient: Recipient::Device,
+                request: ACCESSORY_GET_PROTOCOL,
+                value: 0,
+                index: 0,
+                length: 2,
+            },
+            CONTROL_TIMEOUT,
+        )
+        .map_err(|e| format!("GET_PROTOCOL failed: {e}"))?;
+    match data[..] {
+        [lo, hi, ..] => Ok(u16::from_le_bytes([lo, hi])),
+        _ => Err("short GET_PROTOCOL response".to_string()),
+    }
+}
+
+struct AccessoryLayout {
+    interface: u8,
+    bulk_in: u8,
+    bulk_out: u8,
+}
+
+fn find_accessory_layout(
+    config: &ConfigurationDescriptor,
+) -> std::result::Result<AccessoryLayout, String> {
+    config
+        .interface_alt_settings()
+        .filter(|alt| {
+            alt.alternate_setting() == 0
+                && alt.class() == CLASS_VENDOR
+                && alt.subclass() == SUBCLASS_ACCESSORY
+        })
+        .find_map(|alt| {
+            let endpoint = |direction| {
+                alt.endpoints()
+                    .find(|ep| {
+                        ep.direction() == direction && ep.transfer_type() == TransferType::Bulk
+                    })
+                    .map(|ep| ep.address() & 0x7f)
+            };
+            Some(AccessoryLayout {
+                interface: alt.interface_number(),
+                bulk_in: endpoint(Direction::In)?,
+                bulk_out: endpoint(Direction::Out)?,
+            })
+        })
+        .ok_or_else(|| "no accessory interface found".to_string())
+}
+
+/// The bulk endpoints of a phone in accessory mode, carrying the app's own protocol.
+#[napi]
+pub struct AccessoryConnection {
+    shared: Arc<SharedDevice>,
+    layout: AccessoryLayout,
+    reader: StreamSlot,
+
+    #[napi(writable = false)]
+    pub interfaceNumber: u8,
+}
+
+#[napi]
+impl AccessoryConnection {
+    #[napi(js_name = "nativeWrite")]
+    pub async fn write(&self, data: Uint8Array, timeout: u32) -> Result<u32> {
+        let mut endpoint = self
+            .shared
+            .get_endpoint::<nusb::transfer::Out>(self.layout.bulk_out)
+            .ok_or_else(|| napi::Error::from_reason("write error: invalid state"))?;
+        let shared = self.shared.clone();
+        run_blocking(move || {
+            let result = pooled_transfer_out(
+                &shared,
+                &mut endpoint,
+                &data,
+                Duration::from_millis(timeout as u64),
+            )
+            .map(|len| len as u32)
+            .map_err(|e| format!("write error: {e:?}"));
+            shared.put_endpoint(endpoint);
+            result
+        })
+        .await
+    }
+
+    #[napi(js_name = "nativeStartReading")]
+    pub fn startReading(
+        &self,
+        bufferSize: Option<u32>,
+        onData: DataCallback,
+        onEnd: EndCallback,
+    ) -> Result<()> {
+        self.reader.start(
+            "startReading",
+            &self.shared,
+            self.layout.bulk_in,
+            |endpoint| {
+                InStream::start(
+                    endpoint,
+                    bufferSize.unwrap_or(DEFAULT_BUFFER_SIZE) as usize,
+                    READ_TRANSFERS,
+                    move |data| {
+                        onData.call(
+                            Uint8Array::new(data.to_vec()),
+                            ThreadsafeFunctionCallMode::NonBlocking,
+                        );
+                    },
+                    move |error| {
+                        onEnd.call(error, ThreadsafeFunctionCallMode::NonBlocking);
+                    },
+                )
+            },
+        )
+    }
+
+    #[napi]
+    pub fn stopReading(&self) {
+        self.reader.stop();
+    }
+
+    /// Stops reading from the device until `resumeReading()`, leaving it to hold on to the data.
+    #[napi]
+    pub fn pauseReading(&self) {
+        self.reader.pause();
+    }
+
+    #[napi]
+    pub fn resumeReading(&self) {
+        self.reader.resume();
+    }
+
+    /// Stops reading and releases the interface.
+    #[napi]
+    pub async fn close(&self) -> Result<()> {
+        // Its endpoint keeps the interface claimed until the stream has ended
+        self.reader.finish().await;
+        self.shared
+            .release_interface("close", self.layout.interface)
+            .await
+    }
+}
+
+#[napi]
+impl UsbDevice {
+    /// Whether the device is a phone already in accessory mode (`0x18D1:0x2D00` to `0x2D05`).
+    #[napi(getter)]
+    pub fn accessoryMode(&self) -> bool {
+        is_accessory(self.vendorId, self.productId)
+    }
+
+    /// AOA protocol version the phone supports: 0 for none, 1 for accessories or 2 adding audio and HID.
+    #[napi]
+    pub async fn getAccessoryProtocol(&self) -> Result<u16> {
+        let shared = self.shared().clone();
+        run_blocking(move || {
+            get_protocol(&accessory_target(&shared)?)
+                .map_err(|e| format!("getAccessoryProtocol error: {e}"))
+        })
+        .await
+    }
+
+    /// Identifies the accessory and switches the phone to accessory mode, resolving with the
+    /// re-enumerated device once it appears.
+    #[napi]
+    pub async fn startAccessory(&self, info: AccessoryInfo) -> Result<UsbDevice> {
+        let original = self.device_info().clone();
+        let wait = DeviceWait::start()
+            .map_err(|e| napi::Error::from_reason(format!("startAccessory error: {e}")))?;
+
+        let shared = self.shared().clone();
+        let strings = [
+            Some(info.manufacturer),
+            Some(info.model),
+            info.description,
+            info.version,
+            info.uri,
+            info.serial,
+        ];
+        let audio = info.audio.unwrap_or(false);
+        run_blocking(move || {
+            let start = || {
+                let target = accessory_target(&shared)?;
+                let protocol = get_protocol(&target)?;
+                if protocol == 0 {
+                    return Err("device doesn't support accessory mode".to_string());
+                }
+                if audio && protocol < 2 {
+                    return Err("device doesn't support accessory audio".to_string());
+                }
+                for (index, string) in strings.iter().enumerate() {
+                    if let Some(string) = string {
+                        let mut data = string.as_bytes().to_vec();
+                        data.push(0);
+                        accessory_out(&target, ACCESSORY_SEND_STRING, 0, index as u16, &data)?;
+                    }
+                }
+                if audio {
+                    accessory_out(&target, ACCESSORY_SET_AUDIO_MODE, AUDIO_MODE_PCM, 0, &[])?;
+                }
+                accessory_out(&target, ACCESSORY_START, 0, 0, &[])
+            };
+            start().map_err(|e| format!("startAccessory error: {e}"))
+        })
+        .await?;
+
+        let info = wait
+            .until(
+                |info| {
+                    is_accessory(info.vendor_id(), info.product_id())
+                        && info.bus_id() == original.bus_id()
+                        && info.port_chain() == original.port_chain()
+                },
+                Duration::from_millis(info.timeout.unwrap_or(DEFAULT_START_WAIT) as u64),
+            )
+            .await
+            .map_err(|e| napi::Error::from_reason(format!("startAccessory error: {e}")))?;
+        Ok(UsbDevice::new(info))
+    }
+
+    /// Claims the accessory interface of a phone in accessory mode.
+    #[napi]
+    pub async fn openAccessory(
+        &self,
+        options: Option<AccessoryOptions>,
+    ) -> Result<AccessoryConnection> {
+        if !self.accessoryMode() {
+            return Err(napi::Error::from_reason(
+                "openAccessory error: device is not in accessory mode",
+            ));
+        }
+        let detach = options.and_then(|o| o.detachKernelDriver).unwrap_or(true);
+
+        let shared = self.shared().clone();
+        let device = shared
+            .opened_device()
+            .ok_or_else(|| napi::Error::from_reason("openAccessory error: invalid state"))?;
+        let layout = run_blocking(move || {
+            let config = device
+                .active_configuration()
+                .map_err(|e| format!("openAccessory error: {e}"))?;
+            find_accessory_layout(&config).map_err(|e| format!("openAccessory error: {e}"))
+        })
+        .await?;
+
+        shared
+            .claim_interface("openAccessory", layout.interface, detach)
+            .await?;
+        Ok(AccessoryConnection {
+            shared,
+            interfaceNumber: layout.interface,
+            layout,
+            reader: StreamSlot::default(),
+        })
+    }
+
+    /// Registers a HID device with the phone (AOA 2.0) and sends its report descriptor.
+    #[napi]
+    pub async fn registerAccessoryHid(&self, id: u16, reportDescriptor: Uint8Array) -> Result<()> {
+        let shared = self.shared().clone();
+        let descriptor = reportDescriptor.to_vec();
+        run_blocking(move || {
+            let register = || {
+                let device = shared.opened_device().ok_or("invalid state")?;
+                let target = accessory_target(&shared)?;
+                accessory_out(
+                    &target,
+                    ACCESSORY_REGISTER_HID,
+                    id,
+                    descriptor.len() as u16,
+                    &[],
+                )?;
+                // Sent in pieces no larger than the control endpoint's packet size
+                let chunk = device.device_descriptor().max_packet_size_0() as usize;
+                for (n, piece) in descriptor.chunks(chunk).enumerate() {
+                    accessory_out(
+                        &target,
+                        ACCESSORY_SET_HID_REPORT_DESC,
+                        id,
+                        (n * chunk) as u16,
+                        piece,
+                    )?;
+                }
+                Ok(())
+            };
+            register().map_err(|e: String| format!("registerAccessoryHid error: {e}"))
+        })
+        .await
+    }
+
+    #[napi]
+    pub async fn unregisterAccessoryHid(&self, id: u16) -> Result<()> {
+        let shared = self.shared().clone();
+        run_blocking(move || {
+            accessory_target(&shared)
+                .and_then(|target| accessory_out(&target, ACCESSORY_UNREGISTER_HID, id, 0, &[]))
+                .map_err(|e| format!("unregisterAccessoryHid error: {e}"))
+        })
+        .await
+    }
+
+    /// Sends an input report for a HID device registered with `registerAccessoryHid()`.
+    #[napi]
+    pub async fn sendAccessoryHidEvent(&self, id: u16, report: Uint8Array) -> Result<()> {
+        let shared = self.shared().clone();
+        let report = report.to_vec();
+        run_blocking(move || {
+            accessory_target(&shared)
+                .and_then(|target| accessory_out(&target, ACCESSORY_SEND_HID_EVENT, id, 0, &report))
+                .map_err(|e| format!("sendAccessoryHidEvent error: {e}"))
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognises_accessory_product_ids() {
+        // Accessory, accessory + ADB, then the audio and audio + ADB combinations
+        for product_id in [0x2d00, 0x2d01, 0x2d04, 0x2d05] {
+            assert!(is_accessory(GOOGLE_VENDOR_ID, product_id));
+        }
+        assert!(!is_accessory(GOOGLE_VENDOR_ID, 0x2d06));
+        assert!(!is_accessory(GOOGLE_VENDOR_ID, 0x4ee7));
+        assert!(!is_accessory(0x04e8, 0x2d00));
+    }
+}
@@ -0,0 +1,918 @@
+use crate::endpoint_stream::InStream;
+use crate::transfer_pool::{pooled_transfer_in, pooled_transfer_out, PooledEndpoint};
+use crate::webusb_device::{run_blocking, SharedDevice, UsbDevice};
+use napi::{
+    bindgen_prelude::*, threadsafe_function::ThreadsafeFunction,
+    threadsafe_function::ThreadsafeFunctionCallMode,
+};
+use napi_derive::napi;
+use nusb::{
+    descriptors::{ConfigurationDescriptor, TransferType},
+    transfer::{Direction, In, Out},
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+const CLASS_SMART_CARD: u8 = 0x0b;
+const CCID_DESCRIPTOR: u8 = 0x21;
+const CCID_DESCRIPTOR_LEN: usize = 54;
+
+const PC_TO_RDR_SET_PARAMETERS: u8 = 0x61;
+const PC_TO_RDR_ICC_POWER_ON: u8 = 0x62;
+const PC_TO_RDR_ICC_POWER_OFF: u8 = 0x63;
+const PC_TO_RDR_GET_SLOT_STATUS: u8 = 0x65;
+const PC_TO_RDR_GET_PARAMETERS: u8 = 0x6c;
+const PC_TO_RDR_XFR_BLOCK: u8 = 0x6f;
+
+const RDR_TO_PC_DATA_BLOCK: u8 = 0x80;
+const RDR_TO_PC_SLOT_STATUS: u8 = 0x81;
+const RDR_TO_PC_PARAMETERS: u8 = 0x82;
+const RDR_TO_PC_NOTIFY_SLOT_CHANGE: u8 = 0x50;
+
+const HEADER_LEN: usize = 10;
+
+const COMMAND_FAILED: u8 = 0x01;
+const TIME_EXTENSION: u8 = 0x02;
+const ICC_ABSENT: u8 = 0x02;
+
+const FEATURE_TPDU: u32 = 0x0001_0000;
+const FEATURE_SHORT_APDU: u32 = 0x0002_0000;
+const FEATURE_EXTENDED_APDU: u32 = 0x0004_0000;
+const FEATURE_LEVEL_MASK: u32 = 0x0007_0000;
+
+/// wLevelParameter and bChainParameter values for extended APDU chaining
+const CHAIN_BEGIN: u16 = 0x01;
+const CHAIN_END: u16 = 0x02;
+const CHAIN_CONTINUE: u16 = 0x03;
+const CHAIN_EMPTY: u16 = 0x10;
+
+const PROTOCOL_T1: u8 = 0x01;
+
+const DEFAULT_TIMEOUT: u32 = 10000;
+const NOTIFY_TRANSFERS: usize = 2;
+
+type SlotChangeCallback =
+    ThreadsafeFunction<CcidSlotChange, (), CcidSlotChange, napi::Status, false>;
+
+#[napi(object)]
+pub struct CcidOptions {
+    /// The CCID interface to use, defaults to the first one. A vendor class interface may be named if it has a CCID descriptor
+    pub interfaceNumber: Option<u8>,
+    /// Detach kernel drivers while the interface is open, defaults to `true`
+    pub detachKernelDriver: Option<bool>,
+    /// Timeout (in milliseconds) for each bulk transfer, defaults to 10000. Readers asking for more time extend it
+    pub timeout: Option<u32>,
+}
+
+/// The smart card device class descriptor.
+#[napi(object)]
+#[derive(Clone)]
+pub struct CcidDescriptor {
+    #[napi(writable = false)]
+    pub ccidVersion: u16,
+    #[napi(writable = false)]
+    pub maxSlotIndex: u8,
+    #[napi(writable = false, ts_type = "Array<'5V' | '3V' | '1.8V'>")]
+    pub voltages: Vec<String>,
+    #[napi(writable = false, ts_type = "Array<'T=0' | 'T=1'>")]
+    pub protocols: Vec<String>,
+    /// In kHz
+    #[napi(writable = false)]
+    pub defaultClock: u32,
+    /// In kHz
+    #[napi(writable = false)]
+    pub maximumClock: u32,
+    /// In bps
+    #[napi(writable = false)]
+    pub dataRate: u32,
+    /// In bps
+    #[napi(writable = false)]
+    pub maxDataRate: u32,
+    #[napi(writable = false)]
+    pub maxIfsd: u32,
+    /// dwFeatures bits
+    #[napi(writable = false)]
+    pub features: u32,
+    /// What XfrBlock carries, from dwFeatures
+    #[napi(
+        writable = false,
+        ts_type = "'character' | 'tpdu' | 'shortApdu' | 'extendedApdu'"
+    )]
+    pub exchangeLevel: String,
+    /// Largest message, header included
+    #[napi(writable = false)]
+    pub maxMessageLength: u32,
+    #[napi(writable = false)]
+    pub maxBusySlots: u8,
+}
+
+#[napi(object)]
+pub struct CcidSlotStatus {
+    #[napi(writable = false)]
+    pub slot: u8,
+    #[napi(writable = false, ts_type = "'active' | 'inactive' | 'absent'")]
+    pub card: String,
+    /// bClockStatus: `0` running, `1` stopped low, `2` stopped high, `3` stopped
+    #[napi(writable = false)]
+    pub clockStatus: u8,
+}
+
+#[napi(object)]
+pub struct CcidSlotChange {
+    #[napi(writable = false)]
+    pub slot: u8,
+    #[napi(writable = false)]
+    pub present: bool,
+    /// Whether the card came or went since the last notification
+    #[napi(writable = false)]
+    pub changed: bool,
+}
+
+struct CcidLayout {
+    interface: u8,
+    bulk_in: u8,
+    bulk_out: u8,
+    interrupt_in: Option<u8>,
+    descriptor: CcidDescriptor,
+}
+
+fn parse_ccid_descriptor(d: &[u8]) -> CcidDescriptor {
+    let u32_at = |i: usize| u32::from_le_bytes([d[i], d[i + 1], d[i + 2], d[i + 3]]);
+    let voltage_support = d[5];
+    let protocols = u32_at(6);
+    let features = u32_at(40);
+    CcidDescriptor {
+        ccidVersion: u16::from_le_bytes([d[2], d[3]]),
+        maxSlotIndex: d[4],
+        voltages: [(0x01, "5V"), (0x02, "3V"), (0x04, "1.8V")]
+            .into_iter()
+            .filter(|(bit, _)| voltage_support & bit != 0)
+            .map(|(_, name)| name.to_string())
+            .collect(),
+        protocols: [(0x01, "T=0"), (0x02, "T=1")]
+            .into_iter()
+            .filter(|(bit, _)| protocols & bit != 0)
+            .map(|(_, name)| name.to_string())
+            .collect(),
+        defaultClock: u32_at(10),
+        maximumClock: u32_at(14),
+        dataRate: u32_at(19),
+        maxDataRate: u32_at(23),
+        maxIfsd: u32_at(28),
+        features,
+        exchangeLevel: match features & FEATURE_LEVEL_MASK {
+            level if level & FEATURE_EXTENDED_APDU != 0 => "extendedApdu",
+            level if level & FEATURE_SHORT_APDU != 0 => "shortApdu",
+            level if level & FEATURE_TPDU != 0 => "tpdu",
+            _ => "character",
+        }
+        .to_string(),
+        maxMessageLength: u32_at(44),
+        maxBusySlots: d[53],
+    }
+}
+
+fn find_ccid_layout(
+    config: &ConfigurationDescriptor,
+    interface_number: Option<u8>,
+) -> std::result::Result<CcidLayout, String> {
+    config
+        .interface_alt_settings()
+        .filter(|alt| alt.alternate_setting() == 0)
+        .filter(|alt| match interface_number {
+            Some(n) => alt.interface_number() == n,
+            None => alt.class() == CLASS_SMART_CARD,
+        })
+        .find_map(|alt| {
+            // Some early readers put the class descriptor after the endpoints
+            let descriptor = alt.descriptors().find(|d| {
+                d.descriptor_type() == CCID_DESCRIPTOR && d.len() >= CCID_DESCRIPTOR_LEN
+            })?;
+            let endpoint = |direction, transfer_type| {
+                alt.endpoints()
+                    .find(|ep| ep.direction() == direction && ep.transfer_type() == transfer_type)
+                    .map(|ep| ep.address() & 0x7f)
+            };
+            Some(CcidLayout {
+                interface: alt.interface_number(),
+                bulk_in: endpoint(Direction::In, TransferType::Bulk)?,
+                bulk_out: endpoint(Direction::Out, TransferType::Bulk)?,
+                interrupt_in: endpoint(Direction::In, TransferType::Interrupt),
+                descriptor: parse_ccid_descriptor(&descriptor),
+            })
+        })
+        .ok_or_else(|| "no CCID interface found".to_string())
+}
+
+fn slot_error(error: u8) -> String {
+    match error {
+        0xff => "command aborted".to_string(),
+        0xfe => "card mute".to_string(),
+        0xfd => "parity error".to_string(),
+        0xfc => "overrun".to_string(),
+        0xfb => "hardware error".to_string(),
+        0xf8 => "bad ATR TS".to_string(),
+        0xf7 => "bad ATR TCK".to_string(),
+        0xf6 => "protocol not supported by the card".to_string(),
+        0xf5 => "class not supported by the card".to_string(),
+        0xf4 => "procedure byte conflict".to_string(),
+        0xf3 => "deactivated protocol".to_string(),
+        0xf2 => "busy with auto sequence".to_string(),
+        0xe0 => "slot busy".to_string(),
+        0x00 => "command not supported".to_string(),
+        error => format!("error {error:#04x}"),
+    }
+}
+
+/// Frames a PC_to_RDR message: type, length, slot, sequence and three message specific bytes.
+fn encode_command(
+    message_type: u8,
+    slot: u8,
+    sequence: u8,
+    specific: [u8; 3],
+    data: &[u8],
+) -> Vec<u8> {
+    let mut message = Vec::with_capacity(HEADER_LEN + data.len());
+    message.push(message_type);
+    message.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    message.push(slot);
+    message.push(sequence);
+    message.extend_from_slice(&specific);
+    message.extend_from_slice(data);
+    message
+}
+
+/// Length of the RDR_to_PC message at the start of `data`, once its header has arrived.
+fn message_len(data: &[u8]) -> Option<usize> {
+    let length = data.get(1..5)?;
+    Some(HEADER_LEN + u32::from_le_bytes([length[0], length[1], length[2], length[3]]) as usize)
+}
+
+/// Decodes RDR_to_PC_NotifySlotChange, two bits per slot: present, then changed.
+fn parse_slot_changes(data: &[u8], slots: usize) -> Vec<CcidSlotChange> {
+    let [RDR_TO_PC_NOTIFY_SLOT_CHANGE, states @ ..] = data else {
+        return Vec::new();
+    };
+    (0..slots.min(states.len() * 4))
+        .map(|slot| {
+            let bits = states[slot / 4] >> ((slot % 4) * 2);
+            CcidSlotChange {
+                slot: slot as u8,
+                present: bits & 0x01 != 0,
+                changed: bits & 0x02 != 0,
+            }
+        })
+        .collect()
+}
+
+struct CcidResponse {
+    message_type: u8,
+    status: u8,
+    error: u8,
+    /// bChainParameter, bClockStatus or bProtocolNum, by message type
+    specific: u8,
+    data: Vec<u8>,
+}
+
+/// Sends PC_to_RDR commands and matches RDR_to_PC responses to them by sequence number.
+struct CcidTransport {
+    shared: Arc<SharedDevice>,
+    bulk_in: u8,
+    bulk_out: u8,
+    max_message_length: usize,
+    exchange_level: u32,
+    timeout: Duration,
+    sequence: u8,
+    /// bProtocolNum of each powered slot, learned with GetParameters
+    protocols: Vec<Option<u8>>,
+}
+
+impl CcidTransport {
+    fn command(
+        &mut self,
+        message_type: u8,
+        slot: u8,
+        specific: [u8; 3],
+        data: &[u8],
+    ) -> std::result::Result<CcidResponse, String> {
+        if HEADER_LEN + data.len() > self.max_message_length {
+            return Err(format!(
+                "{} byte command exceeds the reader's {} byte messages",
+                data.len(),
+                self.max_message_length - HEADER_LEN
+            ));
+        }
+        let sequence = self.sequence;
+        self.sequence = self.sequence.wrapping_add(1);
+
+        let message = encode_command(message_type, slot, sequence, specific, data);
+
+        let mut ep_out = self
+            .shared
+            .get_endpoint::<Out>(self.bulk_out)
+            .ok_or("invalid state")?;
+        let sent = pooled_transfer_out(&self.shared, &mut ep_out, &message, self.timeout)
+            .map(|_| ())
+            .map_err(|e| format!("command failed: {e:?}"));
+        self.shared.put_endpoint(ep_out);
+        sent?;
+
+        let mut ep_in = self
+            .shared
+            .get_endpoint::<In>(self.bulk_in)
+            .ok_or("invalid state")?;
+        let response = self.response(&mut ep_in, slot, sequence);
+        self.shared.put_endpoint(ep_in);
+        let response = response?;
+
+        if response.status >> 6 == COMMAND_FAILED {
+            return Err(match response.status & 0x03 {
+                ICC_ABSENT => "no card in the slot".to_string(),
+                _ => slot_error(response.error),
+            });
+        }
+        Ok(response)
+    }
+
+    /// Reads responses until the one for `sequence`, skipping stale ones and time extension requests.
+    fn response(
+        &self,
+        ep_in: &mut PooledEndpoint<In>,
+        slot: u8,
+        sequence: u8,
+    ) -> std::result::Result<CcidResponse, String> {
+        loop {
+            let message = self.read_message(ep_in)?;
+            if message[5] != slot || message[6] != sequence {
+                continue;
+            }
+            let status = message[7];
+            if status >> 6 == TIME_EXTENSION {
+                continue;
+            }
+            return Ok(CcidResponse {
+                message_type: message[0],
+                status,
+                error: message[8],
+                specific: message[9],
+                data: message[HEADER_LEN..].to_vec(),
+            });
+        }
+    }
+
+    fn read_message(&self, ep_in: &mut PooledEndpoint<In>) -> std::result::Result<Vec<u8>, String> {
+        let mut message = Vec::new();
+        loop {
+            let (buffer, len) =
+                pooled_transfer_in(&self.shared, ep_in, self.max_message_length, self.timeout)
+                    .map_err(|e| format!("response failed: {e:?}"))?;
+            message.extend_from_slice(&buffer[..len]);
+            self.shared.put_buffer(ep_in, buffer);
+
+            if let Some(length) = message_len(&message).filter(|&l| message.len() >= l) {
+                message.truncate(length);
+                return Ok(message);
+            }
+            if len == 0 {
+                return Err("short response".to_string());
+            }
+        }
+    }
+
+    fn power_on(&mut self, slot: u8, voltage: u8) -> std::result::Result<Vec<u8>, String> {
+        let response = self.command(PC_TO_RDR_ICC_POWER_ON, slot, [voltage, 0, 0], &[])?;
+        expect(&response, RDR_TO_PC_DATA_BLOCK)?;
+        let protocol = self
+            .command(PC_TO_RDR_GET_PARAMETERS, slot, [0; 3], &[])
+            .ok()
+            .filter(|parameters| parameters.message_type == RDR_TO_PC_PARAMETERS)
+            .map(|parameters| parameters.specific);
+        if let Some(entry) = self.protocols.get_mut(slot as usize) {
+            *entry = protocol;
+        }
+        Ok(response.data)
+    }
+
+    fn xfr_block(
+        &mut self,
+        slot: u8,
+        data: &[u8],
+        level: u16,
+    ) -> std::result::Result<CcidResponse, String> {
+        let response = self.command(
+            PC_TO_RDR_XFR_BLOCK,
+            slot,
+            [0, level as u8, (level >> 8) as u8],
+            data,
+        )?;
+        expect(&response, RDR_TO_PC_DATA_BLOCK)?;
+        Ok(response)
+    }
+
+    /// Exchanges one APDU, chaining extended APDUs across several XfrBlocks where the reader's
+    /// messages are too short for them.
+    fn exchange_apdu(&mut self, slot: u8, apdu: &[u8]) -> std::result::Result<Vec<u8>, String> {
+        let max_data = self.max_message_length - HEADER_LEN;
+        if self.exchange_level & FEATURE_EXTENDED_APDU == 0 || apdu.len() <= max_data {
+            let response = self.xfr_block(slot, apdu, 0)?;
+            if self.exchange_level & FEATURE_EXTENDED_APDU == 0 {
+                return Ok(response.data);
+            }
+            return self.receive_chain(slot, response);
+        }
+
+        let chunks = apdu.chunks(max_data).collect::<Vec<_>>();
+        let mut response = None;
+        for (i, chunk) in chunks.iter().enumerate() {
+            let level = match (i, i + 1 == chunks.len()) {
+                (0, _) => CHAIN_BEGIN,
+                (_, true) => CHAIN_END,
+                _ => CHAIN_CONTINUE,
+            };
+            response = Some(self.xfr_block(slot, chunk, level)?);
+        }
+        let response = response.ok_or("empty APDU")?;
+        self.receive_chain(slot, response)
+    }
+
+    fn receive_chain(
+        &mut self,
+        slot: u8,
+        mut response: CcidResponse,
+    ) -> std::result::Result<Vec<u8>, String> {
+        let mut data = Vec::new();
+        loop {
+            data.extend_from_slice(&response.data);
+            match response.specific as u16 {
+                CHAIN_BEGIN | CHAIN_CONTINUE => {
+                    response = self.xfr_block(slot, &[], CHAIN_EMPTY)?;
+                }
+                _ => return Ok(data),
+            }
+        }
+    }
+
+    /// Sends an APDU, fetching the rest of the response on `61xx` and resending with the right
+    /// length on `6Cxx`, as T=0 cards ask.
+    fn transmit_apdu(&mut self, slot: u8, apdu: &[u8]) -> std::result::Result<Vec<u8>, String> {
+        if apdu.len() < 4 {
+            return Err("APDU shorter than its header".to_string());
+        }
+        match self.exchange_level {
+            level if level & (FEATURE_SHORT_APDU | FEATURE_EXTENDED_APDU) != 0 => {}
+            level if level & FEATURE_TPDU != 0 => {
+                if self.protocols.get(slot as usize).copied().flatten() == Some(PROTOCOL_T1) {
+                    return Err("T=1 TPDU readers are not supported".to_string());
+                }
+            }
+            _ => return Err("character level readers are not supported".to_string()),
+        }
+
+        // A T=0 TPDU can't carry both Lc data and Le, the data comes back through GET RESPONSE
+        let mut command = apdu.to_vec();
+        if self.exchange_level & FEATURE_LEVEL_MASK == FEATURE_TPDU
+            && apdu.len() > 5
+            && apdu.len() == 5 + apdu[4] as usize + 1
+        {
+            command.pop();
+        }
+
+        let mut response = self.exchange_apdu(slot, &command)?;
+        let mut body = Vec::new();
+        loop {
+            let [.., sw1, sw2] = response[..] else {
+                return Err("response without a status word".to_string());
+            };
+            match sw1 {
+                0x61 => {
+                    body.extend_from_slice(&response[..response.len() - 2]);
+                    response = self.exchange_apdu(slot, &[apdu[0], 0xc0, 0x00, 0x00, sw2])?;
+                }
+                // Only a case 2 command has an Le to correct
+                0x6c if command.len() == 5 => {
+                    command[4] = sw2;
+                    response = self.exchange_apdu(slot, &command)?;
+                }
+                _ => {
+                    body.extend_from_slice(&response);
+                    return Ok(body);
+                }
+            }
+        }
+    }
+}
+
+fn expect(response: &CcidResponse, message_type: u8) -> std::result::Result<(), String> {
+    if response.message_type != message_type {
+        return Err(format!(
+            "unexpected response type {:#04x}",
+            response.message_type
+        ));
+    }
+    Ok(())
+}
+
+/// A CCID smart card reader.
+#[napi]
+pub struct CcidReader {
+    shared: Arc<SharedDevice>,
+    transport: Arc<Mutex<CcidTransport>>,
+    interrupt_in: Option<u8>,
+    notifications: Mutex<Option<InStream>>,
+    descriptor: CcidDescriptor,
+
+    #[napi(writable = false)]
+    pub interfaceNumber: u8,
+}
+
+impl CcidReader {
+    async fn run<T, F>(&self, method: &'static str, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut CcidTransport) -> std::result::Result<T, String> + Send + 'static,
+    {
+        let transport = self.transport.clone();
+        run_blocking(move || {
+            let mut transport = transport
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            f(&mut transport).map_err(|e| format!("{method} error: {e}"))
+        })
+        .await
+    }
+}
+
+#[napi]
+impl CcidReader {
+    #[napi(getter)]
+    pub fn descriptor(&self) -> CcidDescriptor {
+        self.descriptor.clone()
+    }
+
+    /// PC_to_RDR_IccPowerOn, resolving with the card's ATR. `voltage` is `'5V'`, `'3V'` or `'1.8V'`,
+    /// or left out for the reader to choose.
+    #[napi]
+    pub async fn powerOn(
+        &self,
+        slot: Option<u8>,
+        #[napi(ts_arg_type = "'5V' | '3V' | '1.8V'")] voltage: Option<String>,
+    ) -> Result<Uint8Array> {
+        let voltage = match voltage.as_deref() {
+            None => 0,
+            Some("5V") => 1,
+            Some("3V") => 2,
+            Some("1.8V") => 3,
+            Some(voltage) => {
+                return Err(napi::Error::from_reason(format!(
+                    "powerOn error: invalid voltage {voltage}"
+                )))
+            }
+        };
+        let atr = self
+            .run("powerOn", move |transport| {
+                transport.power_on(slot.unwrap_or(0), voltage)
+            })
+            .await?;
+        Ok(Uint8Array::new(atr))
+    }
+
+    #[napi]
+    pub async fn powerOff(&self, slot: Option<u8>) -> Result<()> {
+        self.run("powerOff", move |transport| {
+            let slot = slot.unwrap_or(0);
+            let response = transport.command(PC_TO_RDR_ICC_POWER_OFF, slot, [0; 3], &[])?;
+            if let Some(entry) = transport.protocols.get_mut(slot as usize) {
+                *entry = None;
+            }
+            expect(&response, RDR_TO_PC_SLOT_STATUS)
+        })
+        .await
+    }
+
+    /// PC_to_RDR_GetSlotStatus.
+    #[napi]
+    pub async fn getSlotStatus(&self, slot: Option<u8>) -> Result<CcidSlotStatus> {
+        let slot = slot.unwrap_or(0);
+        let response = self
+            .run("getSlotStatus", move |transport| {
+                // A failed status only means there's no card, which the status already says
+                match transport.command(PC_TO_RDR_GET_SLOT_STATUS, slot, [0; 3], &[]) {
+                    Err(e) if e == "no card in the slot" => Ok(None),
+                    result => result.map(Some),
+                }
+            })
+            .await?;
+        Ok(match response {
+            Some(response) => CcidSlotStatus {
+                slot,
+                card: match response.status & 0x03 {
+                    0 => "active",
+                    1 => "inactive",
+                    _ => "absent",
+                }
+                .to_string(),
+                clockStatus: response.specific,
+            },
+            None => CcidSlotStatus {
+                slot,
+                card: "absent".to_string(),
+                clockStatus: 0,
+            },
+        })
+    }
+
+    /// PC_to_RDR_XfrBlock as it is, resolving with the RDR_to_PC_DataBlock data. What the block holds
+    /// depends on the reader's `exchangeLevel`.
+    #[napi]
+    pub async fn xfrBlock(
+        &self,
+        data: Uint8Array,
+        slot: Option<u8>,
+        levelParameter: Option<u16>,
+    ) -> Result<Uint8Array> {
+        let data = data.to_vec();
+        let response = self
+            .run("xfrBlock", move |transport| {
+                transport.xfr_block(slot.unwrap_or(0), &data, levelParameter.unwrap_or(0))
+            })
+            .await?;
+        Ok(Uint8Array::new(response.data))
+    }
+
+    /// Sends a command APDU to the powered card, resolving with the response APDU including its status word.
+    /// `61xx` and `6Cxx` status words are handled by fetching or resending.
+    #[napi]
+    pub async fn transmitApdu(&self, apdu: Uint8Array, slot: Option<u8>) -> Result<Uint8Array> {
+        let apdu = apdu.to_vec();
+        let response = self
+            .run("transmitApdu", move |transport| {
+                transport.transmit_apdu(slot.unwrap_or(0), &apdu)
+            })
+            .await?;
+        Ok(Uint8Array::new(response))
+    }
+
+    /// PC_to_RDR_SetParameters for T=0 (`protocol` 0) or T=1 (`protocol` 1), with the protocol data structure as it is.
+    #[napi]
+    pub async fn setParameters(
+        &self,
+        protocol: u8,
+        parameters: Uint8Array,
+        slot: Option<u8>,
+    ) -> Result<()> {
+        let parameters = parameters.to_vec();
+        self.run("setParameters", move |transport| {
+            let slot = slot.unwrap_or(0);
+            let response = transport.command(
+                PC_TO_RDR_SET_PARAMETERS,
+                slot,
+                [protocol, 0, 0],
+                &parameters,
+            )?;
+            expect(&response, RDR_TO_PC_PARAMETERS)?;
+            if let Some(entry) = transport.protocols.get_mut(slot as usize) {
+                *entry = Some(response.specific);
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Starts reading RDR_to_PC_NotifySlotChange from the interrupt endpoint, calling back for each slot.
+    #[napi]
+    pub fn onSlotChange(&self, callback: SlotChangeCallback) -> Result<()> {
+        let mut notifications = self
+            .notifications
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if notifications.is_some() {
+            return Err(napi::Error::from_reason(
+                "onSlotChange error: already reading",
+            ));
+        }
+        let interrupt_in = self.interrupt_in.ok_or_else(|| {
+            napi::Error::from_reason("onSlotChange error: no interrupt IN endpoint")
+        })?;
+        let endpoint = self
+            .shared
+            .get_endpoint::<In>(interrupt_in)
+            .ok_or_else(|| napi::Error::from_reason("onSlotChange error: invalid state"))?;
+
+        let slots = self.descriptor.maxSlotIndex as usize + 1;
+        *notifications = Some(InStream::start(
+            endpoint,
+            1 + slots.div_ceil(4),
+            NOTIFY_TRANSFERS,
+            move |data| {
+                for change in parse_slot_changes(data, slots) {
+                    callback.call(change, ThreadsafeFunctionCallMode::NonBlocking);
+                }
+            },
+            |_| {},
+        ));
+        Ok(())
+    }
+
+    #[napi]
+    pub fn stopSlotChanges(&self) {
+        if let Some(stream) = self
+            .notifications
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .take()
+        {
+            stream.stop();
+        }
+    }
+
+    /// Stops slot change notifications and releases the interface, reattaching any kernel driver.
+    #[napi]
+    pub async fn close(&self) -> Result<()> {
+        let notifications = self
+            .notifications
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .take();
+        // Its endpoint keeps the interface claimed until the stream has ended
+        if let Some(stream) = notifications {
+            stream.finish().await;
+        }
+        self.shared
+            .release_interface("close", self.interfaceNumber)
+            .await
+    }
+}
+
+#[napi]
+impl UsbDevice {
+    /// Claims the CCID interface of a smart card reader and reads its class descriptor.
+    #[napi]
+    pub async fn openCcid(&self, options: Option<CcidOptions>) -> Result<CcidReader> {
+        let interface_number = options.as_ref().and_then(|o| o.interfaceNumber);
+        let detach = options
+            .as_ref()
+            .and_then(|o| o.detachKernelDriver)
+            .unwrap_or(true);
+        let timeout = options
+            .as_ref()
+            .and_then(|o| o.timeout)
+            .unwrap_or(DEFAULT_TIMEOUT);
+
+        let shared = self.shared().clone();
+        let device = shared
+            .opened_device()
+            .ok_or_else(|| napi::Error::from_reason("openCcid error: invalid state"))?;
+        let layout = run_blocking(move || {
+            let config = device
+                .active_configuration()
+                .map_err(|e| format!("openCcid error: {e}"))?;
+            find_ccid_layout(&config, interface_number).map_err(|e| format!("openCcid error: {e}"))
+        })
+        .await?;
+
+        shared
+            .claim_interface("openCcid", layout.interface, detach)
+            .await?;
+
+        let descriptor = layout.descriptor;
+        let transport = CcidTransport {
+            shared: shared.clone(),
+            bulk_in: layout.bulk_in,
+            bulk_out: layout.bulk_out,
+            // Short APDUs and their header fit 271 bytes, the least a reader may offer
+            max_message_length: (descriptor.maxMessageLength as usize).max(271),
+            exchange_level: descriptor.features & FEATURE_LEVEL_MASK,
+            timeout: Duration::from_millis(timeout as u64),
+            sequence: 0,
+            protocols: vec![None; descriptor.maxSlotIndex as usize + 1],
+        };
+
+        Ok(CcidReader {
+            shared,
+            transport: Arc::new(Mutex::new(transport)),
+            interrupt_in: layout.interrupt_in,
+            notifications: Mutex::new(None),
+            descriptor,
+            interfaceNumber: layout.interface,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A class descriptor from a short APDU reader with one slot (CCID 1.10, section 5.1).
+    fn descriptor() -> Vec<u8> {
+        let mut d = vec![0u8; CCID_DESCRIPTOR_LEN];
+        d[0] = CCID_DESCRIPTOR_LEN as u8;
+        d[1] = CCID_DESCRIPTOR;
+        d[2..4].copy_from_slice(&0x0110u16.to_le_bytes());
+        d[4] = 0;
+        d[5] = 0x07;
+        d[6..10].copy_from_slice(&0x03u32.to_le_bytes());
+        d[10..14].copy_from_slice(&4000u32.to_le_bytes());
+        d[14..18].copy_from_slice(&8000u32.to_le_bytes());
+        d[19..23].copy_from_slice(&10752u32.to_le_bytes());
+        d[23..27].copy_from_slice(&344_086u32.to_le_bytes());
+        d[28..32].copy_from_slice(&254u32.to_le_bytes());
+        d[40..44].copy_from_slice(&0x0002_04bau32.to_le_bytes());
+        d[44..48].copy_from_slice(&271u32.to_le_bytes());
+        d[53] = 1;
+        d
+    }
+
+    #[test]
+    fn parses_the_class_descriptor() {
+        let descriptor = parse_ccid_descriptor(&descriptor());
+        assert_eq!(descriptor.ccidVersion, 0x0110);
+        assert_eq!(descriptor.maxSlotIndex, 0);
+        assert_eq!(descriptor.voltages, ["5V", "3V", "1.8V"]);
+        assert_eq!(descriptor.protocols, ["T=0", "T=1"]);
+        assert_eq!(descriptor.defaultClock, 4000);
+        assert_eq!(descriptor.maximumClock, 8000);
+        assert_eq!(descriptor.dataRate, 10752);
+        assert_eq!(descriptor.maxDataRate, 344_086);
+        assert_eq!(descriptor.maxIfsd, 254);
+        assert_eq!(descriptor.exchangeLevel, "shortApdu");
+        assert_eq!(descriptor.maxMessageLength, 271);
+        assert_eq!(descriptor.maxBusySlots, 1);
+    }
+
+    #[test]
+    fn picks_the_highest_exchange_level() {
+        let mut d = descriptor();
+        for (features, level) in [
+            (0x0000_0000u32, "character"),
+            (0x0001_0000, "tpdu"),
+            (0x0004_0000, "extendedApdu"),
+        ] {
+            d[40..44].copy_from_slice(&features.to_le_bytes());
+            assert_eq!(parse_ccid_descriptor(&d).exchangeLevel, level);
+        }
+    }
+
+    #[test]
+    fn frames_commands() {
+        assert_eq!(
+            encode_command(PC_TO_RDR_ICC_POWER_ON, 0, 3, [0x01, 0, 0], &[]),
+            [0x62, 0, 0, 0, 0, 0, 3, 0x01, 0, 0]
+        );
+        assert_eq!(
+            encode_command(
+                PC_TO_RDR_XFR_BLOCK,
+                1,
+                0xff,
+                [0, CHAIN_BEGIN as u8, 0],
+                &[0x00, 0xa4, 0x04, 0x00]
+            ),
+            [0x6f, 4, 0, 0, 0, 1, 0xff, 0, 0x01, 0, 0x00, 0xa4, 0x04, 0x00]
+        );
+    }
+
+    #[test]
+    fn measures_responses_once_the_header_has_arrived() {
+        assert_eq!(message_len(&[0x80, 2, 0, 0]), None);
+        assert_eq!(message_len(&[0x80, 2, 0, 0, 0]), Some(12));
+        assert_eq!(message_len(&[0x80, 0, 1, 0, 0, 0, 0, 0, 0, 0]), Some(266));
+    }
+
+    #[test]
+    fn decodes_slot_changes() {
+        let changes = parse_slot_changes(&[RDR_TO_PC_NOTIFY_SLOT_CHANGE, 0b1110_0011, 0b01], 5);
+        let changes: Vec<_> = changes
+            .iter()
+            .map(|c| (c.slot, c.present, c.changed))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                (0, true, true),
+                (1, false, false),
+                (2, false, true),
+                (3, true, true),
+                (4, true, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_other_interrupt_messages() {
+        assert!(parse_slot_changes(&[0x51, 0x03], 1).is_empty());
+        assert!(parse_slot_changes(&[], 1).is_empty());
+        // Slots the reader hasn't reported on are left out
+        assert_eq!(
+            parse_slot_changes(&[RDR_TO_PC_NOTIFY_SLOT_CHANGE, 0x03], 8).len(),
+            4
+        );
+    }
+
+    #[test]
+    fn names_slot_errors() {
+        assert_eq!(slot_error(0xfe), "card mute");
+        assert_eq!(slot_error(0x00), "command not supported");
+        assert_eq!(slot_error(0x05), "error 0x05");
+    }
+}
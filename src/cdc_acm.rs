@@ -0,0 +1,644 @@
+use crate::endpoint_stream::{InStream, StreamSlot};
+use crate::serial::{
+    FlowControl, LineSettings, Parity, SerialInputSignals, SerialOptions, SerialOutputSignals,
+    StopBits,
+};
+use crate::transfer_pool::pooled_transfer_out;
+use crate::webusb_device::{run_blocking, SharedDevice, UsbDevice};
+use napi::{
+    bindgen_prelude::*, threadsafe_function::ThreadsafeFunction,
+    threadsafe_function::ThreadsafeFunctionCallMode,
+};
+use napi_derive::napi;
+use nusb::{
+    descriptors::{ConfigurationDescriptor, TransferType},
+    transfer::{ControlIn, ControlOut, ControlType, Direction, Recipient},
+};
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+const CLASS_COMMUNICATIONS: u8 = 0x02;
+const CLASS_CDC_DATA: u8 = 0x0a;
+const SUBCLASS_ACM: u8 = 0x02;
+
+const CS_INTERFACE: u8 = 0x24;
+const FUNCTIONAL_CALL_MANAGEMENT: u8 = 0x01;
+const FUNCTIONAL_ACM: u8 = 0x02;
+const FUNCTIONAL_UNION: u8 = 0x06;
+
+const SET_LINE_CODING: u8 = 0x20;
+const GET_LINE_CODING: u8 = 0x21;
+const SET_CONTROL_LINE_STATE: u8 = 0x22;
+const SEND_BREAK: u8 = 0x23;
+const NOTIFICATION_SERIAL_STATE: u8 = 0x20;
+
+const CONTROL_LINE_DTR: u16 = 0x01;
+const CONTROL_LINE_RTS: u16 = 0x02;
+
+const CONTROL_TIMEOUT: Duration = Duration::from_millis(1000);
+const READ_TRANSFERS: usize = 4;
+const DEFAULT_BUFFER_SIZE: u32 = 255;
+
+type DataCallback = ThreadsafeFunction<Uint8Array, (), Uint8Array, napi::Status, false>;
+type EndCallback = ThreadsafeFunction<Option<String>, (), Option<String>, napi::Status, false>;
+type SerialStateCallback =
+    ThreadsafeFunction<CdcSerialState, (), CdcSerialState, napi::Status, false>;
+
+#[napi(object)]
+pub struct CdcAcmOptions {
+    /// The communication interface to use, defaults to the first ACM interface
+    pub interfaceNumber: Option<u8>,
+    /// Detach kernel drivers (e.g. `cdc_acm`) while the port is open, defaults to `true`
+    pub detachKernelDriver: Option<bool>,
+    pub baudRate: Option<u32>,
+    #[napi(ts_type = "5 | 6 | 7 | 8 | 16")]
+    pub dataBits: Option<u8>,
+    #[napi(ts_type = "1 | 1.5 | 2")]
+    pub stopBits: Option<f64>,
+    #[napi(ts_type = "SerialParity")]
+    pub parity: Option<String>,
+}
+
+/// Contents of the last SERIAL_STATE notification.
+#[napi(object)]
+#[derive(Clone, Copy, Default)]
+pub struct CdcSerialState {
+    #[napi(writable = false)]
+    pub dataCarrierDetect: bool,
+    #[napi(writable = false)]
+    pub dataSetReady: bool,
+    #[napi(writable = false)]
+    pub breakDetected: bool,
+    #[napi(writable = false)]
+    pub ringIndicator: bool,
+    #[napi(writable = false)]
+    pub framingError: bool,
+    #[napi(writable = false)]
+    pub parityError: bool,
+    #[napi(writable = false)]
+    pub overrun: bool,
+}
+
+impl CdcSerialState {
+    fn from_bits(bits: u16) -> Self {
+        Self {
+            dataCarrierDetect: bits & 0x01 != 0,
+            dataSetReady: bits & 0x02 != 0,
+            breakDetected: bits & 0x04 != 0,
+            ringIndicator: bits & 0x08 != 0,
+            framingError: bits & 0x10 != 0,
+            parityError: bits & 0x20 != 0,
+            overrun: bits & 0x40 != 0,
+        }
+    }
+}
+
+/// Where the pieces of an ACM function live in the active configuration.
+struct AcmLayout {
+    control: u8,
+    data: u8,
+    data_alt: u8,
+    notify: Option<u8>,
+    bulk_in: u8,
+    bulk_out: u8,
+    capabilities: u8,
+}
+
+fn find_acm_layout(
+    config: &ConfigurationDescriptor,
+    interface_number: Option<u8>,
+) -> std::result::Result<AcmLayout, String> {
+    let control = config
+        .interface_alt_settings()
+        .filter(|alt| alt.alternate_setting() == 0)
+        .filter(|alt| interface_number.is_none_or(|n| alt.interface_number() == n))
+        .find(|alt| alt.class() == CLASS_COMMUNICATIONS && alt.subclass() == SUBCLASS_ACM)
+        .ok_or("no ACM interface found")?;
+
+    // The union descriptor names the data interface, older devices only give
+    // it in call management and some give neither, relying on it coming next
+    let mut union_data = None;
+    let mut call_management_data = None;
+    let mut capabilities = 0;
+    for descriptor in control.descriptors() {
+        if descriptor.descriptor_type() != CS_INTERFACE || descriptor.len() < 4 {
+            continue;
+        }
+        match descriptor[2] {
+            FUNCTIONAL_UNION if descriptor.len() >= 5 => union_data = Some(descriptor[4]),
+            FUNCTIONAL_CALL_MANAGEMENT if descriptor.len() >= 5 => {
+                call_management_data = Some(descriptor[4])
+            }
+            FUNCTIONAL_ACM => capabilities = descriptor[3],
+            _ => {}
+        }
+    }
+    let data = union_data
+        .or(call_management_data)
+        .unwrap_or(control.interface_number() + 1);
+
+    let notify = control
+        .endpoints()
+        .find(|ep| ep.direction() == Direction::In && ep.transfer_type() == TransferType::Interrupt)
+        .map(|ep| ep.address() & 0x7f);
+
+    // The bulk pair may only exist in an alternate setting
+    for alt in config
+        .interface_alt_settings()
+        .filter(|alt| alt.interface_number() == data && alt.class() == CLASS_CDC_DATA)
+    {
+        let bulk = |direction| {
+            alt.endpoints()
+                .find(|ep| ep.direction() == direction && ep.transfer_type() == TransferType::Bulk)
+                .map(|ep| ep.address() & 0x7f)
+        };
+        if let (Some(bulk_in), Some(bulk_out)) = (bulk(Direction::In), bulk(Direction::Out)) {
+            return Ok(AcmLayout {
+                control: control.interface_number(),
+                data,
+                data_alt: alt.alternate_setting(),
+                notify,
+                bulk_in,
+                bulk_out,
+                capabilities,
+            });
+        }
+    }
+
+    Err(format!(
+        "no data interface with bulk endpoints found for interface {}",
+        control.interface_number()
+    ))
+}
+
+fn line_coding_bytes(settings: &LineSettings) -> Vec<u8> {
+    let mut data = settings.baud_rate.to_le_bytes().to_vec();
+    data.push(match settings.stop_bits {
+        StopBits::One => 0,
+        StopBits::OnePointFive => 1,
+        StopBits::Two => 2,
+    });
+    data.push(match settings.parity {
+        Parity::None => 0,
+        Parity::Odd => 1,
+        Parity::Even => 2,
+        Parity::Mark => 3,
+        Parity::Space => 4,
+    });
+    data.push(settings.data_bits);
+    data
+}
+
+fn parse_line_coding(data: &[u8]) -> std::result::Result<LineSettings, String> {
+    if data.len() < 7 {
+        return Err(format!("short line coding ({} bytes)", data.len()));
+    }
+    Ok(LineSettings {
+        baud_rate: u32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+        stop_bits: match data[4] {
+            1 => StopBits::OnePointFive,
+            2 => StopBits::Two,
+            _ => StopBits::One,
+        },
+        parity: match data[5] {
+            1 => Parity::Odd,
+            2 => Parity::Even,
+            3 => Parity::Mark,
+            4 => Parity::Space,
+            _ => Parity::None,
+        },
+        data_bits: data[6],
+        flow_control: FlowControl::None,
+    })
+}
+
+struct Notifications {
+    serial_state: u16,
+    callback: Option<SerialStateCallback>,
+}
+
+struct PortState {
+    control_lines: u16,
+    notifications: Option<InStream>,
+}
+
+/// A CDC-ACM (USB modem / virtual COM port) function driven directly, without the OS serial driver.
+#[napi]
+pub struct CdcAcmPort {
+    shared: Arc<SharedDevice>,
+    layout: AcmLayout,
+    state: Mutex<PortState>,
+    reader: StreamSlot,
+    notifications: Arc<Mutex<Notifications>>,
+
+    #[napi(writable = false)]
+    pub controlInterface: u8,
+    #[napi(writable = false)]
+    pub dataInterface: u8,
+    /// `bmCapabilities` from the ACM functional descriptor
+    #[napi(writable = false)]
+    pub capabilities: u8,
+}
+
+fn notifications_guard(notifications: &Mutex<Notifications>) -> MutexGuard<'_, Notifications> {
+    notifications
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl CdcAcmPort {
+    fn state(&self) -> MutexGuard<'_, PortState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    async fn control_out(
+        &self,
+        method: &'static str,
+        request: u8,
+        value: u16,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        let target = self
+            .shared
+            .control_target(Recipient::Interface, self.layout.control as u16)
+            .ok_or_else(|| napi::Error::from_reason(format!("{method} error: invalid state")))?;
+        let index = self.layout.control as u16;
+        run_blocking(move || {
+            let request = ControlOut {
+                control_type: ControlType::Class,
+                recipient: Recipient::Interface,
+                request,
+                value,
+                index,
+                data: &data,
+            };
+            target
+                .control_out_blocking(request, CONTROL_TIMEOUT)
+                .map_err(|e| format!("{method} error: {e}"))
+        })
+        .await
+    }
+
+    async fn set_control_lines(&self, method: &'static str, control_lines: u16) -> Result<()> {
+        self.control_out(method, SET_CONTROL_LINE_STATE, control_lines, Vec::new())
+            .await?;
+        self.state().control_lines = control_lines;
+        Ok(())
+    }
+
+    fn start_notifications(&self) {
+        let Some(notify) = self.layout.notify else {
+            return;
+        };
+        let Some(endpoint) = self.shared.get_endpoint::<nusb::transfer::In>(notify) else {
+            return;
+        };
+
+        let notifications = self.notifications.clone();
+        let stream = InStream::start(
+            endpoint,
+            16,
+            1,
+            move |data| {
+                // bmRequestType, bNotification, wValue, wIndex, wLength then the state bitmap
+                if data.len() < 10 || data[1] != NOTIFICATION_SERIAL_STATE {
+                    return;
+                }
+                let bits = u16::from_le_bytes([data[8], data[9]]);
+                let mut notifications = notifications_guard(&notifications);
+                notifications.serial_state = bits;
+                if let Some(callback) = notifications.callback.as_ref() {
+                    callback.call(
+                        CdcSerialState::from_bits(bits),
+                        ThreadsafeFunctionCallMode::NonBlocking,
+                    );
+                }
+            },
+            |_| {},
+        );
+        self.state().notifications = Some(stream);
+    }
+}
+
+#[napi]
+impl CdcAcmPort {
+    #[napi]
+    pub async fn setLineCoding(&self, options: SerialOptions) -> Result<()> {
+        let settings = LineSettings::from_options(&options)
+            .map_err(|e| napi::Error::from_reason(format!("setLineCoding error: {e}")))?;
+        if settings.flow_control != FlowControl::None {
+            return Err(napi::Error::from_reason(
+                "setLineCoding error: ACM has no flow control",
+            ));
+        }
+        self.control_out(
+            "setLineCoding",
+            SET_LINE_CODING,
+            0,
+            line_coding_bytes(&settings),
+        )
+        .await
+    }
+
+    #[napi]
+    pub async fn getLineCoding(&self) -> Result<SerialOptions> {
+        let target = self
+            .shared
+            .control_target(Recipient::Interface, self.layout.control as u16)
+            .ok_or_else(|| napi::Error::from_reason("getLineCoding error: invalid state"))?;
+        let index = self.layout.control as u16;
+        run_blocking(move || {
+            let request = ControlIn {
+                control_type: ControlType::Class,
+                recipient: Recipient::Interface,
+                request: GET_LINE_CODING,
+                value: 0,
+                index,
+                length: 7,
+            };
+            let data = target
+                .control_in_blocking(request, CONTROL_TIMEOUT)
+                .map_err(|e| format!("getLineCoding error: {e}"))?;
+            parse_line_coding(&data)
+                .map(LineSettings::to_options)
+                .map_err(|e| format!("getLineCoding error: {e}"))
+        })
+        .await
+    }
+
+    /// Sets DTR and RTS, and starts or ends a break. Signals left out are unchanged.
+    #[napi]
+    pub async fn setSignals(&self, signals: SerialOutputSignals) -> Result<()> {
+        let mut control_lines = self.state().control_lines;
+        if let Some(dtr) = signals.dataTerminalReady {
+            control_lines =
+                (control_lines & !CONTROL_LINE_DTR) | if dtr { CONTROL_LINE_DTR } else { 0 };
+        }
+        if let Some(rts) = signals.requestToSend {
+            control_lines =
+                (control_lines & !CONTROL_LINE_RTS) | if rts { CONTROL_LINE_RTS } else { 0 };
+        }
+        if signals.dataTerminalReady.is_some() || signals.requestToSend.is_some() {
+            self.set_control_lines("setSignals", control_lines).await?;
+        }
+
+        if let Some(on) = signals._break {
+            self.control_out(
+                "setSignals",
+                SEND_BREAK,
+                if on { 0xffff } else { 0 },
+                Vec::new(),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Input signals from the last SERIAL_STATE notification. ACM does not report CTS, so it is always `false`.
+    #[napi]
+    pub fn getSignals(&self) -> SerialInputSignals {
+        let state =
+            CdcSerialState::from_bits(notifications_guard(&self.notifications).serial_state);
+        SerialInputSignals {
+            dataCarrierDetect: state.dataCarrierDetect,
+            clearToSend: false,
+            ringIndicator: state.ringIndicator,
+            dataSetReady: state.dataSetReady,
+        }
+    }
+
+    #[napi(getter)]
+    pub fn serialState(&self) -> CdcSerialState {
+        CdcSerialState::from_bits(notifications_guard(&self.notifications).serial_state)
+    }
+
+    /// Calls back with each SERIAL_STATE notification, replacing any earlier callback.
+    #[napi]
+    pub fn onSerialState(&self, callback: SerialStateCallback) {
+        notifications_guard(&self.notifications).callback = Some(callback);
+    }
+
+    #[napi(js_name = "nativeWrite")]
+    pub async fn write(&self, data: Uint8Array, timeout: u32) -> Result<u32> {
+        let mut endpoint = self
+            .shared
+            .get_endpoint::<nusb::transfer::Out>(self.layout.bulk_out)
+            .ok_or_else(|| napi::Error::from_reason("write error: invalid state"))?;
+        let shared = self.shared.clone();
+        run_blocking(move || {
+            let result = pooled_transfer_out(
+                &shared,
+                &mut endpoint,
+                &data,
+                Duration::from_millis(timeout as u64),
+            )
+            .map(|len| len as u32)
+            .map_err(|e| format!("write error: {e:?}"));
+            shared.put_endpoint(endpoint);
+            result
+        })
+        .await
+    }
+
+    #[napi(js_name = "nativeStartReading")]
+    pub fn startReading(
+        &self,
+        bufferSize: Option<u32>,
+        onData: DataCallback,
+        onEnd: EndCallback,
+    ) -> Result<()> {
+        self.reader.start(
+            "startReading",
+            &self.shared,
+            self.layout.bulk_in,
+            |endpoint| {
+                InStream::start(
+                    endpoint,
+                    bufferSize.unwrap_or(DEFAULT_BUFFER_SIZE) as usize,
+                    READ_TRANSFERS,
+                    move |data| {
+                        onData.call(
+                            Uint8Array::new(data.to_vec()),
+                            ThreadsafeFunctionCallMode::NonBlocking,
+                        );
+                    },
+                    move |error| {
+                        onEnd.call(error, ThreadsafeFunctionCallMode::NonBlocking);
+                    },
+                )
+            },
+        )
+    }
+
+    #[napi]
+    pub fn stopReading(&self) {
+        self.reader.stop();
+    }
+
+    /// Stops reading from the device until `resumeReading()`, leaving it to hold on to the data.
+    #[napi]
+    pub fn pauseReading(&self) {
+        self.reader.pause();
+    }
+
+    #[napi]
+    pub fn resumeReading(&self) {
+        self.reader.resume();
+    }
+
+    /// Stops reading, drops DTR/RTS and releases the interfaces, reattaching any kernel driver.
+    #[napi]
+    pub async fn close(&self) -> Result<()> {
+        // Their endpoints keep the interfaces claimed until the streams have ended
+        self.reader.finish().await;
+        let notifications = self.state().notifications.take();
+        if let Some(stream) = notifications {
+            stream.finish().await;
+        }
+        notifications_guard(&self.notifications).callback = None;
+
+        // Best effort, the device may already be gone
+        let _ = self.set_control_lines("close", 0).await;
+
+        self.shared
+            .release_interface("close", self.layout.data)
+            .await?;
+        if self.layout.control != self.layout.data {
+            self.shared
+                .release_interface("close", self.layout.control)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[napi]
+impl UsbDevice {
+    /// Claims a CDC-ACM function and returns a port for it. DTR and RTS are raised as an OS serial driver would.
+    #[napi]
+    pub async fn openCdcAcm(&self, options: Option<CdcAcmOptions>) -> Result<CdcAcmPort> {
+        let options = options.unwrap_or(CdcAcmOptions {
+            interfaceNumber: None,
+            detachKernelDriver: None,
+            baudRate: None,
+            dataBits: None,
+            stopBits: None,
+            parity: None,
+        });
+        let line_settings = match options.baudRate {
+            Some(baudRate) => Some(
+                LineSettings::from_options(&SerialOptions {
+                    baudRate,
+                    dataBits: options.dataBits,
+                    stopBits: options.stopBits,
+                    parity: options.parity.clone(),
+                    flowControl: None,
+                })
+                .map_err(|e| napi::Error::from_reason(format!("openCdcAcm error: {e}")))?,
+            ),
+            None => None,
+        };
+
+        let shared = self.shared().clone();
+        let device = shared
+            .opened_device()
+            .ok_or_else(|| napi::Error::from_reason("openCdcAcm error: invalid state"))?;
+        let interface_number = options.interfaceNumber;
+        let layout = run_blocking(move || {
+            let config = device
+                .active_configuration()
+                .map_err(|e| format!("openCdcAcm error: {e}"))?;
+            find_acm_layout(&config, interface_number).map_err(|e| format!("openCdcAcm error: {e}"))
+        })
+        .await?;
+
+        let detach = options.detachKernelDriver.unwrap_or(true);
+        shared
+            .claim_interface("openCdcAcm", layout.control, detach)
+            .await?;
+        let mut claimed = Ok(());
+        if layout.data != layout.control {
+            claimed = shared
+                .claim_interface("openCdcAcm", layout.data, detach)
+                .await;
+        }
+        if claimed.is_ok() && layout.data_alt != 0 {
+            claimed = shared
+                .select_alternate_interface("openCdcAcm", layout.data, layout.data_alt)
+                .await;
+        }
+        if let Err(e) = claimed {
+            let _ = shared.release_interface("openCdcAcm", layout.data).await;
+            if layout.control != layout.data {
+                let _ = shared.release_interface("openCdcAcm", layout.control).await;
+            }
+            return Err(e);
+        }
+
+        let port = CdcAcmPort {
+            shared,
+            controlInterface: layout.control,
+            dataInterface: layout.data,
+            capabilities: layout.capabilities,
+            layout,
+            state: Mutex::new(PortState {
+                control_lines: 0,
+                notifications: None,
+            }),
+            reader: StreamSlot::default(),
+            notifications: Arc::new(Mutex::new(Notifications {
+                serial_state: 0,
+                callback: None,
+            })),
+        };
+        port.start_notifications();
+
+        if let Some(settings) = line_settings {
+            let applied = port
+                .control_out(
+                    "openCdcAcm",
+                    SET_LINE_CODING,
+                    0,
+                    line_coding_bytes(&settings),
+                )
+                .await;
+            if let Err(e) = applied {
+                let _ = port.close().await;
+                return Err(e);
+            }
+        }
+        // Best effort, as some devices without line state support stall the request
+        let _ = port
+            .set_control_lines("openCdcAcm", CONTROL_LINE_DTR | CONTROL_LINE_RTS)
+            .await;
+        Ok(port)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_coding_round_trips() {
+        let settings = LineSettings {
+            baud_rate: 115_200,
+            data_bits: 7,
+            stop_bits: StopBits::Two,
+            parity: Parity::Even,
+            flow_control: FlowControl::None,
+        };
+        let data = line_coding_bytes(&settings);
+        assert_eq!(data, [0x00, 0xc2, 0x01, 0x00, 2, 2, 7]);
+        assert_eq!(parse_line_coding(&data), Ok(settings));
+    }
+
+    #[test]
+    fn short_line_coding_is_rejected() {
+        assert!(parse_line_coding(&[0x80, 0x25, 0x00, 0x00, 0, 0]).is_err());
+    }
+}
@@ -0,0 +1,780 @@
+mod ntb;
+
+use crate::endpoint_stream::InStream;
+use crate::transfer_pool::{pooled_transfer_out, PooledEndpoint};
+use crate::webusb_device::{run_blocking, ControlTarget, SharedDevice, UsbDevice};
+use napi::{
+    bindgen_prelude::*, threadsafe_function::ThreadsafeFunction,
+    threadsafe_function::ThreadsafeFunctionCallMode,
+};
+use napi_derive::napi;
+use ntb::{parse_ntb, NtbFormat, NtbOutParameters, NtbWriter};
+use nusb::{
+    descriptors::{language_id::US_ENGLISH, ConfigurationDescriptor, TransferType},
+    transfer::{ControlIn, ControlOut, ControlType, Direction, In, Out, Recipient},
+    MaybeFuture,
+};
+use std::{
+    num::NonZeroU8,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+const CLASS_COMMUNICATIONS: u8 = 0x02;
+const CLASS_CDC_DATA: u8 = 0x0a;
+const SUBCLASS_ECM: u8 = 0x06;
+const SUBCLASS_NCM: u8 = 0x0d;
+
+const CS_INTERFACE: u8 = 0x24;
+const FUNCTIONAL_UNION: u8 = 0x06;
+const FUNCTIONAL_ETHERNET: u8 = 0x0f;
+const FUNCTIONAL_NCM: u8 = 0x1a;
+
+const SET_ETHERNET_PACKET_FILTER: u8 = 0x43;
+const GET_NTB_PARAMETERS: u8 = 0x80;
+const SET_NTB_FORMAT: u8 = 0x84;
+const SET_NTB_INPUT_SIZE: u8 = 0x86;
+
+const NOTIFICATION_NETWORK_CONNECTION: u8 = 0x00;
+const NOTIFICATION_CONNECTION_SPEED_CHANGE: u8 = 0x2a;
+
+const PACKET_TYPE_PROMISCUOUS: u16 = 0x01;
+const PACKET_TYPE_ALL_MULTICAST: u16 = 0x02;
+const PACKET_TYPE_DIRECTED: u16 = 0x04;
+const PACKET_TYPE_BROADCAST: u16 = 0x08;
+const PACKET_TYPE_MULTICAST: u16 = 0x10;
+
+const NTB_FORMAT_NTB32: u16 = 0x02;
+/// bmNetworkCapabilities bit for an 8 byte SET_NTB_INPUT_SIZE carrying wNtbInMaxDatagrams
+const NCM_CAPABILITY_INPUT_DATAGRAMS: u8 = 0x20;
+
+const CONTROL_TIMEOUT: Duration = Duration::from_millis(1000);
+const DEFAULT_TIMEOUT: u32 = 1000;
+const DEFAULT_NTB_INPUT_SIZE: u32 = 16384;
+/// Ethernet header and 1500 byte payload, for devices reporting no segment size
+const MIN_SEGMENT_SIZE: u16 = 1514;
+const READ_TRANSFERS: usize = 4;
+const NOTIFICATION_LEN: usize = 16;
+
+type FrameCallback = ThreadsafeFunction<Uint8Array, (), Uint8Array, napi::Status, false>;
+type LinkCallback = ThreadsafeFunction<CdcLinkState, (), CdcLinkState, napi::Status, false>;
+
+#[napi(object)]
+pub struct CdcEthernetOptions {
+    /// The communication interface to use, defaults to the first ECM or NCM interface
+    pub interfaceNumber: Option<u8>,
+    /// Detach kernel drivers (e.g. `cdc_ether`, `cdc_ncm`) while the interface is open, defaults to `true`
+    pub detachKernelDriver: Option<bool>,
+    /// NTB format for NCM, defaults to `'ntb16'`
+    #[napi(ts_type = "'ntb16' | 'ntb32'")]
+    pub ntbFormat: Option<String>,
+    /// Largest NTB the device may send for NCM, defaults to 16384 or the device's limit if lower
+    pub ntbInputSize: Option<u32>,
+}
+
+/// Packet types for SET_ETHERNET_PACKET_FILTER.
+#[napi(object)]
+pub struct CdcPacketFilter {
+    pub promiscuous: Option<bool>,
+    pub allMulticast: Option<bool>,
+    pub directed: Option<bool>,
+    pub broadcast: Option<bool>,
+    /// Multicast frames matching the device's multicast filters
+    pub multicast: Option<bool>,
+}
+
+/// The NCM function's GET_NTB_PARAMETERS response.
+#[napi(object)]
+#[derive(Clone)]
+pub struct CdcNtbParameters {
+    #[napi(writable = false, ts_type = "Array<'ntb16' | 'ntb32'>")]
+    pub formats: Vec<String>,
+    #[napi(writable = false)]
+    pub inMaxSize: u32,
+    #[napi(writable = false)]
+    pub inDivisor: u16,
+    #[napi(writable = false)]
+    pub inPayloadRemainder: u16,
+    #[napi(writable = false)]
+    pub inAlignment: u16,
+    #[napi(writable = false)]
+    pub outMaxSize: u32,
+    #[napi(writable = false)]
+    pub outDivisor: u16,
+    #[napi(writable = false)]
+    pub outPayloadRemainder: u16,
+    #[napi(writable = false)]
+    pub outAlignment: u16,
+    /// `0` for no limit
+    #[napi(writable = false)]
+    pub outMaxDatagrams: u16,
+}
+
+impl CdcNtbParameters {
+    fn parse(data: &[u8]) -> std::result::Result<Self, String> {
+        if data.len() < 28 {
+            return Err(format!("short NTB parameters ({} bytes)", data.len()));
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        let u32_at =
+            |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        let formats = u16_at(2);
+        Ok(Self {
+            formats: [(0x01, "ntb16"), (NTB_FORMAT_NTB32, "ntb32")]
+                .into_iter()
+                .filter(|(bit, _)| formats & bit != 0)
+                .map(|(_, name)| name.to_string())
+                .collect(),
+            inMaxSize: u32_at(4),
+            inDivisor: u16_at(8),
+            inPayloadRemainder: u16_at(10),
+            inAlignment: u16_at(12),
+            outMaxSize: u32_at(16),
+            outDivisor: u16_at(20),
+            outPayloadRemainder: u16_at(22),
+            outAlignment: u16_at(24),
+            outMaxDatagrams: u16_at(26),
+        })
+    }
+
+    fn out_parameters(&self) -> NtbOutParameters {
+        NtbOutParameters {
+            max_size: self.outMaxSize as usize,
+            divisor: self.outDivisor as usize,
+            remainder: self.outPayloadRemainder as usize,
+            alignment: self.outAlignment as usize,
+            max_datagrams: self.outMaxDatagrams as usize,
+        }
+    }
+}
+
+/// State from the last NETWORK_CONNECTION and CONNECTION_SPEED_CHANGE notifications.
+#[napi(object)]
+#[derive(Clone, Copy, Default)]
+pub struct CdcLinkState {
+    #[napi(writable = false)]
+    pub connected: bool,
+    /// In bits per second, `0` until the device reports it
+    #[napi(writable = false)]
+    pub downlinkSpeed: u32,
+    /// In bits per second, `0` until the device reports it
+    #[napi(writable = false)]
+    pub uplinkSpeed: u32,
+}
+
+/// Where the pieces of an ECM or NCM function live in the active configuration.
+struct EtherLayout {
+    control: u8,
+    data: u8,
+    data_alt: u8,
+    notify: Option<u8>,
+    bulk_in: u8,
+    bulk_out: u8,
+    ncm: bool,
+    mac_address_index: u8,
+    max_segment_size: u16,
+    ncm_capabilities: u8,
+}
+
+fn find_ether_layout(
+    config: &ConfigurationDescriptor,
+    interface_number: Option<u8>,
+) -> std::result::Result<EtherLayout, String> {
+    let control = config
+        .interface_alt_settings()
+        .filter(|alt| alt.alternate_setting() == 0)
+        .filter(|alt| interface_number.is_none_or(|n| alt.interface_number() == n))
+        .find(|alt| {
+            alt.class() == CLASS_COMMUNICATIONS
+                && (alt.subclass() == SUBCLASS_ECM || alt.subclass() == SUBCLASS_NCM)
+        })
+        .ok_or("no ECM or NCM interface found")?;
+
+    let mut union_data = None;
+    let mut mac_address_index = 0;
+    let mut max_segment_size = 0;
+    let mut ncm_capabilities = 0;
+    for descriptor in control.descriptors() {
+        if descriptor.descriptor_type() != CS_INTERFACE || descriptor.len() < 4 {
+            continue;
+        }
+        match descriptor[2] {
+            FUNCTIONAL_UNION if descriptor.len() >= 5 => union_data = Some(descriptor[4]),
+            FUNCTIONAL_ETHERNET if descriptor.len() >= 13 => {
+                mac_address_index = descriptor[3];
+                max_segment_size = u16::from_le_bytes([descriptor[8], descriptor[9]]);
+            }
+            FUNCTIONAL_NCM if descriptor.len() >= 6 => ncm_capabilities = descriptor[5],
+            _ => {}
+        }
+    }
+    let data = union_data.unwrap_or(control.interface_number() + 1);
+
+    let notify = control
+        .endpoints()
+        .find(|ep| ep.direction() == Direction::In && ep.transfer_type() == TransferType::Interrupt)
+        .map(|ep| ep.address() & 0x7f);
+
+    // Alternate setting 0 has no endpoints, selecting the one with the bulk pair starts the function
+    for alt in config
+        .interface_alt_settings()
+        .filter(|alt| alt.interface_number() == data && alt.class() == CLASS_CDC_DATA)
+    {
+        let bulk = |direction| {
+            alt.endpoints()
+                .find(|ep| ep.direction() == direction && ep.transfer_type() == TransferType::Bulk)
+                .map(|ep| ep.address() & 0x7f)
+        };
+        if let (Some(bulk_in), Some(bulk_out)) = (bulk(Direction::In), bulk(Direction::Out)) {
+            return Ok(EtherLayout {
+                control: control.interface_number(),
+                data,
+                data_alt: alt.alternate_setting(),
+                notify,
+                bulk_in,
+                bulk_out,
+                ncm: control.subclass() == SUBCLASS_NCM,
+                mac_address_index,
+                max_segment_size: max_segment_size.max(MIN_SEGMENT_SIZE),
+                ncm_capabilities,
+            });
+        }
+    }
+
+    Err(format!(
+        "no data interface with bulk endpoints found for interface {}",
+        control.interface_number()
+    ))
+}
+
+/// The MAC address string is 12 hex digits, most significant byte first.
+fn parse_mac_address(string: &str) -> Option<String> {
+    let string = string.trim();
+    if string.len() != 12 || !string.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let bytes = (0..12)
+        .step_by(2)
+        .map(|i| string[i..i + 2].to_ascii_lowercase())
+        .collect::<Vec<_>>();
+    Some(bytes.join(":"))
+}
+
+fn class_control_out(
+    target: &ControlTarget,
+    interface: u8,
+    request: u8,
+    value: u16,
+    data: &[u8],
+) -> std::result::Result<(), String> {
+    let request = ControlOut {
+        control_type: ControlType::Class,
+        recipient: Recipient::Interface,
+        request,
+        value,
+        index: interface as u16,
+        data,
+    };
+    target
+        .control_out_blocking(request, CONTROL_TIMEOUT)
+        .map_err(|e| e.to_string())
+}
+
+/// Writes one transfer and ends it with a zero-length packet where its length alone wouldn't.
+fn write_transfer(
+    shared: &SharedDevice,
+    endpoint: &mut PooledEndpoint<Out>,
+    data: &[u8],
+    max_len: usize,
+    timeout: Duration,
+) -> std::result::Result<(), String> {
+    pooled_transfer_out(shared, endpoint, data, timeout).map_err(|e| format!("{e:?}"))?;
+    if data.len().is_multiple_of(endpoint.max_packet_size()) && data.len() < max_len {
+        pooled_transfer_out(shared, endpoint, &[], timeout).map_err(|e| format!("{e:?}"))?;
+    }
+    Ok(())
+}
+
+struct Link {
+    state: CdcLinkState,
+    callback: Option<LinkCallback>,
+}
+
+struct EtherState {
+    packet_filter: u16,
+    reader: Option<InStream>,
+    notifications: Option<InStream>,
+}
+
+/// A CDC-ECM or CDC-NCM network function, sending and receiving Ethernet frames without the OS network driver.
+#[napi]
+pub struct CdcEthernet {
+    shared: Arc<SharedDevice>,
+    layout: EtherLayout,
+    ntb_format: NtbFormat,
+    ntb_parameters: Option<CdcNtbParameters>,
+    ntb_input_size: usize,
+    /// wSequence of the next NTB, held while a batch is written so NTBs go out in order
+    sequence: Arc<Mutex<u16>>,
+    state: Mutex<EtherState>,
+    link: Arc<Mutex<Link>>,
+
+    #[napi(writable = false, ts_type = "'ecm' | 'ncm'")]
+    pub protocol: String,
+    #[napi(writable = false)]
+    pub controlInterface: u8,
+    #[napi(writable = false)]
+    pub dataInterface: u8,
+    /// From the string descriptor named by the Ethernet networking functional descriptor, as `aa:bb:cc:dd:ee:ff`
+    #[napi(writable = false)]
+    pub macAddress: Option<String>,
+    /// `wMaxSegmentSize` from the Ethernet networking functional descriptor
+    #[napi(writable = false)]
+    pub maxSegmentSize: u16,
+    /// The NTB format in use for NCM
+    #[napi(writable = false, ts_type = "'ntb16' | 'ntb32'")]
+    pub ntbFormat: Option<String>,
+}
+
+fn link_guard(link: &Mutex<Link>) -> MutexGuard<'_, Link> {
+    link.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl CdcEthernet {
+    fn state(&self) -> MutexGuard<'_, EtherState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    async fn set_packet_filter(&self, method: &'static str, packet_filter: u16) -> Result<()> {
+        let target = self
+            .shared
+            .control_target(Recipient::Interface, self.layout.control as u16)
+            .ok_or_else(|| napi::Error::from_reason(format!("{method} error: invalid state")))?;
+        let control = self.layout.control;
+        run_blocking(move || {
+            class_control_out(
+                &target,
+                control,
+                SET_ETHERNET_PACKET_FILTER,
+                packet_filter,
+                &[],
+            )
+            .map_err(|e| format!("{method} error: {e}"))
+        })
+        .await?;
+        self.state().packet_filter = packet_filter;
+        Ok(())
+    }
+
+    fn start_notifications(&self) {
+        let Some(notify) = self.layout.notify else {
+            return;
+        };
+        let Some(endpoint) = self.shared.get_endpoint::<In>(notify) else {
+            return;
+        };
+
+        let link = self.link.clone();
+        let stream = InStream::start(
+            endpoint,
+            NOTIFICATION_LEN,
+            1,
+            move |data| {
+                // bmRequestType, bNotification, wValue, wIndex, wLength then any data
+                if data.len() < 8 {
+                    return;
+                }
+                let mut link = link_guard(&link);
+                match data[1] {
+                    NOTIFICATION_NETWORK_CONNECTION => {
+                        link.state.connected = u16::from_le_bytes([data[2], data[3]]) != 0;
+                    }
+                    NOTIFICATION_CONNECTION_SPEED_CHANGE if data.len() >= 16 => {
+                        link.state.downlinkSpeed =
+                            u32::from_le_bytes([data[8], data[9], data[10], data[11]]);
+                        link.state.uplinkSpeed =
+                            u32::from_le_bytes([data[12], data[13], data[14], data[15]]);
+                    }
+                    _ => return,
+                }
+                if let Some(callback) = link.callback.as_ref() {
+                    callback.call(link.state, ThreadsafeFunctionCallMode::NonBlocking);
+                }
+            },
+            |_| {},
+        );
+        self.state().notifications = Some(stream);
+    }
+}
+
+#[napi]
+impl CdcEthernet {
+    #[napi(getter)]
+    pub fn ntbParameters(&self) -> Option<CdcNtbParameters> {
+        self.ntb_parameters.clone()
+    }
+
+    #[napi(getter)]
+    pub fn linkState(&self) -> CdcLinkState {
+        link_guard(&self.link).state
+    }
+
+    /// Calls back with the link state on each connection or speed notification, replacing any earlier callback.
+    #[napi]
+    pub fn onLinkChange(&self, callback: LinkCallback) {
+        link_guard(&self.link).callback = Some(callback);
+    }
+
+    /// SET_ETHERNET_PACKET_FILTER, choosing which frames the device passes on. Packet types left out are unchanged.
+    #[napi]
+    pub async fn setPacketFilter(&self, filter: CdcPacketFilter) -> Result<()> {
+        let mut packet_filter = self.state().packet_filter;
+        for (enabled, bit) in [
+            (filter.promiscuous, PACKET_TYPE_PROMISCUOUS),
+            (filter.allMulticast, PACKET_TYPE_ALL_MULTICAST),
+            (filter.directed, PACKET_TYPE_DIRECTED),
+            (filter.broadcast, PACKET_TYPE_BROADCAST),
+            (filter.multicast, PACKET_TYPE_MULTICAST),
+        ] {
+            match enabled {
+                Some(true) => packet_filter |= bit,
+                Some(false) => packet_filter &= !bit,
+                None => {}
+            }
+        }
+        self.set_packet_filter("setPacketFilter", packet_filter)
+            .await
+    }
+
+    /// Sends one Ethernet frame, from the destination address up without the FCS.
+    #[napi]
+    pub async fn send(&self, frame: Uint8Array, timeout: Option<u32>) -> Result<()> {
+        self.sendFrames(vec![frame], timeout).await
+    }
+
+    /// Sends Ethernet frames in order. For NCM they are packed into as few NTBs as the device's limits allow.
+    #[napi]
+    pub async fn sendFrames(&self, frames: Vec<Uint8Array>, timeout: Option<u32>) -> Result<()> {
+        let max_segment_size = self.layout.max_segment_size as usize;
+        if let Some(frame) = frames.iter().find(|frame| frame.len() > max_segment_size) {
+            return Err(napi::Error::from_reason(format!(
+                "sendFrames error: {} byte frame exceeds the {max_segment_size} byte maximum segment size",
+                frame.len()
+            )));
+        }
+        let mut endpoint = self
+            .shared
+            .get_endpoint::<Out>(self.layout.bulk_out)
+            .ok_or_else(|| napi::Error::from_reason("sendFrames error: invalid state"))?;
+        let shared = self.shared.clone();
+        let sequence = self.sequence.clone();
+        let format = self.ntb_format;
+        let out_parameters = self
+            .ntb_parameters
+            .as_ref()
+            .map(CdcNtbParameters::out_parameters);
+        let timeout = Duration::from_millis(timeout.unwrap_or(DEFAULT_TIMEOUT) as u64);
+
+        run_blocking(move || {
+            let result = (|| {
+                let Some(parameters) = out_parameters else {
+                    // ECM sends each frame as a transfer of its own
+                    for frame in &frames {
+                        write_transfer(&shared, &mut endpoint, frame, usize::MAX, timeout)?;
+                    }
+                    return Ok(());
+                };
+
+                let mut sequence = sequence
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                let mut writer = NtbWriter::new(format, parameters);
+                for frame in &frames {
+                    if !writer.fits_alone(frame) {
+                        return Err(format!(
+                            "{} byte frame does not fit in a {} byte NTB",
+                            frame.len(),
+                            parameters.max_size
+                        ));
+                    }
+                    if !writer.push(frame) {
+                        let block =
+                            std::mem::replace(&mut writer, NtbWriter::new(format, parameters))
+                                .finish(*sequence);
+                        *sequence = sequence.wrapping_add(1);
+                        write_transfer(
+                            &shared,
+                            &mut endpoint,
+                            &block,
+                            parameters.max_size,
+                            timeout,
+                        )?;
+                        writer.push(frame);
+                    }
+                }
+                if !writer.is_empty() {
+                    let block = writer.finish(*sequence);
+                    *sequence = sequence.wrapping_add(1);
+                    write_transfer(&shared, &mut endpoint, &block, parameters.max_size, timeout)?;
+                }
+                Ok(())
+            })()
+            .map_err(|e| format!("sendFrames error: {e}"));
+            shared.put_endpoint(endpoint);
+            result
+        })
+        .await
+    }
+
+    /// Starts reading, calling back with each received Ethernet frame. For NCM every datagram of each NTB
+    /// is passed on, and malformed NTBs are dropped.
+    #[napi]
+    pub fn onFrame(&self, callback: FrameCallback) -> Result<()> {
+        let mut state = self.state();
+        if state.reader.is_some() {
+            return Err(napi::Error::from_reason("onFrame error: already reading"));
+        }
+        let endpoint = self
+            .shared
+            .get_endpoint::<In>(self.layout.bulk_in)
+            .ok_or_else(|| napi::Error::from_reason("onFrame error: invalid state"))?;
+
+        let ncm = self.layout.ncm;
+        let transfer_len = if ncm {
+            self.ntb_input_size
+        } else {
+            self.layout.max_segment_size as usize
+        };
+        state.reader = Some(InStream::start(
+            endpoint,
+            transfer_len,
+            READ_TRANSFERS,
+            move |data| {
+                if !ncm {
+                    callback.call(
+                        Uint8Array::new(data.to_vec()),
+                        ThreadsafeFunctionCallMode::NonBlocking,
+                    );
+                    return;
+                }
+                let Ok(datagrams) = parse_ntb(data) else {
+                    return;
+                };
+                for datagram in datagrams {
+                    callback.call(
+                        Uint8Array::new(datagram.to_vec()),
+                        ThreadsafeFunctionCallMode::NonBlocking,
+                    );
+                }
+            },
+            |_| {},
+        ));
+        Ok(())
+    }
+
+    #[napi]
+    pub fn stopFrames(&self) {
+        if let Some(reader) = self.state().reader.take() {
+            reader.stop();
+        }
+    }
+
+    /// Stops reading, returns the data interface to its idle setting and releases the interfaces,
+    /// reattaching any kernel driver.
+    #[napi]
+    pub async fn close(&self) -> Result<()> {
+        let (reader, notifications) = {
+            let mut state = self.state();
+            (state.reader.take(), state.notifications.take())
+        };
+        // Their endpoints keep the interfaces claimed until the streams have ended
+        for stream in [reader, notifications].into_iter().flatten() {
+            stream.finish().await;
+        }
+        link_guard(&self.link).callback = None;
+
+        // Best effort, the device may already be gone
+        let _ = self
+            .shared
+            .select_alternate_interface("close", self.layout.data, 0)
+            .await;
+
+        self.shared
+            .release_interface("close", self.layout.data)
+            .await?;
+        if self.layout.control != self.layout.data {
+            self.shared
+                .release_interface("close", self.layout.control)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[napi]
+impl UsbDevice {
+    /// Claims a CDC-ECM or CDC-NCM function, sets up NCM framing and starts the data interface.
+    #[napi]
+    pub async fn openCdcEthernet(
+        &self,
+        options: Option<CdcEthernetOptions>,
+    ) -> Result<CdcEthernet> {
+        let interface_number = options.as_ref().and_then(|o| o.interfaceNumber);
+        let detach = options
+            .as_ref()
+            .and_then(|o| o.detachKernelDriver)
+            .unwrap_or(true);
+        let ntb_format = match options.as_ref().and_then(|o| o.ntbFormat.as_deref()) {
+            None | Some("ntb16") => NtbFormat::Ntb16,
+            Some("ntb32") => NtbFormat::Ntb32,
+            Some(format) => {
+                return Err(napi::Error::from_reason(format!(
+                    "openCdcEthernet error: invalid NTB format {format}"
+                )))
+            }
+        };
+        let ntb_input_size = options
+            .as_ref()
+            .and_then(|o| o.ntbInputSize)
+            .unwrap_or(DEFAULT_NTB_INPUT_SIZE);
+
+        let shared = self.shared().clone();
+        let device = shared
+            .opened_device()
+            .ok_or_else(|| napi::Error::from_reason("openCdcEthernet error: invalid state"))?;
+        let (layout, mac_address) = run_blocking(move || {
+            let config = device
+                .active_configuration()
+                .map_err(|e| format!("openCdcEthernet error: {e}"))?;
+            let layout = find_ether_layout(&config, interface_number)
+                .map_err(|e| format!("openCdcEthernet error: {e}"))?;
+            let mac_address = NonZeroU8::new(layout.mac_address_index)
+                .and_then(|index| {
+                    device
+                        .get_string_descriptor(index, US_ENGLISH, CONTROL_TIMEOUT)
+                        .wait()
+                        .ok()
+                })
+                .and_then(|string| parse_mac_address(&string));
+            Ok((layout, mac_address))
+        })
+        .await?;
+
+        shared
+            .claim_interface("openCdcEthernet", layout.control, detach)
+            .await?;
+        if layout.data != layout.control {
+            shared
+                .claim_interface("openCdcEthernet", layout.data, detach)
+                .await?;
+        }
+
+        // NTB parameters are set while the data interface is idle in alternate setting 0
+        let (ntb_parameters, ntb_input_size) = if layout.ncm {
+            let target = shared
+                .control_target(Recipient::Interface, layout.control as u16)
+                .ok_or_else(|| napi::Error::from_reason("openCdcEthernet error: invalid state"))?;
+            let control = layout.control;
+            let capabilities = layout.ncm_capabilities;
+            let setup = run_blocking(move || {
+                let request = ControlIn {
+                    control_type: ControlType::Class,
+                    recipient: Recipient::Interface,
+                    request: GET_NTB_PARAMETERS,
+                    value: 0,
+                    index: control as u16,
+                    length: 28,
+                };
+                let data = target
+                    .control_in_blocking(request, CONTROL_TIMEOUT)
+                    .map_err(|e| format!("openCdcEthernet error: {e}"))?;
+                let parameters = CdcNtbParameters::parse(&data)
+                    .map_err(|e| format!("openCdcEthernet error: {e}"))?;
+
+                if ntb_format == NtbFormat::Ntb32 {
+                    if !parameters.formats.iter().any(|format| format == "ntb32") {
+                        return Err("openCdcEthernet error: NTB32 not supported".to_string());
+                    }
+                    class_control_out(&target, control, SET_NTB_FORMAT, 1, &[])
+                        .map_err(|e| format!("openCdcEthernet error: {e}"))?;
+                }
+
+                // Devices that refuse a smaller input size may send NTBs up to their own limit
+                let input_size = ntb_input_size.min(parameters.inMaxSize);
+                let mut request = input_size.to_le_bytes().to_vec();
+                if capabilities & NCM_CAPABILITY_INPUT_DATAGRAMS != 0 {
+                    request.extend_from_slice(&[0; 4]);
+                }
+                let input_size =
+                    match class_control_out(&target, control, SET_NTB_INPUT_SIZE, 0, &request) {
+                        Ok(()) => input_size,
+                        Err(_) => parameters.inMaxSize,
+                    };
+                Ok((Some(parameters), input_size as usize))
+            })
+            .await;
+            match setup {
+                Ok(setup) => setup,
+                Err(e) => {
+                    let _ = shared
+                        .release_interface("openCdcEthernet", layout.data)
+                        .await;
+                    if layout.control != layout.data {
+                        let _ = shared
+                            .release_interface("openCdcEthernet", layout.control)
+                            .await;
+                    }
+                    return Err(e);
+                }
+            }
+        } else {
+            (None, 0)
+        };
+
+        shared
+            .select_alternate_interface("openCdcEthernet", layout.data, layout.data_alt)
+            .await?;
+
+        let ethernet = CdcEthernet {
+            shared,
+            protocol: if layout.ncm { "ncm" } else { "ecm" }.to_string(),
+            controlInterface: layout.control,
+            dataInterface: layout.data,
+            macAddress: mac_address,
+            maxSegmentSize: layout.max_segment_size,
+            ntbFormat: layout.ncm.then(|| {
+                match ntb_format {
+                    NtbFormat::Ntb16 => "ntb16",
+                    NtbFormat::Ntb32 => "ntb32",
+                }
+                .to_string()
+            }),
+            layout,
+            ntb_format,
+            ntb_parameters,
+            ntb_input_size,
+            sequence: Arc::new(Mutex::new(0)),
+            state: Mutex::new(EtherState {
+                packet_filter: 0,
+                reader: None,
+                notifications: None,
+            }),
+            link: Arc::new(Mutex::new(Link {
+                state: CdcLinkState::default(),
+                callback: None,
+            })),
+        };
+        ethernet.start_notifications();
+
+        // Best effort, as the filter is optional for NCM and devices start out passing these anyway
+        let _ = ethernet
+            .set_packet_filter(
+                "openCdcEthernet",
+                PACKET_TYPE_DIRECTED | PACKET_TYPE_BROADCAST | PACKET_TYPE_MULTICAST,
+            )
+            .await;
+        Ok(ethernet)
+    }
+}
@@ -0,0 +1,359 @@
+const NTH16_SIGNATURE: &[u8; 4] = b"NCMH";
+const NTH32_SIGNATURE: &[u8; 4] = b"ncmh";
+const NDP16_SIGNATURE: &[u8; 4] = b"NCM0";
+const NDP16_CRC_SIGNATURE: &[u8; 4] = b"NCM1";
+const NDP32_SIGNATURE: &[u8; 4] = b"ncm0";
+const NDP32_CRC_SIGNATURE: &[u8; 4] = b"ncm1";
+
+const NTH16_LEN: usize = 12;
+const NTH32_LEN: usize = 16;
+const CRC_LEN: usize = 4;
+
+/// Pointer tables followed in one NTB, to stop on a looping chain.
+const MAX_NDPS: usize = 64;
+
+#[derive(Clone, Copy, PartialEq)]
+pub(super) enum NtbFormat {
+    Ntb16,
+    Ntb32,
+}
+
+impl NtbFormat {
+    fn header_len(self) -> usize {
+        match self {
+            NtbFormat::Ntb16 => NTH16_LEN,
+            NtbFormat::Ntb32 => NTH32_LEN,
+        }
+    }
+
+    /// Pointer table length before its entries, and the length of each entry.
+    fn ndp_layout(self) -> (usize, usize) {
+        match self {
+            NtbFormat::Ntb16 => (8, 4),
+            NtbFormat::Ntb32 => (16, 8),
+        }
+    }
+}
+
+/// The OUT half of GET_NTB_PARAMETERS, as far as building NTBs goes.
+#[derive(Clone, Copy)]
+pub(super) struct NtbOutParameters {
+    pub(super) max_size: usize,
+    pub(super) divisor: usize,
+    pub(super) remainder: usize,
+    pub(super) alignment: usize,
+    /// 0 for no limit
+    pub(super) max_datagrams: usize,
+}
+
+fn u16_at(data: &[u8], i: usize) -> Option<usize> {
+    Some(u16::from_le_bytes(data.get(i..i + 2)?.try_into().ok()?) as usize)
+}
+
+fn u32_at(data: &[u8], i: usize) -> Option<usize> {
+    Some(u32::from_le_bytes(data.get(i..i + 4)?.try_into().ok()?) as usize)
+}
+
+/// Splits a received NTB16 or NTB32 into its datagrams, following every pointer table.
+pub(super) fn parse_ntb(data: &[u8]) -> std::result::Result<Vec<&[u8]>, String> {
+    let format = match data.get(..4) {
+        Some(signature) if signature == NTH16_SIGNATURE => NtbFormat::Ntb16,
+        Some(signature) if signature == NTH32_SIGNATURE => NtbFormat::Ntb32,
+        _ => return Err("bad NTB signature".to_string()),
+    };
+    let (block_len, first_ndp) = match format {
+        NtbFormat::Ntb16 => (u16_at(data, 8), u16_at(data, 10)),
+        NtbFormat::Ntb32 => (u32_at(data, 8), u32_at(data, 12)),
+    };
+    let (Some(block_len), Some(first_ndp)) = (block_len, first_ndp) else {
+        return Err("short NTB header".to_string());
+    };
+    if u16_at(data, 4) != Some(format.header_len()) {
+        return Err("bad NTB header length".to_string());
+    }
+    // A block length of 0 in NTB16 means the block runs to the end of the transfer
+    let block = &data[..if block_len == 0 {
+        data.len()
+    } else {
+        block_len.min(data.len())
+    }];
+    let mut ndp_index = Some(first_ndp);
+
+    let mut datagrams = Vec::new();
+    for _ in 0..MAX_NDPS {
+        let index = match ndp_index {
+            Some(0) | None => return Ok(datagrams),
+            Some(index) => index,
+        };
+        let crc = match (format, block.get(index..index + 4)) {
+            (NtbFormat::Ntb16, Some(s)) if s == NDP16_SIGNATURE => false,
+            (NtbFormat::Ntb16, Some(s)) if s == NDP16_CRC_SIGNATURE => true,
+            (NtbFormat::Ntb32, Some(s)) if s == NDP32_SIGNATURE => false,
+            (NtbFormat::Ntb32, Some(s)) if s == NDP32_CRC_SIGNATURE => true,
+            _ => return Err(format!("bad NDP signature at {index}")),
+        };
+        let ndp_len = u16_at(block, index + 4).ok_or("short NDP")?;
+        let ndp = block
+            .get(index..index + ndp_len)
+            .ok_or("NDP past the end of the block")?;
+        let (entries_start, entry_len) = format.ndp_layout();
+        ndp_index = match format {
+            NtbFormat::Ntb16 => u16_at(ndp, 6),
+            NtbFormat::Ntb32 => u32_at(ndp, 8),
+        };
+
+        for entry in ndp
+            .get(entries_start..)
+            .unwrap_or(&[])
+            .chunks_exact(entry_len)
+        {
+            let (offset, len) = match format {
+                NtbFormat::Ntb16 => (u16_at(entry, 0), u16_at(entry, 2)),
+                NtbFormat::Ntb32 => (u32_at(entry, 0), u32_at(entry, 4)),
+            };
+            let (Some(offset), Some(len)) = (offset, len) else {
+                break;
+            };
+            if offset == 0 || len == 0 {
+                break;
+            }
+            let datagram = block
+                .get(offset..offset + len)
+                .ok_or("datagram past the end of the block")?;
+            datagrams.push(if crc {
+                &datagram[..datagram.len().saturating_sub(CRC_LEN)]
+            } else {
+                datagram
+            });
+        }
+    }
+    Err("too many NDPs".to_string())
+}
+
+fn align(offset: usize, alignment: usize) -> usize {
+    offset.next_multiple_of(alignment.max(1))
+}
+
+/// Builds one OUT NTB from datagrams, placed as the device's divisor and remainder ask,
+/// with a single pointer table after them.
+pub(super) struct NtbWriter {
+    format: NtbFormat,
+    parameters: NtbOutParameters,
+    block: Vec<u8>,
+    entries: Vec<(usize, usize)>,
+}
+
+impl NtbWriter {
+    pub(super) fn new(format: NtbFormat, parameters: NtbOutParameters) -> Self {
+        Self {
+            format,
+            parameters,
+            block: vec![0; format.header_len()],
+            entries: Vec::new(),
+        }
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn datagram_offset(&self, from: usize) -> usize {
+        let divisor = self.parameters.divisor.max(1);
+        let remainder = self.parameters.remainder % divisor;
+        let aligned = from - from % divisor + remainder;
+        if aligned < from {
+            aligned + divisor
+        } else {
+            aligned
+        }
+    }
+
+    fn ndp_len(&self, datagrams: usize) -> usize {
+        let (entries_start, entry_len) = self.format.ndp_layout();
+        // Room for the zero entry ending the table
+        entries_start + (datagrams + 1) * entry_len
+    }
+
+    /// Length of the block with `datagram` added.
+    fn len_with(&self, datagram: &[u8]) -> usize {
+        let end = self.datagram_offset(self.block.len()) + datagram.len();
+        align(end, self.parameters.alignment.max(4)) + self.ndp_len(self.entries.len() + 1)
+    }
+
+    /// Whether `datagram` alone fits in an NTB.
+    pub(super) fn fits_alone(&self, datagram: &[u8]) -> bool {
+        NtbWriter::new(self.format, self.parameters).len_with(datagram) <= self.parameters.max_size
+    }
+
+    /// Adds `datagram` if it fits.
+    pub(super) fn push(&mut self, datagram: &[u8]) -> bool {
+        if self.parameters.max_datagrams != 0 && self.entries.len() >= self.parameters.max_datagrams
+        {
+            return false;
+        }
+        if self.len_with(datagram) > self.parameters.max_size {
+            return false;
+        }
+        let offset = self.datagram_offset(self.block.len());
+        self.block.resize(offset, 0);
+        self.block.extend_from_slice(datagram);
+        self.entries.push((offset, datagram.len()));
+        true
+    }
+
+    pub(super) fn finish(mut self, sequence: u16) -> Vec<u8> {
+        let ndp_index = align(self.block.len(), self.parameters.alignment.max(4));
+        self.block.resize(ndp_index, 0);
+        let ndp_len = self.ndp_len(self.entries.len());
+
+        match self.format {
+            NtbFormat::Ntb16 => {
+                self.block.extend_from_slice(NDP16_SIGNATURE);
+                self.block
+                    .extend_from_slice(&(ndp_len as u16).to_le_bytes());
+                self.block.extend_from_slice(&0u16.to_le_bytes());
+                for &(offset, len) in &self.entries {
+                    self.block.extend_from_slice(&(offset as u16).to_le_bytes());
+                    self.block.extend_from_slice(&(len as u16).to_le_bytes());
+                }
+                self.block.extend_from_slice(&[0; 4]);
+            }
+            NtbFormat::Ntb32 => {
+                self.block.extend_from_slice(NDP32_SIGNATURE);
+                self.block
+                    .extend_from_slice(&(ndp_len as u16).to_le_bytes());
+                self.block.extend_from_slice(&[0; 10]);
+                for &(offset, len) in &self.entries {
+                    self.block.extend_from_slice(&(offset as u32).to_le_bytes());
+                    self.block.extend_from_slice(&(len as u32).to_le_bytes());
+                }
+                self.block.extend_from_slice(&[0; 8]);
+            }
+        }
+
+        let block_len = self.block.len();
+        let header = &mut self.block[..self.format.header_len()];
+        match self.format {
+            NtbFormat::Ntb16 => {
+                header[..4].copy_from_slice(NTH16_SIGNATURE);
+                header[4..6].copy_from_slice(&(NTH16_LEN as u16).to_le_bytes());
+                header[6..8].copy_from_slice(&sequence.to_le_bytes());
+                header[8..10].copy_from_slice(&(block_len as u16).to_le_bytes());
+                header[10..12].copy_from_slice(&(ndp_index as u16).to_le_bytes());
+            }
+            NtbFormat::Ntb32 => {
+                header[..4].copy_from_slice(NTH32_SIGNATURE);
+                header[4..6].copy_from_slice(&(NTH32_LEN as u16).to_le_bytes());
+                header[6..8].copy_from_slice(&sequence.to_le_bytes());
+                header[8..12].copy_from_slice(&(block_len as u32).to_le_bytes());
+                header[12..16].copy_from_slice(&(ndp_index as u32).to_le_bytes());
+            }
+        }
+        self.block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARAMETERS: NtbOutParameters = NtbOutParameters {
+        max_size: 2048,
+        divisor: 4,
+        remainder: 2,
+        alignment: 4,
+        max_datagrams: 0,
+    };
+
+    /// Two datagrams chained across an NDP16 with CRCs and a plain one, with the block
+    /// length left at 0.
+    fn chained_ntb16() -> Vec<u8> {
+        let mut ntb = Vec::new();
+        ntb.extend_from_slice(b"NCMH\x0c\x00\x01\x00\x00\x00\x0c\x00");
+        ntb.extend_from_slice(b"NCM1\x10\x00\x1c\x00\x2c\x00\x06\x00\x00\x00\x00\x00");
+        ntb.extend_from_slice(b"NCM0\x10\x00\x00\x00\x32\x00\x02\x00\x00\x00\x00\x00");
+        ntb.extend_from_slice(&[1, 2, 0xcc, 0xcc, 0xcc, 0xcc, 3, 4]);
+        ntb
+    }
+
+    #[test]
+    fn builds_ntb16_with_divisor_and_remainder() {
+        let mut writer = NtbWriter::new(NtbFormat::Ntb16, PARAMETERS);
+        assert!(writer.is_empty());
+        assert!(writer.push(&[0xaa; 3]));
+        assert!(writer.push(&[0xbb; 2]));
+        assert_eq!(
+            writer.finish(7),
+            [
+                b'N', b'C', b'M', b'H', 0x0c, 0x00, 0x07, 0x00, 0x28, 0x00, 0x14, 0x00, //
+                0x00, 0x00, 0xaa, 0xaa, 0xaa, 0x00, 0xbb, 0xbb, //
+                b'N', b'C', b'M', b'0', 0x14, 0x00, 0x00, 0x00, //
+                0x0e, 0x00, 0x03, 0x00, 0x12, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00,
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_what_it_builds() {
+        for format in [NtbFormat::Ntb16, NtbFormat::Ntb32] {
+            let mut writer = NtbWriter::new(format, PARAMETERS);
+            let datagrams: [&[u8]; 3] = [&[1; 60], &[2; 1], &[3; 1514]];
+            for datagram in datagrams {
+                assert!(writer.push(datagram));
+            }
+            let ntb = writer.finish(0xffff);
+            assert_eq!(parse_ntb(&ntb).unwrap(), datagrams);
+        }
+    }
+
+    #[test]
+    fn stops_at_the_size_and_datagram_limits() {
+        let mut writer = NtbWriter::new(
+            NtbFormat::Ntb16,
+            NtbOutParameters {
+                max_size: 128,
+                max_datagrams: 2,
+                ..PARAMETERS
+            },
+        );
+        assert!(writer.fits_alone(&[0; 80]));
+        assert!(!writer.fits_alone(&[0; 120]));
+        assert!(writer.push(&[0; 80]));
+        assert!(!writer.push(&[0; 40]));
+        assert!(writer.push(&[0; 10]));
+        assert!(!writer.push(&[0; 1]));
+        assert!(writer.finish(0).len() <= 128);
+    }
+
+    #[test]
+    fn follows_chained_pointer_tables() {
+        let ntb = chained_ntb16();
+        assert_eq!(parse_ntb(&ntb).unwrap(), [&[1, 2][..], &[3, 4][..]]);
+    }
+
+    #[test]
+    fn rejects_malformed_blocks() {
+        assert_eq!(parse_ntb(b"NCMX").unwrap_err(), "bad NTB signature");
+        assert_eq!(parse_ntb(b"NCMH\x0c\x00").unwrap_err(), "short NTB header");
+
+        let mut ntb = chained_ntb16();
+        ntb[4] = 0x10;
+        assert_eq!(parse_ntb(&ntb).unwrap_err(), "bad NTB header length");
+
+        let mut ntb = chained_ntb16();
+        ntb[12] = b'X';
+        assert_eq!(parse_ntb(&ntb).unwrap_err(), "bad NDP signature at 12");
+
+        let mut ntb = chained_ntb16();
+        ntb[8] = 0x30;
+        assert_eq!(
+            parse_ntb(&ntb).unwrap_err(),
+            "datagram past the end of the block"
+        );
+
+        // A second table pointing back at itself
+        let mut ntb = chained_ntb16();
+        ntb[34] = 0x1c;
+        assert_eq!(parse_ntb(&ntb).unwrap_err(), "too many NDPs");
+    }
+}
@@ -0,0 +1,1148 @@
+use crate::standard_request::{
+    clear_endpoint_halt, clear_feature, get_configuration, get_descriptor, get_interface,
+    get_status, set_feature, DESCRIPTOR_BOS, DESCRIPTOR_CONFIGURATION, DESCRIPTOR_DEVICE,
+    DESCRIPTOR_ENDPOINT, DESCRIPTOR_INTERFACE, DESCRIPTOR_SS_ENDPOINT_COMPANION, DESCRIPTOR_STRING,
+    FEATURE_DEVICE_REMOTE_WAKEUP, FEATURE_ENDPOINT_HALT, STATUS_HALT, STATUS_REMOTE_WAKEUP,
+};
+use crate::webusb_device::{run_blocking, ControlTarget, SharedDevice, UsbDevice};
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use nusb::{transfer::Recipient, Speed};
+use std::collections::{BTreeMap, HashMap};
+
+const DEVICE_DESCRIPTOR_LEN: u8 = 18;
+const CONFIGURATION_DESCRIPTOR_LEN: u8 = 9;
+const INTERFACE_DESCRIPTOR_LEN: u8 = 9;
+const ENDPOINT_DESCRIPTOR_LEN: u8 = 7;
+/// Audio class 1 endpoints carry bRefresh and bSynchAddress as well
+const AUDIO_ENDPOINT_DESCRIPTOR_LEN: u8 = 9;
+const BOS_DESCRIPTOR_LEN: u8 = 5;
+
+const CLASS_AUDIO: u8 = 0x01;
+/// UAC2 and later set the interface protocol, UAC1 leaves it zero
+const PROTOCOL_UAC1: u8 = 0x00;
+
+const CONFIG_ATTRIBUTES_RESERVED_ONE: u8 = 0x80;
+const CONFIG_ATTRIBUTES_RESERVED_ZERO: u8 = 0x1f;
+const CONFIG_ATTRIBUTES_REMOTE_WAKEUP: u8 = 0x20;
+
+const ENDPOINT_NUMBER_MASK: u8 = 0x0f;
+const ENDPOINT_ADDRESS_RESERVED: u8 = 0x70;
+
+const TRANSFER_CONTROL: u8 = 0;
+const TRANSFER_ISOCHRONOUS: u8 = 1;
+const TRANSFER_BULK: u8 = 2;
+const TRANSFER_INTERRUPT: u8 = 3;
+
+const USB3_VERSION: u16 = 0x0300;
+/// The first bcdUSB with link power management, which needs a BOS descriptor
+const USB_LPM_VERSION: u16 = 0x0210;
+/// USB 2.0 defines self-powered and remote wakeup, USB 3 adds U1, U2 and LTM enable
+const USB2_DEVICE_STATUS_BITS: u16 = 0x0003;
+const USB3_DEVICE_STATUS_BITS: u16 = 0x001f;
+/// Function remote wake capable and enabled, USB 3 only
+const USB3_INTERFACE_STATUS_BITS: u16 = 0x0003;
+
+#[napi(object)]
+pub struct Chapter9Options {
+    /// Halt and clear each bulk and interrupt endpoint of the claimed interfaces, defaults to
+    /// `false` as it disturbs any transfers in flight
+    pub haltEndpoints: Option<bool>,
+}
+
+#[napi(object)]
+pub struct Chapter9Violation {
+    /// Which rule was broken, e.g. `endpoint.maxPacketSize`
+    #[napi(writable = false)]
+    pub check: String,
+    /// Where, e.g. `configuration 1 interface 0.0 endpoint 0x81`
+    #[napi(writable = false)]
+    pub location: String,
+    #[napi(writable = false)]
+    pub message: String,
+}
+
+#[napi(object)]
+pub struct Chapter9Report {
+    /// No violations were found
+    #[napi(writable = false)]
+    pub passed: bool,
+    /// Speed the speed-dependent rules were checked against, they're skipped when unknown
+    #[napi(writable = false, ts_type = "USBSpeed")]
+    pub speed: Option<String>,
+    #[napi(writable = false)]
+    pub violations: Vec<Chapter9Violation>,
+    /// Checks that couldn't run, with the reason
+    #[napi(writable = false)]
+    pub skipped: Vec<String>,
+}
+
+struct Report {
+    violations: Vec<Chapter9Violation>,
+    skipped: Vec<String>,
+}
+
+impl Report {
+    fn violation(&mut self, check: &str, location: &str, message: impl Into<String>) {
+        self.violations.push(Chapter9Violation {
+            check: check.to_string(),
+            location: location.to_string(),
+            message: message.into(),
+        });
+    }
+
+    fn skip(&mut self, message: impl Into<String>) {
+        self.skipped.push(message.into());
+    }
+}
+
+struct Endpoint {
+    address: u8,
+    attributes: u8,
+    max_packet_size: u16,
+    interval: u8,
+    companion: bool,
+}
+
+impl Endpoint {
+    fn transfer_type(&self) -> u8 {
+        self.attributes & 0x03
+    }
+
+    fn location(&self, alternate: &Alternate) -> String {
+        format!("{} endpoint {:#04x}", alternate.location, self.address)
+    }
+}
+
+struct Alternate {
+    number: u8,
+    alternate: u8,
+    num_endpoints: u8,
+    endpoints: Vec<Endpoint>,
+    location: String,
+}
+
+struct Configuration {
+    value: u8,
+    attributes: u8,
+    num_interfaces: u8,
+    alternates: Vec<Alternate>,
+}
+
+fn speed_name(speed: Option<Speed>) -> Option<String> {
+    match speed? {
+        Speed::Low => Some("low".to_string()),
+        Speed::Full => Some("full".to_string()),
+        Speed::High => Some("high".to_string()),
+        Speed::Super => Some("super".to_string()),
+        Speed::SuperPlus => Some("superPlus".to_string()),
+        _ => None,
+    }
+}
+
+fn check_device_descriptor(data: &[u8], speed: Option<Speed>, report: &mut Report) -> Option<u16> {
+    let location = "device";
+    if data.len() != DEVICE_DESCRIPTOR_LEN as usize
+        || data[0] != DEVICE_DESCRIPTOR_LEN
+        || data[1] != DESCRIPTOR_DEVICE
+    {
+        report.violation(
+            "device.length",
+            location,
+            format!(
+                "device descriptor is {} bytes with bLength {}, expected 18",
+                data.len(),
+                data.first().copied().unwrap_or(0)
+            ),
+        );
+        if data.len() < DEVICE_DESCRIPTOR_LEN as usize {
+            return None;
+        }
+    }
+
+    let bcd_usb = u16::from_le_bytes([data[2], data[3]]);
+    let max_packet_size_0 = data[7];
+    let minimum_version = match speed {
+        Some(Speed::High) => Some(0x0200),
+        Some(Speed::Super | Speed::SuperPlus) => Some(USB3_VERSION),
+        _ => None,
+    };
+    if let Some(minimum) = minimum_version.filter(|&minimum| bcd_usb < minimum) {
+        report.violation(
+            "device.bcdUSB",
+            location,
+            format!("bcdUSB {bcd_usb:#06x} is below {minimum:#06x} for the speed it runs at"),
+        );
+    }
+
+    let allowed: &[u8] = match speed {
+        Some(Speed::Low) => &[8],
+        Some(Speed::Full) => &[8, 16, 32, 64],
+        Some(Speed::High) => &[64],
+        // An exponent, 2^9 = 512 bytes
+        Some(Speed::Super | Speed::SuperPlus) => &[9],
+        _ => &[],
+    };
+    if !allowed.is_empty() && !allowed.contains(&max_packet_size_0) {
+        report.violation(
+            "device.maxPacketSize0",
+            location,
+            format!(
+                "bMaxPacketSize0 {max_packet_size_0} isn't allowed, expected one of {allowed:?}"
+            ),
+        );
+    }
+
+    if data[17] == 0 {
+        report.violation(
+            "device.numConfigurations",
+            location,
+            "bNumConfigurations is 0",
+        );
+    }
+    Some(bcd_usb)
+}
+
+fn check_strings(target: &ControlTarget, device_descriptor: &[u8], report: &mut Report) {
+    let indexes = [
+        ("iManufacturer", device_descriptor[14]),
+        ("iProduct", device_descriptor[15]),
+        ("iSerialNumber", device_descriptor[16]),
+    ];
+    if indexes.iter().all(|(_, index)| *index == 0) {
+        return;
+    }
+
+    let languages = match get_descriptor(target, DESCRIPTOR_STRING, 0, 0, 255) {
+        Ok(data) if data.len() >= 4 && data[0] as usize == data.len() && data[0] % 2 == 0 => data,
+        Ok(data) => {
+            report.violation(
+                "device.string",
+                "string 0",
+                format!("language table of {} bytes is malformed", data.len()),
+            );
+            return;
+        }
+        Err(e) => {
+            report.violation("device.string", "string 0", e);
+            return;
+        }
+    };
+    let language = u16::from_le_bytes([languages[2], languages[3]]);
+
+    for (field, index) in indexes {
+        if index == 0 {
+            continue;
+        }
+        let location = format!("string {index}");
+        match get_descriptor(target, DESCRIPTOR_STRING, index, language, 255) {
+            Ok(data)
+                if data.len() >= 2
+                    && data[0] as usize == data.len()
+                    && data[0] % 2 == 0
+                    && data[1] == DESCRIPTOR_STRING => {}
+            Ok(data) => report.violation(
+                "device.string",
+                &location,
+                format!("{field} string of {} bytes is malformed", data.len()),
+            ),
+            Err(e) => report.violation(
+                "device.string",
+                &location,
+                format!("{field} can't be read: {e}"),
+            ),
+        }
+    }
+}
+
+fn check_bos(target: &ControlTarget, report: &mut Report) {
+    let location = "bos";
+    let header = match get_descriptor(target, DESCRIPTOR_BOS, 0, 0, BOS_DESCRIPTOR_LEN as u16) {
+        Ok(header)
+            if header.len() == BOS_DESCRIPTOR_LEN as usize
+                && header[0] == BOS_DESCRIPTOR_LEN
+                && header[1] == DESCRIPTOR_BOS =>
+        {
+            header
+        }
+        Ok(_) => {
+            report.violation("bos.length", location, "BOS descriptor header is malformed");
+            return;
+        }
+        Err(e) => {
+            report.violation(
+                "bos.missing",
+                location,
+                format!("bcdUSB requires a BOS descriptor but it can't be read: {e}"),
+            );
+            return;
+        }
+    };
+    let total_length = u16::from_le_bytes([header[2], header[3]]);
+    match get_descriptor(target, DESCRIPTOR_BOS, 0, 0, total_length) {
+        Ok(data) if data.len() == total_length as usize => {
+            let mut offset = 0;
+            while offset < data.len() {
+                let len = data[offset] as usize;
+                if len < 2 || offset + len > data.len() {
+                    report.violation(
+                        "descriptor.length",
+                        location,
+                        format!("descriptor at offset {offset} overruns wTotalLength"),
+                    );
+                    return;
+                }
+                offset += len;
+            }
+        }
+        Ok(data) => report.violation(
+            "bos.totalLength",
+            location,
+            format!(
+                "wTotalLength is {total_length} but {} bytes were returned",
+                data.len()
+            ),
+        ),
+        Err(e) => report.violation("bos.totalLength", location, e),
+    }
+}
+
+/// Walks a configuration's descriptors, checking each length and collecting the interfaces and
+/// endpoints for the checks that look across them.
+fn parse_configuration(data: &[u8], location: &str, report: &mut Report) -> Option<Configuration> {
+    if data.len() < CONFIGURATION_DESCRIPTOR_LEN as usize {
+        report.violation(
+            "configuration.length",
+            location,
+            format!(
+                "configuration descriptor is {} bytes, expected 9",
+                data.len()
+            ),
+        );
+        return None;
+    }
+    let mut configuration = Configuration {
+        value: data[5],
+        attributes: data[7],
+        num_interfaces: data[4],
+        alternates: Vec::new(),
+    };
+    let mut class = (0u8, 0u8);
+    let mut offset = data[0] as usize;
+
+    while offset < data.len() {
+        let len = data[offset];
+        let descriptor = match data.get(offset..offset + len as usize) {
+            Some(descriptor) if len >= 2 => descriptor,
+            _ => {
+                report.violation(
+                    "descriptor.length",
+                    location,
+                    format!(
+                        "descriptor at offset {offset} has bLength {len}, overrunning wTotalLength"
+                    ),
+                );
+                break;
+            }
+        };
+
+        match descriptor[1] {
+            DESCRIPTOR_INTERFACE => {
+                let location = match descriptor.get(2..4) {
+                    Some(numbers) => format!("{location} interface {}.{}", numbers[0], numbers[1]),
+                    None => format!("{location} offset {offset}"),
+                };
+                if len != INTERFACE_DESCRIPTOR_LEN {
+                    report.violation(
+                        "interface.length",
+                        &location,
+                        format!("bLength is {len}, expected {INTERFACE_DESCRIPTOR_LEN}"),
+                    );
+                }
+                if len >= INTERFACE_DESCRIPTOR_LEN {
+                    class = (descriptor[5], descriptor[7]);
+                    configuration.alternates.push(Alternate {
+                        number: descriptor[2],
+                        alternate: descriptor[3],
+                        num_endpoints: descriptor[4],
+                        endpoints: Vec::new(),
+                        location,
+                    });
+                }
+            }
+            DESCRIPTOR_ENDPOINT => {
+                let Some(alternate) = configuration.alternates.last_mut() else {
+                    report.violation(
+                        "endpoint.interface",
+                        location,
+                        format!("endpoint at offset {offset} comes before any interface"),
+                    );
+                    offset += len as usize;
+                    continue;
+                };
+                let audio = class == (CLASS_AUDIO, PROTOCOL_UAC1);
+                if len != ENDPOINT_DESCRIPTOR_LEN
+                    && !(audio && len == AUDIO_ENDPOINT_DESCRIPTOR_LEN)
+                {
+                    report.violation(
+                        "endpoint.length",
+                        &format!("{} offset {offset}", alternate.location),
+                        format!("bLength is {len}, expected {ENDPOINT_DESCRIPTOR_LEN}"),
+                    );
+                }
+                if len >= ENDPOINT_DESCRIPTOR_LEN {
+                    alternate.endpoints.push(Endpoint {
+                        address: descriptor[2],
+                        attributes: descriptor[3],
+                        max_packet_size: u16::from_le_bytes([descriptor[4], descriptor[5]]),
+                        interval: descriptor[6],
+                        companion: false,
+                    });
+                }
+            }
+            DESCRIPTOR_SS_ENDPOINT_COMPANION => {
+                if let Some(endpoint) = configuration
+                    .alternates
+                    .last_mut()
+                    .and_then(|alternate| alternate.endpoints.last_mut())
+                {
+                    endpoint.companion = true;
+                }
+            }
+            _ => {}
+        }
+        offset += len as usize;
+    }
+    Some(configuration)
+}
+
+fn check_max_packet_size(speed: Speed, endpoint: &Endpoint) -> Option<String> {
+    let size = endpoint.max_packet_size & 0x07ff;
+    let transactions = (endpoint.max_packet_size >> 11) & 0x03;
+    let transfer_type = endpoint.transfer_type();
+    let periodic = matches!(transfer_type, TRANSFER_ISOCHRONOUS | TRANSFER_INTERRUPT);
+
+    if endpoint.max_packet_size & 0xe000 != 0 {
+        return Some(format!(
+            "wMaxPacketSize {:#06x} sets reserved bits",
+            endpoint.max_packet_size
+        ));
+    }
+    if transactions != 0 && !(speed == Speed::High && periodic) {
+        return Some(
+            "additional transactions are only allowed for high speed periodic endpoints"
+                .to_string(),
+        );
+    }
+
+    let fits = match (speed, transfer_type) {
+        (Speed::Low, TRANSFER_CONTROL) => size == 8,
+        (Speed::Low, TRANSFER_INTERRUPT) => size <= 8,
+        (Speed::Low, _) => {
+            return Some("bulk and isochronous endpoints aren't allowed at low speed".to_string())
+        }
+        (Speed::Full, TRANSFER_CONTROL | TRANSFER_BULK) => matches!(size, 8 | 16 | 32 | 64),
+        (Speed::Full, TRANSFER_INTERRUPT) => size <= 64,
+        (Speed::Full, _) => size <= 1023,
+        (Speed::High, TRANSFER_CONTROL) => size == 64,
+        (Speed::High, TRANSFER_BULK) => size == 512,
+        (Speed::High, _) => match transactions {
+            0 => size <= 1024,
+            1 => (513..=1024).contains(&size),
+            2 => (683..=1024).contains(&size),
+            _ => return Some("more than 2 additional transactions per microframe".to_string()),
+        },
+        (_, TRANSFER_CONTROL) => size == 512,
+        (_, TRANSFER_BULK) => size == 1024,
+        (_, _) => size <= 1024,
+    };
+    (!fits).then(|| match transactions {
+        0 => format!("wMaxPacketSize {size} isn't allowed for this speed and type"),
+        _ => format!(
+            "wMaxPacketSize {size} with {transactions} additional transactions isn't allowed for this speed and type"
+        ),
+    })
+}
+
+fn check_interval(speed: Speed, endpoint: &Endpoint) -> Option<String> {
+    let interval = endpoint.interval;
+    let range = match (speed, endpoint.transfer_type()) {
+        (Speed::Low | Speed::Full, TRANSFER_INTERRUPT) => 1..=255,
+        (_, TRANSFER_ISOCHRONOUS | TRANSFER_INTERRUPT) => 1..=16,
+        _ => return None,
+    };
+    (!range.contains(&interval)).then(|| {
+        format!(
+            "bInterval {interval} is outside {}..={}",
+            range.start(),
+            range.end()
+        )
+    })
+}
+
+fn check_configuration(
+    configuration: &Configuration,
+    speed: Option<Speed>,
+    location: &str,
+    report: &mut Report,
+) {
+    if configuration.value == 0 {
+        report.violation(
+            "configuration.value",
+            location,
+            "bConfigurationValue 0 is reserved for the unconfigured state",
+        );
+    }
+    if configuration.attributes & CONFIG_ATTRIBUTES_RESERVED_ONE == 0
+        || configuration.attributes & CONFIG_ATTRIBUTES_RESERVED_ZERO != 0
+    {
+        report.violation(
+            "configuration.attributes",
+            location,
+            format!(
+                "bmAttributes {:#04x} has reserved bits wrong",
+                configuration.attributes
+            ),
+        );
+    }
+
+    let mut interfaces: BTreeMap<u8, Vec<u8>> = BTreeMap::new();
+    for alternate in &configuration.alternates {
+        interfaces
+            .entry(alternate.number)
+            .or_default()
+            .push(alternate.alternate);
+    }
+    if interfaces.len() != configuration.num_interfaces as usize {
+        report.violation(
+            "configuration.numInterfaces",
+            location,
+            format!(
+                "bNumInterfaces is {} but {} interfaces are described",
+                configuration.num_interfaces,
+                interfaces.len()
+            ),
+        );
+    }
+    for (number, alternates) in &interfaces {
+        let location = format!("{location} interface {number}");
+        if *number >= configuration.num_interfaces {
+            report.violation(
+                "interface.number",
+                &location,
+                format!(
+                    "interface number {number} is beyond bNumInterfaces {}",
+                    configuration.num_interfaces
+                ),
+            );
+        }
+        let mut sorted = alternates.clone();
+        sorted.sort_unstable();
+        if sorted
+            .iter()
+            .enumerate()
+            .any(|(i, alternate)| i != *alternate as usize)
+        {
+            report.violation(
+                "interface.alternateSetting",
+                &location,
+                format!("alternate settings {sorted:?} aren't unique and numbered from 0"),
+            );
+        }
+    }
+
+    // Alternate settings of one interface may share an endpoint, different interfaces may not
+    let mut owners: HashMap<u8, u8> = HashMap::new();
+    for alternate in &configuration.alternates {
+        if alternate.endpoints.len() != alternate.num_endpoints as usize {
+            report.violation(
+                "interface.numEndpoints",
+                &alternate.location,
+                format!(
+                    "bNumEndpoints is {} but {} endpoints follow",
+                    alternate.num_endpoints,
+                    alternate.endpoints.len()
+                ),
+            );
+        }
+
+        for (i, endpoint) in alternate.endpoints.iter().enumerate() {
+            let location = endpoint.location(alternate);
+            if endpoint.address & ENDPOINT_NUMBER_MASK == 0
+                || endpoint.address & ENDPOINT_ADDRESS_RESERVED != 0
+            {
+                report.violation(
+                    "endpoint.address",
+                    &location,
+                    "endpoint address is 0 or sets reserved bits",
+                );
+            }
+            if alternate.endpoints[..i]
+                .iter()
+                .any(|other| other.address == endpoint.address)
+            {
+                report.violation(
+                    "endpoint.address",
+                    &location,
+                    "endpoint address appears twice in the alternate setting",
+                );
+            }
+            match owners.insert(endpoint.address, alternate.number) {
+                Some(owner) if owner != alternate.number => report.violation(
+                    "endpoint.address",
+                    &location,
+                    format!("endpoint address is also used by interface {owner}"),
+                ),
+                _ => {}
+            }
+
+            let Some(speed) = speed else {
+                continue;
+            };
+            if let Some(message) = check_max_packet_size(speed, endpoint) {
+                report.violation("endpoint.maxPacketSize", &location, message);
+            }
+            if let Some(message) = check_interval(speed, endpoint) {
+                report.violation("endpoint.interval", &location, message);
+            }
+            if matches!(speed, Speed::Super | Speed::SuperPlus) && !endpoint.companion {
+                report.violation(
+                    "endpoint.companion",
+                    &location,
+                    "SuperSpeed endpoint companion descriptor is missing",
+                );
+            }
+        }
+    }
+}
+
+fn read_configuration(
+    target: &ControlTarget,
+    index: u8,
+    location: &str,
+    report: &mut Report,
+) -> Option<Vec<u8>> {
+    let header = match get_descriptor(
+        target,
+        DESCRIPTOR_CONFIGURATION,
+        index,
+        0,
+        CONFIGURATION_DESCRIPTOR_LEN as u16,
+    ) {
+        Ok(header) => header,
+        Err(e) => {
+            report.violation("configuration.length", location, e);
+            return None;
+        }
+    };
+    if header.len() != CONFIGURATION_DESCRIPTOR_LEN as usize
+        || header[0] != CONFIGURATION_DESCRIPTOR_LEN
+        || header[1] != DESCRIPTOR_CONFIGURATION
+    {
+        report.violation(
+            "configuration.length",
+            location,
+            format!(
+                "configuration descriptor is {} bytes with bLength {}, expected 9",
+                header.len(),
+                header.first().copied().unwrap_or(0)
+            ),
+        );
+        return None;
+    }
+
+    let total_length = u16::from_le_bytes([header[2], header[3]]);
+    if total_length < CONFIGURATION_DESCRIPTOR_LEN as u16 {
+        report.violation(
+            "configuration.totalLength",
+            location,
+            format!("wTotalLength is {total_length}, shorter than the configuration descriptor"),
+        );
+        return None;
+    }
+    match get_descriptor(target, DESCRIPTOR_CONFIGURATION, index, 0, total_length) {
+        Ok(data) if data.len() == total_length as usize => Some(data),
+        Ok(data) => {
+            report.violation(
+                "configuration.totalLength",
+                location,
+                format!(
+                    "wTotalLength is {total_length} but {} bytes were returned",
+                    data.len()
+                ),
+            );
+            None
+        }
+        Err(e) => {
+            report.violation("configuration.totalLength", location, e);
+            None
+        }
+    }
+}
+
+fn check_device_requests(
+    shared: &SharedDevice,
+    active: &Configuration,
+    bcd_usb: u16,
+    report: &mut Report,
+) {
+    let Some(device) = shared.control_target(Recipient::Device, 0) else {
+        report.skip("device requests: no claimed interface to send them through");
+        return;
+    };
+    let usb3 = bcd_usb >= USB3_VERSION;
+
+    match get_configuration(&device) {
+        Ok(value) if value == active.value => {}
+        Ok(value) => report.violation(
+            "request.getConfiguration",
+            "device",
+            format!(
+                "GET_CONFIGURATION returned {value}, the active configuration is {}",
+                active.value
+            ),
+        ),
+        Err(e) => report.violation("request.getConfiguration", "device", e),
+    }
+
+    let defined = if usb3 {
+        USB3_DEVICE_STATUS_BITS
+    } else {
+        USB2_DEVICE_STATUS_BITS
+    };
+    match get_status(&device, Recipient::Device, 0) {
+        Ok(status) if status & !defined != 0 => report.violation(
+            "request.getStatus",
+            "device",
+            format!("device status {status:#06x} sets reserved bits"),
+        ),
+        Ok(_) => {}
+        Err(e) => report.violation("request.getStatus", "device", e),
+    }
+
+    // A device that can wake the host has to let it be turned on and off; one that can't has to
+    // refuse
+    let remote_wakeup = |enable: bool| {
+        if enable {
+            set_feature(&device, Recipient::Device, FEATURE_DEVICE_REMOTE_WAKEUP, 0)
+        } else {
+            clear_feature(&device, Recipient::Device, FEATURE_DEVICE_REMOTE_WAKEUP, 0)
+        }
+    };
+    if active.attributes & CONFIG_ATTRIBUTES_REMOTE_WAKEUP != 0 {
+        for enable in [true, false] {
+            let result =
+                remote_wakeup(enable).and_then(|_| get_status(&device, Recipient::Device, 0));
+            match result {
+                Ok(status) if (status & STATUS_REMOTE_WAKEUP != 0) == enable => {}
+                Ok(_) => report.violation(
+                    "request.setFeature",
+                    "device",
+                    format!(
+                        "remote wakeup status doesn't follow {}",
+                        if enable {
+                            "SET_FEATURE"
+                        } else {
+                            "CLEAR_FEATURE"
+                        }
+                    ),
+                ),
+                Err(e) => report.violation("request.setFeature", "device", e),
+            }
+        }
+    } else if remote_wakeup(true).is_ok() {
+        let _ = remote_wakeup(false);
+        report.violation(
+            "request.setFeature",
+            "device",
+            "remote wakeup was accepted by a configuration that doesn't support it",
+        );
+    }
+}
+
+fn check_interface_requests(
+    shared: &SharedDevice,
+    active: &Configuration,
+    bcd_usb: u16,
+    halt_endpoints: bool,
+    report: &mut Report,
+) {
+    let mut numbers: Vec<u8> = active.alternates.iter().map(|a| a.number).collect();
+    numbers.dedup();
+
+    for number in numbers {
+        let location = format!("interface {number}");
+        // Only a claimed interface is asked, as the OS would otherwise claim it for the request
+        let claimed = shared
+            .get_interface(Recipient::Interface, number as u16)
+            .is_some_and(|interface| interface.interface_number() == number);
+        let target = match shared.control_target(Recipient::Interface, number as u16) {
+            Some(target) if claimed => target,
+            _ => {
+                report.skip(format!("{location}: not claimed"));
+                continue;
+            }
+        };
+
+        let current = match get_interface(&target, number) {
+            Ok(setting) => setting,
+            Err(e) => {
+                report.violation("request.getInterface", &location, e);
+                continue;
+            }
+        };
+        let Some(alternate) = active
+            .alternates
+            .iter()
+            .find(|a| a.number == number && a.alternate == current)
+        else {
+            report.violation(
+                "request.getInterface",
+                &location,
+                format!(
+                    "GET_INTERFACE returned alternate setting {current}, which isn't described"
+                ),
+            );
+            continue;
+        };
+
+        let defined = if bcd_usb >= USB3_VERSION {
+            USB3_INTERFACE_STATUS_BITS
+        } else {
+            0
+        };
+        match get_status(&target, Recipient::Interface, number as u16) {
+            Ok(status) if status & !defined != 0 => report.violation(
+                "request.getStatus",
+                &location,
+                format!("interface status {status:#06x} sets reserved bits"),
+            ),
+            Ok(_) => {}
+            Err(e) => report.violation("request.getStatus", &location, e),
+        }
+
+        for endpoint in &alternate.endpoints {
+            check_endpoint_requests(shared, &target, alternate, endpoint, halt_endpoints, report);
+        }
+    }
+}
+
+fn check_endpoint_requests(
+    shared: &SharedDevice,
+    target: &ControlTarget,
+    alternate: &Alternate,
+    endpoint: &Endpoint,
+    halt_endpoints: bool,
+    report: &mut Report,
+) {
+    let location = endpoint.location(alternate);
+    let address = endpoint.address as u16;
+    let halted = |report: &mut Report| match get_status(target, Recipient::Endpoint, address) {
+        Ok(status) if status & !STATUS_HALT != 0 => {
+            report.violation(
+                "request.getStatus",
+                &location,
+                format!("endpoint status {status:#06x} sets reserved bits"),
+            );
+            Some(status & STATUS_HALT != 0)
+        }
+        Ok(status) => Some(status & STATUS_HALT != 0),
+        Err(e) => {
+            report.violation("request.getStatus", &location, e);
+            None
+        }
+    };
+
+    if halted(report).is_none()
+        || !halt_endpoints
+        || !matches!(endpoint.transfer_type(), TRANSFER_BULK | TRANSFER_INTERRUPT)
+    {
+        return;
+    }
+
+    if let Err(e) = set_feature(target, Recipient::Endpoint, FEATURE_ENDPOINT_HALT, address) {
+        report.violation("request.setFeature", &location, e);
+        return;
+    }
+    if halted(report) == Some(false) {
+        report.violation(
+            "request.setFeature",
+            &location,
+            "endpoint isn't halted after SET_FEATURE(ENDPOINT_HALT)",
+        );
+    }
+
+    if let Err(e) = clear_endpoint_halt(shared, target, endpoint.address) {
+        report.violation("request.clearFeature", &location, e);
+        return;
+    }
+    if halted(report) == Some(true) {
+        report.violation(
+            "request.clearFeature",
+            &location,
+            "endpoint is still halted after CLEAR_FEATURE(ENDPOINT_HALT)",
+        );
+    }
+}
+
+fn check_device(
+    shared: &SharedDevice,
+    speed: Option<Speed>,
+    halt_endpoints: bool,
+) -> std::result::Result<Report, String> {
+    let device = shared.opened_device().ok_or("invalid state")?;
+    let target = shared
+        .control_target(Recipient::Device, 0)
+        .ok_or("no claimed interface to send requests through")?;
+    let mut report = Report {
+        violations: Vec::new(),
+        skipped: Vec::new(),
+    };
+    if speed_name(speed).is_none() {
+        report.skip("speed dependent checks: speed is unknown");
+    }
+
+    let descriptor = get_descriptor(
+        &target,
+        DESCRIPTOR_DEVICE,
+        0,
+        0,
+        DEVICE_DESCRIPTOR_LEN as u16,
+    )?;
+    let Some(bcd_usb) = check_device_descriptor(&descriptor, speed, &mut report) else {
+        return Ok(report);
+    };
+    check_strings(&target, &descriptor, &mut report);
+    if bcd_usb >= USB_LPM_VERSION {
+        check_bos(&target, &mut report);
+    }
+
+    let active_value = device
+        .active_configuration()
+        .ok()
+        .map(|config| config.configuration_value());
+    let mut active = None;
+    let mut values = Vec::new();
+    for index in 0..descriptor[17] {
+        let location = format!("configuration index {index}");
+        let Some(data) = read_configuration(&target, index, &location, &mut report) else {
+            continue;
+        };
+        let location = format!("configuration {}", data[5]);
+        if values.contains(&data[5]) {
+            report.violation(
+                "configuration.value",
+                &location,
+                "bConfigurationValue is used by another configuration",
+            );
+        }
+        values.push(data[5]);
+        let Some(configuration) = parse_configuration(&data, &location, &mut report) else {
+            continue;
+        };
+        check_configuration(&configuration, speed, &location, &mut report);
+        if Some(configuration.value) == active_value {
+            active = Some(configuration);
+        }
+    }
+
+    match active {
+        Some(active) => {
+            check_device_requests(shared, &active, bcd_usb, &mut report);
+            check_interface_requests(shared, &active, bcd_usb, halt_endpoints, &mut report);
+        }
+        None => report.skip("standard requests: the device isn't configured"),
+    }
+    Ok(report)
+}
+
+#[napi]
+impl UsbDevice {
+    /// Checks the device's descriptors and standard requests against chapter 9 of the USB
+    /// specification, reporting every violation found.
+    #[napi]
+    pub async fn checkChapter9(&self, options: Option<Chapter9Options>) -> Result<Chapter9Report> {
+        let halt_endpoints = options.and_then(|o| o.haltEndpoints).unwrap_or(false);
+        let speed = self.device_info().speed();
+        let shared = self.shared().clone();
+        let report = run_blocking(move || {
+            check_device(&shared, speed, halt_endpoints)
+                .map_err(|e| format!("checkChapter9 error: {e}"))
+        })
+        .await?;
+        Ok(Chapter9Report {
+            passed: report.violations.is_empty(),
+            speed: speed_name(speed),
+            violations: report.violations,
+            skipped: report.skipped,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report() -> Report {
+        Report {
+            violations: Vec::new(),
+            skipped: Vec::new(),
+        }
+    }
+
+    fn checks(report: &Report) -> Vec<&str> {
+        report
+            .violations
+            .iter()
+            .map(|violation| violation.check.as_str())
+            .collect()
+    }
+
+    fn endpoint(attributes: u8, max_packet_size: u16, interval: u8) -> Endpoint {
+        Endpoint {
+            address: 0x81,
+            attributes,
+            max_packet_size,
+            interval,
+            companion: false,
+        }
+    }
+
+    // One interface with a bulk IN and an interrupt OUT endpoint
+    const CONFIGURATION: [u8; 32] = [
+        0x09, 0x02, 0x20, 0x00, 0x01, 0x01, 0x00, 0x80, 0x32, //
+        0x09, 0x04, 0x00, 0x00, 0x02, 0xff, 0x00, 0x00, 0x00, //
+        0x07, 0x05, 0x81, 0x02, 0x00, 0x02, 0x00, //
+        0x07, 0x05, 0x02, 0x03, 0x40, 0x00, 0x04,
+    ];
+
+    const DEVICE: [u8; 18] = [
+        0x12, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x40, 0x34, 0x12, 0x78, 0x56, 0x00, 0x01, 0x01,
+        0x02, 0x03, 0x01,
+    ];
+
+    #[test]
+    fn parses_a_configuration() {
+        let mut report = report();
+        let configuration = parse_configuration(&CONFIGURATION, "configuration 1", &mut report)
+            .expect("configuration");
+        assert_eq!(configuration.value, 1);
+        assert_eq!(configuration.num_interfaces, 1);
+        assert_eq!(configuration.alternates.len(), 1);
+        let endpoints = &configuration.alternates[0].endpoints;
+        assert_eq!(endpoints.len(), 2);
+        assert_eq!(endpoints[0].address, 0x81);
+        assert_eq!(endpoints[0].max_packet_size, 512);
+        assert_eq!(endpoints[1].transfer_type(), TRANSFER_INTERRUPT);
+        assert_eq!(endpoints[1].interval, 4);
+
+        check_configuration(
+            &configuration,
+            Some(Speed::High),
+            "configuration 1",
+            &mut report,
+        );
+        assert!(report.violations.is_empty(), "{:?}", checks(&report));
+    }
+
+    #[test]
+    fn reports_a_truncated_configuration() {
+        for len in 0..CONFIGURATION_DESCRIPTOR_LEN as usize {
+            let mut report = report();
+            let configuration =
+                parse_configuration(&CONFIGURATION[..len], "configuration 1", &mut report);
+            assert!(configuration.is_none());
+            assert_eq!(checks(&report), ["configuration.length"]);
+        }
+    }
+
+    #[test]
+    fn reports_a_descriptor_overrunning_the_configuration() {
+        let mut report = report();
+        let configuration =
+            parse_configuration(&CONFIGURATION[..30], "configuration 1", &mut report)
+                .expect("configuration");
+        assert_eq!(checks(&report), ["descriptor.length"]);
+        assert_eq!(configuration.alternates[0].endpoints.len(), 1);
+
+        check_configuration(&configuration, None, "configuration 1", &mut report);
+        assert_eq!(
+            checks(&report),
+            ["descriptor.length", "interface.numEndpoints"]
+        );
+    }
+
+    #[test]
+    fn checks_the_device_descriptor() {
+        let mut report = report();
+        assert_eq!(
+            check_device_descriptor(&DEVICE, Some(Speed::High), &mut report),
+            Some(0x0200)
+        );
+        assert!(report.violations.is_empty());
+
+        let mut device = DEVICE;
+        device[7] = 8;
+        device[17] = 0;
+        check_device_descriptor(&device, Some(Speed::High), &mut report);
+        assert_eq!(
+            checks(&report),
+            ["device.maxPacketSize0", "device.numConfigurations"]
+        );
+
+        let mut report = self::report();
+        check_device_descriptor(&DEVICE, Some(Speed::Super), &mut report);
+        assert_eq!(checks(&report), ["device.bcdUSB", "device.maxPacketSize0"]);
+    }
+
+    #[test]
+    fn reports_a_truncated_device_descriptor() {
+        let mut report = report();
+        assert_eq!(
+            check_device_descriptor(&DEVICE[..8], Some(Speed::High), &mut report),
+            None
+        );
+        assert_eq!(checks(&report), ["device.length"]);
+    }
+
+    #[test]
+    fn checks_max_packet_sizes() {
+        assert_eq!(
+            check_max_packet_size(Speed::High, &endpoint(TRANSFER_BULK, 512, 0)),
+            None
+        );
+        assert!(check_max_packet_size(Speed::High, &endpoint(TRANSFER_BULK, 64, 0)).is_some());
+        assert_eq!(
+            check_max_packet_size(Speed::Full, &endpoint(TRANSFER_BULK, 64, 0)),
+            None
+        );
+        assert!(check_max_packet_size(Speed::Low, &endpoint(TRANSFER_BULK, 8, 0)).is_some());
+        // Two additional transactions need more than 682 bytes each
+        assert_eq!(
+            check_max_packet_size(Speed::High, &endpoint(TRANSFER_ISOCHRONOUS, 0x1400, 1)),
+            None
+        );
+        assert!(
+            check_max_packet_size(Speed::High, &endpoint(TRANSFER_ISOCHRONOUS, 0x1200, 1))
+                .is_some()
+        );
+        assert!(
+            check_max_packet_size(Speed::Full, &endpoint(TRANSFER_INTERRUPT, 0x0840, 1)).is_some()
+        );
+        assert!(check_max_packet_size(Speed::High, &endpoint(TRANSFER_BULK, 0x2200, 0)).is_some());
+    }
+
+    #[test]
+    fn checks_intervals() {
+        assert_eq!(
+            check_interval(Speed::Full, &endpoint(TRANSFER_INTERRUPT, 64, 255)),
+            None
+        );
+        assert!(check_interval(Speed::High, &endpoint(TRANSFER_INTERRUPT, 64, 17)).is_some());
+        assert!(check_interval(Speed::Full, &endpoint(TRANSFER_ISOCHRONOUS, 64, 0)).is_some());
+        assert_eq!(
+            check_interval(Speed::High, &endpoint(TRANSFER_BULK, 512, 0)),
+            None
+        );
+    }
+}
@@ -0,0 +1,845 @@
+use crate::transfer_pool::{pooled_transfer_in, pooled_transfer_out, PooledEndpoint};
+use crate::webusb_device::{run_blocking, SharedDevice, UsbDevice};
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use nusb::{
+    descriptors::{language_id::US_ENGLISH, ConfigurationDescriptor, TransferType},
+    transfer::{ControlOut, ControlType, Direction, In, Out, Recipient},
+    MaybeFuture,
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+const CLASS_HID: u8 = 0x03;
+const HID_SET_REPORT: u8 = 0x09;
+const HID_REPORT_TYPE_OUTPUT: u16 = 0x02;
+
+/// Interface (v2) or product (v1) strings of CMSIS-DAP probes contain this
+const DAP_NAME: &str = "CMSIS-DAP";
+
+const DAP_INFO: u8 = 0x00;
+const DAP_CONNECT: u8 = 0x02;
+const DAP_DISCONNECT: u8 = 0x03;
+const DAP_TRANSFER_CONFIGURE: u8 = 0x04;
+const DAP_TRANSFER: u8 = 0x05;
+const DAP_TRANSFER_BLOCK: u8 = 0x06;
+const DAP_SWJ_PINS: u8 = 0x10;
+const DAP_SWJ_CLOCK: u8 = 0x11;
+const DAP_SWJ_SEQUENCE: u8 = 0x12;
+const DAP_SWD_CONFIGURE: u8 = 0x13;
+/// Sent back in place of the command ID for commands the probe doesn't implement
+const DAP_INVALID: u8 = 0xff;
+
+const INFO_PROTOCOL_VERSION: u8 = 0x04;
+const INFO_CAPABILITIES: u8 = 0xf0;
+const INFO_PACKET_COUNT: u8 = 0xfe;
+const INFO_PACKET_SIZE: u8 = 0xff;
+
+const DAP_OK: u8 = 0x00;
+
+const TRANSFER_AP: u8 = 0x01;
+const TRANSFER_READ: u8 = 0x02;
+const TRANSFER_ACK_OK: u8 = 0x01;
+
+const CONTROL_TIMEOUT: Duration = Duration::from_millis(1000);
+const COMMAND_TIMEOUT: Duration = Duration::from_millis(5000);
+/// For reading back responses still queued in the probe after a failure
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Most bits per DAP_SWJ_Sequence, longer sequences are split.
+const MAX_SEQUENCE_BITS: usize = 256;
+
+#[napi(object)]
+pub struct CmsisDapOptions {
+    /// The DAP interface to use, defaults to the first found (v2 ahead of v1)
+    pub interfaceNumber: Option<u8>,
+    /// Detach kernel drivers (e.g. `usbhid` for v1 probes) while the interface is open, defaults to `true`
+    pub detachKernelDriver: Option<bool>,
+}
+
+/// One register access in a DAP_Transfer.
+#[napi(object)]
+pub struct DapTransferRequest {
+    /// Access an AP register rather than a DP one, defaults to `false`
+    pub ap: Option<bool>,
+    /// Register address: `0x0`, `0x4`, `0x8` or `0xC`
+    pub register: u8,
+    /// Value to write, the register is read when left out
+    pub value: Option<u32>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum DapVersion {
+    /// HID reports over interrupt endpoints, or SET_REPORT without an OUT endpoint
+    V1,
+    /// Bulk endpoints on a vendor interface
+    V2,
+}
+
+struct DapLayout {
+    interface: u8,
+    version: DapVersion,
+    ep_in: u8,
+    ep_out: Option<u8>,
+    max_packet_size: usize,
+}
+
+fn find_dap_layouts(device: &nusb::Device, config: &ConfigurationDescriptor) -> Vec<DapLayout> {
+    let string = |index: Option<std::num::NonZeroU8>| {
+        index.and_then(|index| {
+            device
+                .get_string_descriptor(index, US_ENGLISH, CONTROL_TIMEOUT)
+                .wait()
+                .ok()
+        })
+    };
+    let product_is_dap = string(device.device_descriptor().product_string_index())
+        .is_some_and(|name| name.contains(DAP_NAME));
+
+    let mut found = Vec::new();
+    for alt in config
+        .interface_alt_settings()
+        .filter(|alt| alt.alternate_setting() == 0)
+    {
+        let interface_is_dap =
+            string(alt.string_index()).is_some_and(|name| name.contains(DAP_NAME));
+        let endpoint = |direction, transfer_type| {
+            alt.endpoints()
+                .find(|ep| ep.direction() == direction && ep.transfer_type() == transfer_type)
+        };
+
+        if alt.class() == CLASS_HID {
+            if !(interface_is_dap || product_is_dap) {
+                continue;
+            }
+            let Some(ep_in) = endpoint(Direction::In, TransferType::Interrupt) else {
+                continue;
+            };
+            found.push(DapLayout {
+                interface: alt.interface_number(),
+                version: DapVersion::V1,
+                ep_in: ep_in.address() & 0x7f,
+                ep_out: endpoint(Direction::Out, TransferType::Interrupt)
+                    .map(|ep| ep.address() & 0x7f),
+                max_packet_size: ep_in.max_packet_size(),
+            });
+        } else if interface_is_dap {
+            // The first bulk OUT and IN carry commands, a third (SWO) endpoint is ignored
+            let (Some(ep_out), Some(ep_in)) = (
+                endpoint(Direction::Out, TransferType::Bulk),
+                endpoint(Direction::In, TransferType::Bulk),
+            ) else {
+                continue;
+            };
+            found.push(DapLayout {
+                interface: alt.interface_number(),
+                version: DapVersion::V2,
+                ep_in: ep_in.address() & 0x7f,
+                ep_out: Some(ep_out.address() & 0x7f),
+                max_packet_size: ep_in.max_packet_size(),
+            });
+        }
+    }
+    found.sort_by_key(|layout| layout.version == DapVersion::V1);
+    found
+}
+
+fn ack_error(ack: u8) -> String {
+    if ack & 0x08 != 0 {
+        return "SWD protocol error".to_string();
+    }
+    if ack & 0x10 != 0 {
+        return "value mismatch".to_string();
+    }
+    match ack & 0x07 {
+        0x02 => "WAIT".to_string(),
+        0x04 => "FAULT".to_string(),
+        0x07 => "no ACK".to_string(),
+        ack => format!("ACK {ack}"),
+    }
+}
+
+fn check_status(response: &[u8], name: &str) -> std::result::Result<(), String> {
+    match response.get(1) {
+        Some(&DAP_OK) => Ok(()),
+        _ => Err(format!("{name} failed")),
+    }
+}
+
+fn transfer_request(ap: bool, register: u8, read: bool) -> u8 {
+    let mut request = register & 0x0c;
+    if ap {
+        request |= TRANSFER_AP;
+    }
+    if read {
+        request |= TRANSFER_READ;
+    }
+    request
+}
+
+/// Commands and responses are exchanged as whole packets. Up to `packet_count` commands are sent
+/// ahead of reading their responses, which the probe returns in order.
+struct DapTransport {
+    shared: Arc<SharedDevice>,
+    layout: DapLayout,
+    packet_size: usize,
+    packet_count: usize,
+}
+
+impl DapTransport {
+    /// Runs a batch of commands, pipelined, and returns their responses.
+    fn exchange(&self, commands: &[Vec<u8>]) -> std::result::Result<Vec<Vec<u8>>, String> {
+        if commands.iter().any(|c| c.is_empty()) {
+            return Err("empty command".to_string());
+        }
+        if let Some(command) = commands.iter().find(|c| c.len() > self.packet_size) {
+            return Err(format!(
+                "command of {} bytes doesn't fit a {} byte packet",
+                command.len(),
+                self.packet_size
+            ));
+        }
+
+        let mut ep_in = self
+            .shared
+            .get_endpoint::<In>(self.layout.ep_in)
+            .ok_or("invalid state")?;
+        let mut ep_out = match self.layout.ep_out {
+            Some(number) => Some(
+                self.shared
+                    .get_endpoint::<Out>(number)
+                    .ok_or("invalid state")?,
+            ),
+            None => None,
+        };
+
+        let mut outstanding = 0;
+        let result = self.pipeline(&mut ep_in, ep_out.as_mut(), commands, &mut outstanding);
+        if result.is_err() {
+            // Keep later commands from picking up responses meant for this batch
+            for _ in 0..outstanding {
+                if self.receive(&mut ep_in, DRAIN_TIMEOUT).is_err() {
+                    break;
+                }
+            }
+        }
+
+        self.shared.put_endpoint(ep_in);
+        if let Some(ep_out) = ep_out {
+            self.shared.put_endpoint(ep_out);
+        }
+        result
+    }
+
+    fn pipeline(
+        &self,
+        ep_in: &mut PooledEndpoint<In>,
+        mut ep_out: Option<&mut PooledEndpoint<Out>>,
+        commands: &[Vec<u8>],
+        outstanding: &mut usize,
+    ) -> std::result::Result<Vec<Vec<u8>>, String> {
+        let mut responses = Vec::with_capacity(commands.len());
+        let mut sent = 0;
+        while responses.len() < commands.len() {
+            while sent < commands.len() && *outstanding < self.packet_count {
+                self.send(ep_out.as_deref_mut(), &commands[sent])?;
+                sent += 1;
+                *outstanding += 1;
+            }
+
+            let response = self.receive(ep_in, COMMAND_TIMEOUT)?;
+            *outstanding -= 1;
+            let command = commands[responses.len()][0];
+            match response.first() {
+                Some(&id) if id == command => responses.push(response),
+                Some(&DAP_INVALID) => return Err(format!("command {command:#04x} unsupported")),
+                _ => return Err(format!("unexpected response to command {command:#04x}")),
+            }
+        }
+        Ok(responses)
+    }
+
+    fn send(
+        &self,
+        ep_out: Option<&mut PooledEndpoint<Out>>,
+        command: &[u8],
+    ) -> std::result::Result<(), String> {
+        // HID reports are always full size
+        let mut packet = command.to_vec();
+        if self.layout.version == DapVersion::V1 {
+            packet.resize(self.packet_size, 0);
+        }
+
+        match ep_out {
+            Some(ep_out) => pooled_transfer_out(&self.shared, ep_out, &packet, COMMAND_TIMEOUT)
+                .map(|_| ())
+                .map_err(|e| format!("command failed: {e:?}")),
+            None => {
+                let target = self
+                    .shared
+                    .control_target(Recipient::Interface, self.layout.interface as u16)
+                    .ok_or("invalid state")?;
+                target
+                    .control_out_blocking(
+                        ControlOut {
+                            control_type: ControlType::Class,
+                            recipient: Recipient::Interface,
+                            request: HID_SET_REPORT,
+                            value: HID_REPORT_TYPE_OUTPUT << 8,
+                            index: self.layout.interface as u16,
+                            data: &packet,
+                        },
+                        COMMAND_TIMEOUT,
+                    )
+                    .map_err(|e| format!("command failed: {e}"))
+            }
+        }
+    }
+
+    fn receive(
+        &self,
+        ep_in: &mut PooledEndpoint<In>,
+        timeout: Duration,
+    ) -> std::result::Result<Vec<u8>, String> {
+        let (buffer, len) = pooled_transfer_in(&self.shared, ep_in, self.packet_size, timeout)
+            .map_err(|e| format!("response failed: {e:?}"))?;
+        let response = buffer[..len].to_vec();
+        self.shared.put_buffer(ep_in, buffer);
+        Ok(response)
+    }
+
+    fn command(&self, command: Vec<u8>) -> std::result::Result<Vec<u8>, String> {
+        Ok(self.exchange(&[command])?.remove(0))
+    }
+
+    /// DAP_Info, with `None` for items the probe doesn't report.
+    fn info(&self, id: u8) -> std::result::Result<Option<Vec<u8>>, String> {
+        let response = self.command(vec![DAP_INFO, id])?;
+        let len = *response.get(1).ok_or("short DAP_Info response")? as usize;
+        if len == 0 {
+            return Ok(None);
+        }
+        response
+            .get(2..2 + len)
+            .map(|data| Some(data.to_vec()))
+            .ok_or_else(|| "short DAP_Info response".to_string())
+    }
+
+    fn transfer(
+        &self,
+        dap_index: u8,
+        requests: &[(u8, Option<u32>)],
+    ) -> std::result::Result<Vec<u32>, String> {
+        // Split so each command and its response fits a packet
+        let mut commands = Vec::new();
+        let mut counts = Vec::new();
+        let mut start = 0;
+        while start < requests.len() {
+            let mut command = vec![DAP_TRANSFER, dap_index, 0];
+            let mut response_len = 3;
+            let mut count = 0;
+            for (request, value) in &requests[start..] {
+                let command_len = command.len() + 1 + value.map_or(0, |_| 4);
+                let reply_len = response_len + value.map_or(4, |_| 0);
+                if count == u8::MAX as usize
+                    || command_len > self.packet_size
+                    || reply_len > self.packet_size
+                {
+                    break;
+                }
+                command.push(*request);
+                if let Some(value) = value {
+                    command.extend_from_slice(&value.to_le_bytes());
+                }
+                response_len = reply_len;
+                count += 1;
+            }
+            if count == 0 {
+                return Err("packet size too small for DAP_Transfer".to_string());
+            }
+            command[2] = count as u8;
+            commands.push(command);
+            counts.push(count);
+            start += count;
+        }
+
+        let mut values = Vec::new();
+        let mut done = 0;
+        for (response, count) in self.exchange(&commands)?.into_iter().zip(counts) {
+            let [_, executed, ack, data @ ..] = response.as_slice() else {
+                return Err("short DAP_Transfer response".to_string());
+            };
+            if *ack != TRANSFER_ACK_OK || *executed as usize != count {
+                return Err(format!(
+                    "transfer {} failed: {}",
+                    done + *executed as usize,
+                    ack_error(*ack)
+                ));
+            }
+            let reads = requests[done..done + count]
+                .iter()
+                .filter(|(_, value)| value.is_none())
+                .count();
+            if data.len() < reads * 4 {
+                return Err("short DAP_Transfer response".to_string());
+            }
+            values.extend(
+                data[..reads * 4]
+                    .chunks_exact(4)
+                    .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]])),
+            );
+            done += count;
+        }
+        Ok(values)
+    }
+
+    /// DAP_TransferBlock of `count` reads, or of writing `data`, split into packets.
+    fn transfer_block(
+        &self,
+        dap_index: u8,
+        request: u8,
+        count: usize,
+        data: Option<&[u32]>,
+    ) -> std::result::Result<Vec<u32>, String> {
+        let header = 5;
+        let per_packet = match data {
+            Some(_) => (self.packet_size - header) / 4,
+            None => (self.packet_size - 4) / 4,
+        }
+        .min(u16::MAX as usize);
+        if per_packet == 0 {
+            return Err("packet size too small for DAP_TransferBlock".to_string());
+        }
+
+        let mut commands = Vec::new();
+        let mut counts = Vec::new();
+        let mut start = 0;
+        while start < count {
+            let chunk = per_packet.min(count - start);
+            let mut command = vec![DAP_TRANSFER_BLOCK, dap_index];
+            command.extend_from_slice(&(chunk as u16).to_le_bytes());
+            command.push(request);
+            if let Some(data) = data {
+                for word in &data[start..start + chunk] {
+                    command.extend_from_slice(&word.to_le_bytes());
+                }
+            }
+            commands.push(command);
+            counts.push(chunk);
+            start += chunk;
+        }
+
+        let mut values = Vec::new();
+        let mut done = 0;
+        for (response, chunk) in self.exchange(&commands)?.into_iter().zip(counts) {
+            let [_, lo, hi, ack, words @ ..] = response.as_slice() else {
+                return Err("short DAP_TransferBlock response".to_string());
+            };
+            let executed = u16::from_le_bytes([*lo, *hi]) as usize;
+            if *ack != TRANSFER_ACK_OK || executed != chunk {
+                return Err(format!(
+                    "transfer {} failed: {}",
+                    done + executed,
+                    ack_error(*ack)
+                ));
+            }
+            if data.is_none() {
+                if words.len() < chunk * 4 {
+                    return Err("short DAP_TransferBlock response".to_string());
+                }
+                values.extend(
+                    words[..chunk * 4]
+                        .chunks_exact(4)
+                        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]])),
+                );
+            }
+            done += chunk;
+        }
+        Ok(values)
+    }
+
+    fn swj_sequence(&self, bit_count: usize, data: &[u8]) -> std::result::Result<(), String> {
+        if data.len() < bit_count.div_ceil(8) {
+            return Err("not enough data for the bit count".to_string());
+        }
+        let commands = (0..bit_count)
+            .step_by(MAX_SEQUENCE_BITS)
+            .map(|start| {
+                let bits = MAX_SEQUENCE_BITS.min(bit_count - start);
+                let bytes = &data[start / 8..start / 8 + bits.div_ceil(8)];
+                // A count of 0 means 256
+                let mut command = vec![DAP_SWJ_SEQUENCE, bits as u8];
+                command.extend_from_slice(bytes);
+                command
+            })
+            .collect::<Vec<_>>();
+        for response in self.exchange(&commands)? {
+            check_status(&response, "DAP_SWJ_Sequence")?;
+        }
+        Ok(())
+    }
+}
+
+/// A CMSIS-DAP debug probe, driven with DAP commands rather than through a debugger.
+#[napi]
+pub struct CmsisDapProbe {
+    shared: Arc<SharedDevice>,
+    transport: Arc<Mutex<DapTransport>>,
+
+    #[napi(writable = false)]
+    pub interfaceNumber: u8,
+    /// `v1` for HID probes, `v2` for bulk ones
+    #[napi(writable = false, ts_type = "'v1' | 'v2'")]
+    pub version: String,
+    /// Largest command or response, from DAP_Info
+    #[napi(writable = false)]
+    pub packetSize: u32,
+    /// Commands the probe buffers, and so how many are sent ahead of their responses
+    #[napi(writable = false)]
+    pub packetCount: u32,
+    /// DAP_Info capabilities bits (`0x01` SWD, `0x02` JTAG, `0x04` SWO UART and so on)
+    #[napi(writable = false)]
+    pub capabilities: u32,
+    /// CMSIS-DAP protocol version the firmware implements, if it says
+    #[napi(writable = false)]
+    pub protocolVersion: Option<String>,
+}
+
+impl CmsisDapProbe {
+    async fn run<T, F>(&self, method: &'static str, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&DapTransport) -> std::result::Result<T, String> + Send + 'static,
+    {
+        let transport = self.transport.clone();
+        run_blocking(move || {
+            let transport = transport
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            f(&transport).map_err(|e| format!("{method} error: {e}"))
+        })
+        .await
+    }
+}
+
+#[napi]
+impl CmsisDapProbe {
+    /// Sends raw commands, each starting with its command ID, and resolves with their responses.
+    /// Commands are pipelined up to `packetCount` at a time.
+    #[napi]
+    pub async fn commands(&self, commands: Vec<Uint8Array>) -> Result<Vec<Uint8Array>> {
+        let commands = commands.iter().map(|c| c.to_vec()).collect::<Vec<_>>();
+        let responses = self
+            .run("commands", move |transport| transport.exchange(&commands))
+            .await?;
+        Ok(responses.into_iter().map(Uint8Array::new).collect())
+    }
+
+    /// DAP_Info for the given ID, or `undefined` if the probe doesn't report it.
+    #[napi]
+    pub async fn info(&self, id: u8) -> Result<Option<Uint8Array>> {
+        let info = self
+            .run("info", move |transport| transport.info(id))
+            .await?;
+        Ok(info.map(Uint8Array::new))
+    }
+
+    /// DAP_Connect, resolving with the port connected.
+    #[napi(ts_return_type = "Promise<'swd' | 'jtag'>")]
+    pub async fn connect(
+        &self,
+        #[napi(ts_arg_type = "'swd' | 'jtag'")] port: Option<String>,
+    ) -> Result<String> {
+        let port = match port.as_deref() {
+            None => 0,
+            Some("swd") => 1,
+            Some("jtag") => 2,
+            Some(port) => {
+                return Err(napi::Error::from_reason(format!(
+                    "connect error: invalid port {port}"
+                )))
+            }
+        };
+        self.run("connect", move |transport| {
+            let response = transport.command(vec![DAP_CONNECT, port])?;
+            match response.get(1) {
+                Some(1) => Ok("swd".to_string()),
+                Some(2) => Ok("jtag".to_string()),
+                _ => Err("probe refused to connect".to_string()),
+            }
+        })
+        .await
+    }
+
+    #[napi]
+    pub async fn disconnect(&self) -> Result<()> {
+        self.run("disconnect", |transport| {
+            check_status(&transport.command(vec![DAP_DISCONNECT])?, "DAP_Disconnect")
+        })
+        .await
+    }
+
+    /// DAP_TransferConfigure: idle cycles after each transfer, and retries on WAIT and value mismatch.
+    #[napi]
+    pub async fn transferConfigure(
+        &self,
+        idleCycles: u8,
+        waitRetry: u16,
+        matchRetry: u16,
+    ) -> Result<()> {
+        self.run("transferConfigure", move |transport| {
+            let mut command = vec![DAP_TRANSFER_CONFIGURE, idleCycles];
+            command.extend_from_slice(&waitRetry.to_le_bytes());
+            command.extend_from_slice(&matchRetry.to_le_bytes());
+            check_status(&transport.command(command)?, "DAP_TransferConfigure")
+        })
+        .await
+    }
+
+    /// DAP_SWD_Configure: turnaround period (1 to 4 cycles) and whether to always generate a data phase.
+    #[napi]
+    pub async fn swdConfigure(&self, turnaround: u8, dataPhase: bool) -> Result<()> {
+        if !(1..=4).contains(&turnaround) {
+            return Err(napi::Error::from_reason(
+                "swdConfigure error: invalid turnaround",
+            ));
+        }
+        self.run("swdConfigure", move |transport| {
+            let config = (turnaround - 1) | ((dataPhase as u8) << 2);
+            check_status(
+                &transport.command(vec![DAP_SWD_CONFIGURE, config])?,
+                "DAP_SWD_Configure",
+            )
+        })
+        .await
+    }
+
+    /// DAP_SWJ_Clock, in Hz.
+    #[napi]
+    pub async fn swjClock(&self, clock: u32) -> Result<()> {
+        self.run("swjClock", move |transport| {
+            let mut command = vec![DAP_SWJ_CLOCK];
+            command.extend_from_slice(&clock.to_le_bytes());
+            check_status(&transport.command(command)?, "DAP_SWJ_Clock")
+        })
+        .await
+    }
+
+    /// DAP_SWJ_Pins: drives the `select`ed pins to `output`, waiting up to `wait` µs for them to settle,
+    /// and resolves with the pins read back.
+    #[napi]
+    pub async fn swjPins(&self, output: u8, select: u8, wait: u32) -> Result<u8> {
+        self.run("swjPins", move |transport| {
+            let mut command = vec![DAP_SWJ_PINS, output, select];
+            command.extend_from_slice(&wait.to_le_bytes());
+            let response = transport.command(command)?;
+            response
+                .get(1)
+                .copied()
+                .ok_or_else(|| "short DAP_SWJ_Pins response".to_string())
+        })
+        .await
+    }
+
+    /// DAP_SWJ_Sequence of `bitCount` bits from `data`, LSB first. Longer sequences are split.
+    #[napi]
+    pub async fn swjSequence(&self, bitCount: u32, data: Uint8Array) -> Result<()> {
+        let data = data.to_vec();
+        self.run("swjSequence", move |transport| {
+            transport.swj_sequence(bitCount as usize, &data)
+        })
+        .await
+    }
+
+    /// DAP_Transfer of a list of DP/AP register accesses, resolving with the values read in order.
+    /// Long lists are split into packets and pipelined, and the first failed access rejects.
+    #[napi]
+    pub async fn transfer(
+        &self,
+        requests: Vec<DapTransferRequest>,
+        dapIndex: Option<u8>,
+    ) -> Result<Uint32Array> {
+        let requests = requests
+            .iter()
+            .map(|r| {
+                (
+                    transfer_request(r.ap.unwrap_or(false), r.register, r.value.is_none()),
+                    r.value,
+                )
+            })
+            .collect::<Vec<_>>();
+        let values = self
+            .run("transfer", move |transport| {
+                transport.transfer(dapIndex.unwrap_or(0), &requests)
+            })
+            .await?;
+        Ok(Uint32Array::new(values))
+    }
+
+    /// DAP_TransferBlock reading one register `count` times.
+    #[napi]
+    pub async fn transferBlockRead(
+        &self,
+        ap: bool,
+        register: u8,
+        count: u32,
+        dapIndex: Option<u8>,
+    ) -> Result<Uint32Array> {
+        let values = self
+            .run("transferBlockRead", move |transport| {
+                transport.transfer_block(
+                    dapIndex.unwrap_or(0),
+                    transfer_request(ap, register, true),
+                    count as usize,
+                    None,
+                )
+            })
+            .await?;
+        Ok(Uint32Array::new(values))
+    }
+
+    /// DAP_TransferBlock writing `data` to one register.
+    #[napi]
+    pub async fn transferBlockWrite(
+        &self,
+        ap: bool,
+        register: u8,
+        data: Uint32Array,
+        dapIndex: Option<u8>,
+    ) -> Result<()> {
+        let data = data.to_vec();
+        self.run("transferBlockWrite", move |transport| {
+            transport
+                .transfer_block(
+                    dapIndex.unwrap_or(0),
+                    transfer_request(ap, register, false),
+                    data.len(),
+                    Some(&data),
+                )
+                .map(|_| ())
+        })
+        .await
+    }
+
+    /// Releases the interface, reattaching any kernel driver.
+    #[napi]
+    pub async fn close(&self) -> Result<()> {
+        self.shared
+            .release_interface("close", self.interfaceNumber)
+            .await
+    }
+}
+
+#[napi]
+impl UsbDevice {
+    /// Claims the CMSIS-DAP interface of a debug probe and reads its packet size and count.
+    #[napi]
+    pub async fn openCmsisDap(&self, options: Option<CmsisDapOptions>) -> Result<CmsisDapProbe> {
+        let interface_number = options.as_ref().and_then(|o| o.interfaceNumber);
+        let detach = options
+            .as_ref()
+            .and_then(|o| o.detachKernelDriver)
+            .unwrap_or(true);
+
+        let shared = self.shared().clone();
+        let device = shared
+            .opened_device()
+            .ok_or_else(|| napi::Error::from_reason("openCmsisDap error: invalid state"))?;
+        let layout = run_blocking(move || {
+            let config = device
+                .active_configuration()
+                .map_err(|e| format!("openCmsisDap error: {e}"))?;
+            find_dap_layouts(&device, &config)
+                .into_iter()
+                .find(|layout| interface_number.is_none_or(|n| layout.interface == n))
+                .ok_or_else(|| "openCmsisDap error: no CMSIS-DAP interface found".to_string())
+        })
+        .await?;
+
+        let interface = layout.interface;
+        let version = layout.version;
+        shared
+            .claim_interface("openCmsisDap", interface, detach)
+            .await?;
+
+        let mut transport = DapTransport {
+            shared: shared.clone(),
+            packet_size: layout.max_packet_size,
+            packet_count: 1,
+            layout,
+        };
+        let (transport, capabilities, protocol_version) = run_blocking(move || {
+            let info = |transport: &DapTransport, id| {
+                transport
+                    .info(id)
+                    .map_err(|e| format!("openCmsisDap error: {e}"))
+            };
+            if let Some(size) = info(&transport, INFO_PACKET_SIZE)? {
+                if let [lo, hi] = size[..] {
+                    transport.packet_size = u16::from_le_bytes([lo, hi]) as usize;
+                }
+            }
+            if let Some(count) = info(&transport, INFO_PACKET_COUNT)? {
+                transport.packet_count = count.first().copied().unwrap_or(1).max(1) as usize;
+            }
+            let capabilities = info(&transport, INFO_CAPABILITIES)?
+                .and_then(|caps| caps.first().copied())
+                .unwrap_or(0);
+            let protocol_version = info(&transport, INFO_PROTOCOL_VERSION)?.map(|version| {
+                String::from_utf8_lossy(&version)
+                    .trim_end_matches('\0')
+                    .to_string()
+            });
+            Ok((transport, capabilities, protocol_version))
+        })
+        .await?;
+
+        Ok(CmsisDapProbe {
+            shared,
+            interfaceNumber: interface,
+            version: match version {
+                DapVersion::V1 => "v1",
+                DapVersion::V2 => "v2",
+            }
+            .to_string(),
+            packetSize: transport.packet_size as u32,
+            packetCount: transport.packet_count as u32,
+            capabilities: capabilities as u32,
+            protocolVersion: protocol_version,
+            transport: Arc::new(Mutex::new(transport)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transfer_requests_encode_port_and_direction() {
+        // Read DP IDCODE, write AP CSW, read AP DRW
+        assert_eq!(transfer_request(false, 0x00, true), 0x02);
+        assert_eq!(transfer_request(true, 0x00, false), 0x01);
+        assert_eq!(transfer_request(true, 0x0c, true), 0x0f);
+        // Only A[3:2] are sent
+        assert_eq!(transfer_request(false, 0x0b, false), 0x08);
+    }
+
+    #[test]
+    fn describes_acks() {
+        assert_eq!(ack_error(0x02), "WAIT");
+        assert_eq!(ack_error(0x04), "FAULT");
+        assert_eq!(ack_error(0x07), "no ACK");
+        assert_eq!(ack_error(0x09), "SWD protocol error");
+        assert_eq!(ack_error(0x11), "value mismatch");
+    }
+
+    #[test]
+    fn checks_command_status() {
+        assert!(check_status(&[DAP_CONNECT, DAP_OK], "DAP_Connect").is_ok());
+        assert!(check_status(&[DAP_CONNECT, 0xff], "DAP_Connect").is_err());
+        assert!(check_status(&[DAP_CONNECT], "DAP_Connect").is_err());
+    }
+}
@@ -0,0 +1,992 @@
+use crate::webusb_device::{run_blocking, ControlTarget, SharedDevice, UsbDevice};
+use crate::DeviceWait;
+use napi::{
+    bindgen_prelude::*, threadsafe_function::ThreadsafeFunction,
+    threadsafe_function::ThreadsafeFunctionCallMode,
+};
+use napi_derive::napi;
+use nusb::{
+    descriptors::{language_id::US_ENGLISH, ConfigurationDescriptor},
+    transfer::{ControlIn, ControlOut, ControlType, Recipient},
+    MaybeFuture,
+};
+use std::{sync::Arc, thread::sleep, time::Duration};
+
+const CLASS_APPLICATION_SPECIFIC: u8 = 0xfe;
+const SUBCLASS_DFU: u8 = 0x01;
+const PROTOCOL_RUNTIME: u8 = 0x01;
+const PROTOCOL_DFU_MODE: u8 = 0x02;
+const DESCRIPTOR_DFU_FUNCTIONAL: u8 = 0x21;
+
+const DFU_DETACH: u8 = 0;
+const DFU_DNLOAD: u8 = 1;
+const DFU_UPLOAD: u8 = 2;
+const DFU_GETSTATUS: u8 = 3;
+const DFU_CLRSTATUS: u8 = 4;
+const DFU_GETSTATE: u8 = 5;
+const DFU_ABORT: u8 = 6;
+
+const STATE_DFU_IDLE: u8 = 2;
+const STATE_DNLOAD_SYNC: u8 = 3;
+const STATE_DNBUSY: u8 = 4;
+const STATE_DNLOAD_IDLE: u8 = 5;
+const STATE_MANIFEST_SYNC: u8 = 6;
+const STATE_MANIFEST: u8 = 7;
+const STATE_MANIFEST_WAIT_RESET: u8 = 8;
+const STATE_UPLOAD_IDLE: u8 = 9;
+const STATE_DFU_ERROR: u8 = 10;
+
+const STATUS_OK: u8 = 0;
+
+const DFUSE_VERSION: u16 = 0x011a;
+const DFUSE_SET_ADDRESS: u8 = 0x21;
+const DFUSE_ERASE: u8 = 0x41;
+const DFUSE_READ_UNPROTECT: u8 = 0x92;
+// DfuSe data blocks start at 2, blocks 0 and 1 carry commands
+const DFUSE_DATA_BLOCK: u16 = 2;
+
+const CONTROL_TIMEOUT: Duration = Duration::from_millis(5000);
+const DEFAULT_DETACH_WAIT: u32 = 5000;
+
+const STATUS_NAMES: [&str; 16] = [
+    "OK",
+    "errTARGET",
+    "errFILE",
+    "errWRITE",
+    "errERASE",
+    "errCHECK_ERASED",
+    "errPROG",
+    "errVERIFY",
+    "errADDRESS",
+    "errNOTDONE",
+    "errFIRMWARE",
+    "errVENDOR",
+    "errUSBR",
+    "errPOR",
+    "errUNKNOWN",
+    "errSTALLEDPKT",
+];
+
+type ProgressCallback = ThreadsafeFunction<DfuProgress, (), DfuProgress, napi::Status, false>;
+
+#[napi(object)]
+pub struct DfuOptions {
+    /// The DFU interface to use, defaults to the first one
+    pub interfaceNumber: Option<u8>,
+    /// Alternate setting to select, e.g. the memory to target on DfuSe devices
+    pub alternateSetting: Option<u8>,
+    /// Detach kernel drivers while the interface is open, defaults to `true`
+    pub detachKernelDriver: Option<bool>,
+}
+
+#[napi(object)]
+#[derive(Clone, Copy)]
+pub struct DfuFunctionalDescriptor {
+    #[napi(writable = false)]
+    pub canDownload: bool,
+    #[napi(writable = false)]
+    pub canUpload: bool,
+    #[napi(writable = false)]
+    pub manifestationTolerant: bool,
+    #[napi(writable = false)]
+    pub willDetach: bool,
+    /// Milliseconds the device waits for a reset after DFU_DETACH
+    #[napi(writable = false)]
+    pub detachTimeout: u16,
+    #[napi(writable = false)]
+    pub transferSize: u16,
+    /// 0x0110 for DFU 1.1, 0x011a for DfuSe
+    #[napi(writable = false)]
+    pub dfuVersion: u16,
+}
+
+#[napi(object)]
+pub struct DfuInterface {
+    #[napi(writable = false)]
+    pub interfaceNumber: u8,
+    #[napi(writable = false)]
+    pub alternateSetting: u8,
+    #[napi(writable = false, ts_type = "'runtime' | 'dfu'")]
+    pub mode: String,
+    #[napi(writable = false)]
+    pub name: Option<String>,
+    #[napi(writable = false)]
+    pub functionalDescriptor: DfuFunctionalDescriptor,
+}
+
+/// The result of DFU_GETSTATUS.
+#[napi(object)]
+pub struct DfuStatus {
+    /// 0 (OK) or one of the DFU error codes
+    #[napi(writable = false)]
+    pub status: u8,
+    /// Milliseconds to wait before the next DFU_GETSTATUS
+    #[napi(writable = false)]
+    pub pollTimeout: u32,
+    /// 0 appIDLE to 10 dfuERROR
+    #[napi(writable = false)]
+    pub state: u8,
+}
+
+#[napi(object)]
+pub struct DfuProgress {
+    #[napi(writable = false, ts_type = "'erase' | 'download' | 'upload'")]
+    pub phase: String,
+    #[napi(writable = false)]
+    pub bytesDone: u32,
+    #[napi(writable = false)]
+    pub bytesTotal: u32,
+}
+
+/// A run of equal sectors from a DfuSe memory layout string.
+#[napi(object)]
+#[derive(Clone)]
+pub struct DfuseSegment {
+    #[napi(writable = false)]
+    pub start: u32,
+    #[napi(writable = false)]
+    pub sectorCount: u32,
+    #[napi(writable = false)]
+    pub sectorSize: u32,
+    #[napi(writable = false)]
+    pub readable: bool,
+    #[napi(writable = false)]
+    pub erasable: bool,
+    #[napi(writable = false)]
+    pub writable: bool,
+}
+
+#[napi(object)]
+#[derive(Clone)]
+pub struct DfuseMemory {
+    #[napi(writable = false)]
+    pub name: String,
+    #[napi(writable = false)]
+    pub segments: Vec<DfuseSegment>,
+}
+
+fn parse_functional_descriptor(descriptor: &[u8]) -> Option<DfuFunctionalDescriptor> {
+    if descriptor.len() < 7 {
+        return None;
+    }
+    let attributes = descriptor[2];
+    Some(DfuFunctionalDescriptor {
+        canDownload: attributes & 0x01 != 0,
+        canUpload: attributes & 0x02 != 0,
+        manifestationTolerant: attributes & 0x04 != 0,
+        willDetach: attributes & 0x08 != 0,
+        detachTimeout: u16::from_le_bytes([descriptor[3], descriptor[4]]),
+        transferSize: u16::from_le_bytes([descriptor[5], descriptor[6]]),
+        // DFU 1.0 descriptors stop before bcdDFUVersion
+        dfuVersion: descriptor
+            .get(7..9)
+            .map(|v| u16::from_le_bytes([v[0], v[1]]))
+            .unwrap_or(0x0100),
+    })
+}
+
+fn parse_number(text: &str) -> Option<u32> {
+    let text = text.trim();
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// Parses a DfuSe layout such as `@Internal Flash  /0x08000000/04*016Kg,01*064Kg,07*128Kg`.
+fn parse_dfuse_memory(layout: &str) -> Option<DfuseMemory> {
+    let layout = layout.strip_prefix('@')?;
+    let mut parts = layout.split('/');
+    let name = parts.next()?.trim().to_string();
+
+    let mut segments = Vec::new();
+    while let (Some(address), Some(sectors)) = (parts.next(), parts.next()) {
+        // Kept wide so a layout may end right at the top of the address space
+        let mut next = parse_number(address)? as u64;
+        for sector in sectors.split(',') {
+            let start = u32::try_from(next).ok()?;
+            let (count, rest) = sector.trim().split_once('*')?;
+            let count = parse_number(count)?;
+            let digits = rest.trim_start().find(|c: char| !c.is_ascii_digit())?;
+            let rest = rest.trim_start();
+            let mut size = rest[..digits].parse::<u32>().ok()?;
+            let mut kind = rest[digits..].chars();
+            let mut attributes = kind.next()?;
+            match attributes {
+                'K' => size = size.checked_mul(1024)?,
+                'M' => size = size.checked_mul(1024 * 1024)?,
+                _ => {}
+            }
+            if matches!(attributes, 'K' | 'M' | ' ' | 'B') {
+                attributes = kind.next()?;
+            }
+            // a-g encode readable (1), erasable (2) and writable (4)
+            let bits = (attributes as u32).checked_sub('a' as u32 - 1)?;
+            segments.push(DfuseSegment {
+                start,
+                sectorCount: count,
+                sectorSize: size,
+                readable: bits & 1 != 0,
+                erasable: bits & 2 != 0,
+                writable: bits & 4 != 0,
+            });
+            next = start as u64 + count as u64 * size as u64;
+            if next > 1 << 32 {
+                return None;
+            }
+        }
+    }
+    Some(DfuseMemory { name, segments })
+}
+
+/// The DFU interfaces of a configuration, with the functional descriptor shared by their alternate settings.
+fn find_dfu_interfaces(
+    config: &ConfigurationDescriptor,
+) -> Vec<(u8, u8, u8, Option<u8>, DfuFunctionalDescriptor)> {
+    let mut found = Vec::new();
+    for alt in config.interface_alt_settings() {
+        if alt.class() != CLASS_APPLICATION_SPECIFIC || alt.subclass() != SUBCLASS_DFU {
+            continue;
+        }
+        // Some bootloaders only put the functional descriptor after one of the alternate settings
+        let functional = config
+            .interface_alt_settings()
+            .filter(|other| other.interface_number() == alt.interface_number())
+            .flat_map(|other| other.descriptors())
+            .find(|descriptor| descriptor.descriptor_type() == DESCRIPTOR_DFU_FUNCTIONAL)
+            .and_then(|descriptor| parse_functional_descriptor(&descriptor));
+        if let Some(functional) = functional {
+            found.push((
+                alt.interface_number(),
+                alt.alternate_setting(),
+                alt.protocol(),
+                alt.string_index().map(|index| index.get()),
+                functional,
+            ));
+        }
+    }
+    found
+}
+
+fn interface_name(device: &nusb::Device, index: Option<u8>) -> Option<String> {
+    let index = std::num::NonZeroU8::new(index?)?;
+    device
+        .get_string_descriptor(index, US_ENGLISH, CONTROL_TIMEOUT)
+        .wait()
+        .ok()
+}
+
+fn check_status(status: &DfuStatus) -> std::result::Result<(), String> {
+    if status.status == STATUS_OK && status.state != STATE_DFU_ERROR {
+        return Ok(());
+    }
+    let name = STATUS_NAMES
+        .get(status.status as usize)
+        .copied()
+        .unwrap_or("unknown");
+    Err(format!(
+        "device reported {name} ({}) in state {}",
+        status.status, status.state
+    ))
+}
+
+/// DFU requests and the state machine around them, run from blocking threads.
+struct DfuTransport {
+    shared: Arc<SharedDevice>,
+    interface: u8,
+    functional: DfuFunctionalDescriptor,
+}
+
+impl DfuTransport {
+    /// Looked up for each request, so nothing keeps the interface claimed once it is released.
+    fn target(&self) -> std::result::Result<ControlTarget, String> {
+        self.shared
+            .control_target(Recipient::Interface, self.interface as u16)
+            .ok_or_else(|| "invalid state".to_string())
+    }
+
+    fn control_in(
+        &self,
+        request: u8,
+        value: u16,
+        length: u16,
+    ) -> std::result::Result<Vec<u8>, String> {
+        self.target()?
+            .control_in_blocking(
+                ControlIn {
+                    control_type: ControlType::Class,
+                    recipient: Recipient::Interface,
+                    request,
+                    value,
+                    index: self.interface as u16,
+                    length,
+                },
+                CONTROL_TIMEOUT,
+            )
+            .map_err(|e| e.to_string())
+    }
+
+    fn control_out(&self, request: u8, value: u16, data: &[u8]) -> std::result::Result<(), String> {
+        self.target()?
+            .control_out_blocking(
+                ControlOut {
+                    control_type: ControlType::Class,
+                    recipient: Recipient::Interface,
+                    request,
+                    value,
+                    index: self.interface as u16,
+                    data,
+                },
+                CONTROL_TIMEOUT,
+            )
+            .map_err(|e| e.to_string())
+    }
+
+    fn get_status(&self) -> std::result::Result<DfuStatus, String> {
+        let data = self.control_in(DFU_GETSTATUS, 0, 6)?;
+        if data.len() < 6 {
+            return Err("short DFU_GETSTATUS response".to_string());
+        }
+        Ok(DfuStatus {
+            status: data[0],
+            pollTimeout: u32::from_le_bytes([data[1], data[2], data[3], 0]),
+            state: data[4],
+        })
+    }
+
+    fn get_state(&self) -> std::result::Result<u8, String> {
+        let data = self.control_in(DFU_GETSTATE, 0, 1)?;
+        data.first()
+            .copied()
+            .ok_or_else(|| "short DFU_GETSTATE response".to_string())
+    }
+
+    /// Polls DFU_GETSTATUS, honouring bwPollTimeout, until the device leaves its busy states.
+    fn wait_idle(&self) -> std::result::Result<DfuStatus, String> {
+        loop {
+            let status = self.get_status()?;
+            check_status(&status)?;
+            if !matches!(
+                status.state,
+                STATE_DNLOAD_SYNC | STATE_DNBUSY | STATE_MANIFEST_SYNC | STATE_MANIFEST
+            ) {
+                return Ok(status);
+            }
+            sleep(Duration::from_millis(status.pollTimeout as u64));
+        }
+    }
+
+    /// Brings the device back to dfuIDLE from an error or an unfinished transfer.
+    fn ensure_idle(&self) -> std::result::Result<(), String> {
+        let status = self.get_status()?;
+        match status.state {
+            STATE_DFU_IDLE => return Ok(()),
+            STATE_DFU_ERROR => self.control_out(DFU_CLRSTATUS, 0, &[])?,
+            STATE_DNLOAD_IDLE | STATE_UPLOAD_IDLE => self.control_out(DFU_ABORT, 0, &[])?,
+            state => return Err(format!("device busy in state {state}")),
+        }
+        let status = self.get_status()?;
+        if status.state != STATE_DFU_IDLE {
+            return Err(format!(
+                "could not reach dfuIDLE, in state {}",
+                status.state
+            ));
+        }
+        Ok(())
+    }
+
+    fn download_block(&self, block: u16, data: &[u8]) -> std::result::Result<(), String> {
+        self.control_out(DFU_DNLOAD, block, data)?;
+        let status = self.wait_idle()?;
+        if status.state != STATE_DNLOAD_IDLE {
+            return Err(format!(
+                "unexpected state {} after block {block}",
+                status.state
+            ));
+        }
+        Ok(())
+    }
+
+    /// Sends the zero length DFU_DNLOAD and waits out manifestation.
+    fn manifest(&self, block: u16) -> std::result::Result<(), String> {
+        self.control_out(DFU_DNLOAD, block, &[])?;
+        match self.wait_idle() {
+            Ok(status) if matches!(status.state, STATE_DFU_IDLE | STATE_MANIFEST_WAIT_RESET) => {
+                Ok(())
+            }
+            Ok(status) => Err(format!(
+                "unexpected state {} after manifestation",
+                status.state
+            )),
+            // A device that isn't manifestation tolerant may reset itself straight away
+            Err(_) if !self.functional.manifestationTolerant => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn transfer_size(&self) -> usize {
+        (self.functional.transferSize as usize).max(1)
+    }
+
+    fn download(
+        &self,
+        data: &[u8],
+        progress: &dyn Fn(&str, usize, usize),
+    ) -> std::result::Result<(), String> {
+        self.ensure_idle()?;
+        let mut block: u16 = 0;
+        for (i, chunk) in data.chunks(self.transfer_size()).enumerate() {
+            self.download_block(block, chunk)?;
+            block = block.wrapping_add(1);
+            progress(
+                "download",
+                (i * self.transfer_size() + chunk.len()).min(data.len()),
+                data.len(),
+            );
+        }
+        self.manifest(block)
+    }
+
+    fn upload(
+        &self,
+        first_block: u16,
+        length: Option<usize>,
+        progress: &dyn Fn(&str, usize, usize),
+    ) -> std::result::Result<Vec<u8>, String> {
+        let transfer_size = self.transfer_size();
+        let mut data = Vec::new();
+        let mut block = first_block;
+        loop {
+            let wanted = match length {
+                Some(length) => (length - data.len()).min(transfer_size),
+                None => transfer_size,
+            };
+            let chunk = self.control_in(DFU_UPLOAD, block, wanted as u16)?;
+            data.extend_from_slice(&chunk);
+            block = block.wrapping_add(1);
+            progress("upload", data.len(), length.unwrap_or(data.len()));
+
+            // A short block ends the upload, otherwise stop once we have what was asked for
+            if chunk.len() < wanted {
+                break;
+            }
+            if length.is_some_and(|length| data.len() >= length) {
+                self.control_out(DFU_ABORT, 0, &[])?;
+                break;
+            }
+        }
+        Ok(data)
+    }
+
+    fn dfuse_command(&self, command: &[u8]) -> std::result::Result<(), String> {
+        self.control_out(DFU_DNLOAD, 0, command)?;
+        let status = self.wait_idle()?;
+        if status.state != STATE_DNLOAD_IDLE {
+            return Err(format!(
+                "unexpected state {} after command {:#04x}",
+                status.state, command[0]
+            ));
+        }
+        Ok(())
+    }
+
+    fn dfuse_address_command(&self, command: u8, address: u32) -> std::result::Result<(), String> {
+        let mut data = vec![command];
+        data.extend_from_slice(&address.to_le_bytes());
+        self.dfuse_command(&data)
+    }
+
+    fn dfuse_download(
+        &self,
+        memory: Option<&DfuseMemory>,
+        address: u32,
+        data: &[u8],
+        progress: &dyn Fn(&str, usize, usize),
+    ) -> std::result::Result<(), String> {
+        self.ensure_idle()?;
+        let end = address as u64 + data.len() as u64;
+
+        // Erase each erasable sector the data touches
+        if let Some(memory) = memory {
+            let sectors = memory
+                .segments
+                .iter()
+                .filter(|segment| segment.erasable)
+                .flat_map(|segment| {
+                    (0..segment.sectorCount).map(move |i| {
+                        i.checked_mul(segment.sectorSize)
+                            .and_then(|offset| segment.start.checked_add(offset))
+                            .map(|start| (start, segment.sectorSize))
+                            .ok_or_else(|| "memory layout overflows".to_string())
+                    })
+                })
+                .collect::<std::result::Result<Vec<_>, _>>()?
+                .into_iter()
+                .filter(|(start, size)| {
+                    (*start as u64) < end && *start as u64 + *size as u64 > address as u64
+                })
+                .map(|(start, _)| start)
+                .collect::<Vec<_>>();
+            for (i, sector) in sectors.iter().enumerate() {
+                self.dfuse_address_command(DFUSE_ERASE, *sector)?;
+                progress("erase", i + 1, sectors.len());
+            }
+        }
+
+        let transfer_size = self.transfer_size();
+        for (i, chunk) in data.chunks(transfer_size).enumerate() {
+            let offset = i * transfer_size;
+            let chunk_address = u32::try_from(offset)
+                .ok()
+                .and_then(|offset| address.checked_add(offset))
+                .ok_or("data runs past the end of the address space")?;
+            self.dfuse_address_command(DFUSE_SET_ADDRESS, chunk_address)?;
+            self.download_block(DFUSE_DATA_BLOCK, chunk)?;
+            progress("download", offset + chunk.len(), data.len());
+        }
+        self.control_out(DFU_ABORT, 0, &[])
+    }
+}
+
+/// A DFU interface, in runtime or DFU mode.
+#[napi]
+pub struct DfuDevice {
+    shared: Arc<SharedDevice>,
+    device_info: nusb::DeviceInfo,
+    transport: Arc<DfuTransport>,
+    functional: DfuFunctionalDescriptor,
+    memory: Option<DfuseMemory>,
+
+    #[napi(writable = false)]
+    pub interfaceNumber: u8,
+    #[napi(writable = false)]
+    pub alternateSetting: u8,
+    #[napi(writable = false, ts_type = "'runtime' | 'dfu'")]
+    pub mode: String,
+}
+
+fn progress_fn(callback: Option<ProgressCallback>) -> impl Fn(&str, usize, usize) {
+    move |phase, done, total| {
+        if let Some(callback) = callback.as_ref() {
+            callback.call(
+                DfuProgress {
+                    phase: phase.to_string(),
+                    bytesDone: done as u32,
+                    bytesTotal: total as u32,
+                },
+                ThreadsafeFunctionCallMode::NonBlocking,
+            );
+        }
+    }
+}
+
+impl DfuDevice {
+    async fn run<T, F>(&self, method: &'static str, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&DfuTransport) -> std::result::Result<T, String> + Send + 'static,
+    {
+        let transport = self.transport.clone();
+        run_blocking(move || f(&transport).map_err(|e| format!("{method} error: {e}"))).await
+    }
+
+    fn require_dfuse(&self, method: &'static str) -> Result<()> {
+        if self.functional.dfuVersion != DFUSE_VERSION {
+            return Err(napi::Error::from_reason(format!(
+                "{method} error: not a DfuSe device"
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[napi]
+impl DfuDevice {
+    #[napi(getter)]
+    pub fn functionalDescriptor(&self) -> DfuFunctionalDescriptor {
+        self.functional
+    }
+
+    /// The memory layout named by a DfuSe alternate setting, if it has one
+    #[napi(getter)]
+    pub fn dfuseMemory(&self) -> Option<DfuseMemory> {
+        self.memory.clone()
+    }
+
+    #[napi]
+    pub async fn getStatus(&self) -> Result<DfuStatus> {
+        self.run("getStatus", |transport| transport.get_status())
+            .await
+    }
+
+    #[napi]
+    pub async fn getState(&self) -> Result<u8> {
+        self.run("getState", |transport| transport.get_state())
+            .await
+    }
+
+    #[napi]
+    pub async fn clearStatus(&self) -> Result<()> {
+        self.run("clearStatus", |transport| {
+            transport.control_out(DFU_CLRSTATUS, 0, &[])
+        })
+        .await
+    }
+
+    #[napi]
+    pub async fn abort(&self) -> Result<()> {
+        self.run("abort", |transport| {
+            transport.control_out(DFU_ABORT, 0, &[])
+        })
+        .await
+    }
+
+    /// Asks a runtime mode device to switch to DFU mode, resetting it unless it detaches itself,
+    /// and resolves with the re-enumerated DFU mode device once it appears.
+    #[napi]
+    pub async fn detach(&self, timeout: Option<u32>) -> Result<UsbDevice> {
+        let original = self.device_info.clone();
+        let wait = DeviceWait::start()
+            .map_err(|e| napi::Error::from_reason(format!("detach error: {e}")))?;
+
+        let detach_timeout = self.functional.detachTimeout;
+        self.run("detach", move |transport| {
+            transport.control_out(DFU_DETACH, detach_timeout, &[])
+        })
+        .await?;
+
+        // The device is about to go away, so errors from here on don't matter
+        let _ = self
+            .shared
+            .release_interface("detach", self.interfaceNumber)
+            .await;
+        if !self.functional.willDetach {
+            if let Some(device) = self.shared.opened_device() {
+                let _ =
+                    run_blocking(move || device.reset().wait().map_err(|e| e.to_string())).await;
+            }
+        }
+
+        let info = wait
+            .until(
+                |info| {
+                    info.id() != original.id()
+                        && info.bus_id() == original.bus_id()
+                        && info.port_chain() == original.port_chain()
+                },
+                Duration::from_millis(timeout.unwrap_or(DEFAULT_DETACH_WAIT) as u64),
+            )
+            .await
+            .map_err(|e| napi::Error::from_reason(format!("detach error: {e}")))?;
+        Ok(UsbDevice::new(info))
+    }
+
+    /// Downloads a firmware image with DFU_DNLOAD and waits out manifestation.
+    #[napi]
+    pub async fn download(
+        &self,
+        data: Uint8Array,
+        onProgress: Option<ProgressCallback>,
+    ) -> Result<()> {
+        if !self.functional.canDownload {
+            return Err(napi::Error::from_reason(
+                "download error: device can't download",
+            ));
+        }
+        let progress = progress_fn(onProgress);
+        self.run("download", move |transport| {
+            transport.download(&data, &progress)
+        })
+        .await
+    }
+
+    /// Uploads the firmware with DFU_UPLOAD, until the device sends a short block or `length` bytes are read.
+    #[napi]
+    pub async fn upload(
+        &self,
+        length: Option<u32>,
+        onProgress: Option<ProgressCallback>,
+    ) -> Result<Uint8Array> {
+        if !self.functional.canUpload {
+            return Err(napi::Error::from_reason(
+                "upload error: device can't upload",
+            ));
+        }
+        let progress = progress_fn(onProgress);
+        let data = self
+            .run("upload", move |transport| {
+                transport.ensure_idle()?;
+                transport.upload(0, length.map(|length| length as usize), &progress)
+            })
+            .await?;
+        Ok(Uint8Array::from(data))
+    }
+
+    #[napi]
+    pub async fn dfuseSetAddress(&self, address: u32) -> Result<()> {
+        self.require_dfuse("dfuseSetAddress")?;
+        self.run("dfuseSetAddress", move |transport| {
+            transport.dfuse_address_command(DFUSE_SET_ADDRESS, address)
+        })
+        .await
+    }
+
+    /// Erases the page or sector containing `address`.
+    #[napi]
+    pub async fn dfuseErase(&self, address: u32) -> Result<()> {
+        self.require_dfuse("dfuseErase")?;
+        self.run("dfuseErase", move |transport| {
+            transport.dfuse_address_command(DFUSE_ERASE, address)
+        })
+        .await
+    }
+
+    #[napi]
+    pub async fn dfuseMassErase(&self) -> Result<()> {
+        self.require_dfuse("dfuseMassErase")?;
+        self.run("dfuseMassErase", |transport| {
+            transport.dfuse_command(&[DFUSE_ERASE])
+        })
+        .await
+    }
+
+    /// Removes read protection, which mass erases the device and usually resets it.
+    #[napi]
+    pub async fn dfuseReadUnprotect(&self) -> Result<()> {
+        self.require_dfuse("dfuseReadUnprotect")?;
+        self.run("dfuseReadUnprotect", |transport| {
+            transport.control_out(DFU_DNLOAD, 0, &[DFUSE_READ_UNPROTECT])?;
+            // The device may reset before answering
+            let _ = transport.get_status();
+            Ok(())
+        })
+        .await
+    }
+
+    /// Erases the sectors covered by `data` (using the memory layout when there is one) and writes it at `address`.
+    #[napi]
+    pub async fn dfuseDownload(
+        &self,
+        address: u32,
+        data: Uint8Array,
+        onProgress: Option<ProgressCallback>,
+    ) -> Result<()> {
+        self.require_dfuse("dfuseDownload")?;
+        let memory = self.memory.clone();
+        let progress = progress_fn(onProgress);
+        self.run("dfuseDownload", move |transport| {
+            transport.dfuse_download(memory.as_ref(), address, &data, &progress)
+        })
+        .await
+    }
+
+    #[napi]
+    pub async fn dfuseUpload(
+        &self,
+        address: u32,
+        length: u32,
+        onProgress: Option<ProgressCallback>,
+    ) -> Result<Uint8Array> {
+        self.require_dfuse("dfuseUpload")?;
+        let progress = progress_fn(onProgress);
+        let data = self
+            .run("dfuseUpload", move |transport| {
+                transport.ensure_idle()?;
+                transport.dfuse_address_command(DFUSE_SET_ADDRESS, address)?;
+                transport.control_out(DFU_ABORT, 0, &[])?;
+                transport.upload(DFUSE_DATA_BLOCK, Some(length as usize), &progress)
+            })
+            .await?;
+        Ok(Uint8Array::from(data))
+    }
+
+    /// Leaves DFU mode, starting the firmware at `address` if given.
+    #[napi]
+    pub async fn dfuseLeave(&self, address: Option<u32>) -> Result<()> {
+        self.require_dfuse("dfuseLeave")?;
+        self.run("dfuseLeave", move |transport| {
+            transport.ensure_idle()?;
+            if let Some(address) = address {
+                transport.dfuse_address_command(DFUSE_SET_ADDRESS, address)?;
+            }
+            transport.control_out(DFU_DNLOAD, DFUSE_DATA_BLOCK, &[])?;
+            // The device leaves after this status request and may not answer it
+            let _ = transport.get_status();
+            Ok(())
+        })
+        .await
+    }
+
+    /// Releases the interface, reattaching any kernel driver.
+    #[napi]
+    pub async fn close(&self) -> Result<()> {
+        self.shared
+            .release_interface("close", self.interfaceNumber)
+            .await
+    }
+}
+
+#[napi]
+impl UsbDevice {
+    /// Lists the DFU interfaces (runtime and DFU mode) of the active configuration.
+    #[napi]
+    pub async fn getDfuInterfaces(&self) -> Result<Vec<DfuInterface>> {
+        let device = self
+            .shared()
+            .opened_device()
+            .ok_or_else(|| napi::Error::from_reason("getDfuInterfaces error: invalid state"))?;
+        run_blocking(move || {
+            let config = device
+                .active_configuration()
+                .map_err(|e| format!("getDfuInterfaces error: {e}"))?;
+            Ok(find_dfu_interfaces(&config)
+                .into_iter()
+                .map(
+                    |(interface, alt, protocol, string_index, functional)| DfuInterface {
+                        interfaceNumber: interface,
+                        alternateSetting: alt,
+                        mode: if protocol == PROTOCOL_DFU_MODE {
+                            "dfu"
+                        } else {
+                            "runtime"
+                        }
+                        .to_string(),
+                        name: interface_name(&device, string_index),
+                        functionalDescriptor: functional,
+                    },
+                )
+                .collect())
+        })
+        .await
+    }
+
+    /// Claims a DFU interface and returns a device to drive it.
+    #[napi]
+    pub async fn openDfu(&self, options: Option<DfuOptions>) -> Result<DfuDevice> {
+        let interface_number = options.as_ref().and_then(|o| o.interfaceNumber);
+        let alternate_setting = options.as_ref().and_then(|o| o.alternateSetting);
+        let detach = options
+            .as_ref()
+            .and_then(|o| o.detachKernelDriver)
+            .unwrap_or(true);
+
+        let shared = self.shared().clone();
+        let device = shared
+            .opened_device()
+            .ok_or_else(|| napi::Error::from_reason("openDfu error: invalid state"))?;
+        let (interface, alt, protocol, name, functional) = run_blocking(move || {
+            let config = device
+                .active_configuration()
+                .map_err(|e| format!("openDfu error: {e}"))?;
+            let (interface, alt, protocol, string_index, functional) = find_dfu_interfaces(&config)
+                .into_iter()
+                .filter(|(interface, ..)| interface_number.is_none_or(|n| *interface == n))
+                .find(|(_, alt, ..)| *alt == alternate_setting.unwrap_or(0))
+                .ok_or("openDfu error: no DFU interface found")?;
+            let name = interface_name(&device, string_index);
+            Ok((interface, alt, protocol, name, functional))
+        })
+        .await?;
+
+        shared.claim_interface("openDfu", interface, detach).await?;
+        if alt != 0 {
+            shared
+                .select_alternate_interface("openDfu", interface, alt)
+                .await?;
+        }
+
+        let memory = match functional.dfuVersion {
+            DFUSE_VERSION => name.as_deref().and_then(parse_dfuse_memory),
+            _ => None,
+        };
+
+        Ok(DfuDevice {
+            shared: shared.clone(),
+            transport: Arc::new(DfuTransport {
+                shared,
+                interface,
+                functional,
+            }),
+            device_info: self.device_info().clone(),
+            functional,
+            memory,
+            interfaceNumber: interface,
+            alternateSetting: alt,
+            mode: if protocol == PROTOCOL_RUNTIME {
+                "runtime"
+            } else {
+                "dfu"
+            }
+            .to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sectors(memory: &DfuseMemory) -> Vec<(u32, u32, u32, u32)> {
+        memory
+            .segments
+            .iter()
+            .map(|segment| {
+                let bits = u32::from(segment.readable)
+                    | u32::from(segment.erasable) << 1
+                    | u32::from(segment.writable) << 2;
+                (segment.start, segment.sectorCount, segment.sectorSize, bits)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_stm32_layout() {
+        let memory =
+            parse_dfuse_memory("@Internal Flash  /0x08000000/04*016Kg,01*064Kg,07*128Kg").unwrap();
+        assert_eq!(memory.name, "Internal Flash");
+        assert_eq!(
+            sectors(&memory),
+            [
+                (0x0800_0000, 4, 16 * 1024, 7),
+                (0x0801_0000, 1, 64 * 1024, 7),
+                (0x0802_0000, 7, 128 * 1024, 7),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_several_regions() {
+        let memory =
+            parse_dfuse_memory("@Option Bytes  /0x1FFFC000/01*016 e/0x1FFEC000/01*016 e").unwrap();
+        assert_eq!(
+            sectors(&memory),
+            [(0x1fff_c000, 1, 16, 5), (0x1ffe_c000, 1, 16, 5)]
+        );
+    }
+
+    #[test]
+    fn layout_may_end_at_the_top_of_the_address_space() {
+        let memory = parse_dfuse_memory("@Flash/0xFFFF0000/64*1Kg").unwrap();
+        assert_eq!(sectors(&memory), [(0xffff_0000, 64, 1024, 7)]);
+    }
+
+    #[test]
+    fn rejects_overflowing_layouts() {
+        assert!(parse_dfuse_memory("@Flash/0xFFFF0000/65*1Kg").is_none());
+        assert!(parse_dfuse_memory("@Flash/0xFFFF0000/64*1Kg,1*1Kg").is_none());
+        assert!(parse_dfuse_memory("@Flash/0x0/1*8192Mg").is_none());
+        assert!(parse_dfuse_memory("Flash/0x08000000/04*016Kg").is_none());
+    }
+
+    #[test]
+    fn parses_functional_descriptor() {
+        let functional =
+            parse_functional_descriptor(&[0x09, 0x21, 0x0b, 0xff, 0x00, 0x00, 0x08, 0x1a, 0x01])
+                .unwrap();
+        assert!(functional.canDownload && functional.canUpload && functional.willDetach);
+        assert!(!functional.manifestationTolerant);
+        assert_eq!(functional.detachTimeout, 255);
+        assert_eq!(functional.transferSize, 2048);
+        assert_eq!(functional.dfuVersion, DFUSE_VERSION);
+    }
+}
@@ -0,0 +1,207 @@
+use crate::transfer_pool::PooledEndpoint;
+use crate::webusb_device::SharedDevice;
+use nusb::transfer::In;
+use std::{
+    future::poll_fn,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+use tokio::{
+    sync::{oneshot, watch},
+    task::JoinHandle,
+};
+
+/// Continuous reader for an IN endpoint, used by the class drivers for data
+/// and notification endpoints.
+///
+/// Several transfers are kept queued so nothing is missed while a completed
+/// one is handed on, and each buffer is resubmitted once `on_data` has seen
+/// it. While paused, completed buffers are held rather than resubmitted, so
+/// the device is left to buffer (or NAK) until the stream is resumed. The
+/// stream ends when stopped or dropped, or on the first failed transfer, and
+/// `on_end` is called with the error if there was one, once the endpoint has
+/// been dropped.
+pub(crate) struct InStream {
+    stop: Mutex<Option<oneshot::Sender<()>>>,
+    paused: watch::Sender<bool>,
+    task: Mutex<Option<JoinHandle<()>>>,
+    ended: Arc<AtomicBool>,
+}
+
+impl InStream {
+    pub(crate) fn start<D, E>(
+        mut endpoint: PooledEndpoint<In>,
+        transfer_len: usize,
+        transfers: usize,
+        mut on_data: D,
+        on_end: E,
+    ) -> Self
+    where
+        D: FnMut(&[u8]) + Send + 'static,
+        E: FnOnce(Option<String>) + Send + 'static,
+    {
+        let packet_size = endpoint.max_packet_size();
+        let transfer_len = transfer_len.div_ceil(packet_size).max(1) * packet_size;
+        for _ in 0..transfers.max(1) {
+            let buffer = endpoint.allocate(transfer_len);
+            endpoint.submit(buffer);
+        }
+
+        let (stop, mut stopped) = oneshot::channel::<()>();
+        let (paused, mut resumed) = watch::channel(false);
+        let ended = Arc::new(AtomicBool::new(false));
+        let ended_flag = ended.clone();
+        let task = napi::bindgen_prelude::spawn(async move {
+            let error = 'read: loop {
+                let completion = tokio::select! {
+                    completion = poll_fn(|cx| endpoint.poll_next_complete(cx)) => completion,
+                    _ = &mut stopped => break None,
+                };
+
+                if let Err(e) = completion.status {
+                    break Some(format!("{e:?}"));
+                }
+                if completion.actual_len > 0 {
+                    on_data(&completion.buffer[..completion.actual_len]);
+                }
+
+                while *resumed.borrow_and_update() {
+                    tokio::select! {
+                        _ = resumed.changed() => {}
+                        _ = &mut stopped => break 'read None,
+                    }
+                }
+
+                let mut buffer = completion.buffer;
+                buffer.clear();
+                endpoint.submit(buffer);
+            };
+
+            endpoint.cancel_all();
+            while endpoint.pending() > 0 {
+                poll_fn(|cx| endpoint.poll_next_complete(cx)).await;
+            }
+            // Free for a new stream before anyone hears this one ended
+            drop(endpoint);
+            ended_flag.store(true, Ordering::SeqCst);
+            on_end(error);
+        });
+
+        Self {
+            stop: Mutex::new(Some(stop)),
+            paused,
+            task: Mutex::new(Some(task)),
+            ended,
+        }
+    }
+
+    /// Whether the stream has ended and let go of its endpoint.
+    pub(crate) fn has_ended(&self) -> bool {
+        self.ended.load(Ordering::SeqCst)
+    }
+
+    fn stop_guard(&self) -> MutexGuard<'_, Option<oneshot::Sender<()>>> {
+        self.stop
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub(crate) fn stop(&self) {
+        if let Some(stop) = self.stop_guard().take() {
+            let _ = stop.send(());
+        }
+    }
+
+    /// Holds on to completed transfers instead of reading more, until resumed.
+    pub(crate) fn pause(&self) {
+        self.paused.send_replace(true);
+    }
+
+    pub(crate) fn resume(&self) {
+        self.paused.send_replace(false);
+    }
+
+    /// Stops the stream and waits for it to end, after which its endpoint is
+    /// dropped and no longer keeps the interface claimed.
+    pub(crate) async fn finish(self) {
+        self.stop();
+        let task = self
+            .task
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .take();
+        if let Some(task) = task {
+            let _ = task.await;
+        }
+    }
+}
+
+impl Drop for InStream {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// The stream behind a driver's `startReading()` or `onX()`, which can be
+/// started again once the previous one has been stopped or has ended.
+#[derive(Default)]
+pub(crate) struct StreamSlot(Mutex<Option<InStream>>);
+
+impl StreamSlot {
+    fn guard(&self) -> MutexGuard<'_, Option<InStream>> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Starts a stream on `endpoint_number` unless one is still running. One
+    /// that ended on an error is replaced.
+    pub(crate) fn start(
+        &self,
+        method: &str,
+        shared: &SharedDevice,
+        endpoint_number: u8,
+        start: impl FnOnce(PooledEndpoint<In>) -> InStream,
+    ) -> napi::Result<()> {
+        let mut slot = self.guard();
+        if slot.as_ref().is_some_and(|stream| !stream.has_ended()) {
+            return Err(napi::Error::from_reason(format!(
+                "{method} error: already reading"
+            )));
+        }
+        let endpoint = shared
+            .get_endpoint::<In>(endpoint_number)
+            .ok_or_else(|| napi::Error::from_reason(format!("{method} error: invalid state")))?;
+        *slot = Some(start(endpoint));
+        Ok(())
+    }
+
+    pub(crate) fn stop(&self) {
+        if let Some(stream) = self.guard().take() {
+            stream.stop();
+        }
+    }
+
+    pub(crate) fn pause(&self) {
+        if let Some(stream) = &*self.guard() {
+            stream.pause();
+        }
+    }
+
+    pub(crate) fn resume(&self) {
+        if let Some(stream) = &*self.guard() {
+            stream.resume();
+        }
+    }
+
+    /// Stops any stream and waits for it to let go of its endpoint, which
+    /// keeps the interface claimed until then.
+    pub(crate) async fn finish(&self) {
+        let stream = self.guard().take();
+        if let Some(stream) = stream {
+            stream.finish().await;
+        }
+    }
+}
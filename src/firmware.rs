@@ -0,0 +1,279 @@
+mod image;
+
+use crate::webusb_device::{run_blocking, ControlTarget, UsbDevice};
+use crate::DeviceWait;
+use image::{Image, Segment};
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use nusb::transfer::{ControlOut, ControlType, Recipient};
+use std::time::Duration;
+
+/// Cypress "firmware load": writes RAM at wValue (and wIndex for the FX3's 32-bit addresses)
+const REQUEST_FIRMWARE_LOAD: u8 = 0xa0;
+
+/// CPUCS register of the FX2 and FX2LP, bit 0 holds the 8051 in reset
+const FX2_CPUCS: u16 = 0xe600;
+/// CPUCS register of the AN21xx and FX
+const AN21_CPUCS: u16 = 0x7f92;
+const CPUCS_RESET: u8 = 0x01;
+const CPUCS_RUN: u8 = 0x00;
+
+const FX2_CHUNK: usize = 1024;
+/// The FX3 bootloader takes up to 4 KiB per request
+const FX3_CHUNK: usize = 4096;
+
+const CONTROL_TIMEOUT: Duration = Duration::from_millis(1000);
+const DEFAULT_LOAD_WAIT: u32 = 10000;
+
+#[napi(object)]
+pub struct FirmwareOptions {
+    /// Which loader the device runs, defaults to `'fx2'`
+    #[napi(ts_type = "'an21' | 'fx' | 'fx2' | 'fx2lp' | 'fx3'")]
+    pub chip: Option<String>,
+    /// Image format, detected from the contents when not given: Intel HEX starts with `:` and FX3
+    /// images with `CY`, anything else is raw
+    #[napi(ts_type = "'ihex' | 'fx3' | 'raw'")]
+    pub format: Option<String>,
+    /// Load address of a raw image, which is also where an FX3 starts it, defaults to 0
+    pub address: Option<u32>,
+    /// Vendor ID the device comes back with, any when not given
+    pub vendorId: Option<u16>,
+    /// Product ID the device comes back with, any when not given
+    pub productId: Option<u16>,
+    /// Maximum time (in milliseconds) to wait for the device to re-enumerate, defaults to 10 seconds
+    pub timeout: Option<u32>,
+}
+
+enum Chip {
+    Fx2 { cpucs: u16 },
+    Fx3,
+}
+
+impl Chip {
+    fn parse(chip: Option<&str>) -> std::result::Result<Self, String> {
+        match chip.unwrap_or("fx2") {
+            "fx2" | "fx2lp" => Ok(Chip::Fx2 { cpucs: FX2_CPUCS }),
+            "an21" | "fx" => Ok(Chip::Fx2 { cpucs: AN21_CPUCS }),
+            "fx3" => Ok(Chip::Fx3),
+            chip => Err(format!("unknown chip '{chip}'")),
+        }
+    }
+}
+
+fn parse_image(
+    data: &[u8],
+    format: Option<&str>,
+    address: u32,
+) -> std::result::Result<Image, String> {
+    let format = match format {
+        Some(format) => format,
+        None if image::is_intel_hex(data) => "ihex",
+        None if image::is_fx3_image(data) => "fx3",
+        None => "raw",
+    };
+    let image = match format {
+        "ihex" => image::parse_intel_hex(data)?,
+        "fx3" => image::parse_fx3_image(data)?,
+        "raw" => Image {
+            segments: vec![Segment {
+                address,
+                data: data.to_vec(),
+            }],
+            entry: Some(address),
+        },
+        format => return Err(format!("unknown format '{format}'")),
+    };
+    if image.segments.iter().all(|segment| segment.data.is_empty()) {
+        return Err("image is empty".to_string());
+    }
+    // A segment may end right at the top of memory, but not wrap past it
+    if let Some(segment) = image
+        .segments
+        .iter()
+        .find(|segment| segment.address as u64 + segment.data.len() as u64 > 1 << 32)
+    {
+        return Err(format!(
+            "segment at {:#x} runs past the end of the address space",
+            segment.address
+        ));
+    }
+    Ok(image)
+}
+
+fn firmware_load(
+    target: &ControlTarget,
+    address: u32,
+    data: &[u8],
+) -> std::result::Result<(), String> {
+    target
+        .control_out_blocking(
+            ControlOut {
+                control_type: ControlType::Vendor,
+                recipient: Recipient::Device,
+                request: REQUEST_FIRMWARE_LOAD,
+                value: address as u16,
+                index: (address >> 16) as u16,
+                data,
+            },
+            CONTROL_TIMEOUT,
+        )
+        .map_err(|e| format!("write to {address:#x} failed: {e}"))
+}
+
+/// Address of the `n`th `chunk` sized piece of a segment.
+fn chunk_address(segment: &Segment, n: usize, chunk: usize) -> std::result::Result<u32, String> {
+    n.checked_mul(chunk)
+        .and_then(|offset| u32::try_from(offset).ok())
+        .and_then(|offset| segment.address.checked_add(offset))
+        .ok_or_else(|| {
+            format!(
+                "segment at {:#x} runs past the end of the address space",
+                segment.address
+            )
+        })
+}
+
+fn write_segments(
+    target: &ControlTarget,
+    segments: &[Segment],
+    chunk: usize,
+) -> std::result::Result<(), String> {
+    for segment in segments {
+        for (n, piece) in segment.data.chunks(chunk).enumerate() {
+            firmware_load(target, chunk_address(segment, n, chunk)?, piece)?;
+        }
+    }
+    Ok(())
+}
+
+/// Holds the 8051 in reset while its RAM is written, then lets it run the new code.
+fn load_fx2(target: &ControlTarget, image: &Image, cpucs: u16) -> std::result::Result<(), String> {
+    if let Some(segment) = image
+        .segments
+        .iter()
+        .find(|segment| segment.address as u64 + segment.data.len() as u64 > 0x10000)
+    {
+        return Err(format!(
+            "segment at {:#x} is outside the 8051's 64 KiB",
+            segment.address
+        ));
+    }
+    firmware_load(target, cpucs as u32, &[CPUCS_RESET])?;
+    write_segments(target, &image.segments, FX2_CHUNK)?;
+    firmware_load(target, cpucs as u32, &[CPUCS_RUN])
+}
+
+/// Writes the sections through the bootloader, then has it jump to the entry point.
+fn load_fx3(target: &ControlTarget, image: &Image) -> std::result::Result<(), String> {
+    let entry = image
+        .entry
+        .ok_or_else(|| "image has no entry point".to_string())?;
+    write_segments(target, &image.segments, FX3_CHUNK)?;
+    // The bootloader may drop off the bus before it acknowledges the jump, so a failure here
+    // doesn't mean the firmware didn't start; waiting for it to re-enumerate tells us that.
+    let _ = firmware_load(target, entry, &[]);
+    Ok(())
+}
+
+#[napi]
+impl UsbDevice {
+    /// Loads firmware into the RAM of a Cypress FX2 or FX3 and waits for it to re-enumerate
+    /// running that firmware.
+    #[napi]
+    pub async fn loadFirmware(
+        &self,
+        image: Uint8Array,
+        options: Option<FirmwareOptions>,
+    ) -> Result<UsbDevice> {
+        let options = options.unwrap_or(FirmwareOptions {
+            chip: None,
+            format: None,
+            address: None,
+            vendorId: None,
+            productId: None,
+            timeout: None,
+        });
+        let chip = Chip::parse(options.chip.as_deref())
+            .map_err(|e| napi::Error::from_reason(format!("loadFirmware error: {e}")))?;
+        let image = parse_image(
+            &image,
+            options.format.as_deref(),
+            options.address.unwrap_or(0),
+        )
+        .map_err(|e| napi::Error::from_reason(format!("loadFirmware error: {e}")))?;
+
+        let original = self.device_info().clone();
+        let wait = DeviceWait::start()
+            .map_err(|e| napi::Error::from_reason(format!("loadFirmware error: {e}")))?;
+
+        let shared = self.shared().clone();
+        run_blocking(move || {
+            let load = || {
+                let target = shared
+                    .control_target(Recipient::Device, 0)
+                    .ok_or("invalid state")?;
+                match chip {
+                    Chip::Fx2 { cpucs } => load_fx2(&target, &image, cpucs),
+                    Chip::Fx3 => load_fx3(&target, &image),
+                }
+            };
+            load().map_err(|e| format!("loadFirmware error: {e}"))
+        })
+        .await?;
+
+        let (vendor_id, product_id) = (options.vendorId, options.productId);
+        let info = wait
+            .until(
+                |info| {
+                    vendor_id.is_none_or(|id| id == info.vendor_id())
+                        && product_id.is_none_or(|id| id == info.product_id())
+                        && info.bus_id() == original.bus_id()
+                        && info.port_chain() == original.port_chain()
+                },
+                Duration::from_millis(options.timeout.unwrap_or(DEFAULT_LOAD_WAIT) as u64),
+            )
+            .await
+            .map_err(|e| napi::Error::from_reason(format!("loadFirmware error: {e}")))?;
+        Ok(UsbDevice::new(info))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_images_must_fit_below_the_top_of_memory() {
+        let image = parse_image(&[0; 0x1000], Some("raw"), 0xffff_f000).unwrap();
+        assert_eq!(image.entry, Some(0xffff_f000));
+        assert_eq!(
+            parse_image(&[0; 0x1001], Some("raw"), 0xffff_f000)
+                .err()
+                .unwrap(),
+            "segment at 0xfffff000 runs past the end of the address space"
+        );
+    }
+
+    #[test]
+    fn fx3_sections_must_fit_below_the_top_of_memory() {
+        let mut data = b"CY\x1c\xb0".to_vec();
+        for word in [2u32, 0xffff_fffc, 1, 2, 0, 0xffff_fffc, 3] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        assert_eq!(
+            parse_image(&data, None, 0).err().unwrap(),
+            "segment at 0xfffffffc runs past the end of the address space"
+        );
+    }
+
+    #[test]
+    fn chunk_addresses_are_checked() {
+        let segment = Segment {
+            address: 0xffff_e000,
+            data: vec![0; 0x2000],
+        };
+        assert_eq!(chunk_address(&segment, 1, FX3_CHUNK), Ok(0xffff_f000));
+        assert!(chunk_address(&segment, 2, FX3_CHUNK).is_err());
+        assert!(chunk_address(&segment, usize::MAX, FX3_CHUNK).is_err());
+    }
+}
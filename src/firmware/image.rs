@@ -0,0 +1,282 @@
+const RECORD_DATA: u8 = 0x00;
+const RECORD_EOF: u8 = 0x01;
+const RECORD_EXTENDED_SEGMENT: u8 = 0x02;
+const RECORD_START_SEGMENT: u8 = 0x03;
+const RECORD_EXTENDED_LINEAR: u8 = 0x04;
+const RECORD_START_LINEAR: u8 = 0x05;
+
+const FX3_SIGNATURE: &[u8; 2] = b"CY";
+/// bImageType of a normal firmware image with its checksum after the entry point
+const FX3_IMAGE_NORMAL: u8 = 0xb0;
+/// bImageCTL bit set on images meant for the I2C EEPROM rather than execution
+const FX3_IMAGE_DATA: u8 = 0x01;
+
+/// A run of bytes to load at one address.
+pub(super) struct Segment {
+    pub(super) address: u32,
+    pub(super) data: Vec<u8>,
+}
+
+pub(super) struct Image {
+    pub(super) segments: Vec<Segment>,
+    /// Where to start execution, for loaders that jump rather than release a reset
+    pub(super) entry: Option<u32>,
+}
+
+pub(super) fn is_intel_hex(data: &[u8]) -> bool {
+    data.iter()
+        .find(|b| !b.is_ascii_whitespace())
+        .is_some_and(|&b| b == b':')
+}
+
+pub(super) fn is_fx3_image(data: &[u8]) -> bool {
+    data.starts_with(FX3_SIGNATURE)
+}
+
+/// Parses Intel HEX, joining data records that follow on from each other into segments.
+pub(super) fn parse_intel_hex(text: &[u8]) -> std::result::Result<Image, String> {
+    let text = std::str::from_utf8(text).map_err(|_| "Intel HEX is not ASCII".to_string())?;
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut base = 0u32;
+    let mut entry = None;
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |message: &str| format!("line {}: {message}", number + 1);
+        let hex = line
+            .strip_prefix(':')
+            .ok_or_else(|| error("record doesn't start with ':'"))?;
+        if hex.len() % 2 != 0 || hex.len() < 10 {
+            return Err(error("short record"));
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|_| error("bad hex digit"))?;
+        if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(error("bad checksum"));
+        }
+        let len = bytes[0] as usize;
+        let data = bytes
+            .get(4..4 + len)
+            .filter(|_| bytes.len() == len + 5)
+            .ok_or_else(|| error("record length doesn't match its data"))?;
+        let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+
+        match bytes[3] {
+            RECORD_DATA => {
+                let address = base.wrapping_add(offset);
+                match segments.last_mut() {
+                    Some(segment)
+                        if segment.address.checked_add(segment.data.len() as u32)
+                            == Some(address) =>
+                    {
+                        segment.data.extend_from_slice(data);
+                    }
+                    _ => segments.push(Segment {
+                        address,
+                        data: data.to_vec(),
+                    }),
+                }
+            }
+            RECORD_EOF => break,
+            RECORD_EXTENDED_SEGMENT if len == 2 => {
+                base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4;
+            }
+            RECORD_EXTENDED_LINEAR if len == 2 => {
+                base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16;
+            }
+            RECORD_START_SEGMENT if len == 4 => {
+                let segment = u16::from_be_bytes([data[0], data[1]]) as u32;
+                let offset = u16::from_be_bytes([data[2], data[3]]) as u32;
+                entry = Some((segment << 4) + offset);
+            }
+            RECORD_START_LINEAR if len == 4 => {
+                entry = Some(u32::from_be_bytes([data[0], data[1], data[2], data[3]]));
+            }
+            record => return Err(error(&format!("unsupported record type {record:#04x}"))),
+        }
+    }
+
+    Ok(Image { segments, entry })
+}
+
+/// Parses an FX3 boot image: sections of 32-bit words, ended by a zero length section holding the
+/// entry point and followed by the checksum of every word loaded.
+pub(super) fn parse_fx3_image(data: &[u8]) -> std::result::Result<Image, String> {
+    if data.len() < 4 || !is_fx3_image(data) {
+        return Err("not an FX3 image".to_string());
+    }
+    if data[2] & FX3_IMAGE_DATA != 0 {
+        return Err("image is for the I2C EEPROM, not for loading".to_string());
+    }
+    if data[3] != FX3_IMAGE_NORMAL {
+        return Err(format!("unsupported image type {:#04x}", data[3]));
+    }
+
+    let word = |i: usize| {
+        data.get(i..i + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .ok_or_else(|| "image is truncated".to_string())
+    };
+    let mut segments = Vec::new();
+    let mut checksum = 0u32;
+    let mut i = 4;
+    loop {
+        let words = word(i)? as usize;
+        let address = word(i + 4)?;
+        i += 8;
+        if words == 0 {
+            if word(i)? != checksum {
+                return Err("bad image checksum".to_string());
+            }
+            return Ok(Image {
+                segments,
+                entry: Some(address),
+            });
+        }
+        let section = data
+            .get(i..i + words * 4)
+            .ok_or_else(|| "image is truncated".to_string())?;
+        checksum = section.chunks_exact(4).fold(checksum, |sum, b| {
+            sum.wrapping_add(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        });
+        segments.push(Segment {
+            address,
+            data: section.to_vec(),
+        });
+        i += words * 4;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segments(image: &Image) -> Vec<(u32, Vec<u8>)> {
+        image
+            .segments
+            .iter()
+            .map(|segment| (segment.address, segment.data.clone()))
+            .collect()
+    }
+
+    /// An FX3 image with one two word section loaded at 0x40000000.
+    fn fx3_image() -> Vec<u8> {
+        let mut data = b"CY\x1c\xb0".to_vec();
+        for word in [2u32, 0x4000_0000, 0x1111_1111, 0x2222_2222] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        for word in [0u32, 0x4000_0010, 0x3333_3333] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn recognises_formats() {
+        assert!(is_intel_hex(b"\r\n  :00000001FF"));
+        assert!(!is_intel_hex(b"CY\x1c\xb0"));
+        assert!(is_fx3_image(b"CY\x1c\xb0"));
+        assert!(!is_fx3_image(b":00000001FF"));
+    }
+
+    #[test]
+    fn joins_contiguous_data_records() {
+        let image = parse_intel_hex(
+            b":04010000214601365D\n:020104000121D7\r\n\n:01020000AA53\n:00000001FF\n:0000000AFF\n",
+        )
+        .unwrap();
+        assert_eq!(
+            segments(&image),
+            [
+                (0x0100, vec![0x21, 0x46, 0x01, 0x36, 0x01, 0x21]),
+                (0x0200, vec![0xaa]),
+            ]
+        );
+        assert_eq!(image.entry, None);
+    }
+
+    #[test]
+    fn applies_extended_addresses() {
+        let image =
+            parse_intel_hex(b":020000021234B6\n:01001000559A\n:0400000312340010A3\n").unwrap();
+        assert_eq!(segments(&image), [(0x12350, vec![0x55])]);
+        assert_eq!(image.entry, Some(0x12350));
+
+        // A segment running to the top of memory doesn't join the next one
+        let image = parse_intel_hex(
+            b":02000004FFFFFC\n:02FFFE000102FE\n:01020000AA53\n:0400000510000100E6\n",
+        )
+        .unwrap();
+        assert_eq!(
+            segments(&image),
+            [(0xffff_fffe, vec![1, 2]), (0xffff_0200, vec![0xaa])]
+        );
+        assert_eq!(image.entry, Some(0x1000_0100));
+    }
+
+    #[test]
+    fn rejects_bad_records() {
+        let error = |text: &[u8]| parse_intel_hex(text).err().unwrap();
+        assert_eq!(error(b":0300000002000FED\n"), "line 1: bad checksum");
+        assert_eq!(
+            error(b":0300000002000FEC\n0300000002000FEC\n"),
+            "line 2: record doesn't start with ':'"
+        );
+        assert_eq!(error(b":00000001F\n"), "line 1: short record");
+        assert_eq!(error(b":0000000GF9\n"), "line 1: bad hex digit");
+        assert_eq!(
+            error(b":0400000002000FEB\n"),
+            "line 1: record length doesn't match its data"
+        );
+        assert_eq!(
+            error(b":00000006FA\n"),
+            "line 1: unsupported record type 0x06"
+        );
+        assert_eq!(error(&[0xff]), "Intel HEX is not ASCII");
+    }
+
+    #[test]
+    fn parses_fx3_images() {
+        let image = parse_fx3_image(&fx3_image()).unwrap();
+        assert_eq!(
+            segments(&image),
+            [(
+                0x4000_0000,
+                vec![0x11, 0x11, 0x11, 0x11, 0x22, 0x22, 0x22, 0x22]
+            )]
+        );
+        assert_eq!(image.entry, Some(0x4000_0010));
+    }
+
+    #[test]
+    fn rejects_bad_fx3_images() {
+        let error = |data: &[u8]| parse_fx3_image(data).err().unwrap();
+        let image = fx3_image();
+
+        let mut bad = image.clone();
+        *bad.last_mut().unwrap() ^= 1;
+        assert_eq!(error(&bad), "bad image checksum");
+
+        let mut eeprom = image.clone();
+        eeprom[2] |= FX3_IMAGE_DATA;
+        assert_eq!(
+            error(&eeprom),
+            "image is for the I2C EEPROM, not for loading"
+        );
+
+        let mut other = image.clone();
+        other[3] = 0xb2;
+        assert_eq!(error(&other), "unsupported image type 0xb2");
+
+        assert_eq!(error(&image[..image.len() - 1]), "image is truncated");
+        assert_eq!(error(&image[..20]), "image is truncated");
+        assert_eq!(error(b"CY"), "not an FX3 image");
+        assert_eq!(error(b":000"), "not an FX3 image");
+    }
+}
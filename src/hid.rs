@@ -0,0 +1,886 @@
+use crate::endpoint_stream::{InStream, StreamSlot};
+use crate::transfer_pool::pooled_transfer_out;
+use crate::webusb_device::{run_blocking, ControlTarget, SharedDevice, UsbDevice};
+use napi::{
+    bindgen_prelude::*, threadsafe_function::ThreadsafeFunction,
+    threadsafe_function::ThreadsafeFunctionCallMode,
+};
+use napi_derive::napi;
+use nusb::{
+    descriptors::{ConfigurationDescriptor, TransferType},
+    transfer::{ControlIn, ControlOut, ControlType, Direction, Recipient},
+};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+const CLASS_HID: u8 = 0x03;
+const DESCRIPTOR_HID: u8 = 0x21;
+const DESCRIPTOR_REPORT: u8 = 0x22;
+
+const GET_DESCRIPTOR: u8 = 0x06;
+const GET_REPORT: u8 = 0x01;
+const SET_REPORT: u8 = 0x09;
+
+const REPORT_TYPE_INPUT: u8 = 1;
+const REPORT_TYPE_OUTPUT: u8 = 2;
+const REPORT_TYPE_FEATURE: u8 = 3;
+
+const CONTROL_TIMEOUT: Duration = Duration::from_millis(1000);
+const INPUT_TRANSFERS: usize = 4;
+
+type InputReportCallback =
+    ThreadsafeFunction<HidInputReport, (), HidInputReport, napi::Status, false>;
+
+#[napi(object)]
+pub struct HidOptions {
+    /// The HID interface to use, defaults to the first one
+    pub interfaceNumber: Option<u8>,
+    /// Detach kernel drivers (e.g. `usbhid`) while the interface is open, defaults to `true`
+    pub detachKernelDriver: Option<bool>,
+}
+
+/// The class descriptor found alongside a HID interface descriptor.
+#[napi(object)]
+pub struct HidDescriptor {
+    #[napi(writable = false)]
+    pub hidVersion: u16,
+    #[napi(writable = false)]
+    pub countryCode: u8,
+    #[napi(writable = false)]
+    pub reportDescriptorLength: u16,
+}
+
+/// One Input, Output or Feature main item, following the WebHID `HIDReportItem` names.
+#[napi(object)]
+#[derive(Clone)]
+pub struct HidReportItem {
+    #[napi(writable = false)]
+    pub isAbsolute: bool,
+    #[napi(writable = false)]
+    pub isArray: bool,
+    #[napi(writable = false)]
+    pub isBufferedBytes: bool,
+    #[napi(writable = false)]
+    pub isConstant: bool,
+    #[napi(writable = false)]
+    pub isLinear: bool,
+    #[napi(writable = false)]
+    pub isRange: bool,
+    #[napi(writable = false)]
+    pub isVolatile: bool,
+    #[napi(writable = false)]
+    pub hasNull: bool,
+    #[napi(writable = false)]
+    pub hasPreferredState: bool,
+    #[napi(writable = false)]
+    pub wrap: bool,
+    /// Extended usages, with the usage page in the upper 16 bits
+    #[napi(writable = false)]
+    pub usages: Vec<u32>,
+    #[napi(writable = false)]
+    pub usageMinimum: Option<u32>,
+    #[napi(writable = false)]
+    pub usageMaximum: Option<u32>,
+    #[napi(writable = false)]
+    pub reportSize: u32,
+    #[napi(writable = false)]
+    pub reportCount: u32,
+    /// Offset of the first field in bits, from the start of the report data after any report ID
+    #[napi(writable = false)]
+    pub bitOffset: u32,
+    #[napi(writable = false)]
+    pub unitExponent: i32,
+    #[napi(writable = false)]
+    pub unit: u32,
+    #[napi(writable = false)]
+    pub logicalMinimum: i32,
+    /// Unsigned when the minimum isn't negative, so a 32 bit maximum may not fit an i32
+    #[napi(writable = false)]
+    pub logicalMaximum: i64,
+    #[napi(writable = false)]
+    pub physicalMinimum: i32,
+    #[napi(writable = false)]
+    pub physicalMaximum: i64,
+}
+
+#[napi(object)]
+#[derive(Clone)]
+pub struct HidReportInfo {
+    #[napi(writable = false)]
+    pub reportId: u8,
+    #[napi(writable = false)]
+    pub items: Vec<HidReportItem>,
+}
+
+#[napi(object)]
+#[derive(Clone)]
+pub struct HidCollectionInfo {
+    #[napi(writable = false)]
+    pub usagePage: u16,
+    #[napi(writable = false)]
+    pub usage: u16,
+    /// 0 physical, 1 application, 2 logical, 3 report, 4 named array, 5 usage switch, 6 usage modifier
+    #[napi(writable = false, js_name = "type")]
+    pub _type: u8,
+    #[napi(writable = false)]
+    pub children: Vec<HidCollectionInfo>,
+    #[napi(writable = false)]
+    pub inputReports: Vec<HidReportInfo>,
+    #[napi(writable = false)]
+    pub outputReports: Vec<HidReportInfo>,
+    #[napi(writable = false)]
+    pub featureReports: Vec<HidReportInfo>,
+}
+
+#[napi(object)]
+pub struct HidInputReport {
+    #[napi(writable = false)]
+    pub reportId: u8,
+    /// The report data, without the report ID
+    #[napi(writable = false)]
+    pub data: Uint8Array,
+}
+
+#[derive(Clone, Default)]
+struct GlobalItems {
+    usage_page: u16,
+    logical_minimum: i32,
+    // Maxima are kept with their item size until the minimum they pair with is known
+    logical_maximum: (u32, usize),
+    physical_minimum: i32,
+    physical_maximum: (u32, usize),
+    unit_exponent: i32,
+    unit: u32,
+    report_size: u32,
+    report_id: u8,
+    report_count: u32,
+}
+
+#[derive(Default)]
+struct LocalItems {
+    // Usage values with their item size, as short usages take the usage page in effect at the main item
+    usages: Vec<(u32, usize)>,
+    usage_minimum: Option<(u32, usize)>,
+    usage_maximum: Option<(u32, usize)>,
+}
+
+fn extended_usage(usage_page: u16, (value, size): (u32, usize)) -> u32 {
+    if size == 4 {
+        value
+    } else {
+        ((usage_page as u32) << 16) | (value & 0xffff)
+    }
+}
+
+fn signed(value: u32, size: usize) -> i32 {
+    match size {
+        1 => value as u8 as i8 as i32,
+        2 => value as u16 as i16 as i32,
+        _ => value as i32,
+    }
+}
+
+/// A non-negative minimum means the maximum is unsigned at its item size, e.g. `0x26 0xff 0x00`
+/// and `0x25 0xff` are both 255 after a minimum of 0.
+fn maximum(minimum: i32, (value, size): (u32, usize)) -> i64 {
+    if minimum >= 0 {
+        value as i64
+    } else {
+        signed(value, size) as i64
+    }
+}
+
+/// Report descriptor parsed into WebHID style collections, plus the size of each report.
+pub(crate) struct ReportDescriptor {
+    pub(crate) collections: Vec<HidCollectionInfo>,
+    // Bits per (report type, report ID)
+    report_bits: HashMap<(u8, u8), u32>,
+}
+
+impl ReportDescriptor {
+    pub(crate) fn uses_report_ids(&self) -> bool {
+        self.report_bits.keys().any(|(_, id)| *id != 0)
+    }
+
+    /// Bytes in a report including its report ID, if it has one.
+    pub(crate) fn report_length(&self, report_type: u8, report_id: u8) -> Option<usize> {
+        self.report_bits
+            .get(&(report_type, report_id))
+            .map(|bits| bits.div_ceil(8) as usize + usize::from(report_id != 0))
+    }
+}
+
+fn report_list(collection: &mut HidCollectionInfo, report_type: u8) -> &mut Vec<HidReportInfo> {
+    match report_type {
+        REPORT_TYPE_INPUT => &mut collection.inputReports,
+        REPORT_TYPE_OUTPUT => &mut collection.outputReports,
+        _ => &mut collection.featureReports,
+    }
+}
+
+pub(crate) fn parse_report_descriptor(
+    descriptor: &[u8],
+) -> std::result::Result<ReportDescriptor, String> {
+    let mut global = GlobalItems::default();
+    let mut global_stack = Vec::new();
+    let mut local = LocalItems::default();
+    let mut report_bits = HashMap::new();
+
+    // The bottom entry collects anything outside a collection
+    let mut stack = vec![HidCollectionInfo {
+        usagePage: 0,
+        usage: 0,
+        _type: 0,
+        children: Vec::new(),
+        inputReports: Vec::new(),
+        outputReports: Vec::new(),
+        featureReports: Vec::new(),
+    }];
+
+    let mut pos = 0;
+    while pos < descriptor.len() {
+        let prefix = descriptor[pos];
+
+        // Long items are reserved and carry nothing we use
+        if prefix == 0xfe {
+            let size = *descriptor.get(pos + 1).ok_or("truncated long item")? as usize;
+            pos += 3 + size;
+            continue;
+        }
+
+        let size = match prefix & 0x03 {
+            3 => 4,
+            s => s as usize,
+        };
+        let item_type = (prefix >> 2) & 0x03;
+        let tag = prefix >> 4;
+        let bytes = descriptor
+            .get(pos + 1..pos + 1 + size)
+            .ok_or_else(|| format!("truncated item at offset {pos}"))?;
+        let value = bytes
+            .iter()
+            .rev()
+            .fold(0u32, |value, byte| (value << 8) | *byte as u32);
+        pos += 1 + size;
+
+        match (item_type, tag) {
+            // Main items
+            (0, 0x08) | (0, 0x09) | (0, 0x0b) => {
+                let report_type = match tag {
+                    0x08 => REPORT_TYPE_INPUT,
+                    0x09 => REPORT_TYPE_OUTPUT,
+                    _ => REPORT_TYPE_FEATURE,
+                };
+                let bits = report_bits
+                    .entry((report_type, global.report_id))
+                    .or_insert(0u32);
+                let bit_offset = *bits;
+                *bits += global.report_size * global.report_count;
+
+                let usage_page = global.usage_page;
+                let item = HidReportItem {
+                    isConstant: value & 0x01 != 0,
+                    isArray: value & 0x02 == 0,
+                    isAbsolute: value & 0x04 == 0,
+                    wrap: value & 0x08 != 0,
+                    isLinear: value & 0x10 == 0,
+                    hasPreferredState: value & 0x20 == 0,
+                    hasNull: value & 0x40 != 0,
+                    isVolatile: report_type != REPORT_TYPE_INPUT && value & 0x80 != 0,
+                    isBufferedBytes: value & 0x100 != 0,
+                    isRange: local.usage_minimum.is_some() && local.usage_maximum.is_some(),
+                    usages: local
+                        .usages
+                        .iter()
+                        .map(|usage| extended_usage(usage_page, *usage))
+                        .collect(),
+                    usageMinimum: local
+                        .usage_minimum
+                        .map(|usage| extended_usage(usage_page, usage)),
+                    usageMaximum: local
+                        .usage_maximum
+                        .map(|usage| extended_usage(usage_page, usage)),
+                    reportSize: global.report_size,
+                    reportCount: global.report_count,
+                    bitOffset: bit_offset,
+                    unitExponent: global.unit_exponent,
+                    unit: global.unit,
+                    logicalMinimum: global.logical_minimum,
+                    logicalMaximum: maximum(global.logical_minimum, global.logical_maximum),
+                    physicalMinimum: global.physical_minimum,
+                    physicalMaximum: maximum(global.physical_minimum, global.physical_maximum),
+                };
+
+                let collection = stack.last_mut().ok_or("collection stack empty")?;
+                let reports = report_list(collection, report_type);
+                match reports
+                    .iter_mut()
+                    .find(|report| report.reportId == global.report_id)
+                {
+                    Some(report) => report.items.push(item),
+                    None => reports.push(HidReportInfo {
+                        reportId: global.report_id,
+                        items: vec![item],
+                    }),
+                }
+                local = LocalItems::default();
+            }
+            (0, 0x0a) => {
+                let usage = local
+                    .usages
+                    .first()
+                    .map(|usage| extended_usage(global.usage_page, *usage))
+                    .unwrap_or((global.usage_page as u32) << 16);
+                stack.push(HidCollectionInfo {
+                    usagePage: (usage >> 16) as u16,
+                    usage: usage as u16,
+                    _type: value as u8,
+                    children: Vec::new(),
+                    inputReports: Vec::new(),
+                    outputReports: Vec::new(),
+                    featureReports: Vec::new(),
+                });
+                local = LocalItems::default();
+            }
+            (0, 0x0c) => {
+                if stack.len() < 2 {
+                    return Err(format!("unbalanced end collection at offset {pos}"));
+                }
+                let collection = stack.pop().ok_or("collection stack empty")?;
+                stack
+                    .last_mut()
+                    .ok_or("collection stack empty")?
+                    .children
+                    .push(collection);
+                local = LocalItems::default();
+            }
+
+            // Global items
+            (1, 0x00) => global.usage_page = value as u16,
+            (1, 0x01) => global.logical_minimum = signed(value, size),
+            (1, 0x02) => global.logical_maximum = (value, size),
+            (1, 0x03) => global.physical_minimum = signed(value, size),
+            (1, 0x04) => global.physical_maximum = (value, size),
+            (1, 0x05) => {
+                // Usually a 4 bit two's complement nibble
+                global.unit_exponent = if value <= 0x0f {
+                    if value > 7 {
+                        value as i32 - 16
+                    } else {
+                        value as i32
+                    }
+                } else {
+                    signed(value, size)
+                }
+            }
+            (1, 0x06) => global.unit = value,
+            (1, 0x07) => global.report_size = value,
+            (1, 0x08) => {
+                if value == 0 || value > 0xff {
+                    return Err(format!("invalid report ID {value}"));
+                }
+                global.report_id = value as u8;
+            }
+            (1, 0x09) => global.report_count = value,
+            (1, 0x0a) => global_stack.push(global.clone()),
+            (1, 0x0b) => {
+                global = global_stack
+                    .pop()
+                    .ok_or_else(|| format!("pop without push at offset {pos}"))?
+            }
+
+            // Local items
+            (2, 0x00) => local.usages.push((value, size)),
+            (2, 0x01) => local.usage_minimum = Some((value, size)),
+            (2, 0x02) => local.usage_maximum = Some((value, size)),
+
+            // Designators, strings, delimiters and reserved items
+            _ => {}
+        }
+    }
+
+    if stack.len() != 1 {
+        return Err("unterminated collection".to_string());
+    }
+    let root = stack.pop().ok_or("collection stack empty")?;
+    let mut collections = root.children.clone();
+    if !root.inputReports.is_empty()
+        || !root.outputReports.is_empty()
+        || !root.featureReports.is_empty()
+    {
+        collections.push(HidCollectionInfo {
+            children: Vec::new(),
+            ..root
+        });
+    }
+
+    Ok(ReportDescriptor {
+        collections,
+        report_bits,
+    })
+}
+
+/// Where the HID function lives in the active configuration.
+struct HidLayout {
+    interface: u8,
+    descriptor: HidDescriptor,
+    interrupt_in: Option<u8>,
+    interrupt_out: Option<u8>,
+}
+
+fn find_hid_layout(
+    config: &ConfigurationDescriptor,
+    interface_number: Option<u8>,
+) -> std::result::Result<HidLayout, String> {
+    let alt = config
+        .interface_alt_settings()
+        .filter(|alt| alt.alternate_setting() == 0)
+        .filter(|alt| interface_number.is_none_or(|n| alt.interface_number() == n))
+        .find(|alt| alt.class() == CLASS_HID)
+        .ok_or("no HID interface found")?;
+
+    // bcdHID, bCountryCode, bNumDescriptors, then (type, length) pairs
+    let descriptor = alt
+        .descriptors()
+        .find(|descriptor| descriptor.descriptor_type() == DESCRIPTOR_HID)
+        .ok_or("no HID descriptor found")?;
+    if descriptor.len() < 6 {
+        return Err("HID descriptor is truncated".to_string());
+    }
+    let report_length = descriptor
+        .get(6..)
+        .unwrap_or_default()
+        .chunks_exact(3)
+        .find(|entry| entry[0] == DESCRIPTOR_REPORT)
+        .map(|entry| u16::from_le_bytes([entry[1], entry[2]]))
+        .ok_or("no report descriptor listed")?;
+
+    let interrupt = |direction| {
+        alt.endpoints()
+            .find(|ep| ep.direction() == direction && ep.transfer_type() == TransferType::Interrupt)
+            .map(|ep| ep.address() & 0x7f)
+    };
+
+    Ok(HidLayout {
+        interface: alt.interface_number(),
+        descriptor: HidDescriptor {
+            hidVersion: u16::from_le_bytes([descriptor[2], descriptor[3]]),
+            countryCode: descriptor[4],
+            reportDescriptorLength: report_length,
+        },
+        interrupt_in: interrupt(Direction::In),
+        interrupt_out: interrupt(Direction::Out),
+    })
+}
+
+fn with_report_id(report_id: u8, data: &[u8]) -> Vec<u8> {
+    let mut report = Vec::with_capacity(data.len() + 1);
+    if report_id != 0 {
+        report.push(report_id);
+    }
+    report.extend_from_slice(data);
+    report
+}
+
+/// A HID interface driven directly, with its report descriptor parsed.
+#[napi]
+pub struct HidInterface {
+    shared: Arc<SharedDevice>,
+    layout: HidLayout,
+    report_descriptor: Vec<u8>,
+    parsed: ReportDescriptor,
+    input_reports: StreamSlot,
+
+    #[napi(writable = false)]
+    pub interfaceNumber: u8,
+}
+
+impl HidInterface {
+    fn control_target(&self, method: &'static str) -> Result<ControlTarget> {
+        self.shared
+            .control_target(Recipient::Interface, self.layout.interface as u16)
+            .ok_or_else(|| napi::Error::from_reason(format!("{method} error: invalid state")))
+    }
+
+    async fn get_report(
+        &self,
+        method: &'static str,
+        report_type: u8,
+        report_id: u8,
+        length: Option<u16>,
+    ) -> Result<Uint8Array> {
+        let length = match length {
+            Some(length) => length,
+            None => self
+                .parsed
+                .report_length(report_type, report_id)
+                .ok_or_else(|| {
+                    napi::Error::from_reason(format!(
+                        "{method} error: report {report_id} not in report descriptor"
+                    ))
+                })? as u16,
+        };
+        let target = self.control_target(method)?;
+        let index = self.layout.interface as u16;
+        let data = run_blocking(move || {
+            let request = ControlIn {
+                control_type: ControlType::Class,
+                recipient: Recipient::Interface,
+                request: GET_REPORT,
+                value: ((report_type as u16) << 8) | report_id as u16,
+                index,
+                length,
+            };
+            target
+                .control_in_blocking(request, CONTROL_TIMEOUT)
+                .map_err(|e| format!("{method} error: {e}"))
+        })
+        .await?;
+        Ok(Uint8Array::from(data))
+    }
+
+    async fn set_report(
+        &self,
+        method: &'static str,
+        report_type: u8,
+        report_id: u8,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        let target = self.control_target(method)?;
+        let index = self.layout.interface as u16;
+        run_blocking(move || {
+            let request = ControlOut {
+                control_type: ControlType::Class,
+                recipient: Recipient::Interface,
+                request: SET_REPORT,
+                value: ((report_type as u16) << 8) | report_id as u16,
+                index,
+                data: &data,
+            };
+            target
+                .control_out_blocking(request, CONTROL_TIMEOUT)
+                .map_err(|e| format!("{method} error: {e}"))
+        })
+        .await
+    }
+}
+
+#[napi]
+impl HidInterface {
+    #[napi(getter)]
+    pub fn hidDescriptor(&self) -> HidDescriptor {
+        HidDescriptor {
+            hidVersion: self.layout.descriptor.hidVersion,
+            countryCode: self.layout.descriptor.countryCode,
+            reportDescriptorLength: self.layout.descriptor.reportDescriptorLength,
+        }
+    }
+
+    /// The raw report descriptor, as read from the device
+    #[napi(getter)]
+    pub fn reportDescriptor(&self) -> Uint8Array {
+        Uint8Array::from(self.report_descriptor.clone())
+    }
+
+    #[napi(getter)]
+    pub fn collections(&self) -> Vec<HidCollectionInfo> {
+        self.parsed.collections.clone()
+    }
+
+    /// Sends an output report on the interrupt OUT endpoint, or with SET_REPORT if there isn't one.
+    #[napi]
+    pub async fn sendReport(&self, reportId: u8, data: Uint8Array) -> Result<()> {
+        let report = with_report_id(reportId, &data);
+        let Some(interrupt_out) = self.layout.interrupt_out else {
+            return self
+                .set_report("sendReport", REPORT_TYPE_OUTPUT, reportId, report)
+                .await;
+        };
+
+        let mut endpoint = self
+            .shared
+            .get_endpoint::<nusb::transfer::Out>(interrupt_out)
+            .ok_or_else(|| napi::Error::from_reason("sendReport error: invalid state"))?;
+        let shared = self.shared.clone();
+        run_blocking(move || {
+            let result = pooled_transfer_out(&shared, &mut endpoint, &report, CONTROL_TIMEOUT)
+                .map(|_| ())
+                .map_err(|e| format!("sendReport error: {e:?}"));
+            shared.put_endpoint(endpoint);
+            result
+        })
+        .await
+    }
+
+    /// Reads an input report with GET_REPORT, including its report ID if it has one.
+    /// The length defaults to the size given by the report descriptor.
+    #[napi]
+    pub async fn receiveReport(&self, reportId: u8, length: Option<u16>) -> Result<Uint8Array> {
+        self.get_report("receiveReport", REPORT_TYPE_INPUT, reportId, length)
+            .await
+    }
+
+    #[napi]
+    pub async fn sendFeatureReport(&self, reportId: u8, data: Uint8Array) -> Result<()> {
+        let report = with_report_id(reportId, &data);
+        self.set_report("sendFeatureReport", REPORT_TYPE_FEATURE, reportId, report)
+            .await
+    }
+
+    /// Reads a feature report, including its report ID if it has one.
+    /// The length defaults to the size given by the report descriptor.
+    #[napi]
+    pub async fn receiveFeatureReport(
+        &self,
+        reportId: u8,
+        length: Option<u16>,
+    ) -> Result<Uint8Array> {
+        self.get_report(
+            "receiveFeatureReport",
+            REPORT_TYPE_FEATURE,
+            reportId,
+            length,
+        )
+        .await
+    }
+
+    /// Starts reading the interrupt IN endpoint, calling back with each input report.
+    #[napi]
+    pub fn onInputReport(&self, callback: InputReportCallback) -> Result<()> {
+        let interrupt_in = self.layout.interrupt_in.ok_or_else(|| {
+            napi::Error::from_reason("onInputReport error: no interrupt IN endpoint")
+        })?;
+
+        let uses_report_ids = self.parsed.uses_report_ids();
+        let max_length = (1..=255)
+            .chain([0])
+            .filter_map(|id| self.parsed.report_length(REPORT_TYPE_INPUT, id))
+            .max()
+            .unwrap_or(64);
+        self.input_reports
+            .start("onInputReport", &self.shared, interrupt_in, |endpoint| {
+                InStream::start(
+                    endpoint,
+                    max_length,
+                    INPUT_TRANSFERS,
+                    move |data| {
+                        let (report_id, data) = match data.split_first() {
+                            Some((id, rest)) if uses_report_ids => (*id, rest),
+                            _ => (0, data),
+                        };
+                        callback.call(
+                            HidInputReport {
+                                reportId: report_id,
+                                data: Uint8Array::new(data.to_vec()),
+                            },
+                            ThreadsafeFunctionCallMode::NonBlocking,
+                        );
+                    },
+                    |_| {},
+                )
+            })
+    }
+
+    #[napi]
+    pub fn stopInputReports(&self) {
+        self.input_reports.stop();
+    }
+
+    /// Stops input reports and releases the interface, reattaching any kernel driver.
+    #[napi]
+    pub async fn close(&self) -> Result<()> {
+        // Its endpoint keeps the interface claimed until the stream has ended
+        self.input_reports.finish().await;
+        self.shared
+            .release_interface("close", self.layout.interface)
+            .await
+    }
+}
+
+#[napi]
+impl UsbDevice {
+    /// Claims a HID interface, reads its report descriptor and returns it parsed.
+    #[napi]
+    pub async fn openHid(&self, options: Option<HidOptions>) -> Result<HidInterface> {
+        let interface_number = options.as_ref().and_then(|o| o.interfaceNumber);
+        let detach = options
+            .as_ref()
+            .and_then(|o| o.detachKernelDriver)
+            .unwrap_or(true);
+
+        let shared = self.shared().clone();
+        let device = shared
+            .opened_device()
+            .ok_or_else(|| napi::Error::from_reason("openHid error: invalid state"))?;
+        let layout = run_blocking(move || {
+            let config = device
+                .active_configuration()
+                .map_err(|e| format!("openHid error: {e}"))?;
+            find_hid_layout(&config, interface_number).map_err(|e| format!("openHid error: {e}"))
+        })
+        .await?;
+
+        shared
+            .claim_interface("openHid", layout.interface, detach)
+            .await?;
+
+        let target = shared
+            .control_target(Recipient::Interface, layout.interface as u16)
+            .ok_or_else(|| napi::Error::from_reason("openHid error: invalid state"))?;
+        let index = layout.interface as u16;
+        let length = layout.descriptor.reportDescriptorLength;
+        let (report_descriptor, parsed) = run_blocking(move || {
+            let request = ControlIn {
+                control_type: ControlType::Standard,
+                recipient: Recipient::Interface,
+                request: GET_DESCRIPTOR,
+                value: (DESCRIPTOR_REPORT as u16) << 8,
+                index,
+                length,
+            };
+            let descriptor = target
+                .control_in_blocking(request, CONTROL_TIMEOUT)
+                .map_err(|e| format!("openHid error: {e}"))?;
+            let parsed = parse_report_descriptor(&descriptor)
+                .map_err(|e| format!("openHid error: report descriptor {e}"))?;
+            Ok((descriptor, parsed))
+        })
+        .await?;
+
+        Ok(HidInterface {
+            shared,
+            interfaceNumber: layout.interface,
+            layout,
+            report_descriptor,
+            parsed,
+            input_reports: StreamSlot::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The boot keyboard from appendix B.1 of the HID spec, with keys up to 255
+    const BOOT_KEYBOARD: &[u8] = &[
+        0x05, 0x01, 0x09, 0x06, 0xa1, 0x01, 0x05, 0x07, 0x19, 0xe0, 0x29, 0xe7, 0x15, 0x00, 0x25,
+        0x01, 0x75, 0x01, 0x95, 0x08, 0x81, 0x02, 0x95, 0x01, 0x75, 0x08, 0x81, 0x01, 0x95, 0x05,
+        0x75, 0x01, 0x05, 0x08, 0x19, 0x01, 0x29, 0x05, 0x91, 0x02, 0x95, 0x01, 0x75, 0x03, 0x91,
+        0x01, 0x95, 0x06, 0x75, 0x08, 0x15, 0x00, 0x26, 0xff, 0x00, 0x05, 0x07, 0x19, 0x00, 0x2a,
+        0xff, 0x00, 0x81, 0x00, 0xc0,
+    ];
+
+    #[test]
+    fn parses_boot_keyboard() {
+        let parsed = parse_report_descriptor(BOOT_KEYBOARD).unwrap();
+        assert!(!parsed.uses_report_ids());
+        assert_eq!(parsed.report_length(REPORT_TYPE_INPUT, 0), Some(8));
+        assert_eq!(parsed.report_length(REPORT_TYPE_OUTPUT, 0), Some(1));
+
+        let [keyboard] = parsed.collections.as_slice() else {
+            panic!("expected one collection");
+        };
+        assert_eq!(
+            (keyboard.usagePage, keyboard.usage, keyboard._type),
+            (0x01, 0x06, 1)
+        );
+
+        let items = &keyboard.inputReports[0].items;
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0].usageMinimum, Some(0x0007_00e0));
+        assert_eq!(items[0].usageMaximum, Some(0x0007_00e7));
+        assert!(!items[0].isArray);
+        assert!(items[1].isConstant);
+        assert_eq!((items[2].bitOffset, items[2].reportCount), (16, 6));
+        assert_eq!((items[2].logicalMinimum, items[2].logicalMaximum), (0, 255));
+        assert!(items[2].isArray);
+
+        let leds = &keyboard.outputReports[0].items[0];
+        assert_eq!(leds.usageMinimum, Some(0x0008_0001));
+        assert_eq!(leds.usageMaximum, Some(0x0008_0005));
+    }
+
+    #[test]
+    fn maximum_is_unsigned_after_a_non_negative_minimum() {
+        let descriptor = [
+            0x06, 0x00, 0xff, 0x09, 0x01, 0xa1, 0x01, // Vendor collection
+            0x15, 0x00, 0x25, 0xff, 0x75, 0x08, 0x95, 0x01, 0x81, 0x02, // 0 to 0xff
+            0x26, 0xff, 0xff, 0x75, 0x10, 0x81, 0x02, // 0 to 0xffff
+            0x27, 0xff, 0xff, 0xff, 0xff, 0x75, 0x20, 0x81, 0x02, // 0 to 0xffffffff
+            0x15, 0x81, 0x25, 0x7f, 0x75, 0x08, 0x81, 0x02, // -127 to 127
+            0x15, 0xff, 0x25, 0xff, 0x81, 0x02, // -1 to -1
+            0xc0,
+        ];
+        let parsed = parse_report_descriptor(&descriptor).unwrap();
+        let ranges: Vec<_> = parsed.collections[0].inputReports[0]
+            .items
+            .iter()
+            .map(|item| (item.logicalMinimum, item.logicalMaximum))
+            .collect();
+        assert_eq!(
+            ranges,
+            [
+                (0, 0xff),
+                (0, 0xffff),
+                (0, 0xffff_ffff),
+                (-127, 127),
+                (-1, -1)
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_unbalanced_collections() {
+        assert!(parse_report_descriptor(&[0x05, 0x01, 0xa1, 0x01]).is_err());
+        assert!(parse_report_descriptor(&[0xc0]).is_err());
+        assert!(parse_report_descriptor(&[0x26, 0xff]).is_err());
+    }
+
+    /// A configuration with one HID interface, its HID descriptor and an interrupt IN endpoint.
+    fn configuration(hid: &[u8]) -> Vec<u8> {
+        let mut data = vec![9, 0x02, 0, 0, 1, 1, 0, 0x80, 50];
+        data.extend_from_slice(&[9, 0x04, 0, 0, 1, CLASS_HID, 1, 1, 0]);
+        data.extend_from_slice(hid);
+        data.extend_from_slice(&[7, 0x05, 0x81, 0x03, 8, 0, 10]);
+        let total = data.len() as u16;
+        data[2..4].copy_from_slice(&total.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn finds_the_hid_layout() {
+        let data = configuration(&[
+            9,
+            DESCRIPTOR_HID,
+            0x11,
+            0x01,
+            0,
+            1,
+            DESCRIPTOR_REPORT,
+            63,
+            0,
+        ]);
+        let config = ConfigurationDescriptor::new(&data).unwrap();
+        let layout = find_hid_layout(&config, None).unwrap();
+        assert_eq!(layout.interface, 0);
+        assert_eq!(layout.descriptor.hidVersion, 0x0111);
+        assert_eq!(layout.descriptor.reportDescriptorLength, 63);
+        assert_eq!(layout.interrupt_in, Some(1));
+        assert_eq!(layout.interrupt_out, None);
+    }
+
+    #[test]
+    fn rejects_a_truncated_hid_descriptor() {
+        for hid in [
+            &[3, DESCRIPTOR_HID, 0x11][..],
+            &[5, DESCRIPTOR_HID, 0x11, 0x01, 0],
+        ] {
+            let data = configuration(hid);
+            let config = ConfigurationDescriptor::new(&data).unwrap();
+            assert_eq!(
+                find_hid_layout(&config, None).err().unwrap(),
+                "HID descriptor is truncated"
+            );
+        }
+    }
+}
@@ -1,5 +1,30 @@
 #![allow(non_snake_case)]
 
+mod adb;
+mod aoa;
+mod ccid;
+mod cdc_acm;
+mod cdc_ether;
+mod chapter9;
+mod cmsis_dap;
+mod dfu;
+mod endpoint_stream;
+mod firmware;
+mod hid;
+mod mass_storage;
+mod midi;
+mod power;
+mod printer;
+mod ptp;
+mod sequence;
+mod serial;
+mod standard_request;
+mod transfer_pool;
+mod uac;
+mod udev;
+mod usb_serial;
+mod usbtmc;
+mod uvc;
 mod webusb_device;
 
 use futures_lite::StreamExt;
@@ -11,7 +36,8 @@ use napi_derive::napi;
 use nusb::{hotplug::HotplugEvent, MaybeFuture};
 use std::sync::{Arc, Mutex, MutexGuard};
 use tokio::task::JoinHandle;
-use webusb_device::{run_blocking, UsbDevice};
+use udev::{UdevRule, UdevRuleFilter, UdevRuleOptions};
+use webusb_device::{forget_shared_device, run_blocking, UsbDevice};
 
 struct Callbacks {
     attach: Option<ThreadsafeFunction<UsbDevice, (), UsbDevice, napi::Status, false>>,
@@ -38,6 +64,12 @@ pub struct Emitter {
     watch_task: Mutex<Option<JoinHandle<()>>>,
 }
 
+impl Default for Emitter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[napi]
 impl Emitter {
     fn callbacks(&self) -> MutexGuard<'_, Callbacks> {
@@ -90,6 +122,7 @@ impl Emitter {
                         }
                     }
                     HotplugEvent::Disconnected(id) => {
+                        forget_shared_device(id);
                         let guard = callbacks_guard(&callbacks);
                         if let Some(cb) = guard.detach.as_ref() {
                             cb.call(format!("{:?}", id), ThreadsafeFunctionCallMode::NonBlocking);
@@ -163,6 +196,45 @@ impl Drop for Emitter {
     }
 }
 
+/// Waits for a device to arrive, e.g. one re-enumerating in another mode after a detach or reset.
+/// Start it before triggering the re-enumeration so a quick arrival isn't missed.
+pub(crate) struct DeviceWait {
+    watch_stream: nusb::hotplug::HotplugWatch,
+}
+
+impl DeviceWait {
+    pub(crate) fn start() -> std::result::Result<Self, String> {
+        let watch_stream =
+            nusb::watch_devices().map_err(|e| format!("watch devices error: {e}"))?;
+        Ok(Self { watch_stream })
+    }
+
+    pub(crate) async fn until<F>(
+        mut self,
+        matches: F,
+        timeout: std::time::Duration,
+    ) -> std::result::Result<nusb::DeviceInfo, String>
+    where
+        F: Fn(&nusb::DeviceInfo) -> bool,
+    {
+        let arrival = async {
+            while let Some(ev) = self.watch_stream.next().await {
+                match ev {
+                    HotplugEvent::Connected(info) if matches(&info) => return Some(info),
+                    HotplugEvent::Connected(_) => {}
+                    HotplugEvent::Disconnected(id) => forget_shared_device(id),
+                }
+            }
+            None
+        };
+        tokio::time::timeout(timeout, arrival)
+            .await
+            .ok()
+            .flatten()
+            .ok_or_else(|| "timed out waiting for device".to_string())
+    }
+}
+
 async fn list_devices(error_prefix: &'static str) -> Result<Vec<nusb::DeviceInfo>> {
     run_blocking(move || {
         nusb::list_devices()
@@ -196,3 +268,11 @@ pub async fn findDeviceBySerial(serialNumber: String) -> Result<Option<UsbDevice
         .find(|dev| dev.serial_number() == Some(serialNumber.as_str()));
     Ok(device.map(UsbDevice::new))
 }
+
+#[napi(js_name = "nativeUdevRule")]
+pub async fn udevRule(
+    filter: UdevRuleFilter,
+    options: Option<UdevRuleOptions>,
+) -> Result<UdevRule> {
+    run_blocking(move || udev::rule_for_filter(filter, options)).await
+}
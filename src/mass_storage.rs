@@ -0,0 +1,670 @@
+use crate::transfer_pool::{pooled_transfer_in, pooled_transfer_out, PooledEndpoint};
+use crate::webusb_device::{run_blocking, SharedDevice, UsbDevice};
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use nusb::{
+    descriptors::{ConfigurationDescriptor, TransferType},
+    transfer::{ControlIn, ControlOut, ControlType, Direction, In, Out, Recipient, TransferError},
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+const CLASS_MASS_STORAGE: u8 = 0x08;
+const SUBCLASS_SCSI: u8 = 0x06;
+const PROTOCOL_BULK_ONLY: u8 = 0x50;
+
+const BULK_ONLY_RESET: u8 = 0xff;
+const GET_MAX_LUN: u8 = 0xfe;
+
+const CBW_SIGNATURE: u32 = 0x4342_5355;
+const CSW_SIGNATURE: u32 = 0x5342_5355;
+const CBW_LENGTH: usize = 31;
+const CSW_LENGTH: usize = 13;
+
+const CSW_PASSED: u8 = 0;
+const CSW_FAILED: u8 = 1;
+
+const TEST_UNIT_READY: u8 = 0x00;
+const REQUEST_SENSE: u8 = 0x03;
+const INQUIRY: u8 = 0x12;
+const READ_CAPACITY_10: u8 = 0x25;
+const READ_10: u8 = 0x28;
+const WRITE_10: u8 = 0x2a;
+const SERVICE_ACTION_IN_16: u8 = 0x9e;
+const READ_CAPACITY_16: u8 = 0x10;
+
+const CONTROL_TIMEOUT: Duration = Duration::from_millis(1000);
+const COMMAND_TIMEOUT: Duration = Duration::from_millis(10000);
+
+/// Largest data phase per READ(10)/WRITE(10), longer requests are split.
+const MAX_TRANSFER_LENGTH: usize = 64 * 1024;
+
+#[napi(object)]
+pub struct MassStorageOptions {
+    /// The mass storage interface to use, defaults to the first Bulk-Only SCSI one
+    pub interfaceNumber: Option<u8>,
+    /// Detach kernel drivers (e.g. `usb-storage`) while the interface is open, defaults to `true`
+    pub detachKernelDriver: Option<bool>,
+}
+
+#[napi(object)]
+pub struct ScsiInquiry {
+    #[napi(writable = false)]
+    pub peripheralDeviceType: u8,
+    #[napi(writable = false)]
+    pub removable: bool,
+    #[napi(writable = false)]
+    pub vendor: String,
+    #[napi(writable = false)]
+    pub product: String,
+    #[napi(writable = false)]
+    pub revision: String,
+}
+
+#[napi(object)]
+pub struct ScsiSense {
+    #[napi(writable = false)]
+    pub senseKey: u8,
+    #[napi(writable = false)]
+    pub additionalSenseCode: u8,
+    #[napi(writable = false)]
+    pub additionalSenseCodeQualifier: u8,
+}
+
+#[napi(object)]
+pub struct BlockCapacity {
+    #[napi(writable = false)]
+    pub blockCount: i64,
+    #[napi(writable = false)]
+    pub blockLength: u32,
+}
+
+enum DataPhase<'a> {
+    None,
+    In(usize),
+    Out(&'a [u8]),
+}
+
+struct CommandResult {
+    data: Vec<u8>,
+    status: u8,
+}
+
+/// Where the Bulk-Only function lives in the active configuration.
+struct BotLayout {
+    interface: u8,
+    bulk_in: u8,
+    bulk_out: u8,
+}
+
+fn find_bot_layout(
+    config: &ConfigurationDescriptor,
+    interface_number: Option<u8>,
+) -> std::result::Result<BotLayout, String> {
+    let alt = config
+        .interface_alt_settings()
+        .filter(|alt| alt.alternate_setting() == 0)
+        .filter(|alt| interface_number.is_none_or(|n| alt.interface_number() == n))
+        .find(|alt| {
+            alt.class() == CLASS_MASS_STORAGE
+                && alt.subclass() == SUBCLASS_SCSI
+                && alt.protocol() == PROTOCOL_BULK_ONLY
+        })
+        .ok_or("no Bulk-Only mass storage interface found")?;
+
+    let bulk = |direction| {
+        alt.endpoints()
+            .find(|ep| ep.direction() == direction && ep.transfer_type() == TransferType::Bulk)
+            .map(|ep| ep.address() & 0x7f)
+    };
+
+    Ok(BotLayout {
+        interface: alt.interface_number(),
+        bulk_in: bulk(Direction::In).ok_or("no bulk IN endpoint")?,
+        bulk_out: bulk(Direction::Out).ok_or("no bulk OUT endpoint")?,
+    })
+}
+
+fn trimmed_ascii(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).trim_end().to_string()
+}
+
+/// Bulk-Only Transport, with the endpoints checked out of the pool for each command.
+/// It sits behind a mutex so a command and its REQUEST SENSE run uninterrupted.
+struct BotTransport {
+    shared: Arc<SharedDevice>,
+    layout: BotLayout,
+    tag: u32,
+}
+
+impl BotTransport {
+    fn endpoints(&self) -> std::result::Result<(PooledEndpoint<In>, PooledEndpoint<Out>), String> {
+        let bulk_in = self
+            .shared
+            .get_endpoint::<In>(self.layout.bulk_in)
+            .ok_or("invalid state")?;
+        let bulk_out = self
+            .shared
+            .get_endpoint::<Out>(self.layout.bulk_out)
+            .ok_or("invalid state")?;
+        Ok((bulk_in, bulk_out))
+    }
+
+    /// Bulk-Only Mass Storage Reset followed by clearing both halts, as section 5.3.4 asks.
+    fn reset_recovery(&self) -> std::result::Result<(), String> {
+        let target = self
+            .shared
+            .control_target(Recipient::Interface, self.layout.interface as u16)
+            .ok_or("invalid state")?;
+        target
+            .control_out_blocking(
+                ControlOut {
+                    control_type: ControlType::Class,
+                    recipient: Recipient::Interface,
+                    request: BULK_ONLY_RESET,
+                    value: 0,
+                    index: self.layout.interface as u16,
+                    data: &[],
+                },
+                CONTROL_TIMEOUT,
+            )
+            .map_err(|e| format!("reset failed: {e}"))?;
+
+        let (mut bulk_in, mut bulk_out) = self.endpoints()?;
+        let result = bulk_in
+            .clear_halt_blocking()
+            .and_then(|_| bulk_out.clear_halt_blocking())
+            .map_err(|e| format!("clear halt failed: {e}"));
+        self.shared.put_endpoint(bulk_in);
+        self.shared.put_endpoint(bulk_out);
+        result
+    }
+
+    fn max_lun(&self) -> std::result::Result<u8, String> {
+        let target = self
+            .shared
+            .control_target(Recipient::Interface, self.layout.interface as u16)
+            .ok_or("invalid state")?;
+        let result = target.control_in_blocking(
+            ControlIn {
+                control_type: ControlType::Class,
+                recipient: Recipient::Interface,
+                request: GET_MAX_LUN,
+                value: 0,
+                index: self.layout.interface as u16,
+                length: 1,
+            },
+            CONTROL_TIMEOUT,
+        );
+        match result {
+            Ok(data) => Ok(data.first().copied().unwrap_or(0)),
+            // Devices with a single LUN may stall the request
+            Err(TransferError::Stall) => Ok(0),
+            Err(e) => Err(format!("GET MAX LUN failed: {e}")),
+        }
+    }
+
+    /// Runs one command block, recovering the transport as the spec asks whenever the exchange goes wrong.
+    fn execute(
+        &mut self,
+        lun: u8,
+        command: &[u8],
+        data_phase: DataPhase,
+    ) -> std::result::Result<CommandResult, String> {
+        self.tag = self.tag.wrapping_add(1);
+        let (mut bulk_in, mut bulk_out) = self.endpoints()?;
+        let result = self.exchange(&mut bulk_in, &mut bulk_out, lun, command, data_phase);
+        self.shared.put_endpoint(bulk_in);
+        self.shared.put_endpoint(bulk_out);
+
+        if result.is_err() {
+            // Best effort, the original error is the one worth reporting
+            let _ = self.reset_recovery();
+        }
+        result
+    }
+
+    fn exchange(
+        &self,
+        bulk_in: &mut PooledEndpoint<In>,
+        bulk_out: &mut PooledEndpoint<Out>,
+        lun: u8,
+        command: &[u8],
+        data_phase: DataPhase,
+    ) -> std::result::Result<CommandResult, String> {
+        let tag = self.tag;
+        let (data_length, flags) = match data_phase {
+            DataPhase::None => (0, 0x00),
+            DataPhase::In(length) => (length, 0x80),
+            DataPhase::Out(data) => (data.len(), 0x00),
+        };
+        let mut cbw = Vec::with_capacity(CBW_LENGTH);
+        cbw.extend_from_slice(&CBW_SIGNATURE.to_le_bytes());
+        cbw.extend_from_slice(&tag.to_le_bytes());
+        cbw.extend_from_slice(&(data_length as u32).to_le_bytes());
+        cbw.push(flags);
+        cbw.push(lun);
+        cbw.push(command.len() as u8);
+        cbw.extend_from_slice(command);
+        cbw.resize(CBW_LENGTH, 0);
+        pooled_transfer_out(&self.shared, bulk_out, &cbw, COMMAND_TIMEOUT)
+            .map_err(|e| format!("CBW failed: {e:?}"))?;
+
+        // A stalled data phase is cleared and the CSW still read, which reports what happened
+        let mut data = Vec::new();
+        match data_phase {
+            DataPhase::None => {}
+            DataPhase::In(length) => {
+                match pooled_transfer_in(&self.shared, bulk_in, length, COMMAND_TIMEOUT) {
+                    Ok((buffer, len)) => {
+                        data.extend_from_slice(&buffer[..len]);
+                        self.shared.put_buffer(bulk_in, buffer);
+                    }
+                    Err(TransferError::Stall) => bulk_in
+                        .clear_halt_blocking()
+                        .map_err(|e| format!("clear halt failed: {e}"))?,
+                    Err(e) => return Err(format!("data IN failed: {e:?}")),
+                }
+            }
+            DataPhase::Out(out) => {
+                match pooled_transfer_out(&self.shared, bulk_out, out, COMMAND_TIMEOUT) {
+                    Ok(_) => {}
+                    Err(TransferError::Stall) => bulk_out
+                        .clear_halt_blocking()
+                        .map_err(|e| format!("clear halt failed: {e}"))?,
+                    Err(e) => return Err(format!("data OUT failed: {e:?}")),
+                }
+            }
+        }
+
+        // The CSW read is retried once after a stall
+        let csw = match pooled_transfer_in(&self.shared, bulk_in, CSW_LENGTH, COMMAND_TIMEOUT) {
+            Err(TransferError::Stall) => {
+                bulk_in
+                    .clear_halt_blocking()
+                    .map_err(|e| format!("clear halt failed: {e}"))?;
+                pooled_transfer_in(&self.shared, bulk_in, CSW_LENGTH, COMMAND_TIMEOUT)
+            }
+            result => result,
+        };
+        let (buffer, len) = csw.map_err(|e| format!("CSW failed: {e:?}"))?;
+        let csw = buffer[..len].to_vec();
+        self.shared.put_buffer(bulk_in, buffer);
+
+        if csw.len() != CSW_LENGTH
+            || u32::from_le_bytes([csw[0], csw[1], csw[2], csw[3]]) != CSW_SIGNATURE
+        {
+            return Err("invalid CSW".to_string());
+        }
+        if u32::from_le_bytes([csw[4], csw[5], csw[6], csw[7]]) != tag {
+            return Err("CSW tag mismatch".to_string());
+        }
+        let status = csw[12];
+        if status != CSW_PASSED && status != CSW_FAILED {
+            return Err("phase error".to_string());
+        }
+        Ok(CommandResult { data, status })
+    }
+
+    fn request_sense(&mut self, lun: u8) -> std::result::Result<ScsiSense, String> {
+        let result = self.execute(lun, &[REQUEST_SENSE, 0, 0, 0, 18, 0], DataPhase::In(18))?;
+        let sense = result.data;
+        if result.status != CSW_PASSED || sense.len() < 14 {
+            return Err("REQUEST SENSE failed".to_string());
+        }
+        Ok(ScsiSense {
+            senseKey: sense[2] & 0x0f,
+            additionalSenseCode: sense[12],
+            additionalSenseCodeQualifier: sense[13],
+        })
+    }
+
+    /// Runs a command, turning CHECK CONDITION into an error carrying the sense data.
+    fn command(
+        &mut self,
+        lun: u8,
+        command: &[u8],
+        data_phase: DataPhase,
+    ) -> std::result::Result<Vec<u8>, String> {
+        let result = self.execute(lun, command, data_phase)?;
+        if result.status == CSW_PASSED {
+            return Ok(result.data);
+        }
+        let sense = self.request_sense(lun)?;
+        Err(format!(
+            "command {:#04x} failed (sense key {:#04x}, ASC {:#04x}, ASCQ {:#04x})",
+            command[0],
+            sense.senseKey,
+            sense.additionalSenseCode,
+            sense.additionalSenseCodeQualifier
+        ))
+    }
+
+    fn read_capacity(&mut self, lun: u8) -> std::result::Result<(u64, u32), String> {
+        let data = self.command(
+            lun,
+            &[READ_CAPACITY_10, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            DataPhase::In(8),
+        )?;
+        if data.len() < 8 {
+            return Err("short READ CAPACITY(10) data".to_string());
+        }
+        let last_lba = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let block_length = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+        if last_lba != u32::MAX {
+            return Ok((last_lba as u64 + 1, block_length));
+        }
+
+        // Too large for READ CAPACITY(10)
+        let mut command = [0u8; 16];
+        command[0] = SERVICE_ACTION_IN_16;
+        command[1] = READ_CAPACITY_16;
+        command[13] = 32;
+        let data = self.command(lun, &command, DataPhase::In(32))?;
+        if data.len() < 12 {
+            return Err("short READ CAPACITY(16) data".to_string());
+        }
+        let last_lba = u64::from_be_bytes([
+            data[0], data[1], data[2], data[3], data[4], data[5], data[6], data[7],
+        ]);
+        let block_length = u32::from_be_bytes([data[8], data[9], data[10], data[11]]);
+        Ok((last_lba + 1, block_length))
+    }
+}
+
+fn rw10(opcode: u8, lba: u32, blocks: u16) -> [u8; 10] {
+    let lba = lba.to_be_bytes();
+    let blocks = blocks.to_be_bytes();
+    [
+        opcode, 0, lba[0], lba[1], lba[2], lba[3], 0, blocks[0], blocks[1], 0,
+    ]
+}
+
+/// Blocks per READ(10)/WRITE(10) for this block length.
+fn blocks_per_command(block_length: u32) -> u32 {
+    (MAX_TRANSFER_LENGTH as u32 / block_length).clamp(1, u16::MAX as u32)
+}
+
+/// A Bulk-Only SCSI block device, such as a flash drive, accessed without mounting it.
+#[napi]
+pub struct MassStorageDevice {
+    shared: Arc<SharedDevice>,
+    transport: Arc<Mutex<BotTransport>>,
+    block_lengths: Mutex<HashMap<u8, u32>>,
+
+    #[napi(writable = false)]
+    pub interfaceNumber: u8,
+    /// Highest logical unit number, from GET MAX LUN
+    #[napi(writable = false)]
+    pub maxLun: u8,
+}
+
+impl MassStorageDevice {
+    async fn run<T, F>(&self, method: &'static str, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut BotTransport) -> std::result::Result<T, String> + Send + 'static,
+    {
+        let transport = self.transport.clone();
+        run_blocking(move || {
+            let mut transport = transport
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            f(&mut transport).map_err(|e| format!("{method} error: {e}"))
+        })
+        .await
+    }
+
+    /// Block length of a LUN, from READ CAPACITY the first time it is needed.
+    async fn block_length(&self, method: &'static str, lun: u8) -> Result<u32> {
+        if let Some(block_length) = self.block_lengths_guard().get(&lun) {
+            return Ok(*block_length);
+        }
+        let (_, block_length) = self
+            .run(method, move |transport| transport.read_capacity(lun))
+            .await?;
+        if block_length == 0 {
+            return Err(napi::Error::from_reason(format!(
+                "{method} error: invalid block length"
+            )));
+        }
+        self.block_lengths_guard().insert(lun, block_length);
+        Ok(block_length)
+    }
+
+    fn block_lengths_guard(&self) -> MutexGuard<'_, HashMap<u8, u32>> {
+        self.block_lengths
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[napi]
+impl MassStorageDevice {
+    #[napi]
+    pub async fn inquiry(&self, lun: Option<u8>) -> Result<ScsiInquiry> {
+        let lun = lun.unwrap_or(0);
+        self.run("inquiry", move |transport| {
+            let data = transport.command(lun, &[INQUIRY, 0, 0, 0, 36, 0], DataPhase::In(36))?;
+            if data.len() < 36 {
+                return Err("short INQUIRY data".to_string());
+            }
+            Ok(ScsiInquiry {
+                peripheralDeviceType: data[0] & 0x1f,
+                removable: data[1] & 0x80 != 0,
+                vendor: trimmed_ascii(&data[8..16]),
+                product: trimmed_ascii(&data[16..32]),
+                revision: trimmed_ascii(&data[32..36]),
+            })
+        })
+        .await
+    }
+
+    /// Resolves `true` if the unit is ready, or `false` if it reports CHECK CONDITION (see `requestSense()`).
+    #[napi]
+    pub async fn testUnitReady(&self, lun: Option<u8>) -> Result<bool> {
+        let lun = lun.unwrap_or(0);
+        self.run("testUnitReady", move |transport| {
+            let result =
+                transport.execute(lun, &[TEST_UNIT_READY, 0, 0, 0, 0, 0], DataPhase::None)?;
+            Ok(result.status == CSW_PASSED)
+        })
+        .await
+    }
+
+    #[napi]
+    pub async fn requestSense(&self, lun: Option<u8>) -> Result<ScsiSense> {
+        let lun = lun.unwrap_or(0);
+        self.run("requestSense", move |transport| {
+            transport.request_sense(lun)
+        })
+        .await
+    }
+
+    /// Uses READ CAPACITY(10), or READ CAPACITY(16) for devices too large for it.
+    #[napi]
+    pub async fn readCapacity(&self, lun: Option<u8>) -> Result<BlockCapacity> {
+        let lun = lun.unwrap_or(0);
+        let (block_count, block_length) = self
+            .run("readCapacity", move |transport| {
+                transport.read_capacity(lun)
+            })
+            .await?;
+        if block_length != 0 {
+            self.block_lengths_guard().insert(lun, block_length);
+        }
+        Ok(BlockCapacity {
+            blockCount: block_count as i64,
+            blockLength: block_length,
+        })
+    }
+
+    /// Reads `count` blocks starting at `lba` with READ(10).
+    #[napi]
+    pub async fn readBlocks(&self, lba: u32, count: u32, lun: Option<u8>) -> Result<Uint8Array> {
+        let lun = lun.unwrap_or(0);
+        let block_length = self.block_length("readBlocks", lun).await?;
+        lba.checked_add(count).ok_or_else(|| {
+            napi::Error::from_reason("readBlocks error: range beyond READ(10) addressing")
+        })?;
+
+        let data = self
+            .run("readBlocks", move |transport| {
+                let mut data = Vec::with_capacity(count as usize * block_length as usize);
+                let step = blocks_per_command(block_length);
+                let mut done = 0;
+                while done < count {
+                    let blocks = (count - done).min(step);
+                    let length = blocks as usize * block_length as usize;
+                    let chunk = transport.command(
+                        lun,
+                        &rw10(READ_10, lba + done, blocks as u16),
+                        DataPhase::In(length),
+                    )?;
+                    if chunk.len() != length {
+                        return Err(format!("short read at block {}", lba + done));
+                    }
+                    data.extend_from_slice(&chunk);
+                    done += blocks;
+                }
+                Ok(data)
+            })
+            .await?;
+        Ok(Uint8Array::from(data))
+    }
+
+    /// Writes whole blocks starting at `lba` with WRITE(10).
+    #[napi]
+    pub async fn writeBlocks(&self, lba: u32, data: Uint8Array, lun: Option<u8>) -> Result<()> {
+        let lun = lun.unwrap_or(0);
+        let block_length = self.block_length("writeBlocks", lun).await?;
+        if !data.len().is_multiple_of(block_length as usize) {
+            return Err(napi::Error::from_reason(format!(
+                "writeBlocks error: data is not a multiple of the {block_length} byte block length"
+            )));
+        }
+        let count = (data.len() / block_length as usize) as u32;
+        lba.checked_add(count).ok_or_else(|| {
+            napi::Error::from_reason("writeBlocks error: range beyond WRITE(10) addressing")
+        })?;
+
+        self.run("writeBlocks", move |transport| {
+            let step = blocks_per_command(block_length);
+            let mut done = 0;
+            while done < count {
+                let blocks = (count - done).min(step);
+                let start = done as usize * block_length as usize;
+                let end = start + blocks as usize * block_length as usize;
+                transport.command(
+                    lun,
+                    &rw10(WRITE_10, lba + done, blocks as u16),
+                    DataPhase::Out(&data[start..end]),
+                )?;
+                done += blocks;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Performs Bulk-Only reset recovery, e.g. after a command was interrupted.
+    #[napi]
+    pub async fn reset(&self) -> Result<()> {
+        self.run("reset", |transport| transport.reset_recovery())
+            .await
+    }
+
+    /// Releases the interface, reattaching any kernel driver.
+    #[napi]
+    pub async fn close(&self) -> Result<()> {
+        self.shared
+            .release_interface("close", self.interfaceNumber)
+            .await
+    }
+}
+
+#[napi]
+impl UsbDevice {
+    /// Claims a Bulk-Only mass storage interface and returns a block device for it.
+    #[napi]
+    pub async fn openMassStorage(
+        &self,
+        options: Option<MassStorageOptions>,
+    ) -> Result<MassStorageDevice> {
+        let interface_number = options.as_ref().and_then(|o| o.interfaceNumber);
+        let detach = options
+            .as_ref()
+            .and_then(|o| o.detachKernelDriver)
+            .unwrap_or(true);
+
+        let shared = self.shared().clone();
+        let device = shared
+            .opened_device()
+            .ok_or_else(|| napi::Error::from_reason("openMassStorage error: invalid state"))?;
+        let layout = run_blocking(move || {
+            let config = device
+                .active_configuration()
+                .map_err(|e| format!("openMassStorage error: {e}"))?;
+            find_bot_layout(&config, interface_number)
+                .map_err(|e| format!("openMassStorage error: {e}"))
+        })
+        .await?;
+
+        shared
+            .claim_interface("openMassStorage", layout.interface, detach)
+            .await?;
+
+        let interface = layout.interface;
+        let transport = BotTransport {
+            shared: shared.clone(),
+            layout,
+            tag: 0,
+        };
+        let opened = run_blocking(move || {
+            let max_lun = transport
+                .max_lun()
+                .map_err(|e| format!("openMassStorage error: {e}"))?;
+            Ok((transport, max_lun))
+        })
+        .await;
+        let (transport, max_lun) = match opened {
+            Ok(opened) => opened,
+            Err(e) => {
+                let _ = shared.release_interface("openMassStorage", interface).await;
+                return Err(e);
+            }
+        };
+
+        Ok(MassStorageDevice {
+            shared,
+            transport: Arc::new(Mutex::new(transport)),
+            block_lengths: Mutex::new(HashMap::new()),
+            interfaceNumber: interface,
+            maxLun: max_lun,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_10_is_big_endian() {
+        assert_eq!(
+            rw10(READ_10, 0x0102_0304, 0x0506),
+            [0x28, 0, 0x01, 0x02, 0x03, 0x04, 0, 0x05, 0x06, 0]
+        );
+    }
+
+    #[test]
+    fn commands_stay_within_the_transfer_length() {
+        assert_eq!(blocks_per_command(512), 128);
+        assert_eq!(blocks_per_command(4096), 16);
+        assert_eq!(blocks_per_command(1 << 20), 1);
+        assert_eq!(blocks_per_command(1), u16::MAX as u32);
+    }
+}
@@ -0,0 +1,787 @@
+mod packet;
+
+use crate::endpoint_stream::InStream;
+use crate::transfer_pool::pooled_transfer_out;
+use crate::webusb_device::{run_blocking, SharedDevice, UsbDevice};
+use napi::{
+    bindgen_prelude::*, threadsafe_function::ThreadsafeFunction,
+    threadsafe_function::ThreadsafeFunctionCallMode,
+};
+use napi_derive::napi;
+use nusb::{
+    descriptors::{language_id::US_ENGLISH, ConfigurationDescriptor, TransferType},
+    transfer::{ControlIn, ControlType, Direction, In, Out, Recipient},
+    MaybeFuture,
+};
+use packet::{Ump, UmpUnpacker, Unpacker};
+use std::{
+    num::NonZeroU8,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+const CLASS_AUDIO: u8 = 0x01;
+const SUBCLASS_MIDI_STREAMING: u8 = 0x03;
+
+const CS_INTERFACE: u8 = 0x24;
+const CS_ENDPOINT: u8 = 0x25;
+const CS_GR_TRM_BLOCK: u8 = 0x26;
+
+const MS_HEADER: u8 = 0x01;
+const MIDI_IN_JACK: u8 = 0x02;
+const MIDI_OUT_JACK: u8 = 0x03;
+const MS_GENERAL: u8 = 0x01;
+const GR_TRM_BLOCK: u8 = 0x02;
+const JACK_EMBEDDED: u8 = 0x01;
+
+/// bcdMSC of alternate settings carrying Universal MIDI Packets
+const MIDI_2_0: u16 = 0x0200;
+
+const GET_DESCRIPTOR: u8 = 0x06;
+const GROUP_TERMINAL_BLOCKS_LENGTH: u16 = 512;
+
+const CONTROL_TIMEOUT: Duration = Duration::from_millis(1000);
+const DEFAULT_TIMEOUT: u32 = 1000;
+const READ_TRANSFER_LENGTH: usize = 512;
+const READ_TRANSFERS: usize = 4;
+
+type MessageCallback = ThreadsafeFunction<MidiMessage, (), MidiMessage, napi::Status, false>;
+
+#[napi(object)]
+pub struct MidiOptions {
+    /// The MIDI streaming interface to use, defaults to the first one
+    pub interfaceNumber: Option<u8>,
+    /// Alternate setting to select, defaults to `0` (USB MIDI 1.0)
+    pub alternateSetting: Option<u8>,
+    /// Detach kernel drivers (e.g. `snd-usb-audio`) while the interface is open, defaults to `true`
+    pub detachKernelDriver: Option<bool>,
+}
+
+/// A MIDI IN or OUT jack from the MIDI streaming interface descriptors.
+#[napi(object)]
+#[derive(Clone)]
+pub struct MidiJack {
+    #[napi(writable = false)]
+    pub id: u8,
+    /// `in` jacks take MIDI into the function, `out` jacks send it out
+    #[napi(writable = false, ts_type = "'in' | 'out'")]
+    pub direction: String,
+    /// Embedded jacks connect to the USB endpoints, external ones to physical connectors
+    #[napi(writable = false)]
+    pub embedded: bool,
+    #[napi(writable = false)]
+    pub name: Option<String>,
+    /// Jack IDs feeding an `out` jack
+    #[napi(writable = false)]
+    pub sources: Vec<u8>,
+}
+
+/// A virtual cable: an embedded jack reached through a MIDI endpoint.
+#[napi(object)]
+#[derive(Clone)]
+pub struct MidiCable {
+    #[napi(writable = false)]
+    pub cable: u8,
+    /// `in` for messages from the device, `out` for messages to it
+    #[napi(writable = false, ts_type = "USBDirection")]
+    pub direction: String,
+    #[napi(writable = false)]
+    pub jackId: u8,
+    #[napi(writable = false)]
+    pub name: Option<String>,
+}
+
+/// A USB MIDI 2.0 group terminal block.
+#[napi(object)]
+#[derive(Clone)]
+pub struct MidiGroupTerminalBlock {
+    #[napi(writable = false)]
+    pub id: u8,
+    /// From the device's point of view
+    #[napi(writable = false, ts_type = "'bidirectional' | 'input' | 'output'")]
+    pub direction: String,
+    #[napi(writable = false)]
+    pub firstGroup: u8,
+    #[napi(writable = false)]
+    pub groupCount: u8,
+    #[napi(writable = false)]
+    pub name: Option<String>,
+    /// bMIDIProtocol, e.g. `0x01`/`0x02` MIDI 1.0 or `0x11`/`0x12` MIDI 2.0
+    #[napi(writable = false)]
+    pub protocol: u8,
+}
+
+#[napi(object)]
+#[derive(Clone, Copy)]
+pub struct MidiAlternateSetting {
+    #[napi(writable = false)]
+    pub alternateSetting: u8,
+    /// bcdMSC: `0x0100` for USB MIDI 1.0 event packets, `0x0200` for Universal MIDI Packets
+    #[napi(writable = false)]
+    pub version: u16,
+}
+
+/// A received MIDI message. MIDI 1.0 messages carry their bytes in `data`, and in a
+/// USB MIDI 2.0 alternate setting every message carries its Universal MIDI Packet words in `ump`.
+#[napi(object)]
+pub struct MidiMessage {
+    /// Virtual cable, or UMP group
+    #[napi(writable = false)]
+    pub cable: u8,
+    #[napi(writable = false, ts_type = "MidiMessageType", js_name = "type")]
+    pub _type: String,
+    #[napi(writable = false)]
+    pub channel: Option<u8>,
+    #[napi(writable = false)]
+    pub note: Option<u8>,
+    /// 7 bits for MIDI 1.0, 16 bits for MIDI 2.0 channel voice messages
+    #[napi(writable = false)]
+    pub velocity: Option<u32>,
+    #[napi(writable = false)]
+    pub controller: Option<u8>,
+    /// Controller, pressure, program, pitch bend or song position value
+    #[napi(writable = false)]
+    pub value: Option<u32>,
+    #[napi(writable = false)]
+    pub data: Option<Uint8Array>,
+    #[napi(writable = false)]
+    pub ump: Option<Uint32Array>,
+}
+
+impl MidiMessage {
+    fn new(cable: u8, _type: &str) -> Self {
+        Self {
+            cable,
+            _type: _type.to_string(),
+            channel: None,
+            note: None,
+            velocity: None,
+            controller: None,
+            value: None,
+            data: None,
+            ump: None,
+        }
+    }
+
+    /// Names a MIDI 1.0 message and picks out its fields.
+    fn midi1(cable: u8, data: Vec<u8>) -> Self {
+        let status = data.first().copied().unwrap_or(0);
+        let byte = |i: usize| data.get(i).copied().unwrap_or(0);
+        let (d1, d2) = (byte(1), byte(2));
+        let channel = (status < 0xf0).then_some(status & 0x0f);
+        let mut message = match status {
+            0x80..=0x9f => Self {
+                note: Some(d1),
+                velocity: Some(d2 as u32),
+                ..Self::new(cable, if status < 0x90 { "noteOff" } else { "noteOn" })
+            },
+            0xa0..=0xaf => Self {
+                note: Some(d1),
+                value: Some(d2 as u32),
+                ..Self::new(cable, "polyPressure")
+            },
+            0xb0..=0xbf => Self {
+                controller: Some(d1),
+                value: Some(d2 as u32),
+                ..Self::new(cable, "controlChange")
+            },
+            0xc0..=0xcf => Self {
+                value: Some(d1 as u32),
+                ..Self::new(cable, "programChange")
+            },
+            0xd0..=0xdf => Self {
+                value: Some(d1 as u32),
+                ..Self::new(cable, "channelPressure")
+            },
+            0xe0..=0xef => Self {
+                value: Some(d1 as u32 | (d2 as u32) << 7),
+                ..Self::new(cable, "pitchBend")
+            },
+            0xf0 => Self::new(cable, "sysex"),
+            0xf1 => Self {
+                value: Some(d1 as u32),
+                ..Self::new(cable, "timeCode")
+            },
+            0xf2 => Self {
+                value: Some(d1 as u32 | (d2 as u32) << 7),
+                ..Self::new(cable, "songPosition")
+            },
+            0xf3 => Self {
+                value: Some(d1 as u32),
+                ..Self::new(cable, "songSelect")
+            },
+            0xf6 => Self::new(cable, "tuneRequest"),
+            0xf8 => Self::new(cable, "clock"),
+            0xfa => Self::new(cable, "start"),
+            0xfb => Self::new(cable, "continue"),
+            0xfc => Self::new(cable, "stop"),
+            0xfe => Self::new(cable, "activeSensing"),
+            0xff => Self::new(cable, "reset"),
+            _ => Self::new(cable, "other"),
+        };
+        message.channel = channel;
+        message.data = Some(Uint8Array::new(data));
+        message
+    }
+
+    /// Names a Universal MIDI Packet, decoding MIDI 2.0 channel voice messages as well as MIDI 1.0 ones.
+    fn ump(ump: Ump) -> Self {
+        let first = ump.words[0];
+        let second = ump.words.get(1).copied().unwrap_or(0);
+        let mut message = match ump.midi1 {
+            Some(data) => Self::midi1(ump.group, data),
+            None if first >> 28 == 0x4 => {
+                let index = (first >> 8 & 0x7f) as u8;
+                let mut message = match first >> 20 & 0x0f {
+                    0x8 | 0x9 => Self {
+                        note: Some(index),
+                        velocity: Some(second >> 16),
+                        ..Self::new(
+                            ump.group,
+                            if first >> 20 & 0x0f == 0x8 {
+                                "noteOff"
+                            } else {
+                                "noteOn"
+                            },
+                        )
+                    },
+                    0xa => Self {
+                        note: Some(index),
+                        value: Some(second),
+                        ..Self::new(ump.group, "polyPressure")
+                    },
+                    0xb => Self {
+                        controller: Some(index),
+                        value: Some(second),
+                        ..Self::new(ump.group, "controlChange")
+                    },
+                    0xc => Self {
+                        value: Some(second >> 24 & 0x7f),
+                        ..Self::new(ump.group, "programChange")
+                    },
+                    0xd => Self {
+                        value: Some(second),
+                        ..Self::new(ump.group, "channelPressure")
+                    },
+                    0xe => Self {
+                        value: Some(second),
+                        ..Self::new(ump.group, "pitchBend")
+                    },
+                    _ => Self::new(ump.group, "other"),
+                };
+                message.channel = Some((first >> 16 & 0x0f) as u8);
+                message
+            }
+            None => Self::new(ump.group, "other"),
+        };
+        message.ump = Some(Uint32Array::new(ump.words));
+        message
+    }
+}
+
+#[derive(Clone)]
+struct MidiLayout {
+    alternate_setting: u8,
+    version: u16,
+    ep_in: Option<u8>,
+    ep_out: Option<u8>,
+    jacks: Vec<MidiJack>,
+    cables: Vec<MidiCable>,
+    blocks: Vec<MidiGroupTerminalBlock>,
+}
+
+fn find_midi_interface(
+    config: &ConfigurationDescriptor,
+    interface_number: Option<u8>,
+) -> std::result::Result<u8, String> {
+    config
+        .interface_alt_settings()
+        .filter(|alt| alt.alternate_setting() == 0)
+        .filter(|alt| interface_number.is_none_or(|n| alt.interface_number() == n))
+        .find(|alt| alt.class() == CLASS_AUDIO && alt.subclass() == SUBCLASS_MIDI_STREAMING)
+        .map(|alt| alt.interface_number())
+        .ok_or_else(|| "no MIDI interface found".to_string())
+}
+
+fn alternate_settings(
+    config: &ConfigurationDescriptor,
+    interface: u8,
+) -> Vec<MidiAlternateSetting> {
+    config
+        .interface_alt_settings()
+        .filter(|alt| alt.interface_number() == interface)
+        .map(|alt| {
+            let version = alt
+                .descriptors()
+                .find(|d| d.descriptor_type() == CS_INTERFACE && d.len() >= 5 && d[2] == MS_HEADER)
+                .map(|d| u16::from_le_bytes([d[3], d[4]]))
+                .unwrap_or(0x0100);
+            MidiAlternateSetting {
+                alternateSetting: alt.alternate_setting(),
+                version,
+            }
+        })
+        .collect()
+}
+
+/// Reads the jacks of an alternate setting and the cables its endpoints route to them.
+fn find_midi_layout(
+    device: &nusb::Device,
+    config: &ConfigurationDescriptor,
+    interface: u8,
+    alternate_setting: u8,
+) -> std::result::Result<MidiLayout, String> {
+    let alt = config
+        .interface_alt_settings()
+        .find(|alt| {
+            alt.interface_number() == interface && alt.alternate_setting() == alternate_setting
+        })
+        .ok_or_else(|| format!("alternate setting {alternate_setting} not found"))?;
+    let string = |index: u8| {
+        NonZeroU8::new(index).and_then(|index| {
+            device
+                .get_string_descriptor(index, US_ENGLISH, CONTROL_TIMEOUT)
+                .wait()
+                .ok()
+        })
+    };
+
+    let mut version = 0x0100;
+    let mut jacks = Vec::new();
+    for d in alt.descriptors() {
+        if d.descriptor_type() != CS_INTERFACE || d.len() < 3 {
+            continue;
+        }
+        match d[2] {
+            MS_HEADER if d.len() >= 5 => version = u16::from_le_bytes([d[3], d[4]]),
+            MIDI_IN_JACK if d.len() >= 6 => jacks.push(MidiJack {
+                id: d[4],
+                direction: "in".to_string(),
+                embedded: d[3] == JACK_EMBEDDED,
+                name: string(d[5]),
+                sources: Vec::new(),
+            }),
+            MIDI_OUT_JACK if d.len() >= 6 => {
+                let pins = d[5] as usize;
+                jacks.push(MidiJack {
+                    id: d[4],
+                    direction: "out".to_string(),
+                    embedded: d[3] == JACK_EMBEDDED,
+                    name: d.get(6 + pins * 2).and_then(|&index| string(index)),
+                    sources: (0..pins)
+                        .filter_map(|pin| d.get(6 + pin * 2).copied())
+                        .collect(),
+                })
+            }
+            _ => {}
+        }
+    }
+
+    let mut ep_in = None;
+    let mut ep_out = None;
+    let mut cables = Vec::new();
+    for ep in alt.endpoints().filter(|ep| {
+        matches!(
+            ep.transfer_type(),
+            TransferType::Bulk | TransferType::Interrupt
+        )
+    }) {
+        let direction = ep.direction();
+        match direction {
+            Direction::In if ep_in.is_none() => ep_in = Some(ep.address() & 0x7f),
+            Direction::Out if ep_out.is_none() => ep_out = Some(ep.address() & 0x7f),
+            _ => continue,
+        }
+        // Cable numbers index the embedded jacks the endpoint lists
+        let jack_ids = ep
+            .descriptors()
+            .find(|d| d.descriptor_type() == CS_ENDPOINT && d.len() >= 4 && d[2] == MS_GENERAL)
+            .map(|d| {
+                d[4..]
+                    .iter()
+                    .take(d[3] as usize)
+                    .copied()
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        for (cable, jack_id) in jack_ids.into_iter().enumerate() {
+            cables.push(MidiCable {
+                cable: cable as u8,
+                direction: match direction {
+                    Direction::In => "in",
+                    Direction::Out => "out",
+                }
+                .to_string(),
+                jackId: jack_id,
+                name: jacks
+                    .iter()
+                    .find(|jack| jack.id == jack_id)
+                    .and_then(|jack| jack.name.clone()),
+            });
+        }
+    }
+
+    Ok(MidiLayout {
+        alternate_setting,
+        version,
+        ep_in,
+        ep_out,
+        jacks,
+        cables,
+        blocks: Vec::new(),
+    })
+}
+
+/// Group terminal blocks aren't in the configuration descriptor, they're read with their own GET_DESCRIPTOR.
+fn read_group_terminal_blocks(
+    shared: &SharedDevice,
+    interface: u8,
+    alternate_setting: u8,
+) -> Vec<MidiGroupTerminalBlock> {
+    let (Some(target), Some(device)) = (
+        shared.control_target(Recipient::Interface, interface as u16),
+        shared.opened_device(),
+    ) else {
+        return Vec::new();
+    };
+    let Ok(data) = target.control_in_blocking(
+        ControlIn {
+            control_type: ControlType::Standard,
+            recipient: Recipient::Interface,
+            request: GET_DESCRIPTOR,
+            value: (CS_GR_TRM_BLOCK as u16) << 8 | alternate_setting as u16,
+            index: interface as u16,
+            length: GROUP_TERMINAL_BLOCKS_LENGTH,
+        },
+        CONTROL_TIMEOUT,
+    ) else {
+        return Vec::new();
+    };
+
+    let mut blocks = Vec::new();
+    let mut rest = &data[..];
+    while let [len, ..] = *rest {
+        let len = len as usize;
+        if len < 2 || len > rest.len() {
+            break;
+        }
+        let (d, next) = rest.split_at(len);
+        rest = next;
+        if d[1] != CS_GR_TRM_BLOCK || len < 9 || d[2] != GR_TRM_BLOCK {
+            continue;
+        }
+        blocks.push(MidiGroupTerminalBlock {
+            id: d[3],
+            direction: match d[4] {
+                0x01 => "input",
+                0x02 => "output",
+                _ => "bidirectional",
+            }
+            .to_string(),
+            firstGroup: d[5],
+            groupCount: d[6],
+            name: NonZeroU8::new(d[7]).and_then(|index| {
+                device
+                    .get_string_descriptor(index, US_ENGLISH, CONTROL_TIMEOUT)
+                    .wait()
+                    .ok()
+            }),
+            protocol: d[8],
+        });
+    }
+    blocks
+}
+
+enum MessageUnpacker {
+    Midi1(Unpacker),
+    Ump(UmpUnpacker),
+}
+
+impl MessageUnpacker {
+    fn feed(&mut self, data: &[u8]) -> Vec<MidiMessage> {
+        match self {
+            Self::Midi1(unpacker) => data
+                .chunks_exact(4)
+                .filter_map(|packet| unpacker.packet(packet))
+                .map(|(cable, message)| MidiMessage::midi1(cable, message))
+                .collect(),
+            Self::Ump(unpacker) => unpacker
+                .feed(data)
+                .into_iter()
+                .map(MidiMessage::ump)
+                .collect(),
+        }
+    }
+}
+
+struct MidiState {
+    layout: MidiLayout,
+    reader: Option<InStream>,
+}
+
+/// A USB MIDI streaming interface, sending and receiving messages on up to 16 virtual cables
+/// (or UMP groups in a USB MIDI 2.0 alternate setting).
+#[napi]
+pub struct MidiInterface {
+    shared: Arc<SharedDevice>,
+    state: Mutex<MidiState>,
+    alternate_settings: Vec<MidiAlternateSetting>,
+
+    #[napi(writable = false)]
+    pub interfaceNumber: u8,
+}
+
+impl MidiInterface {
+    fn state(&self) -> MutexGuard<'_, MidiState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    async fn write(&self, method: &'static str, data: Vec<u8>, timeout: Option<u32>) -> Result<()> {
+        let ep_out =
+            self.state().layout.ep_out.ok_or_else(|| {
+                napi::Error::from_reason(format!("{method} error: no OUT endpoint"))
+            })?;
+        let timeout = Duration::from_millis(timeout.unwrap_or(DEFAULT_TIMEOUT) as u64);
+        let shared = self.shared.clone();
+        run_blocking(move || {
+            let mut endpoint = shared
+                .get_endpoint::<Out>(ep_out)
+                .ok_or_else(|| format!("{method} error: invalid state"))?;
+            let result = pooled_transfer_out(&shared, &mut endpoint, &data, timeout)
+                .map(|_| ())
+                .map_err(|e| format!("{method} error: {e:?}"));
+            shared.put_endpoint(endpoint);
+            result
+        })
+        .await
+    }
+}
+
+#[napi]
+impl MidiInterface {
+    #[napi(getter)]
+    pub fn alternateSetting(&self) -> u8 {
+        self.state().layout.alternate_setting
+    }
+
+    /// bcdMSC of the current alternate setting
+    #[napi(getter)]
+    pub fn version(&self) -> u16 {
+        self.state().layout.version
+    }
+
+    #[napi(getter)]
+    pub fn alternateSettings(&self) -> Vec<MidiAlternateSetting> {
+        self.alternate_settings.clone()
+    }
+
+    #[napi(getter)]
+    pub fn jacks(&self) -> Vec<MidiJack> {
+        self.state().layout.jacks.clone()
+    }
+
+    #[napi(getter)]
+    pub fn cables(&self) -> Vec<MidiCable> {
+        self.state().layout.cables.clone()
+    }
+
+    /// Group terminal blocks of a USB MIDI 2.0 alternate setting
+    #[napi(getter)]
+    pub fn groupTerminalBlocks(&self) -> Vec<MidiGroupTerminalBlock> {
+        self.state().layout.blocks.clone()
+    }
+
+    /// Switches between the USB MIDI 1.0 (`0`) and 2.0 alternate settings, stopping any `onMessage()` reader.
+    #[napi]
+    pub async fn selectAlternateInterface(&self, alternateSetting: u8) -> Result<()> {
+        let reader = self.state().reader.take();
+        if let Some(reader) = reader {
+            reader.finish().await;
+        }
+        let interface = self.interfaceNumber;
+        self.shared
+            .select_alternate_interface("selectAlternateInterface", interface, alternateSetting)
+            .await?;
+
+        let shared = self.shared.clone();
+        let layout = run_blocking(move || {
+            let device = shared
+                .opened_device()
+                .ok_or("selectAlternateInterface error: invalid state")?;
+            let config = device
+                .active_configuration()
+                .map_err(|e| format!("selectAlternateInterface error: {e}"))?;
+            let mut layout = find_midi_layout(&device, &config, interface, alternateSetting)
+                .map_err(|e| format!("selectAlternateInterface error: {e}"))?;
+            if layout.version >= MIDI_2_0 {
+                layout.blocks = read_group_terminal_blocks(&shared, interface, alternateSetting);
+            }
+            Ok(layout)
+        })
+        .await?;
+        self.state().layout = layout;
+        Ok(())
+    }
+
+    /// Sends MIDI 1.0 messages (running status allowed) on a virtual cable, or UMP group in a
+    /// USB MIDI 2.0 alternate setting, defaulting to `0`.
+    #[napi]
+    pub async fn send(
+        &self,
+        data: Uint8Array,
+        cable: Option<u8>,
+        timeout: Option<u32>,
+    ) -> Result<()> {
+        let cable = cable.unwrap_or(0);
+        if cable > 15 {
+            return Err(napi::Error::from_reason("send error: invalid cable"));
+        }
+        let messages = packet::split_messages(&data)
+            .map_err(|e| napi::Error::from_reason(format!("send error: {e}")))?;
+        let mut out = Vec::new();
+        if self.version() >= MIDI_2_0 {
+            let mut words = Vec::new();
+            for message in &messages {
+                packet::to_ump(cable, message, &mut words);
+            }
+            out.extend(words.iter().flat_map(|word| word.to_le_bytes()));
+        } else {
+            for message in &messages {
+                packet::pack(cable, message, &mut out);
+            }
+        }
+        self.write("send", out, timeout).await
+    }
+
+    /// Sends Universal MIDI Packets as they are, in a USB MIDI 2.0 alternate setting.
+    #[napi]
+    pub async fn sendUmp(&self, words: Uint32Array, timeout: Option<u32>) -> Result<()> {
+        if self.version() < MIDI_2_0 {
+            return Err(napi::Error::from_reason(
+                "sendUmp error: not a USB MIDI 2.0 alternate setting",
+            ));
+        }
+        let out = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        self.write("sendUmp", out, timeout).await
+    }
+
+    /// Starts reading the IN endpoint, calling back with each complete message. SysEx is reassembled per cable.
+    #[napi]
+    pub fn onMessage(&self, callback: MessageCallback) -> Result<()> {
+        let mut state = self.state();
+        if state.reader.is_some() {
+            return Err(napi::Error::from_reason("onMessage error: already reading"));
+        }
+        let ep_in = state
+            .layout
+            .ep_in
+            .ok_or_else(|| napi::Error::from_reason("onMessage error: no IN endpoint"))?;
+        let endpoint = self
+            .shared
+            .get_endpoint::<In>(ep_in)
+            .ok_or_else(|| napi::Error::from_reason("onMessage error: invalid state"))?;
+
+        let mut unpacker = if state.layout.version >= MIDI_2_0 {
+            MessageUnpacker::Ump(UmpUnpacker::new())
+        } else {
+            MessageUnpacker::Midi1(Unpacker::new())
+        };
+        state.reader = Some(InStream::start(
+            endpoint,
+            READ_TRANSFER_LENGTH,
+            READ_TRANSFERS,
+            move |data| {
+                for message in unpacker.feed(data) {
+                    callback.call(message, ThreadsafeFunctionCallMode::NonBlocking);
+                }
+            },
+            |_| {},
+        ));
+        Ok(())
+    }
+
+    #[napi]
+    pub fn stopMessages(&self) {
+        if let Some(reader) = self.state().reader.take() {
+            reader.stop();
+        }
+    }
+
+    /// Stops reading and releases the interface, reattaching any kernel driver.
+    #[napi]
+    pub async fn close(&self) -> Result<()> {
+        let reader = self.state().reader.take();
+        // Its endpoint keeps the interface claimed until the stream has ended
+        if let Some(reader) = reader {
+            reader.finish().await;
+        }
+        self.shared
+            .release_interface("close", self.interfaceNumber)
+            .await
+    }
+}
+
+#[napi]
+impl UsbDevice {
+    /// Claims a MIDI streaming interface and reads its jacks and cables.
+    #[napi]
+    pub async fn openMidi(&self, options: Option<MidiOptions>) -> Result<MidiInterface> {
+        let interface_number = options.as_ref().and_then(|o| o.interfaceNumber);
+        let alternate_setting = options
+            .as_ref()
+            .and_then(|o| o.alternateSetting)
+            .unwrap_or(0);
+        let detach = options
+            .as_ref()
+            .and_then(|o| o.detachKernelDriver)
+            .unwrap_or(true);
+
+        let shared = self.shared().clone();
+        let device = shared
+            .opened_device()
+            .ok_or_else(|| napi::Error::from_reason("openMidi error: invalid state"))?;
+        let (interface, mut layout, alternate_settings) = run_blocking(move || {
+            let config = device
+                .active_configuration()
+                .map_err(|e| format!("openMidi error: {e}"))?;
+            let interface = find_midi_interface(&config, interface_number)
+                .map_err(|e| format!("openMidi error: {e}"))?;
+            let layout = find_midi_layout(&device, &config, interface, alternate_setting)
+                .map_err(|e| format!("openMidi error: {e}"))?;
+            Ok((interface, layout, alternate_settings(&config, interface)))
+        })
+        .await?;
+
+        shared
+            .claim_interface("openMidi", interface, detach)
+            .await?;
+        if alternate_setting != 0 {
+            shared
+                .select_alternate_interface("openMidi", interface, alternate_setting)
+                .await?;
+        }
+        if layout.version >= MIDI_2_0 {
+            let blocks_shared = shared.clone();
+            layout.blocks = run_blocking(move || {
+                Ok(read_group_terminal_blocks(
+                    &blocks_shared,
+                    interface,
+                    alternate_setting,
+                ))
+            })
+            .await?;
+        }
+
+        Ok(MidiInterface {
+            shared,
+            state: Mutex::new(MidiState {
+                layout,
+                reader: None,
+            }),
+            alternate_settings,
+            interfaceNumber: interface,
+        })
+    }
+}
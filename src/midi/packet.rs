@@ -0,0 +1,473 @@
+const SYSEX_START: u8 = 0xf0;
+const SYSEX_END: u8 = 0xf7;
+
+const CIN_SYSCOM_2: u8 = 0x2;
+const CIN_SYSCOM_3: u8 = 0x3;
+const CIN_SYSEX: u8 = 0x4;
+/// SysEx ending with 1, 2 or 3 bytes is CIN 0x5, 0x6 or 0x7. 0x5 is also a single byte system common message
+const CIN_SYSEX_END_1: u8 = 0x5;
+const CIN_SYSEX_END_3: u8 = 0x7;
+const CIN_SINGLE_BYTE: u8 = 0xf;
+
+const MT_SYSTEM: u32 = 0x1;
+const MT_MIDI1_CHANNEL_VOICE: u32 = 0x2;
+const MT_DATA_64: u32 = 0x3;
+
+const UMP_SYSEX_COMPLETE: u32 = 0x0;
+const UMP_SYSEX_START: u32 = 0x1;
+const UMP_SYSEX_CONTINUE: u32 = 0x2;
+const UMP_SYSEX_END: u32 = 0x3;
+
+/// Bytes in a message starting with `status`, SysEx aside.
+pub(super) fn message_len(status: u8) -> usize {
+    match status {
+        0x80..=0xbf | 0xe0..=0xef | 0xf2 => 3,
+        0xc0..=0xdf | 0xf1 | 0xf3 => 2,
+        _ => 1,
+    }
+}
+
+/// 32-bit words in a Universal MIDI Packet, by message type.
+pub(super) fn ump_len(message_type: u32) -> usize {
+    match message_type {
+        0x0 | 0x1 | 0x2 | 0x6 | 0x7 => 1,
+        0x3 | 0x4 | 0x8 | 0x9 | 0xa => 2,
+        0xb | 0xc => 3,
+        _ => 4,
+    }
+}
+
+/// Splits a MIDI 1.0 byte stream into messages, expanding running status.
+pub(super) fn split_messages(data: &[u8]) -> std::result::Result<Vec<Vec<u8>>, String> {
+    let mut messages = Vec::new();
+    let mut running = None;
+    let mut i = 0;
+    while i < data.len() {
+        let byte = data[i];
+        // Real-time messages may appear anywhere and leave running status alone
+        if byte >= 0xf8 {
+            messages.push(vec![byte]);
+            i += 1;
+            continue;
+        }
+        if byte == SYSEX_START {
+            let len = data[i..]
+                .iter()
+                .position(|&b| b == SYSEX_END)
+                .ok_or("unterminated SysEx")?;
+            messages.push(data[i..=i + len].to_vec());
+            running = None;
+            i += len + 1;
+            continue;
+        }
+
+        let (status, start) = if byte & 0x80 != 0 {
+            (byte, i + 1)
+        } else {
+            (running.ok_or("data byte without a status byte")?, i)
+        };
+        if status == SYSEX_END {
+            return Err("end of SysEx without a start".to_string());
+        }
+        running = (status < 0xf0).then_some(status);
+
+        let end = start + message_len(status) - 1;
+        if end > data.len() {
+            return Err("truncated message".to_string());
+        }
+        let mut message = vec![status];
+        message.extend_from_slice(&data[start..end]);
+        messages.push(message);
+        i = end;
+    }
+    Ok(messages)
+}
+
+/// Packs a message into USB-MIDI event packets for a virtual cable.
+pub(super) fn pack(cable: u8, message: &[u8], out: &mut Vec<u8>) {
+    let header = cable << 4;
+    if message.first() == Some(&SYSEX_START) {
+        let chunks = message.len().div_ceil(3);
+        for (i, chunk) in message.chunks(3).enumerate() {
+            let cin = if i + 1 < chunks {
+                CIN_SYSEX
+            } else {
+                CIN_SYSEX + chunk.len() as u8
+            };
+            let mut packet = [header | cin, 0, 0, 0];
+            packet[1..1 + chunk.len()].copy_from_slice(chunk);
+            out.extend_from_slice(&packet);
+        }
+        return;
+    }
+
+    let status = message[0];
+    let cin = match status {
+        0x80..=0xef => status >> 4,
+        0xf1 | 0xf3 => CIN_SYSCOM_2,
+        0xf2 => CIN_SYSCOM_3,
+        0xf8..=0xff => CIN_SINGLE_BYTE,
+        _ => CIN_SYSEX_END_1,
+    };
+    let mut packet = [header | cin, 0, 0, 0];
+    let len = message.len().min(3);
+    packet[1..1 + len].copy_from_slice(&message[..len]);
+    out.extend_from_slice(&packet);
+}
+
+/// Reassembles messages from USB-MIDI event packets, keeping SysEx in progress per cable.
+pub(super) struct Unpacker {
+    sysex: Vec<Vec<u8>>,
+}
+
+impl Unpacker {
+    pub(super) fn new() -> Self {
+        Self {
+            sysex: vec![Vec::new(); 16],
+        }
+    }
+
+    /// Takes one packet, returning its cable and message once one is complete.
+    pub(super) fn packet(&mut self, packet: &[u8]) -> Option<(u8, Vec<u8>)> {
+        let [header, bytes @ ..] = packet else {
+            return None;
+        };
+        let cable = header >> 4;
+        let sysex = &mut self.sysex[cable as usize];
+        let len = match header & 0x0f {
+            CIN_SYSEX => {
+                sysex.extend_from_slice(bytes.get(..3)?);
+                return None;
+            }
+            cin @ CIN_SYSEX_END_1..=CIN_SYSEX_END_3 => {
+                let end = bytes.get(..(cin - CIN_SYSEX) as usize)?;
+                if !sysex.is_empty() || end.first() == Some(&SYSEX_START) {
+                    sysex.extend_from_slice(end);
+                    return Some((cable, std::mem::take(sysex)));
+                }
+                end.len()
+            }
+            CIN_SYSCOM_2 | 0xc | 0xd => 2,
+            CIN_SYSCOM_3 | 0x8..=0xb | 0xe => 3,
+            CIN_SINGLE_BYTE => 1,
+            // Reserved for future extensions
+            _ => return None,
+        };
+        Some((cable, bytes.get(..len)?.to_vec()))
+    }
+}
+
+/// Converts a MIDI 1.0 message to Universal MIDI Packets in a group: system and channel voice
+/// messages become type 1 and 2 packets, SysEx becomes type 3 packets of up to 6 bytes.
+pub(super) fn to_ump(group: u8, message: &[u8], out: &mut Vec<u32>) {
+    let group = (group as u32 & 0x0f) << 24;
+    if message.first() == Some(&SYSEX_START) {
+        let payload = message[1..]
+            .strip_suffix(&[SYSEX_END])
+            .unwrap_or(&message[1..]);
+        let chunks = payload.len().div_ceil(6).max(1);
+        for i in 0..chunks {
+            let chunk = payload
+                .get(i * 6..((i + 1) * 6).min(payload.len()))
+                .unwrap_or(&[]);
+            let status = match (i == 0, i + 1 == chunks) {
+                (true, true) => UMP_SYSEX_COMPLETE,
+                (true, false) => UMP_SYSEX_START,
+                (false, false) => UMP_SYSEX_CONTINUE,
+                (false, true) => UMP_SYSEX_END,
+            };
+            let mut bytes = [0u8; 6];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            out.push(
+                MT_DATA_64 << 28
+                    | group
+                    | status << 20
+                    | (chunk.len() as u32) << 16
+                    | u32::from_be_bytes([0, 0, bytes[0], bytes[1]]),
+            );
+            out.push(u32::from_be_bytes([bytes[2], bytes[3], bytes[4], bytes[5]]));
+        }
+        return;
+    }
+
+    let message_type = if message[0] >= 0xf0 {
+        MT_SYSTEM
+    } else {
+        MT_MIDI1_CHANNEL_VOICE
+    };
+    let byte = |i: usize| message.get(i).copied().unwrap_or(0) as u32;
+    out.push(message_type << 28 | group | byte(0) << 16 | byte(1) << 8 | byte(2));
+}
+
+/// A complete Universal MIDI Packet, or a whole SysEx message, with its MIDI 1.0 bytes where there are any.
+pub(super) struct Ump {
+    pub(super) group: u8,
+    pub(super) words: Vec<u32>,
+    pub(super) midi1: Option<Vec<u8>>,
+}
+
+/// Reassembles Universal MIDI Packets from little-endian words, collecting SysEx per group.
+pub(super) struct UmpUnpacker {
+    partial: Vec<u32>,
+    sysex: Vec<(Vec<u8>, Vec<u32>)>,
+}
+
+impl UmpUnpacker {
+    pub(super) fn new() -> Self {
+        Self {
+            partial: Vec::new(),
+            sysex: vec![(Vec::new(), Vec::new()); 16],
+        }
+    }
+
+    pub(super) fn feed(&mut self, data: &[u8]) -> Vec<Ump> {
+        let mut packets = Vec::new();
+        for word in data.chunks_exact(4) {
+            self.partial
+                .push(u32::from_le_bytes([word[0], word[1], word[2], word[3]]));
+            let first = self.partial[0];
+            if self.partial.len() < ump_len(first >> 28) {
+                continue;
+            }
+            let words = std::mem::take(&mut self.partial);
+            let group = (first >> 24 & 0x0f) as u8;
+            match first >> 28 {
+                MT_SYSTEM | MT_MIDI1_CHANNEL_VOICE => {
+                    let status = (first >> 16) as u8;
+                    let bytes = [status, (first >> 8) as u8 & 0x7f, first as u8 & 0x7f];
+                    packets.push(Ump {
+                        group,
+                        words,
+                        midi1: Some(bytes[..message_len(status)].to_vec()),
+                    });
+                }
+                MT_DATA_64 => {
+                    let status = first >> 20 & 0x0f;
+                    let len = ((first >> 16 & 0x0f) as usize).min(6);
+                    let [b0, b1] = [(first >> 8) as u8, first as u8];
+                    let [b2, b3, b4, b5] = words[1].to_be_bytes();
+                    let (sysex, sysex_words) = &mut self.sysex[group as usize];
+                    if status == UMP_SYSEX_COMPLETE || status == UMP_SYSEX_START {
+                        sysex.clear();
+                        sysex_words.clear();
+                        sysex.push(SYSEX_START);
+                    }
+                    sysex.extend_from_slice(&[b0, b1, b2, b3, b4, b5][..len]);
+                    sysex_words.extend_from_slice(&words);
+                    if status == UMP_SYSEX_COMPLETE || status == UMP_SYSEX_END {
+                        sysex.push(SYSEX_END);
+                        packets.push(Ump {
+                            group,
+                            words: std::mem::take(sysex_words),
+                            midi1: Some(std::mem::take(sysex)),
+                        });
+                    }
+                }
+                _ => packets.push(Ump {
+                    group,
+                    words,
+                    midi1: None,
+                }),
+            }
+        }
+        packets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packed(cable: u8, message: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        pack(cable, message, &mut out);
+        out
+    }
+
+    #[test]
+    fn splits_running_status_around_real_time() {
+        let messages =
+            split_messages(&[0x90, 0x3c, 0x7f, 0x3e, 0x00, 0xf8, 0x40, 0x10, 0xc1, 0x05]).unwrap();
+        assert_eq!(
+            messages,
+            [
+                vec![0x90, 0x3c, 0x7f],
+                vec![0x90, 0x3e, 0x00],
+                vec![0xf8],
+                vec![0x90, 0x40, 0x10],
+                vec![0xc1, 0x05],
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_sysex_and_clears_running_status() {
+        let data = [0x90, 0x3c, 0x7f, 0xf0, 0x7e, 0x7f, 0xf7, 0x3c];
+        assert_eq!(
+            split_messages(&data).unwrap_err(),
+            "data byte without a status byte"
+        );
+        assert_eq!(
+            split_messages(&data[..7]).unwrap(),
+            [vec![0x90, 0x3c, 0x7f], vec![0xf0, 0x7e, 0x7f, 0xf7]]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_streams() {
+        assert_eq!(
+            split_messages(&[0xf0, 0x01]).unwrap_err(),
+            "unterminated SysEx"
+        );
+        assert_eq!(
+            split_messages(&[0x90, 0x3c]).unwrap_err(),
+            "truncated message"
+        );
+        assert_eq!(
+            split_messages(&[0xf7]).unwrap_err(),
+            "end of SysEx without a start"
+        );
+    }
+
+    #[test]
+    fn packs_channel_and_system_messages() {
+        assert_eq!(packed(0, &[0x90, 0x3c, 0x7f]), [0x09, 0x90, 0x3c, 0x7f]);
+        assert_eq!(packed(1, &[0xc0, 0x05]), [0x1c, 0xc0, 0x05, 0x00]);
+        assert_eq!(packed(2, &[0xf2, 0x10, 0x20]), [0x23, 0xf2, 0x10, 0x20]);
+        assert_eq!(packed(0, &[0xf1, 0x10]), [0x02, 0xf1, 0x10, 0x00]);
+        assert_eq!(packed(0, &[0xf6]), [0x05, 0xf6, 0x00, 0x00]);
+        assert_eq!(packed(15, &[0xf8]), [0xff, 0xf8, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn packs_sysex_by_length_of_the_last_packet() {
+        assert_eq!(packed(0, &[0xf0, 0xf7]), [0x06, 0xf0, 0xf7, 0x00]);
+        assert_eq!(
+            packed(0, &[0xf0, 0x01, 0x02, 0xf7]),
+            [0x04, 0xf0, 0x01, 0x02, 0x05, 0xf7, 0x00, 0x00]
+        );
+        assert_eq!(
+            packed(0, &[0xf0, 0x7e, 0x7f, 0x06, 0x01, 0xf7]),
+            [0x04, 0xf0, 0x7e, 0x7f, 0x07, 0x06, 0x01, 0xf7]
+        );
+    }
+
+    #[test]
+    fn unpacks_what_it_packs() {
+        let mut unpacker = Unpacker::new();
+        for (cable, message) in [
+            (0, vec![0x90, 0x3c, 0x7f]),
+            (3, vec![0xc0, 0x05]),
+            (15, vec![0xf8]),
+            (1, vec![0xf0, 0x7e, 0x7f, 0x06, 0x01, 0xf7]),
+            (0, vec![0xf0, 0xf7]),
+        ] {
+            let data = packed(cable, &message);
+            let mut packets = data.chunks(4).peekable();
+            while let Some(packet) = packets.next() {
+                let result = unpacker.packet(packet);
+                if packets.peek().is_some() {
+                    assert_eq!(result, None);
+                } else {
+                    assert_eq!(result, Some((cable, message.clone())));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_sysex_in_progress_per_cable() {
+        let mut unpacker = Unpacker::new();
+        assert_eq!(unpacker.packet(&[0x04, 0xf0, 0x01, 0x02]), None);
+        assert_eq!(
+            unpacker.packet(&[0x19, 0x90, 0x3c, 0x7f]),
+            Some((1, vec![0x90, 0x3c, 0x7f]))
+        );
+        assert_eq!(
+            unpacker.packet(&[0x05, 0xf7, 0x00, 0x00]),
+            Some((0, vec![0xf0, 0x01, 0x02, 0xf7]))
+        );
+        // A lone CIN 0x5 outside SysEx is a single byte system common message
+        assert_eq!(
+            unpacker.packet(&[0x05, 0xf6, 0x00, 0x00]),
+            Some((0, vec![0xf6]))
+        );
+        assert_eq!(unpacker.packet(&[0x00, 0x01, 0x02, 0x03]), None);
+        assert_eq!(unpacker.packet(&[0x09, 0x90]), None);
+    }
+
+    #[test]
+    fn converts_to_ump() {
+        let mut out = Vec::new();
+        to_ump(2, &[0x90, 0x3c, 0x7f], &mut out);
+        to_ump(0, &[0xf8], &mut out);
+        to_ump(0, &[0xc1, 0x05], &mut out);
+        assert_eq!(out, [0x2290_3c7f, 0x10f8_0000, 0x20c1_0500]);
+    }
+
+    #[test]
+    fn converts_sysex_to_data_64_packets() {
+        let mut out = Vec::new();
+        to_ump(0, &[0xf0, 0x7e, 0x7f, 0x06, 0x01, 0xf7], &mut out);
+        assert_eq!(out, [0x3004_7e7f, 0x0601_0000]);
+
+        out.clear();
+        to_ump(
+            1,
+            &[0xf0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 0xf7],
+            &mut out,
+        );
+        assert_eq!(
+            out,
+            [
+                0x3116_0102,
+                0x0304_0506,
+                0x3126_0708,
+                0x090a_0b0c,
+                0x3131_0d00,
+                0x0000_0000
+            ]
+        );
+    }
+
+    #[test]
+    fn feeds_little_endian_words() {
+        let mut words = Vec::new();
+        to_ump(3, &[0x90, 0x3c, 0x7f], &mut words);
+        to_ump(1, &[0xf0, 1, 2, 3, 4, 5, 6, 7, 8, 0xf7], &mut words);
+        // A MIDI 2.0 note on is two words with no MIDI 1.0 equivalent
+        words.extend_from_slice(&[0x4090_3c00, 0xffff_0000]);
+        let data: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+
+        let mut unpacker = UmpUnpacker::new();
+        // Split inside the SysEx to check partial packets are kept
+        let mut packets = unpacker.feed(&data[..8]);
+        assert_eq!(packets.len(), 1);
+        packets.extend(unpacker.feed(&data[8..]));
+
+        assert_eq!(packets.len(), 3);
+        assert_eq!(packets[0].group, 3);
+        assert_eq!(packets[0].words, [0x2390_3c7f]);
+        assert_eq!(packets[0].midi1.as_deref(), Some(&[0x90, 0x3c, 0x7f][..]));
+        assert_eq!(packets[1].group, 1);
+        assert_eq!(packets[1].words, words[1..5]);
+        assert_eq!(
+            packets[1].midi1.as_deref(),
+            Some(&[0xf0, 1, 2, 3, 4, 5, 6, 7, 8, 0xf7][..])
+        );
+        assert_eq!(packets[2].words, [0x4090_3c00, 0xffff_0000]);
+        assert_eq!(packets[2].midi1, None);
+    }
+
+    #[test]
+    fn lengths_by_status_and_message_type() {
+        assert_eq!(message_len(0x80), 3);
+        assert_eq!(message_len(0xd0), 2);
+        assert_eq!(message_len(0xf3), 2);
+        assert_eq!(message_len(0xfe), 1);
+        assert_eq!(ump_len(0x2), 1);
+        assert_eq!(ump_len(0x4), 2);
+        assert_eq!(ump_len(0xb), 3);
+        assert_eq!(ump_len(0x5), 4);
+    }
+}
@@ -0,0 +1,317 @@
+use crate::standard_request::change_feature;
+use crate::webusb_device::{run_blocking, UsbDevice};
+use napi::{
+    bindgen_prelude::*, threadsafe_function::ThreadsafeFunction,
+    threadsafe_function::ThreadsafeFunctionCallMode,
+};
+use napi_derive::napi;
+use std::{path::PathBuf, time::Duration};
+
+const DEFAULT_RESUME_INTERVAL: u32 = 100;
+
+type ResumeCallback = ThreadsafeFunction<UsbPowerState, (), UsbPowerState, napi::Status, false>;
+
+#[napi(object)]
+pub struct UsbPowerState {
+    /// `auto` lets the kernel suspend the device once idle, `on` keeps it active
+    #[napi(writable = false, ts_type = "'auto' | 'on'")]
+    pub control: String,
+    /// Idle time (in milliseconds) before the device is suspended, negative values never suspend it
+    #[napi(writable = false)]
+    pub autosuspendDelay: Option<i32>,
+    #[napi(
+        writable = false,
+        ts_type = "'active' | 'suspended' | 'suspending' | 'resuming' | 'error' | 'unsupported'"
+    )]
+    pub runtimeStatus: String,
+    /// Whether the kernel enables remote wakeup when it suspends the device, missing when the
+    /// device can't wake the host
+    #[napi(writable = false)]
+    pub wakeup: Option<bool>,
+    /// Total time (in milliseconds) spent suspended
+    #[napi(writable = false)]
+    pub suspendedTime: Option<i64>,
+}
+
+/// The device's `power` directory in sysfs, found from its bus and port.
+fn power_path(device_info: &nusb::DeviceInfo) -> std::result::Result<PathBuf, String> {
+    #[cfg(target_os = "linux")]
+    {
+        Ok(device_info.sysfs_path().join("power"))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = device_info;
+        Err("power management is only available on Linux".to_string())
+    }
+}
+
+fn parse_power_state(
+    attr: impl Fn(&str) -> Option<String>,
+) -> std::result::Result<UsbPowerState, String> {
+    Ok(UsbPowerState {
+        control: attr("control").ok_or("power/control can't be read")?,
+        autosuspendDelay: attr("autosuspend_delay_ms").and_then(|delay| delay.parse().ok()),
+        runtimeStatus: attr("runtime_status").ok_or("power/runtime_status can't be read")?,
+        wakeup: attr("wakeup")
+            .filter(|wakeup| !wakeup.is_empty())
+            .map(|wakeup| wakeup == "enabled"),
+        suspendedTime: attr("runtime_suspended_time").and_then(|time| time.parse().ok()),
+    })
+}
+
+fn read_power_state(power: &std::path::Path) -> std::result::Result<UsbPowerState, String> {
+    parse_power_state(|name| {
+        std::fs::read_to_string(power.join(name))
+            .ok()
+            .map(|value| value.trim().to_string())
+    })
+}
+
+fn write_power_attr(
+    power: &std::path::Path,
+    name: &str,
+    value: &str,
+) -> std::result::Result<(), String> {
+    std::fs::write(power.join(name), value).map_err(|e| format!("can't write power/{name}: {e}"))
+}
+
+/// A resume is an arrival at `active`, or more time spent suspended, for a suspend and resume that
+/// both fell between two polls.
+fn has_resumed(last: &UsbPowerState, state: &UsbPowerState) -> bool {
+    state.runtimeStatus == "active"
+        && (last.runtimeStatus != "active" || state.suspendedTime > last.suspendedTime)
+}
+
+fn wakeup_value(enabled: bool) -> &'static str {
+    if enabled {
+        "enabled"
+    } else {
+        "disabled"
+    }
+}
+
+/// Polls the runtime status, which sysfs doesn't notify on.
+async fn watch_resume(power: PathBuf, interval: Duration, callback: ResumeCallback) {
+    let read = || {
+        let power = power.clone();
+        async move {
+            tokio::task::spawn_blocking(move || read_power_state(&power))
+                .await
+                .ok()?
+                .ok()
+        }
+    };
+
+    let Some(mut last) = read().await else {
+        return;
+    };
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        // Gone with the device
+        let Some(state) = read().await else {
+            return;
+        };
+        let resumed = has_resumed(&last, &state);
+        let next = UsbPowerState {
+            runtimeStatus: state.runtimeStatus.clone(),
+            suspendedTime: state.suspendedTime,
+            ..last
+        };
+        if resumed {
+            callback.call(state, ThreadsafeFunctionCallMode::NonBlocking);
+        }
+        last = next;
+    }
+}
+
+#[napi]
+impl UsbDevice {
+    /// Enables or disables remote wakeup with SET_FEATURE or CLEAR_FEATURE. On Linux the kernel
+    /// arms it itself when suspending, so `power/wakeup` is set to match first, and put back if the
+    /// request fails.
+    #[napi]
+    pub async fn setRemoteWakeup(&self, enabled: bool) -> Result<()> {
+        let power = power_path(self.device_info()).ok();
+        let previous = match power.clone() {
+            Some(power) => {
+                run_blocking(move || {
+                    let previous = read_power_state(&power).ok().and_then(|state| state.wakeup);
+                    write_power_attr(&power, "wakeup", wakeup_value(enabled))
+                        .map(|_| previous)
+                        .map_err(|e| format!("setRemoteWakeup error: {e}"))
+                })
+                .await?
+            }
+            None => None,
+        };
+
+        let result = change_feature(
+            self,
+            "setRemoteWakeup",
+            "remoteWakeup".to_string(),
+            None,
+            enabled,
+        )
+        .await;
+        if let (Err(_), Some(power), Some(previous)) = (&result, power, previous) {
+            let _ =
+                run_blocking(move || write_power_attr(&power, "wakeup", wakeup_value(previous)))
+                    .await;
+        }
+        result
+    }
+
+    /// Runtime power management state from sysfs (Linux only).
+    #[napi]
+    pub async fn getPowerState(&self) -> Result<UsbPowerState> {
+        let power = power_path(self.device_info());
+        run_blocking(move || {
+            power
+                .and_then(|power| read_power_state(&power))
+                .map_err(|e| format!("getPowerState error: {e}"))
+        })
+        .await
+    }
+
+    /// Lets the kernel suspend the device once it has been idle for `delay` milliseconds, or keeps
+    /// it active (Linux only).
+    #[napi]
+    pub async fn setAutosuspend(&self, enabled: bool, delay: Option<i32>) -> Result<()> {
+        let power = power_path(self.device_info());
+        run_blocking(move || {
+            let set = || {
+                let power = power?;
+                if let Some(delay) = delay {
+                    write_power_attr(&power, "autosuspend_delay_ms", &delay.to_string())?;
+                }
+                write_power_attr(&power, "control", if enabled { "auto" } else { "on" })
+            };
+            set().map_err(|e| format!("setAutosuspend error: {e}"))
+        })
+        .await
+    }
+
+    /// Calls back with the power state each time the device resumes, checking every `interval`
+    /// milliseconds (Linux only).
+    #[napi]
+    pub async fn onResume(&self, callback: ResumeCallback, interval: Option<u32>) -> Result<()> {
+        let power = power_path(self.device_info())
+            .and_then(|power| read_power_state(&power).map(|_| power))
+            .map_err(|e| napi::Error::from_reason(format!("onResume error: {e}")))?;
+        let interval =
+            Duration::from_millis(interval.unwrap_or(DEFAULT_RESUME_INTERVAL).max(1) as u64);
+
+        let mut watch = self.resume_watch();
+        if let Some(task) = watch.take() {
+            task.abort();
+        }
+        *watch = Some(tokio::spawn(watch_resume(power, interval, callback)));
+        Ok(())
+    }
+
+    #[napi]
+    pub fn stopResume(&self) {
+        if let Some(task) = self.resume_watch().take() {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn parse(attrs: &[(&str, &str)]) -> std::result::Result<UsbPowerState, String> {
+        let attrs: HashMap<_, _> = attrs.iter().copied().collect();
+        parse_power_state(|name| attrs.get(name).map(|value| value.to_string()))
+    }
+
+    fn state(runtime_status: &str, suspended_time: i64) -> UsbPowerState {
+        parse(&[
+            ("control", "auto"),
+            ("runtime_status", runtime_status),
+            ("runtime_suspended_time", &suspended_time.to_string()),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_the_power_attributes() {
+        let state = parse(&[
+            ("control", "auto"),
+            ("autosuspend_delay_ms", "2000"),
+            ("runtime_status", "suspended"),
+            ("wakeup", "enabled"),
+            ("runtime_suspended_time", "1234"),
+        ])
+        .unwrap();
+        assert_eq!(state.control, "auto");
+        assert_eq!(state.autosuspendDelay, Some(2000));
+        assert_eq!(state.runtimeStatus, "suspended");
+        assert_eq!(state.wakeup, Some(true));
+        assert_eq!(state.suspendedTime, Some(1234));
+
+        // No wakeup capability and no autosuspend support
+        let state = parse(&[
+            ("control", "on"),
+            ("autosuspend_delay_ms", ""),
+            ("runtime_status", "unsupported"),
+            ("wakeup", ""),
+        ])
+        .unwrap();
+        assert_eq!(state.autosuspendDelay, None);
+        assert_eq!(state.wakeup, None);
+        assert_eq!(state.suspendedTime, None);
+        assert_eq!(
+            parse(&[
+                ("control", "on"),
+                ("runtime_status", "active"),
+                ("wakeup", "disabled")
+            ])
+            .unwrap()
+            .wakeup,
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn requires_control_and_runtime_status() {
+        assert!(parse(&[("runtime_status", "active")]).is_err());
+        assert!(parse(&[("control", "auto")]).is_err());
+    }
+
+    #[test]
+    fn detects_a_resume() {
+        assert!(has_resumed(&state("suspended", 10), &state("active", 10)));
+        assert!(has_resumed(&state("resuming", 10), &state("active", 20)));
+        // Suspended and resumed again between two polls
+        assert!(has_resumed(&state("active", 10), &state("active", 20)));
+        assert!(!has_resumed(&state("active", 10), &state("active", 10)));
+        assert!(!has_resumed(&state("active", 10), &state("suspended", 20)));
+        assert!(!has_resumed(
+            &state("suspended", 10),
+            &state("resuming", 10)
+        ));
+    }
+
+    #[test]
+    fn reads_and_writes_the_power_directory() {
+        let power = std::env::temp_dir().join(format!("usb-power-{}", std::process::id()));
+        std::fs::create_dir_all(&power).unwrap();
+        std::fs::write(power.join("control"), "on\n").unwrap();
+        std::fs::write(power.join("runtime_status"), "active\n").unwrap();
+
+        write_power_attr(&power, "control", "auto").unwrap();
+        write_power_attr(&power, "wakeup", wakeup_value(false)).unwrap();
+        let state = read_power_state(&power).unwrap();
+        assert_eq!(state.control, "auto");
+        assert_eq!(state.runtimeStatus, "active");
+        assert_eq!(state.wakeup, Some(false));
+
+        std::fs::remove_dir_all(&power).unwrap();
+        assert!(write_power_attr(&power, "wakeup", "enabled").is_err());
+    }
+}
@@ -0,0 +1,485 @@
+use crate::transfer_pool::{pooled_transfer_in, pooled_transfer_out};
+use crate::webusb_device::{run_blocking, SharedDevice, UsbDevice};
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use nusb::{
+    descriptors::{ConfigurationDescriptor, TransferType},
+    transfer::{ControlIn, ControlOut, ControlType, Direction, In, Out, Recipient},
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+const CLASS_PRINTER: u8 = 0x07;
+const SUBCLASS_PRINTER: u8 = 0x01;
+const PROTOCOL_UNIDIRECTIONAL: u8 = 0x01;
+const PROTOCOL_BIDIRECTIONAL: u8 = 0x02;
+const PROTOCOL_IEEE1284_4: u8 = 0x03;
+
+const GET_DEVICE_ID: u8 = 0x00;
+const GET_PORT_STATUS: u8 = 0x01;
+const SOFT_RESET: u8 = 0x02;
+
+const PORT_NOT_ERROR: u8 = 0x08;
+const PORT_SELECTED: u8 = 0x10;
+const PORT_PAPER_EMPTY: u8 = 0x20;
+
+const DEVICE_ID_LENGTH: u16 = 1024;
+const CONTROL_TIMEOUT: Duration = Duration::from_millis(1000);
+const DEFAULT_TIMEOUT: u32 = 5000;
+const DEFAULT_CHUNK_SIZE: u32 = 4096;
+const DEFAULT_READ_LENGTH: u32 = 512;
+
+#[napi(object)]
+pub struct PrinterOptions {
+    /// The printer interface to use, defaults to the first one
+    pub interfaceNumber: Option<u8>,
+    /// Detach kernel drivers (e.g. `usblp`) while the interface is open, defaults to `true`
+    pub detachKernelDriver: Option<bool>,
+    /// Alternate setting protocol to select (`1` unidirectional, `2` bidirectional, `3` IEEE 1284.4),
+    /// defaults to bidirectional where the interface offers it
+    pub protocol: Option<u8>,
+    /// Largest bulk transfer `write()` sends at once, defaults to 4096 bytes
+    pub chunkSize: Option<u32>,
+    /// Pause (in milliseconds) between chunks, for printers with small buffers, defaults to 0
+    pub chunkDelay: Option<u32>,
+}
+
+/// The IEEE 1284 device ID string and its fields.
+#[napi(object)]
+pub struct PrinterDeviceId {
+    #[napi(writable = false)]
+    pub raw: String,
+    /// Every `KEY:value;` pair, keyed as the printer spells them (e.g. `MFG`, `MDL`, `CMD`)
+    #[napi(writable = false)]
+    pub fields: HashMap<String, String>,
+    /// `MANUFACTURER` or `MFG`
+    #[napi(writable = false)]
+    pub manufacturer: Option<String>,
+    /// `MODEL` or `MDL`
+    #[napi(writable = false)]
+    pub model: Option<String>,
+    /// `COMMAND SET` or `CMD`, split at commas
+    #[napi(writable = false)]
+    pub commandSet: Vec<String>,
+}
+
+/// The GET_PORT_STATUS byte and its bits.
+#[napi(object)]
+pub struct PrinterPortStatus {
+    #[napi(writable = false)]
+    pub status: u8,
+    #[napi(writable = false)]
+    pub paperEmpty: bool,
+    #[napi(writable = false)]
+    pub selected: bool,
+    #[napi(writable = false)]
+    pub error: bool,
+}
+
+struct PrinterLayout {
+    interface: u8,
+    alternate_setting: u8,
+    protocol: u8,
+    bulk_out: u8,
+    bulk_in: Option<u8>,
+}
+
+fn find_printer_layout(
+    config: &ConfigurationDescriptor,
+    interface_number: Option<u8>,
+    protocol: Option<u8>,
+) -> std::result::Result<PrinterLayout, String> {
+    // IEEE 1284.4 needs its own packet protocol, so plain bulk I/O prefers the others
+    let preference = |protocol| match protocol {
+        PROTOCOL_BIDIRECTIONAL => 0,
+        PROTOCOL_UNIDIRECTIONAL => 1,
+        PROTOCOL_IEEE1284_4 => 2,
+        _ => 3,
+    };
+    let layouts = config
+        .interface_alt_settings()
+        .filter(|alt| alt.class() == CLASS_PRINTER && alt.subclass() == SUBCLASS_PRINTER)
+        .filter(|alt| interface_number.is_none_or(|n| alt.interface_number() == n))
+        .filter(|alt| protocol.is_none_or(|p| alt.protocol() == p))
+        .filter_map(|alt| {
+            let endpoint = |direction| {
+                alt.endpoints()
+                    .find(|ep| {
+                        ep.direction() == direction && ep.transfer_type() == TransferType::Bulk
+                    })
+                    .map(|ep| ep.address() & 0x7f)
+            };
+            Some(PrinterLayout {
+                interface: alt.interface_number(),
+                alternate_setting: alt.alternate_setting(),
+                protocol: alt.protocol(),
+                bulk_out: endpoint(Direction::Out)?,
+                bulk_in: endpoint(Direction::In)
+                    .filter(|_| alt.protocol() != PROTOCOL_UNIDIRECTIONAL),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let interface = layouts
+        .first()
+        .map(|layout| layout.interface)
+        .ok_or_else(|| "no printer interface found".to_string())?;
+    layouts
+        .into_iter()
+        .filter(|layout| layout.interface == interface)
+        .min_by_key(|layout| preference(layout.protocol))
+        .ok_or_else(|| "no printer interface found".to_string())
+}
+
+/// The string of a GET_DEVICE_ID response, after its length prefix.
+fn device_id_string(data: &[u8]) -> Option<String> {
+    let [hi, lo, ..] = data[..] else {
+        return None;
+    };
+    // The length is big-endian and counts itself, though some printers send it little-endian
+    let mut length = u16::from_be_bytes([hi, lo]) as usize;
+    if length > data.len() {
+        length = u16::from_le_bytes([hi, lo]) as usize;
+    }
+    let id = &data[2..length.clamp(2, data.len())];
+    Some(
+        String::from_utf8_lossy(id)
+            .trim_end_matches('\0')
+            .to_string(),
+    )
+}
+
+/// Splits an IEEE 1284 device ID (`MFG:Example;MDL:Receipt 80;CMD:ESC/POS;`) into its fields.
+fn parse_device_id(raw: String) -> PrinterDeviceId {
+    let fields = raw
+        .split(';')
+        .filter_map(|field| field.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect::<HashMap<_, _>>();
+    let field = |long: &str, short: &str| {
+        fields
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(long) || key.eq_ignore_ascii_case(short))
+            .map(|(_, value)| value.clone())
+    };
+    let manufacturer = field("MANUFACTURER", "MFG");
+    let model = field("MODEL", "MDL");
+    let command_set = field("COMMAND SET", "CMD")
+        .map(|commands| {
+            commands
+                .split(',')
+                .map(|command| command.trim().to_string())
+                .filter(|command| !command.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+    PrinterDeviceId {
+        raw,
+        fields,
+        manufacturer,
+        model,
+        commandSet: command_set,
+    }
+}
+
+/// A USB printer, written to over bulk OUT and, for bidirectional alternate settings, read from over bulk IN.
+#[napi]
+pub struct UsbPrinter {
+    shared: Arc<SharedDevice>,
+    layout: PrinterLayout,
+    configuration_index: u8,
+    chunk_size: usize,
+    chunk_delay: Duration,
+    /// Keeps the chunks of concurrent writes from interleaving
+    writer: Arc<Mutex<()>>,
+
+    #[napi(writable = false)]
+    pub interfaceNumber: u8,
+    #[napi(writable = false)]
+    pub alternateSetting: u8,
+    /// `1` unidirectional, `2` bidirectional or `3` IEEE 1284.4
+    #[napi(writable = false)]
+    pub protocol: u8,
+}
+
+impl UsbPrinter {
+    async fn control_in(
+        &self,
+        method: &'static str,
+        request: u8,
+        value: u16,
+        index: u16,
+        length: u16,
+    ) -> Result<Vec<u8>> {
+        let shared = self.shared.clone();
+        let interface = self.layout.interface;
+        run_blocking(move || {
+            let target = shared
+                .control_target(Recipient::Interface, interface as u16)
+                .ok_or_else(|| format!("{method} error: invalid state"))?;
+            target
+                .control_in_blocking(
+                    ControlIn {
+                        control_type: ControlType::Class,
+                        recipient: Recipient::Interface,
+                        request,
+                        value,
+                        index,
+                        length,
+                    },
+                    CONTROL_TIMEOUT,
+                )
+                .map_err(|e| format!("{method} error: {e}"))
+        })
+        .await
+    }
+}
+
+#[napi]
+impl UsbPrinter {
+    /// GET_DEVICE_ID, parsed into its fields.
+    #[napi]
+    pub async fn getDeviceId(&self) -> Result<PrinterDeviceId> {
+        let index = (self.layout.interface as u16) << 8 | self.layout.alternate_setting as u16;
+        let data = self
+            .control_in(
+                "getDeviceId",
+                GET_DEVICE_ID,
+                self.configuration_index as u16,
+                index,
+                DEVICE_ID_LENGTH,
+            )
+            .await?;
+        let raw = device_id_string(&data)
+            .ok_or_else(|| napi::Error::from_reason("getDeviceId error: short response"))?;
+        Ok(parse_device_id(raw))
+    }
+
+    /// GET_PORT_STATUS, with the paper empty, selected and error bits decoded.
+    #[napi]
+    pub async fn getPortStatus(&self) -> Result<PrinterPortStatus> {
+        let data = self
+            .control_in(
+                "getPortStatus",
+                GET_PORT_STATUS,
+                0,
+                self.layout.interface as u16,
+                1,
+            )
+            .await?;
+        let status = *data
+            .first()
+            .ok_or_else(|| napi::Error::from_reason("getPortStatus error: short response"))?;
+        Ok(PrinterPortStatus {
+            status,
+            paperEmpty: status & PORT_PAPER_EMPTY != 0,
+            selected: status & PORT_SELECTED != 0,
+            error: status & PORT_NOT_ERROR == 0,
+        })
+    }
+
+    /// SOFT_RESET: flushes the printer's buffers and resets its bulk pipes.
+    #[napi]
+    pub async fn softReset(&self) -> Result<()> {
+        let shared = self.shared.clone();
+        let interface = self.layout.interface;
+        run_blocking(move || {
+            let target = shared
+                .control_target(Recipient::Interface, interface as u16)
+                .ok_or("softReset error: invalid state")?;
+            target
+                .control_out_blocking(
+                    ControlOut {
+                        control_type: ControlType::Class,
+                        recipient: Recipient::Interface,
+                        request: SOFT_RESET,
+                        value: 0,
+                        index: interface as u16,
+                        data: &[],
+                    },
+                    CONTROL_TIMEOUT,
+                )
+                .map_err(|e| format!("softReset error: {e}"))
+        })
+        .await
+    }
+
+    /// Writes data (e.g. ESC/POS commands) in chunks of at most `chunkSize` bytes, `chunkDelay` apart.
+    /// The timeout applies to each chunk.
+    #[napi]
+    pub async fn write(&self, data: Uint8Array, timeout: Option<u32>) -> Result<()> {
+        let timeout = Duration::from_millis(timeout.unwrap_or(DEFAULT_TIMEOUT) as u64);
+        let data = data.to_vec();
+        let shared = self.shared.clone();
+        let writer = self.writer.clone();
+        let bulk_out = self.layout.bulk_out;
+        let chunk_size = self.chunk_size;
+        let chunk_delay = self.chunk_delay;
+        run_blocking(move || {
+            let _writer = writer
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let mut endpoint = shared
+                .get_endpoint::<Out>(bulk_out)
+                .ok_or("write error: invalid state")?;
+            let mut result = Ok(());
+            for (i, chunk) in data.chunks(chunk_size).enumerate() {
+                if i > 0 && !chunk_delay.is_zero() {
+                    std::thread::sleep(chunk_delay);
+                }
+                if let Err(e) = pooled_transfer_out(&shared, &mut endpoint, chunk, timeout) {
+                    result = Err(format!("write error: {e:?}"));
+                    break;
+                }
+            }
+            shared.put_endpoint(endpoint);
+            result
+        })
+        .await
+    }
+
+    /// Reads what the printer sends back (e.g. ESC/POS status replies) from a bidirectional interface.
+    #[napi]
+    pub async fn read(&self, length: Option<u32>, timeout: Option<u32>) -> Result<Uint8Array> {
+        let bulk_in = self.layout.bulk_in.ok_or_else(|| {
+            napi::Error::from_reason("read error: printer interface is not bidirectional")
+        })?;
+        let length = length.unwrap_or(DEFAULT_READ_LENGTH) as usize;
+        let timeout = Duration::from_millis(timeout.unwrap_or(DEFAULT_TIMEOUT) as u64);
+        let shared = self.shared.clone();
+        let data = run_blocking(move || {
+            let mut endpoint = shared
+                .get_endpoint::<In>(bulk_in)
+                .ok_or("read error: invalid state")?;
+            let result = pooled_transfer_in(&shared, &mut endpoint, length, timeout)
+                .map(|(buffer, len)| {
+                    let data = buffer[..len].to_vec();
+                    shared.put_buffer(&endpoint, buffer);
+                    data
+                })
+                .map_err(|e| format!("read error: {e:?}"));
+            shared.put_endpoint(endpoint);
+            result
+        })
+        .await?;
+        Ok(Uint8Array::new(data))
+    }
+
+    /// Releases the interface, reattaching any kernel driver.
+    #[napi]
+    pub async fn close(&self) -> Result<()> {
+        self.shared
+            .release_interface("close", self.layout.interface)
+            .await
+    }
+}
+
+#[napi]
+impl UsbDevice {
+    /// Claims a printer interface and selects its bidirectional alternate setting where there is one.
+    #[napi]
+    pub async fn openPrinter(&self, options: Option<PrinterOptions>) -> Result<UsbPrinter> {
+        let interface_number = options.as_ref().and_then(|o| o.interfaceNumber);
+        let protocol = options.as_ref().and_then(|o| o.protocol);
+        let detach = options
+            .as_ref()
+            .and_then(|o| o.detachKernelDriver)
+            .unwrap_or(true);
+        let chunk_size = options
+            .as_ref()
+            .and_then(|o| o.chunkSize)
+            .unwrap_or(DEFAULT_CHUNK_SIZE)
+            .max(1) as usize;
+        let chunk_delay =
+            Duration::from_millis(options.as_ref().and_then(|o| o.chunkDelay).unwrap_or(0) as u64);
+
+        let shared = self.shared().clone();
+        let device = shared
+            .opened_device()
+            .ok_or_else(|| napi::Error::from_reason("openPrinter error: invalid state"))?;
+        let (layout, configuration_index) = run_blocking(move || {
+            let config = device
+                .active_configuration()
+                .map_err(|e| format!("openPrinter error: {e}"))?;
+            let layout = find_printer_layout(&config, interface_number, protocol)
+                .map_err(|e| format!("openPrinter error: {e}"))?;
+            // GET_DEVICE_ID is addressed by configuration index rather than value
+            let configuration_index = device
+                .configurations()
+                .position(|c| c.configuration_value() == config.configuration_value())
+                .unwrap_or(0) as u8;
+            Ok((layout, configuration_index))
+        })
+        .await?;
+
+        shared
+            .claim_interface("openPrinter", layout.interface, detach)
+            .await?;
+        if layout.alternate_setting != 0 {
+            shared
+                .select_alternate_interface(
+                    "openPrinter",
+                    layout.interface,
+                    layout.alternate_setting,
+                )
+                .await?;
+        }
+
+        Ok(UsbPrinter {
+            shared,
+            configuration_index,
+            chunk_size,
+            chunk_delay,
+            writer: Arc::new(Mutex::new(())),
+            interfaceNumber: layout.interface,
+            alternateSetting: layout.alternate_setting,
+            protocol: layout.protocol,
+            layout,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_length_prefix_either_way_round() {
+        let id = b"MFG:Example;MDL:Receipt 80;";
+        let mut big = ((id.len() + 2) as u16).to_be_bytes().to_vec();
+        big.extend_from_slice(id);
+        big.extend_from_slice(&[0; 4]);
+        assert_eq!(
+            device_id_string(&big).as_deref(),
+            Some("MFG:Example;MDL:Receipt 80;")
+        );
+
+        let mut little = ((id.len() + 2) as u16).to_le_bytes().to_vec();
+        little.extend_from_slice(id);
+        assert_eq!(
+            device_id_string(&little).as_deref(),
+            Some("MFG:Example;MDL:Receipt 80;")
+        );
+
+        assert_eq!(device_id_string(&[0x00]), None);
+    }
+
+    #[test]
+    fn splits_device_id_fields() {
+        let id = parse_device_id(
+            "MANUFACTURER:Example Inc;Model: LaserJet 9 ;CMD:PCL, PJL,POSTSCRIPT,;CLS:PRINTER;"
+                .to_string(),
+        );
+        assert_eq!(id.manufacturer.as_deref(), Some("Example Inc"));
+        assert_eq!(id.model.as_deref(), Some("LaserJet 9"));
+        assert_eq!(id.commandSet, ["PCL", "PJL", "POSTSCRIPT"]);
+        assert_eq!(id.fields.get("CLS").map(String::as_str), Some("PRINTER"));
+        assert_eq!(id.fields.len(), 4);
+
+        let empty = parse_device_id(String::new());
+        assert!(empty.manufacturer.is_none() && empty.commandSet.is_empty());
+    }
+}
@@ -0,0 +1,1187 @@
+use crate::endpoint_stream::InStream;
+use crate::transfer_pool::{pooled_transfer_in, pooled_transfer_out, PooledEndpoint};
+use crate::webusb_device::{run_blocking, SharedDevice, UsbDevice};
+use napi::{
+    bindgen_prelude::*, threadsafe_function::ThreadsafeFunction,
+    threadsafe_function::ThreadsafeFunctionCallMode,
+};
+use napi_derive::napi;
+use nusb::{
+    descriptors::{ConfigurationDescriptor, TransferType},
+    transfer::{ControlIn, ControlOut, ControlType, Direction, In, Out, Recipient},
+};
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+const CLASS_IMAGE: u8 = 0x06;
+const SUBCLASS_STILL_IMAGE: u8 = 0x01;
+const PROTOCOL_PTP: u8 = 0x01;
+
+const CANCEL_REQUEST: u8 = 0x64;
+const DEVICE_RESET_REQUEST: u8 = 0x66;
+const GET_DEVICE_STATUS: u8 = 0x67;
+/// Event code carried by CANCEL_REQUEST
+const EVENT_CANCEL_TRANSACTION: u16 = 0x4001;
+
+const CONTAINER_COMMAND: u16 = 1;
+const CONTAINER_DATA: u16 = 2;
+const CONTAINER_RESPONSE: u16 = 3;
+const CONTAINER_EVENT: u16 = 4;
+const HEADER_LEN: usize = 12;
+/// Container length of MTP data phases over 4 GiB, which end with a short packet instead
+const LENGTH_UNKNOWN: u32 = u32::MAX;
+
+const OP_GET_DEVICE_INFO: u16 = 0x1001;
+const OP_OPEN_SESSION: u16 = 0x1002;
+const OP_CLOSE_SESSION: u16 = 0x1003;
+const OP_GET_STORAGE_IDS: u16 = 0x1004;
+const OP_GET_STORAGE_INFO: u16 = 0x1005;
+const OP_GET_OBJECT_HANDLES: u16 = 0x1007;
+const OP_GET_OBJECT_INFO: u16 = 0x1008;
+const OP_GET_OBJECT: u16 = 0x1009;
+const OP_GET_THUMB: u16 = 0x100a;
+const OP_DELETE_OBJECT: u16 = 0x100b;
+const OP_SEND_OBJECT_INFO: u16 = 0x100c;
+const OP_SEND_OBJECT: u16 = 0x100d;
+
+const RESPONSE_OK: u16 = 0x2001;
+const RESPONSE_SESSION_ALREADY_OPEN: u16 = 0x201e;
+
+const FORMAT_UNDEFINED: u16 = 0x3000;
+const SESSION_ID: u32 = 1;
+
+const DEFAULT_TIMEOUT: u32 = 10000;
+const CONTROL_TIMEOUT: Duration = Duration::from_millis(1000);
+/// Bulk data is moved in transfers of this size, a multiple of every bulk packet size.
+const CHUNK_LEN: usize = 1024 * 1024;
+const EVENT_TRANSFERS: usize = 2;
+
+type EventCallback = ThreadsafeFunction<PtpEvent, (), PtpEvent, napi::Status, false>;
+
+#[napi(object)]
+pub struct PtpOptions {
+    /// The still image interface to use, defaults to the first one. Any interface with a bulk pair may be named
+    pub interfaceNumber: Option<u8>,
+    /// Detach kernel drivers while the interface is open, defaults to `true`
+    pub detachKernelDriver: Option<bool>,
+    /// Timeout (in milliseconds) for each bulk transfer of an operation, defaults to 10000
+    pub timeout: Option<u32>,
+}
+
+/// The DeviceInfo dataset.
+#[napi(object)]
+#[derive(Clone)]
+pub struct PtpDeviceInfo {
+    #[napi(writable = false)]
+    pub standardVersion: u16,
+    #[napi(writable = false)]
+    pub vendorExtensionId: u32,
+    #[napi(writable = false)]
+    pub vendorExtensionVersion: u16,
+    /// MTP devices describe their extensions here (e.g. `microsoft.com: 1.0;`)
+    #[napi(writable = false)]
+    pub vendorExtensionDesc: String,
+    #[napi(writable = false)]
+    pub functionalMode: u16,
+    #[napi(writable = false)]
+    pub operationsSupported: Vec<u16>,
+    #[napi(writable = false)]
+    pub eventsSupported: Vec<u16>,
+    #[napi(writable = false)]
+    pub devicePropertiesSupported: Vec<u16>,
+    #[napi(writable = false)]
+    pub captureFormats: Vec<u16>,
+    #[napi(writable = false)]
+    pub imageFormats: Vec<u16>,
+    #[napi(writable = false)]
+    pub manufacturer: String,
+    #[napi(writable = false)]
+    pub model: String,
+    #[napi(writable = false)]
+    pub deviceVersion: String,
+    #[napi(writable = false)]
+    pub serialNumber: String,
+}
+
+/// The StorageInfo dataset.
+#[napi(object)]
+pub struct PtpStorageInfo {
+    #[napi(writable = false)]
+    pub storageType: u16,
+    #[napi(writable = false)]
+    pub filesystemType: u16,
+    /// `0` read-write, `1` read-only, `2` read-only with deletion
+    #[napi(writable = false)]
+    pub accessCapability: u16,
+    #[napi(writable = false)]
+    pub maxCapacity: i64,
+    #[napi(writable = false)]
+    pub freeSpaceInBytes: i64,
+    /// `0xFFFFFFFF` when not used
+    #[napi(writable = false)]
+    pub freeSpaceInImages: u32,
+    #[napi(writable = false)]
+    pub storageDescription: String,
+    #[napi(writable = false)]
+    pub volumeLabel: String,
+}
+
+/// The ObjectInfo dataset.
+#[napi(object)]
+pub struct PtpObjectInfo {
+    #[napi(writable = false)]
+    pub storageId: u32,
+    #[napi(writable = false)]
+    pub objectFormat: u16,
+    #[napi(writable = false)]
+    pub protectionStatus: u16,
+    /// `0xFFFFFFFF` for MTP objects of 4 GiB or more
+    #[napi(writable = false)]
+    pub compressedSize: u32,
+    #[napi(writable = false)]
+    pub thumbFormat: u16,
+    #[napi(writable = false)]
+    pub thumbCompressedSize: u32,
+    #[napi(writable = false)]
+    pub thumbWidth: u32,
+    #[napi(writable = false)]
+    pub thumbHeight: u32,
+    #[napi(writable = false)]
+    pub imageWidth: u32,
+    #[napi(writable = false)]
+    pub imageHeight: u32,
+    #[napi(writable = false)]
+    pub imageBitDepth: u32,
+    #[napi(writable = false)]
+    pub parentObject: u32,
+    #[napi(writable = false)]
+    pub associationType: u16,
+    #[napi(writable = false)]
+    pub associationDesc: u32,
+    #[napi(writable = false)]
+    pub sequenceNumber: u32,
+    #[napi(writable = false)]
+    pub filename: String,
+    /// ISO 8601 style `YYYYMMDDThhmmss`, or empty
+    #[napi(writable = false)]
+    pub captureDate: String,
+    #[napi(writable = false)]
+    pub modificationDate: String,
+    #[napi(writable = false)]
+    pub keywords: String,
+}
+
+#[napi(object)]
+pub struct PtpSendObjectOptions {
+    pub filename: String,
+    /// Object format code, defaults to `0x3000` (undefined)
+    pub objectFormat: Option<u16>,
+    /// Storage to put the object in, defaults to `0` for the device to choose
+    pub storageId: Option<u32>,
+    /// Parent folder handle, defaults to `0` for the device to choose (`0xFFFFFFFF` is the storage root)
+    pub parentObject: Option<u32>,
+    pub modificationDate: Option<String>,
+}
+
+/// The response to an operation, with the data phase the device sent, if any.
+#[napi(object)]
+pub struct PtpResponse {
+    #[napi(writable = false)]
+    pub code: u16,
+    #[napi(writable = false)]
+    pub params: Vec<u32>,
+    #[napi(writable = false)]
+    pub data: Option<Uint8Array>,
+}
+
+#[napi(object)]
+pub struct PtpEvent {
+    #[napi(writable = false)]
+    pub code: u16,
+    #[napi(writable = false)]
+    pub transactionId: u32,
+    #[napi(writable = false)]
+    pub params: Vec<u32>,
+}
+
+/// The result of the Get Device Status class request.
+#[napi(object)]
+pub struct PtpDeviceStatus {
+    #[napi(writable = false)]
+    pub code: u16,
+    /// Stalled endpoint addresses after a cancelled transaction
+    #[napi(writable = false)]
+    pub params: Vec<u32>,
+}
+
+struct PtpLayout {
+    interface: u8,
+    bulk_in: u8,
+    bulk_out: u8,
+    interrupt_in: Option<u8>,
+}
+
+fn find_ptp_layout(
+    config: &ConfigurationDescriptor,
+    interface_number: Option<u8>,
+) -> std::result::Result<PtpLayout, String> {
+    config
+        .interface_alt_settings()
+        .filter(|alt| alt.alternate_setting() == 0)
+        .filter(|alt| match interface_number {
+            Some(n) => alt.interface_number() == n,
+            None => {
+                alt.class() == CLASS_IMAGE
+                    && alt.subclass() == SUBCLASS_STILL_IMAGE
+                    && alt.protocol() == PROTOCOL_PTP
+            }
+        })
+        .find_map(|alt| {
+            let endpoint = |direction, transfer_type| {
+                alt.endpoints()
+                    .find(|ep| ep.direction() == direction && ep.transfer_type() == transfer_type)
+                    .map(|ep| ep.address() & 0x7f)
+            };
+            Some(PtpLayout {
+                interface: alt.interface_number(),
+                bulk_in: endpoint(Direction::In, TransferType::Bulk)?,
+                bulk_out: endpoint(Direction::Out, TransferType::Bulk)?,
+                interrupt_in: endpoint(Direction::In, TransferType::Interrupt),
+            })
+        })
+        .ok_or_else(|| "no PTP interface found".to_string())
+}
+
+fn response_error(code: u16) -> String {
+    let name = match code {
+        0x2002 => "general error",
+        0x2003 => "session not open",
+        0x2004 => "invalid transaction ID",
+        0x2005 => "operation not supported",
+        0x2006 => "parameter not supported",
+        0x2007 => "incomplete transfer",
+        0x2008 => "invalid storage ID",
+        0x2009 => "invalid object handle",
+        0x200c => "store full",
+        0x200d => "object write-protected",
+        0x200e => "store read-only",
+        0x200f => "access denied",
+        0x2013 => "store not available",
+        0x2019 => "device busy",
+        0x201a => "invalid parent object",
+        0x201d => "invalid parameter",
+        0x201f => "transaction cancelled",
+        _ => return format!("response {code:#06x}"),
+    };
+    format!("{name} ({code:#06x})")
+}
+
+/// Reads the little-endian fields and datasets PTP is built from.
+struct DatasetReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> DatasetReader<'a> {
+    fn take(&mut self, len: usize) -> std::result::Result<&'a [u8], String> {
+        if self.data.len() < len {
+            return Err("short dataset".to_string());
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn u16(&mut self) -> std::result::Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> std::result::Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> std::result::Result<u64, String> {
+        let low = self.u32()? as u64;
+        let high = self.u32()? as u64;
+        Ok(high << 32 | low)
+    }
+
+    /// A character count (including the terminator) followed by UTF-16LE characters.
+    fn string(&mut self) -> std::result::Result<String, String> {
+        let count = self.take(1)?[0] as usize;
+        let units = (0..count)
+            .map(|_| self.u16())
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let end = units.iter().position(|&c| c == 0).unwrap_or(units.len());
+        Ok(String::from_utf16_lossy(&units[..end]))
+    }
+
+    fn u16_array(&mut self) -> std::result::Result<Vec<u16>, String> {
+        let count = self.u32()?;
+        (0..count).map(|_| self.u16()).collect()
+    }
+
+    fn u32_array(&mut self) -> std::result::Result<Vec<u32>, String> {
+        let count = self.u32()?;
+        (0..count).map(|_| self.u32()).collect()
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, value: &str) -> std::result::Result<(), String> {
+    if value.is_empty() {
+        out.push(0);
+        return Ok(());
+    }
+    let units = value.encode_utf16().chain([0]).collect::<Vec<_>>();
+    let count = u8::try_from(units.len()).map_err(|_| "string too long".to_string())?;
+    out.push(count);
+    for unit in units {
+        out.extend_from_slice(&unit.to_le_bytes());
+    }
+    Ok(())
+}
+
+fn parse_device_info(data: &[u8]) -> std::result::Result<PtpDeviceInfo, String> {
+    let mut r = DatasetReader { data };
+    Ok(PtpDeviceInfo {
+        standardVersion: r.u16()?,
+        vendorExtensionId: r.u32()?,
+        vendorExtensionVersion: r.u16()?,
+        vendorExtensionDesc: r.string()?,
+        functionalMode: r.u16()?,
+        operationsSupported: r.u16_array()?,
+        eventsSupported: r.u16_array()?,
+        devicePropertiesSupported: r.u16_array()?,
+        captureFormats: r.u16_array()?,
+        imageFormats: r.u16_array()?,
+        manufacturer: r.string()?,
+        model: r.string()?,
+        deviceVersion: r.string()?,
+        serialNumber: r.string()?,
+    })
+}
+
+fn parse_storage_info(data: &[u8]) -> std::result::Result<PtpStorageInfo, String> {
+    let mut r = DatasetReader { data };
+    Ok(PtpStorageInfo {
+        storageType: r.u16()?,
+        filesystemType: r.u16()?,
+        accessCapability: r.u16()?,
+        maxCapacity: r.u64()? as i64,
+        freeSpaceInBytes: r.u64()? as i64,
+        freeSpaceInImages: r.u32()?,
+        storageDescription: r.string()?,
+        volumeLabel: r.string()?,
+    })
+}
+
+fn parse_object_info(data: &[u8]) -> std::result::Result<PtpObjectInfo, String> {
+    let mut r = DatasetReader { data };
+    Ok(PtpObjectInfo {
+        storageId: r.u32()?,
+        objectFormat: r.u16()?,
+        protectionStatus: r.u16()?,
+        compressedSize: r.u32()?,
+        thumbFormat: r.u16()?,
+        thumbCompressedSize: r.u32()?,
+        thumbWidth: r.u32()?,
+        thumbHeight: r.u32()?,
+        imageWidth: r.u32()?,
+        imageHeight: r.u32()?,
+        imageBitDepth: r.u32()?,
+        parentObject: r.u32()?,
+        associationType: r.u16()?,
+        associationDesc: r.u32()?,
+        sequenceNumber: r.u32()?,
+        filename: r.string()?,
+        captureDate: r.string()?,
+        modificationDate: r.string()?,
+        keywords: r.string()?,
+    })
+}
+
+/// An ObjectInfo dataset for SendObjectInfo, describing a plain file.
+fn object_info_dataset(
+    options: &PtpSendObjectOptions,
+    size: usize,
+) -> std::result::Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&options.storageId.unwrap_or(0).to_le_bytes());
+    out.extend_from_slice(
+        &options
+            .objectFormat
+            .unwrap_or(FORMAT_UNDEFINED)
+            .to_le_bytes(),
+    );
+    out.extend_from_slice(&0u16.to_le_bytes()); // protection status
+    out.extend_from_slice(&u32::try_from(size).unwrap_or(u32::MAX).to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes()); // thumb format
+    out.extend_from_slice(&[0; 4 * 6]); // thumb size and dimensions, image dimensions and depth
+    out.extend_from_slice(&options.parentObject.unwrap_or(0).to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes()); // association type
+    out.extend_from_slice(&[0; 4 * 2]); // association description, sequence number
+    write_string(&mut out, &options.filename)?;
+    write_string(&mut out, "")?; // capture date
+    write_string(&mut out, options.modificationDate.as_deref().unwrap_or(""))?;
+    write_string(&mut out, "")?; // keywords
+    Ok(out)
+}
+
+fn container_header(length: usize, container_type: u16, code: u16, transaction_id: u32) -> Vec<u8> {
+    let mut header = Vec::with_capacity(length.min(CHUNK_LEN));
+    header.extend_from_slice(
+        &u32::try_from(length)
+            .unwrap_or(LENGTH_UNKNOWN)
+            .to_le_bytes(),
+    );
+    header.extend_from_slice(&container_type.to_le_bytes());
+    header.extend_from_slice(&code.to_le_bytes());
+    header.extend_from_slice(&transaction_id.to_le_bytes());
+    header
+}
+
+fn words(data: &[u8]) -> Vec<u32> {
+    data.chunks_exact(4)
+        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+        .collect()
+}
+
+/// An event container from the interrupt endpoint, ignoring anything else sent there.
+fn parse_event(data: &[u8]) -> Option<PtpEvent> {
+    if data.len() < HEADER_LEN || u16::from_le_bytes([data[4], data[5]]) != CONTAINER_EVENT {
+        return None;
+    }
+    let length = (u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize)
+        .clamp(HEADER_LEN, data.len());
+    Some(PtpEvent {
+        code: u16::from_le_bytes([data[6], data[7]]),
+        transactionId: u32::from_le_bytes([data[8], data[9], data[10], data[11]]),
+        params: words(&data[HEADER_LEN..length]),
+    })
+}
+
+struct Container {
+    container_type: u16,
+    code: u16,
+    payload: Vec<u8>,
+}
+
+struct Response {
+    code: u16,
+    params: Vec<u32>,
+    data: Option<Vec<u8>>,
+}
+
+impl Response {
+    fn check(self) -> std::result::Result<Self, String> {
+        match self.code {
+            RESPONSE_OK => Ok(self),
+            code => Err(response_error(code)),
+        }
+    }
+}
+
+/// Runs operations one at a time: a command container, an optional data phase in either
+/// direction, then the response container.
+struct PtpTransport {
+    shared: Arc<SharedDevice>,
+    layout: PtpLayout,
+    timeout: Duration,
+    /// The ID of the latest transaction, read by `cancel()` without waiting for the lock
+    transaction_id: Arc<AtomicU32>,
+    session_open: bool,
+}
+
+impl PtpTransport {
+    fn status(&self) -> std::result::Result<(u16, Vec<u32>), String> {
+        device_status(&self.shared, self.layout.interface)
+    }
+
+    fn transaction(
+        &self,
+        code: u16,
+        params: &[u32],
+        data_out: Option<&[u8]>,
+    ) -> std::result::Result<Response, String> {
+        // Operations outside a session, OpenSession included, use transaction ID 0
+        let transaction_id = if self.session_open && code != OP_OPEN_SESSION {
+            self.transaction_id.fetch_add(1, Ordering::SeqCst) + 1
+        } else {
+            0
+        };
+
+        let mut ep_in = self
+            .shared
+            .get_endpoint::<In>(self.layout.bulk_in)
+            .ok_or("invalid state")?;
+        let mut ep_out = match self.shared.get_endpoint::<Out>(self.layout.bulk_out) {
+            Some(ep_out) => ep_out,
+            None => {
+                self.shared.put_endpoint(ep_in);
+                return Err("invalid state".to_string());
+            }
+        };
+
+        let result = self.exchange(
+            &mut ep_in,
+            &mut ep_out,
+            code,
+            transaction_id,
+            params,
+            data_out,
+        );
+        if result.is_err() {
+            // A cancelled or failed transaction leaves the device reporting which pipes it stalled
+            if let Ok((_, stalled)) = self.status() {
+                for address in stalled {
+                    if address as u8 == self.layout.bulk_in | 0x80 {
+                        let _ = ep_in.clear_halt_blocking();
+                    } else if address as u8 == self.layout.bulk_out {
+                        let _ = ep_out.clear_halt_blocking();
+                    }
+                }
+            }
+        }
+
+        self.shared.put_endpoint(ep_in);
+        self.shared.put_endpoint(ep_out);
+        result
+    }
+
+    fn exchange(
+        &self,
+        ep_in: &mut PooledEndpoint<In>,
+        ep_out: &mut PooledEndpoint<Out>,
+        code: u16,
+        transaction_id: u32,
+        params: &[u32],
+        data_out: Option<&[u8]>,
+    ) -> std::result::Result<Response, String> {
+        let mut command = container_header(
+            HEADER_LEN + params.len() * 4,
+            CONTAINER_COMMAND,
+            code,
+            transaction_id,
+        );
+        for param in params {
+            command.extend_from_slice(&param.to_le_bytes());
+        }
+        self.send(ep_out, &command)
+            .map_err(|e| format!("command failed: {e}"))?;
+
+        if let Some(data) = data_out {
+            let mut container = container_header(
+                HEADER_LEN + data.len(),
+                CONTAINER_DATA,
+                code,
+                transaction_id,
+            );
+            container.extend_from_slice(data);
+            self.send(ep_out, &container)
+                .map_err(|e| format!("data phase failed: {e}"))?;
+        }
+
+        let mut container = self.receive(ep_in)?;
+        let mut data = None;
+        if container.container_type == CONTAINER_DATA {
+            data = Some(container.payload);
+            container = self.receive(ep_in)?;
+        }
+        if container.container_type != CONTAINER_RESPONSE {
+            return Err(format!(
+                "unexpected container type {}",
+                container.container_type
+            ));
+        }
+        Ok(Response {
+            code: container.code,
+            params: words(&container.payload),
+            data,
+        })
+    }
+
+    /// Sends a container in chunks, ending it with a zero length packet when it fills its last packet.
+    fn send(
+        &self,
+        ep_out: &mut PooledEndpoint<Out>,
+        container: &[u8],
+    ) -> std::result::Result<(), String> {
+        for chunk in container.chunks(CHUNK_LEN) {
+            pooled_transfer_out(&self.shared, ep_out, chunk, self.timeout)
+                .map_err(|e| format!("{e:?}"))?;
+        }
+        if container.len().is_multiple_of(ep_out.max_packet_size()) {
+            pooled_transfer_out(&self.shared, ep_out, &[], self.timeout)
+                .map_err(|e| format!("{e:?}"))?;
+        }
+        Ok(())
+    }
+
+    fn read(
+        &self,
+        ep_in: &mut PooledEndpoint<In>,
+        len: usize,
+    ) -> std::result::Result<Vec<u8>, String> {
+        let (buffer, len) = pooled_transfer_in(&self.shared, ep_in, len, self.timeout)
+            .map_err(|e| format!("read failed: {e:?}"))?;
+        let data = buffer[..len].to_vec();
+        self.shared.put_buffer(ep_in, buffer);
+        Ok(data)
+    }
+
+    /// Reads one container, skipping the zero length packet that may end a previous one.
+    fn receive(&self, ep_in: &mut PooledEndpoint<In>) -> std::result::Result<Container, String> {
+        let mut data = Vec::new();
+        while data.is_empty() {
+            data = self.read(ep_in, CHUNK_LEN)?;
+        }
+        if data.len() < HEADER_LEN {
+            return Err("short container".to_string());
+        }
+
+        let length = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        if length == LENGTH_UNKNOWN {
+            let mut last = data.len();
+            while last == CHUNK_LEN {
+                let more = self.read(ep_in, CHUNK_LEN)?;
+                last = more.len();
+                data.extend_from_slice(&more);
+            }
+        } else {
+            let length = length as usize;
+            if length < HEADER_LEN {
+                return Err("invalid container length".to_string());
+            }
+            while data.len() < length {
+                let more = self.read(ep_in, (length - data.len()).min(CHUNK_LEN))?;
+                if more.is_empty() {
+                    return Err("short container".to_string());
+                }
+                data.extend_from_slice(&more);
+            }
+            data.truncate(length);
+        }
+
+        let container_type = u16::from_le_bytes([data[4], data[5]]);
+        let code = u16::from_le_bytes([data[6], data[7]]);
+        data.drain(..HEADER_LEN);
+        Ok(Container {
+            container_type,
+            code,
+            payload: data,
+        })
+    }
+
+    fn open_session(&mut self) -> std::result::Result<(), String> {
+        self.session_open = false;
+        let response = self.transaction(OP_OPEN_SESSION, &[SESSION_ID], None)?;
+        if response.code != RESPONSE_SESSION_ALREADY_OPEN {
+            response.check()?;
+        }
+        self.transaction_id.store(0, Ordering::SeqCst);
+        self.session_open = true;
+        Ok(())
+    }
+
+    fn device_info(&self) -> std::result::Result<PtpDeviceInfo, String> {
+        let response = self.transaction(OP_GET_DEVICE_INFO, &[], None)?.check()?;
+        parse_device_info(&response.data.unwrap_or_default())
+    }
+
+    fn data(&self, code: u16, params: &[u32]) -> std::result::Result<Vec<u8>, String> {
+        let response = self.transaction(code, params, None)?.check()?;
+        response
+            .data
+            .ok_or_else(|| "no data phase in response".to_string())
+    }
+}
+
+fn device_status(
+    shared: &SharedDevice,
+    interface: u8,
+) -> std::result::Result<(u16, Vec<u32>), String> {
+    let target = shared
+        .control_target(Recipient::Interface, interface as u16)
+        .ok_or("invalid state")?;
+    let data = target
+        .control_in_blocking(
+            ControlIn {
+                control_type: ControlType::Class,
+                recipient: Recipient::Interface,
+                request: GET_DEVICE_STATUS,
+                value: 0,
+                index: interface as u16,
+                length: 64,
+            },
+            CONTROL_TIMEOUT,
+        )
+        .map_err(|e| format!("Get Device Status failed: {e}"))?;
+    let [lo, hi, code_lo, code_hi, ..] = data[..] else {
+        return Err("short Get Device Status response".to_string());
+    };
+    let length = (u16::from_le_bytes([lo, hi]) as usize).clamp(4, data.len());
+    Ok((
+        u16::from_le_bytes([code_lo, code_hi]),
+        words(&data[4..length]),
+    ))
+}
+
+/// A PTP session with a camera or an MTP media player.
+#[napi]
+pub struct PtpSession {
+    shared: Arc<SharedDevice>,
+    transport: Arc<Mutex<PtpTransport>>,
+    transaction_id: Arc<AtomicU32>,
+    interrupt_in: Option<u8>,
+    events: Mutex<Option<InStream>>,
+    device_info: PtpDeviceInfo,
+
+    #[napi(writable = false)]
+    pub interfaceNumber: u8,
+    #[napi(writable = false)]
+    pub sessionId: u32,
+}
+
+impl PtpSession {
+    async fn run<T, F>(&self, method: &'static str, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut PtpTransport) -> std::result::Result<T, String> + Send + 'static,
+    {
+        let transport = self.transport.clone();
+        run_blocking(move || {
+            let mut transport = transport
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            f(&mut transport).map_err(|e| format!("{method} error: {e}"))
+        })
+        .await
+    }
+
+    fn class_request(
+        &self,
+        method: &'static str,
+        request: u8,
+        data: Vec<u8>,
+    ) -> impl std::future::Future<Output = Result<()>> + 'static {
+        let shared = self.shared.clone();
+        let interface = self.interfaceNumber;
+        run_blocking(move || {
+            let target = shared
+                .control_target(Recipient::Interface, interface as u16)
+                .ok_or_else(|| format!("{method} error: invalid state"))?;
+            target
+                .control_out_blocking(
+                    ControlOut {
+                        control_type: ControlType::Class,
+                        recipient: Recipient::Interface,
+                        request,
+                        value: 0,
+                        index: interface as u16,
+                        data: &data,
+                    },
+                    CONTROL_TIMEOUT,
+                )
+                .map_err(|e| format!("{method} error: {e}"))
+        })
+    }
+}
+
+#[napi]
+impl PtpSession {
+    /// The DeviceInfo dataset read when the session was opened.
+    #[napi(getter)]
+    pub fn deviceInfo(&self) -> PtpDeviceInfo {
+        self.device_info.clone()
+    }
+
+    /// Reads the DeviceInfo dataset again, as MTP devices may change it during a session.
+    #[napi]
+    pub async fn getDeviceInfo(&self) -> Result<PtpDeviceInfo> {
+        self.run("getDeviceInfo", |transport| transport.device_info())
+            .await
+    }
+
+    #[napi]
+    pub async fn getStorageIds(&self) -> Result<Vec<u32>> {
+        self.run("getStorageIds", |transport| {
+            let data = transport.data(OP_GET_STORAGE_IDS, &[])?;
+            DatasetReader { data: &data }.u32_array()
+        })
+        .await
+    }
+
+    #[napi]
+    pub async fn getStorageInfo(&self, storageId: u32) -> Result<PtpStorageInfo> {
+        self.run("getStorageInfo", move |transport| {
+            parse_storage_info(&transport.data(OP_GET_STORAGE_INFO, &[storageId])?)
+        })
+        .await
+    }
+
+    /// Lists object handles, across all storages (`0xFFFFFFFF`) by default. A `parentObject` of
+    /// `0xFFFFFFFF` lists the storage root, and `0` (the default) every object.
+    #[napi]
+    pub async fn getObjectHandles(
+        &self,
+        storageId: Option<u32>,
+        objectFormat: Option<u16>,
+        parentObject: Option<u32>,
+    ) -> Result<Vec<u32>> {
+        let params = [
+            storageId.unwrap_or(u32::MAX),
+            objectFormat.unwrap_or(0) as u32,
+            parentObject.unwrap_or(0),
+        ];
+        self.run("getObjectHandles", move |transport| {
+            let data = transport.data(OP_GET_OBJECT_HANDLES, &params)?;
+            DatasetReader { data: &data }.u32_array()
+        })
+        .await
+    }
+
+    #[napi]
+    pub async fn getObjectInfo(&self, handle: u32) -> Result<PtpObjectInfo> {
+        self.run("getObjectInfo", move |transport| {
+            parse_object_info(&transport.data(OP_GET_OBJECT_INFO, &[handle])?)
+        })
+        .await
+    }
+
+    /// Reads a whole object into memory.
+    #[napi]
+    pub async fn getObject(&self, handle: u32) -> Result<Uint8Array> {
+        let data = self
+            .run("getObject", move |transport| {
+                transport.data(OP_GET_OBJECT, &[handle])
+            })
+            .await?;
+        Ok(Uint8Array::new(data))
+    }
+
+    #[napi]
+    pub async fn getThumb(&self, handle: u32) -> Result<Uint8Array> {
+        let data = self
+            .run("getThumb", move |transport| {
+                transport.data(OP_GET_THUMB, &[handle])
+            })
+            .await?;
+        Ok(Uint8Array::new(data))
+    }
+
+    #[napi]
+    pub async fn deleteObject(&self, handle: u32) -> Result<()> {
+        self.run("deleteObject", move |transport| {
+            transport
+                .transaction(OP_DELETE_OBJECT, &[handle], None)?
+                .check()
+                .map(|_| ())
+        })
+        .await
+    }
+
+    /// Sends an ObjectInfo dataset describing the file followed by its data, resolving with the new object's handle.
+    #[napi]
+    pub async fn sendObject(&self, options: PtpSendObjectOptions, data: Uint8Array) -> Result<u32> {
+        let data = data.to_vec();
+        self.run("sendObject", move |transport| {
+            let info = object_info_dataset(&options, data.len())?;
+            let params = [
+                options.storageId.unwrap_or(0),
+                options.parentObject.unwrap_or(0),
+            ];
+            let response = transport
+                .transaction(OP_SEND_OBJECT_INFO, &params, Some(&info))?
+                .check()?;
+            let handle = response
+                .params
+                .get(2)
+                .copied()
+                .ok_or("no object handle in SendObjectInfo response")?;
+            transport
+                .transaction(OP_SEND_OBJECT, &[], Some(&data))?
+                .check()?;
+            Ok(handle)
+        })
+        .await
+    }
+
+    /// Runs any operation, with `data` as its data phase if given. Resolves with the response
+    /// whatever its code, along with any data the device sent.
+    #[napi]
+    pub async fn operation(
+        &self,
+        code: u16,
+        params: Option<Vec<u32>>,
+        data: Option<Uint8Array>,
+    ) -> Result<PtpResponse> {
+        let params = params.unwrap_or_default();
+        if params.len() > 5 {
+            return Err(napi::Error::from_reason(
+                "operation error: at most 5 parameters",
+            ));
+        }
+        let data = data.map(|data| data.to_vec());
+        let response = self
+            .run("operation", move |transport| {
+                transport.transaction(code, &params, data.as_deref())
+            })
+            .await?;
+        Ok(PtpResponse {
+            code: response.code,
+            params: response.params,
+            data: response.data.map(Uint8Array::new),
+        })
+    }
+
+    /// Starts reading events from the interrupt endpoint.
+    #[napi]
+    pub fn onEvent(&self, callback: EventCallback) -> Result<()> {
+        let mut events = self
+            .events
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if events.is_some() {
+            return Err(napi::Error::from_reason("onEvent error: already reading"));
+        }
+        let interrupt_in = self
+            .interrupt_in
+            .ok_or_else(|| napi::Error::from_reason("onEvent error: no interrupt IN endpoint"))?;
+        let endpoint = self
+            .shared
+            .get_endpoint::<In>(interrupt_in)
+            .ok_or_else(|| napi::Error::from_reason("onEvent error: invalid state"))?;
+
+        *events = Some(InStream::start(
+            endpoint,
+            HEADER_LEN + 3 * 4,
+            EVENT_TRANSFERS,
+            move |data| {
+                if let Some(event) = parse_event(data) {
+                    callback.call(event, ThreadsafeFunctionCallMode::NonBlocking);
+                }
+            },
+            |_| {},
+        ));
+        Ok(())
+    }
+
+    #[napi]
+    pub fn stopEvents(&self) {
+        if let Some(stream) = self
+            .events
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .take()
+        {
+            stream.stop();
+        }
+    }
+
+    /// Sends the Cancel Request class request for a transaction, by default the latest one.
+    /// An operation in progress then rejects, and `getDeviceStatus()` reports when the device is ready again.
+    #[napi]
+    pub async fn cancel(&self, transactionId: Option<u32>) -> Result<()> {
+        let transaction_id =
+            transactionId.unwrap_or_else(|| self.transaction_id.load(Ordering::SeqCst));
+        let mut data = EVENT_CANCEL_TRANSACTION.to_le_bytes().to_vec();
+        data.extend_from_slice(&transaction_id.to_le_bytes());
+        self.class_request("cancel", CANCEL_REQUEST, data).await
+    }
+
+    /// Get Device Status: `0x2001` when ready, `0x2019` while busy.
+    #[napi]
+    pub async fn getDeviceStatus(&self) -> Result<PtpDeviceStatus> {
+        let shared = self.shared.clone();
+        let interface = self.interfaceNumber;
+        let (code, params) = run_blocking(move || {
+            device_status(&shared, interface).map_err(|e| format!("getDeviceStatus error: {e}"))
+        })
+        .await?;
+        Ok(PtpDeviceStatus { code, params })
+    }
+
+    /// Sends the Device Reset Request class request, which closes the session, then opens a new one.
+    #[napi]
+    pub async fn reset(&self) -> Result<()> {
+        self.class_request("reset", DEVICE_RESET_REQUEST, Vec::new())
+            .await?;
+        self.run("reset", |transport| transport.open_session())
+            .await
+    }
+
+    /// Stops events, closes the session and releases the interface, reattaching any kernel driver.
+    #[napi]
+    pub async fn close(&self) -> Result<()> {
+        let events = self
+            .events
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .take();
+        // Its endpoint keeps the interface claimed until the stream has ended
+        if let Some(stream) = events {
+            stream.finish().await;
+        }
+        // The session ends with the interface anyway, so a failure here doesn't stop the release
+        let _ = self
+            .run("close", |transport| {
+                transport.transaction(OP_CLOSE_SESSION, &[], None)?;
+                transport.session_open = false;
+                Ok(())
+            })
+            .await;
+        self.shared
+            .release_interface("close", self.interfaceNumber)
+            .await
+    }
+}
+
+#[napi]
+impl UsbDevice {
+    /// Claims a still image (PTP or MTP) interface, reads the DeviceInfo dataset and opens a session.
+    #[napi]
+    pub async fn openPtp(&self, options: Option<PtpOptions>) -> Result<PtpSession> {
+        let interface_number = options.as_ref().and_then(|o| o.interfaceNumber);
+        let detach = options
+            .as_ref()
+            .and_then(|o| o.detachKernelDriver)
+            .unwrap_or(true);
+        let timeout = options
+            .as_ref()
+            .and_then(|o| o.timeout)
+            .unwrap_or(DEFAULT_TIMEOUT);
+
+        let shared = self.shared().clone();
+        let device = shared
+            .opened_device()
+            .ok_or_else(|| napi::Error::from_reason("openPtp error: invalid state"))?;
+        let layout = run_blocking(move || {
+            let config = device
+                .active_configuration()
+                .map_err(|e| format!("openPtp error: {e}"))?;
+            find_ptp_layout(&config, interface_number).map_err(|e| format!("openPtp error: {e}"))
+        })
+        .await?;
+
+        let interface = layout.interface;
+        let interrupt_in = layout.interrupt_in;
+        shared.claim_interface("openPtp", interface, detach).await?;
+
+        let transaction_id = Arc::new(AtomicU32::new(0));
+        let mut transport = PtpTransport {
+            shared: shared.clone(),
+            layout,
+            timeout: Duration::from_millis(timeout as u64),
+            transaction_id: transaction_id.clone(),
+            session_open: false,
+        };
+        let opened = run_blocking(move || {
+            let device_info = transport
+                .device_info()
+                .map_err(|e| format!("openPtp error: {e}"))?;
+            transport
+                .open_session()
+                .map_err(|e| format!("openPtp error: {e}"))?;
+            Ok((transport, device_info))
+        })
+        .await;
+        let (transport, device_info) = match opened {
+            Ok(opened) => opened,
+            Err(e) => {
+                let _ = shared.release_interface("openPtp", interface).await;
+                return Err(e);
+            }
+        };
+
+        Ok(PtpSession {
+            shared,
+            transport: Arc::new(Mutex::new(transport)),
+            transaction_id,
+            interrupt_in,
+            events: Mutex::new(None),
+            device_info,
+            interfaceNumber: interface,
+            sessionId: SESSION_ID,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_command_containers() {
+        let mut command = container_header(HEADER_LEN + 4, CONTAINER_COMMAND, OP_OPEN_SESSION, 0);
+        command.extend_from_slice(&SESSION_ID.to_le_bytes());
+        assert_eq!(
+            command,
+            [
+                0x10, 0x00, 0x00, 0x00, 0x01, 0x00, 0x02, 0x10, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00,
+                0x00, 0x00
+            ]
+        );
+        // Data phases too large for the length field say so
+        let header = container_header(u32::MAX as usize + 1, CONTAINER_DATA, OP_SEND_OBJECT, 7);
+        assert_eq!(&header[..4], &LENGTH_UNKNOWN.to_le_bytes());
+    }
+
+    #[test]
+    fn parses_event_containers() {
+        let mut data = container_header(HEADER_LEN + 4, CONTAINER_EVENT, 0x4002, 5);
+        data.extend_from_slice(&0x0001_0002u32.to_le_bytes());
+        let event = parse_event(&data).unwrap();
+        assert_eq!((event.code, event.transactionId), (0x4002, 5));
+        assert_eq!(event.params, [0x0001_0002]);
+
+        // A length past the packet is clamped to it
+        data[0] = 0x40;
+        assert_eq!(parse_event(&data).unwrap().params, [0x0001_0002]);
+
+        let response = container_header(HEADER_LEN, CONTAINER_RESPONSE, RESPONSE_OK, 5);
+        assert!(parse_event(&response).is_none());
+        assert!(parse_event(&data[..8]).is_none());
+    }
+
+    #[test]
+    fn strings_round_trip() {
+        let mut data = Vec::new();
+        write_string(&mut data, "IMG_0001.JPG").unwrap();
+        write_string(&mut data, "").unwrap();
+        assert_eq!(data[0], 13);
+        assert_eq!(&data[1..5], &[b'I', 0, b'M', 0]);
+        assert_eq!(data.last(), Some(&0));
+
+        let mut reader = DatasetReader { data: &data };
+        assert_eq!(reader.string().unwrap(), "IMG_0001.JPG");
+        assert_eq!(reader.string().unwrap(), "");
+        assert!(reader.string().is_err());
+        assert!(write_string(&mut data, &"x".repeat(255)).is_err());
+    }
+
+    #[test]
+    fn object_info_round_trips() {
+        let options = PtpSendObjectOptions {
+            filename: "notes.txt".to_string(),
+            storageId: Some(0x0001_0001),
+            parentObject: Some(0x42),
+            objectFormat: Some(0x3004),
+            modificationDate: Some("20240102T030405".to_string()),
+        };
+        let dataset = object_info_dataset(&options, 1234).unwrap();
+        let info = parse_object_info(&dataset).unwrap();
+        assert_eq!(info.storageId, 0x0001_0001);
+        assert_eq!(info.objectFormat, 0x3004);
+        assert_eq!(info.compressedSize, 1234);
+        assert_eq!(info.parentObject, 0x42);
+        assert_eq!(info.filename, "notes.txt");
+        assert_eq!(info.modificationDate, "20240102T030405");
+        assert_eq!(info.captureDate, "");
+    }
+}
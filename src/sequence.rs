@@ -0,0 +1,388 @@
+use crate::transfer_pool::{buffer_to_js, pooled_transfer_in, pooled_transfer_out};
+use crate::webusb_device::{
+    control_in_setup, control_out_setup, control_type_from_request_type,
+    recipient_from_request_recipient, SharedDevice, UsbControlTransferParameters, UsbDevice,
+};
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    sync::{
+        atomic::{AtomicBool, Ordering as AtomicOrdering},
+        mpsc, Arc, Mutex, MutexGuard,
+    },
+    time::Duration,
+};
+use tokio::sync::oneshot;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+struct Waiter {
+    priority: i32,
+    order: u64,
+    grant: oneshot::Sender<()>,
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Waiter {}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Waiter {
+    // Highest priority first, then first come first served
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.order.cmp(&self.order))
+    }
+}
+
+struct Grants {
+    active: bool,
+    next_order: u64,
+    waiting: BinaryHeap<Waiter>,
+}
+
+/// Per-device queue that runs sequence operations one at a time on a
+/// dedicated thread, so they complete in the order they were issued rather
+/// than whichever blocking task the runtime happens to schedule first.
+/// Only sequences go through it, transfers made on the device directly don't.
+pub(crate) struct OperationQueue {
+    jobs: mpsc::Sender<Job>,
+    grants: Mutex<Grants>,
+}
+
+impl OperationQueue {
+    pub(crate) fn new() -> Self {
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        std::thread::spawn(move || {
+            while let Ok(job) = receiver.recv() {
+                job();
+            }
+        });
+
+        Self {
+            jobs,
+            grants: Mutex::new(Grants {
+                active: false,
+                next_order: 0,
+                waiting: BinaryHeap::new(),
+            }),
+        }
+    }
+
+    fn grants(&self) -> MutexGuard<'_, Grants> {
+        self.grants
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    async fn acquire(&self, priority: i32, timeout: Option<Duration>) -> Result<()> {
+        let (granted, order) = {
+            let mut grants = self.grants();
+            if !grants.active {
+                grants.active = true;
+                return Ok(());
+            }
+
+            let (grant, granted) = oneshot::channel();
+            let order = grants.next_order;
+            grants.next_order += 1;
+            grants.waiting.push(Waiter {
+                priority,
+                order,
+                grant,
+            });
+            (granted, order)
+        };
+
+        let result = match timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, granted).await {
+                Ok(result) => result,
+                Err(_) => {
+                    // Still waiting means it timed out, otherwise the grant
+                    // raced the timeout and the sequence owns the queue
+                    let mut grants = self.grants();
+                    let waiting = grants.waiting.len();
+                    grants.waiting.retain(|waiter| waiter.order != order);
+                    if grants.waiting.len() < waiting {
+                        return Err(napi::Error::from_reason("sequence error: timed out"));
+                    }
+                    Ok(())
+                }
+            },
+            None => granted.await,
+        };
+
+        result.map_err(|_| napi::Error::from_reason("sequence error: queue closed"))
+    }
+
+    fn release(&self) {
+        let mut grants = self.grants();
+        while let Some(waiter) = grants.waiting.pop() {
+            if waiter.grant.send(()).is_ok() {
+                return;
+            }
+        }
+        grants.active = false;
+    }
+
+    fn submit(&self, job: Job) -> std::result::Result<(), String> {
+        self.jobs.send(job).map_err(|_| "queue closed".to_string())
+    }
+}
+
+#[napi]
+pub struct UsbSequence {
+    shared: Arc<SharedDevice>,
+    ended: AtomicBool,
+}
+
+impl UsbSequence {
+    // Work is queued synchronously when JS makes the call, which is what
+    // keeps operations issued back to back without awaiting in order.
+    fn run<'env, T, F>(
+        &self,
+        env: &'env Env,
+        method: &'static str,
+        work: F,
+    ) -> Result<PromiseRaw<'env, T>>
+    where
+        T: Send + 'static,
+        T: ToNapiValue,
+        F: FnOnce(&Arc<SharedDevice>) -> std::result::Result<T, String> + Send + 'static,
+    {
+        if self.ended.load(AtomicOrdering::SeqCst) {
+            return Err(napi::Error::from_reason(format!(
+                "{method} error: sequence has ended"
+            )));
+        }
+
+        let (done, result) = oneshot::channel();
+        let shared = self.shared.clone();
+        self.shared
+            .queue()
+            .submit(Box::new(move || {
+                let _ = done.send(work(&shared));
+            }))
+            .map_err(|e| napi::Error::from_reason(format!("{method} error: {e}")))?;
+
+        env.spawn_future(async move {
+            result
+                .await
+                .map_err(|_| napi::Error::from_reason(format!("{method} error: queue closed")))?
+                .map_err(napi::Error::from_reason)
+        })
+    }
+}
+
+#[napi]
+impl UsbSequence {
+    #[napi(
+        js_name = "nativeControlTransferIn",
+        ts_return_type = "Promise<Uint8Array | null>"
+    )]
+    pub fn controlTransferIn<'env>(
+        &self,
+        env: &'env Env,
+        setup: UsbControlTransferParameters,
+        timeout: u32,
+        length: u16,
+    ) -> Result<PromiseRaw<'env, Option<Uint8Array>>> {
+        self.run(env, "controlTransferIn", move |shared| {
+            let control_type = control_type_from_request_type(&setup.requestType);
+            let recipient = recipient_from_request_recipient(&setup.recipient);
+            let target = shared
+                .control_target(recipient, setup.index)
+                .ok_or("controlTransferIn error: invalid state")?;
+            let request = control_in_setup(&setup, control_type, recipient, setup.index, length);
+            target
+                .control_in_blocking(request, Duration::from_millis(timeout as u64))
+                .map(|data| Some(Uint8Array::from(data)))
+                .map_err(|e| format!("controlTransferIn error: {e}"))
+        })
+    }
+
+    #[napi(
+        js_name = "nativeControlTransferOut",
+        ts_return_type = "Promise<number>"
+    )]
+    pub fn controlTransferOut<'env>(
+        &self,
+        env: &'env Env,
+        setup: UsbControlTransferParameters,
+        timeout: u32,
+        data: Option<Uint8Array>,
+    ) -> Result<PromiseRaw<'env, u32>> {
+        let bytes = data.map(|b| b.to_vec()).unwrap_or_default();
+        self.run(env, "controlTransferOut", move |shared| {
+            let control_type = control_type_from_request_type(&setup.requestType);
+            let recipient = recipient_from_request_recipient(&setup.recipient);
+            let target = shared
+                .control_target(recipient, setup.index)
+                .ok_or("controlTransferOut error: invalid state")?;
+            let request = control_out_setup(&setup, control_type, recipient, setup.index, &bytes);
+            target
+                .control_out_blocking(request, Duration::from_millis(timeout as u64))
+                .map(|_| bytes.len() as u32)
+                .map_err(|e| format!("controlTransferOut error: {e}"))
+        })
+    }
+
+    #[napi(
+        js_name = "nativeTransferIn",
+        ts_return_type = "Promise<Uint8Array | null>"
+    )]
+    pub fn transferIn<'env>(
+        &self,
+        env: &'env Env,
+        endpointNumber: u8,
+        timeout: u32,
+        length: u32,
+    ) -> Result<PromiseRaw<'env, Option<Uint8Array>>> {
+        self.run(env, "transferIn", move |shared| {
+            let mut endpoint = shared
+                .get_endpoint::<nusb::transfer::In>(endpointNumber)
+                .ok_or("transferIn error: endpoint not found")?;
+            let result = pooled_transfer_in(
+                shared,
+                &mut endpoint,
+                length as usize,
+                Duration::from_millis(timeout as u64),
+            )
+            .map(|(buffer, len)| Some(buffer_to_js(shared, &endpoint, buffer, len)))
+            .map_err(|e| format!("transferIn error: {e:?}"));
+            shared.put_endpoint(endpoint);
+            result
+        })
+    }
+
+    #[napi(js_name = "nativeTransferOut", ts_return_type = "Promise<number>")]
+    pub fn transferOut<'env>(
+        &self,
+        env: &'env Env,
+        endpointNumber: u8,
+        timeout: u32,
+        data: Uint8Array,
+    ) -> Result<PromiseRaw<'env, u32>> {
+        self.run(env, "transferOut", move |shared| {
+            let mut endpoint = shared
+                .get_endpoint::<nusb::transfer::Out>(endpointNumber)
+                .ok_or("transferOut error: endpoint not found")?;
+            let result = pooled_transfer_out(
+                shared,
+                &mut endpoint,
+                &data,
+                Duration::from_millis(timeout as u64),
+            )
+            .map(|len| len as u32)
+            .map_err(|e| format!("transferOut error: {e:?}"));
+            shared.put_endpoint(endpoint);
+            result
+        })
+    }
+
+    /// Lets the next sequence run. Operations already issued still complete.
+    #[napi]
+    pub fn end(&self) {
+        if !self.ended.swap(true, AtomicOrdering::SeqCst) {
+            self.shared.queue().release();
+        }
+    }
+}
+
+impl Drop for UsbSequence {
+    fn drop(&mut self) {
+        self.end();
+    }
+}
+
+#[napi]
+impl UsbDevice {
+    #[napi(js_name = "nativeSequence")]
+    pub async fn sequence(
+        &self,
+        priority: Option<i32>,
+        timeout: Option<u32>,
+    ) -> Result<UsbSequence> {
+        let shared = self.shared().clone();
+        shared
+            .queue()
+            .acquire(
+                priority.unwrap_or(0),
+                timeout.map(|timeout| Duration::from_millis(timeout as u64)),
+            )
+            .await?;
+
+        Ok(UsbSequence {
+            shared,
+            ended: AtomicBool::new(false),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn waiters_are_granted_by_priority_then_order() {
+        let queue = Arc::new(OperationQueue::new());
+        queue.acquire(0, None).await.unwrap();
+
+        let granted = Arc::new(Mutex::new(Vec::new()));
+        let mut tasks = Vec::new();
+        for (name, priority) in [("low", 0), ("high", 5), ("mid", 1), ("high again", 5)] {
+            let (queue, granted) = (queue.clone(), granted.clone());
+            tasks.push(tokio::spawn(async move {
+                queue.acquire(priority, None).await.unwrap();
+                granted.lock().unwrap().push(name);
+                queue.release();
+            }));
+            // Let the task queue up before the next one
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(queue.grants().waiting.len(), 4);
+
+        queue.release();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(
+            *granted.lock().unwrap(),
+            ["high", "high again", "mid", "low"]
+        );
+        assert!(!queue.grants().active);
+    }
+
+    #[tokio::test]
+    async fn acquire_times_out_while_queued() {
+        let queue = OperationQueue::new();
+        queue.acquire(0, None).await.unwrap();
+
+        let error = queue
+            .acquire(0, Some(Duration::from_millis(10)))
+            .await
+            .unwrap_err();
+        assert_eq!(error.reason, "sequence error: timed out");
+        assert!(queue.grants().waiting.is_empty());
+
+        // The timed out waiter doesn't hold the queue once it's released
+        queue.release();
+        assert!(!queue.grants().active);
+        queue
+            .acquire(0, Some(Duration::from_millis(10)))
+            .await
+            .unwrap();
+    }
+}
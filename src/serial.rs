@@ -0,0 +1,140 @@
+use napi_derive::napi;
+
+/// Line settings, following the Web Serial `SerialOptions` names.
+#[napi(object)]
+pub struct SerialOptions {
+    pub baudRate: u32,
+    #[napi(ts_type = "5 | 6 | 7 | 8 | 16")]
+    pub dataBits: Option<u8>,
+    #[napi(ts_type = "1 | 1.5 | 2")]
+    pub stopBits: Option<f64>,
+    #[napi(ts_type = "SerialParity")]
+    pub parity: Option<String>,
+    #[napi(ts_type = "SerialFlowControl")]
+    pub flowControl: Option<String>,
+}
+
+#[napi(object)]
+pub struct SerialOutputSignals {
+    pub dataTerminalReady: Option<bool>,
+    pub requestToSend: Option<bool>,
+    #[napi(js_name = "break")]
+    pub _break: Option<bool>,
+}
+
+#[napi(object)]
+#[derive(Clone, Copy, Default)]
+pub struct SerialInputSignals {
+    #[napi(writable = false)]
+    pub dataCarrierDetect: bool,
+    #[napi(writable = false)]
+    pub clearToSend: bool,
+    #[napi(writable = false)]
+    pub ringIndicator: bool,
+    #[napi(writable = false)]
+    pub dataSetReady: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Parity {
+    None,
+    Odd,
+    Even,
+    Mark,
+    Space,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum FlowControl {
+    None,
+    Hardware,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum StopBits {
+    One,
+    OnePointFive,
+    Two,
+}
+
+/// `SerialOptions` checked and with the defaults filled in (8N1, no flow control).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) struct LineSettings {
+    pub(crate) baud_rate: u32,
+    pub(crate) data_bits: u8,
+    pub(crate) stop_bits: StopBits,
+    pub(crate) parity: Parity,
+    pub(crate) flow_control: FlowControl,
+}
+
+impl LineSettings {
+    pub(crate) fn from_options(options: &SerialOptions) -> Result<Self, String> {
+        if options.baudRate == 0 {
+            return Err("invalid baud rate".to_string());
+        }
+
+        let data_bits = options.dataBits.unwrap_or(8);
+        if !matches!(data_bits, 5..=8 | 16) {
+            return Err(format!("invalid data bits {data_bits}"));
+        }
+
+        let stop_bits = match options.stopBits.unwrap_or(1.0) {
+            1.0 => StopBits::One,
+            1.5 => StopBits::OnePointFive,
+            2.0 => StopBits::Two,
+            s => return Err(format!("invalid stop bits {s}")),
+        };
+
+        let parity = match options.parity.as_deref().unwrap_or("none") {
+            "none" => Parity::None,
+            "odd" => Parity::Odd,
+            "even" => Parity::Even,
+            "mark" => Parity::Mark,
+            "space" => Parity::Space,
+            p => return Err(format!("invalid parity {p}")),
+        };
+
+        let flow_control = match options.flowControl.as_deref().unwrap_or("none") {
+            "none" => FlowControl::None,
+            "hardware" => FlowControl::Hardware,
+            f => return Err(format!("invalid flow control {f}")),
+        };
+
+        Ok(Self {
+            baud_rate: options.baudRate,
+            data_bits,
+            stop_bits,
+            parity,
+            flow_control,
+        })
+    }
+
+    pub(crate) fn to_options(self) -> SerialOptions {
+        SerialOptions {
+            baudRate: self.baud_rate,
+            dataBits: Some(self.data_bits),
+            stopBits: Some(match self.stop_bits {
+                StopBits::One => 1.0,
+                StopBits::OnePointFive => 1.5,
+                StopBits::Two => 2.0,
+            }),
+            parity: Some(
+                match self.parity {
+                    Parity::None => "none",
+                    Parity::Odd => "odd",
+                    Parity::Even => "even",
+                    Parity::Mark => "mark",
+                    Parity::Space => "space",
+                }
+                .to_string(),
+            ),
+            flowControl: Some(
+                match self.flow_control {
+                    FlowControl::None => "none",
+                    FlowControl::Hardware => "hardware",
+                }
+                .to_string(),
+            ),
+        }
+    }
+}
@@ -0,0 +1,672 @@
+use crate::webusb_device::{run_blocking, ControlTarget, SharedDevice, UsbDevice};
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use nusb::{
+    descriptors::{ConfigurationDescriptor, TransferType},
+    transfer::{ControlIn, ControlOut, ControlType, In, Out, Recipient},
+};
+use std::time::Duration;
+
+pub(crate) const GET_STATUS: u8 = 0x00;
+pub(crate) const CLEAR_FEATURE: u8 = 0x01;
+pub(crate) const SET_FEATURE: u8 = 0x03;
+pub(crate) const GET_DESCRIPTOR: u8 = 0x06;
+pub(crate) const GET_CONFIGURATION: u8 = 0x08;
+pub(crate) const GET_INTERFACE: u8 = 0x0a;
+pub(crate) const SYNCH_FRAME: u8 = 0x0c;
+
+pub(crate) const FEATURE_ENDPOINT_HALT: u16 = 0;
+pub(crate) const FEATURE_DEVICE_REMOTE_WAKEUP: u16 = 1;
+pub(crate) const FEATURE_TEST_MODE: u16 = 2;
+pub(crate) const FEATURE_U1_ENABLE: u16 = 48;
+pub(crate) const FEATURE_U2_ENABLE: u16 = 49;
+
+pub(crate) const DESCRIPTOR_DEVICE: u8 = 0x01;
+pub(crate) const DESCRIPTOR_CONFIGURATION: u8 = 0x02;
+pub(crate) const DESCRIPTOR_STRING: u8 = 0x03;
+pub(crate) const DESCRIPTOR_INTERFACE: u8 = 0x04;
+pub(crate) const DESCRIPTOR_ENDPOINT: u8 = 0x05;
+pub(crate) const DESCRIPTOR_BOS: u8 = 0x0f;
+pub(crate) const DESCRIPTOR_SS_ENDPOINT_COMPANION: u8 = 0x30;
+
+pub(crate) const STATUS_SELF_POWERED: u16 = 0x0001;
+pub(crate) const STATUS_REMOTE_WAKEUP: u16 = 0x0002;
+pub(crate) const STATUS_U1_ENABLE: u16 = 0x0004;
+pub(crate) const STATUS_U2_ENABLE: u16 = 0x0008;
+pub(crate) const STATUS_LTM_ENABLE: u16 = 0x0010;
+pub(crate) const STATUS_HALT: u16 = 0x0001;
+pub(crate) const STATUS_FUNCTION_REMOTE_WAKE_CAPABLE: u16 = 0x0001;
+pub(crate) const STATUS_FUNCTION_REMOTE_WAKEUP: u16 = 0x0002;
+
+const CONFIG_ATTRIBUTES_REMOTE_WAKEUP: u8 = 0x20;
+const ENDPOINT_DIRECTION_IN: u8 = 0x80;
+const ENDPOINT_NUMBER_MASK: u8 = 0x0f;
+const USB3_VERSION: u16 = 0x0300;
+/// Test_J, Test_K, Test_SE0_NAK, Test_Packet and Test_Force_Enable
+const TEST_SELECTORS: std::ops::RangeInclusive<u8> = 1..=5;
+const VENDOR_TEST_SELECTORS: std::ops::RangeInclusive<u8> = 0xc0..=0xff;
+
+const CONTROL_TIMEOUT: Duration = Duration::from_millis(1000);
+
+fn request_in(
+    target: &ControlTarget,
+    recipient: Recipient,
+    request: u8,
+    value: u16,
+    index: u16,
+    length: u16,
+) -> std::result::Result<Vec<u8>, String> {
+    target
+        .control_in_blocking(
+            ControlIn {
+                control_type: ControlType::Standard,
+                recipient,
+                request,
+                value,
+                index,
+                length,
+            },
+            CONTROL_TIMEOUT,
+        )
+        .map_err(|e| format!("request {request} failed: {e}"))
+}
+
+fn request_out(
+    target: &ControlTarget,
+    recipient: Recipient,
+    request: u8,
+    value: u16,
+    index: u16,
+) -> std::result::Result<(), String> {
+    target
+        .control_out_blocking(
+            ControlOut {
+                control_type: ControlType::Standard,
+                recipient,
+                request,
+                value,
+                index,
+                data: &[],
+            },
+            CONTROL_TIMEOUT,
+        )
+        .map_err(|e| format!("request {request} failed: {e}"))
+}
+
+/// Checks a response is exactly as long as the request defines, which a short one isn't.
+fn exact<const N: usize>(data: Vec<u8>, request: &str) -> std::result::Result<[u8; N], String> {
+    let len = data.len();
+    data.try_into()
+        .map_err(|_| format!("{request} returned {len} bytes, expected {N}"))
+}
+
+/// Status of the device, an interface or an endpoint; `index` is the interface number or endpoint
+/// address.
+pub(crate) fn get_status(
+    target: &ControlTarget,
+    recipient: Recipient,
+    index: u16,
+) -> std::result::Result<u16, String> {
+    let data = request_in(target, recipient, GET_STATUS, 0, index, 2)?;
+    Ok(u16::from_le_bytes(exact(data, "GET_STATUS")?))
+}
+
+/// `index` is the interface number or endpoint address, 0 for the device.
+pub(crate) fn set_feature(
+    target: &ControlTarget,
+    recipient: Recipient,
+    feature: u16,
+    index: u16,
+) -> std::result::Result<(), String> {
+    request_out(target, recipient, SET_FEATURE, feature, index)
+}
+
+pub(crate) fn clear_feature(
+    target: &ControlTarget,
+    recipient: Recipient,
+    feature: u16,
+    index: u16,
+) -> std::result::Result<(), String> {
+    request_out(target, recipient, CLEAR_FEATURE, feature, index)
+}
+
+pub(crate) fn get_configuration(target: &ControlTarget) -> std::result::Result<u8, String> {
+    let data = request_in(target, Recipient::Device, GET_CONFIGURATION, 0, 0, 1)?;
+    Ok(exact::<1>(data, "GET_CONFIGURATION")?[0])
+}
+
+pub(crate) fn get_interface(
+    target: &ControlTarget,
+    interface_number: u8,
+) -> std::result::Result<u8, String> {
+    let data = request_in(
+        target,
+        Recipient::Interface,
+        GET_INTERFACE,
+        0,
+        interface_number as u16,
+        1,
+    )?;
+    Ok(exact::<1>(data, "GET_INTERFACE")?[0])
+}
+
+/// Frame number an isochronous endpoint's pattern starts from.
+pub(crate) fn synch_frame(
+    target: &ControlTarget,
+    endpoint_address: u8,
+) -> std::result::Result<u16, String> {
+    let data = request_in(
+        target,
+        Recipient::Endpoint,
+        SYNCH_FRAME,
+        0,
+        endpoint_address as u16,
+        2,
+    )?;
+    Ok(u16::from_le_bytes(exact(data, "SYNCH_FRAME")?))
+}
+
+/// Reads a descriptor as the device returns it, which may be shorter than `length`.
+pub(crate) fn get_descriptor(
+    target: &ControlTarget,
+    descriptor_type: u8,
+    descriptor_index: u8,
+    language_id: u16,
+    length: u16,
+) -> std::result::Result<Vec<u8>, String> {
+    request_in(
+        target,
+        Recipient::Device,
+        GET_DESCRIPTOR,
+        (descriptor_type as u16) << 8 | descriptor_index as u16,
+        language_id,
+        length,
+    )
+}
+
+/// Clears a halt through the endpoint when it is open, which resets the host's data toggle along
+/// with the device's, otherwise with CLEAR_FEATURE alone.
+pub(crate) fn clear_endpoint_halt(
+    shared: &SharedDevice,
+    target: &ControlTarget,
+    endpoint_address: u8,
+) -> std::result::Result<(), String> {
+    let number = endpoint_address & ENDPOINT_NUMBER_MASK;
+    if endpoint_address & ENDPOINT_DIRECTION_IN != 0 {
+        shared.get_endpoint::<In>(number).map(|mut endpoint| {
+            let result = endpoint.clear_halt_blocking();
+            shared.put_endpoint(endpoint);
+            result.map_err(|e| e.to_string())
+        })
+    } else {
+        shared.get_endpoint::<Out>(number).map(|mut endpoint| {
+            let result = endpoint.clear_halt_blocking();
+            shared.put_endpoint(endpoint);
+            result.map_err(|e| e.to_string())
+        })
+    }
+    .unwrap_or_else(|| {
+        clear_feature(
+            target,
+            Recipient::Endpoint,
+            FEATURE_ENDPOINT_HALT,
+            endpoint_address as u16,
+        )
+    })
+}
+
+/// Decoded GET_STATUS bits, set for the recipient they belong to.
+#[napi(object)]
+pub struct UsbStatus {
+    /// The status word as returned
+    #[napi(writable = false)]
+    pub status: u16,
+    #[napi(writable = false)]
+    pub selfPowered: Option<bool>,
+    #[napi(writable = false)]
+    pub remoteWakeup: Option<bool>,
+    /// SuperSpeed devices only
+    #[napi(writable = false)]
+    pub u1Enabled: Option<bool>,
+    /// SuperSpeed devices only
+    #[napi(writable = false)]
+    pub u2Enabled: Option<bool>,
+    /// SuperSpeed devices only
+    #[napi(writable = false)]
+    pub ltmEnabled: Option<bool>,
+    /// SuperSpeed interfaces only
+    #[napi(writable = false)]
+    pub functionRemoteWakeCapable: Option<bool>,
+    /// SuperSpeed interfaces only
+    #[napi(writable = false)]
+    pub functionRemoteWakeup: Option<bool>,
+    #[napi(writable = false)]
+    pub halted: Option<bool>,
+}
+
+fn decode_status(recipient: Recipient, status: u16, usb3: bool) -> UsbStatus {
+    let bit = |mask: u16| Some(status & mask != 0);
+    let usb3_bit = |mask: u16| if usb3 { bit(mask) } else { None };
+    let device = recipient == Recipient::Device;
+    let interface = recipient == Recipient::Interface;
+    UsbStatus {
+        status,
+        selfPowered: if device {
+            bit(STATUS_SELF_POWERED)
+        } else {
+            None
+        },
+        remoteWakeup: if device {
+            bit(STATUS_REMOTE_WAKEUP)
+        } else {
+            None
+        },
+        u1Enabled: if device {
+            usb3_bit(STATUS_U1_ENABLE)
+        } else {
+            None
+        },
+        u2Enabled: if device {
+            usb3_bit(STATUS_U2_ENABLE)
+        } else {
+            None
+        },
+        ltmEnabled: if device {
+            usb3_bit(STATUS_LTM_ENABLE)
+        } else {
+            None
+        },
+        functionRemoteWakeCapable: if interface {
+            usb3_bit(STATUS_FUNCTION_REMOTE_WAKE_CAPABLE)
+        } else {
+            None
+        },
+        functionRemoteWakeup: if interface {
+            usb3_bit(STATUS_FUNCTION_REMOTE_WAKEUP)
+        } else {
+            None
+        },
+        halted: if recipient == Recipient::Endpoint {
+            bit(STATUS_HALT)
+        } else {
+            None
+        },
+    }
+}
+
+fn parse_recipient(recipient: &str) -> std::result::Result<Recipient, String> {
+    match recipient {
+        "device" => Ok(Recipient::Device),
+        "interface" => Ok(Recipient::Interface),
+        "endpoint" => Ok(Recipient::Endpoint),
+        recipient => Err(format!("unknown recipient '{recipient}'")),
+    }
+}
+
+enum Feature {
+    RemoteWakeup,
+    TestMode,
+    U1Enable,
+    U2Enable,
+    EndpointHalt,
+}
+
+impl Feature {
+    fn parse(feature: &str) -> std::result::Result<Self, String> {
+        match feature {
+            "remoteWakeup" => Ok(Feature::RemoteWakeup),
+            "testMode" => Ok(Feature::TestMode),
+            "u1Enable" => Ok(Feature::U1Enable),
+            "u2Enable" => Ok(Feature::U2Enable),
+            "endpointHalt" => Ok(Feature::EndpointHalt),
+            feature => Err(format!("unknown feature '{feature}'")),
+        }
+    }
+}
+
+/// Checks an interface number or endpoint address against the active configuration, so requests
+/// for something the device doesn't have aren't sent.
+fn check_recipient(
+    config: &ConfigurationDescriptor,
+    recipient: Recipient,
+    index: u16,
+) -> std::result::Result<(), String> {
+    let found = match recipient {
+        Recipient::Device => index == 0,
+        Recipient::Interface => config
+            .interface_alt_settings()
+            .any(|alt| alt.interface_number() as u16 == index),
+        // Endpoint 0 in either direction
+        _ if index & !(ENDPOINT_DIRECTION_IN as u16) == 0 => true,
+        _ => config
+            .interface_alt_settings()
+            .flat_map(|alt| alt.endpoints().collect::<Vec<_>>())
+            .any(|endpoint| endpoint.address() as u16 == index),
+    };
+    if !found {
+        return Err(match recipient {
+            Recipient::Interface => format!("interface {index} not found"),
+            Recipient::Endpoint => format!("endpoint {index:#04x} not found"),
+            _ => format!("device index must be 0, not {index}"),
+        });
+    }
+    Ok(())
+}
+
+fn request_target(
+    shared: &SharedDevice,
+    recipient: Recipient,
+    index: u16,
+) -> std::result::Result<ControlTarget, String> {
+    let device = shared.opened_device().ok_or("invalid state")?;
+    let config = device.active_configuration().map_err(|e| e.to_string())?;
+    check_recipient(&config, recipient, index)?;
+    shared
+        .control_target(recipient, index)
+        .ok_or_else(|| "invalid state".to_string())
+}
+
+/// Recipient and wIndex of a feature, checked against what the device and its configuration allow.
+fn feature_request(
+    config: &ConfigurationDescriptor,
+    usb_version: u16,
+    feature: &Feature,
+    index: u16,
+    set: bool,
+) -> std::result::Result<(Recipient, u16, u16), String> {
+    match feature {
+        Feature::RemoteWakeup => {
+            if config.attributes() & CONFIG_ATTRIBUTES_REMOTE_WAKEUP == 0 {
+                return Err("the active configuration doesn't support remote wakeup".to_string());
+            }
+            Ok((Recipient::Device, FEATURE_DEVICE_REMOTE_WAKEUP, 0))
+        }
+        Feature::TestMode => {
+            if !set {
+                return Err("test mode can only be left by resetting the device".to_string());
+            }
+            let selector = u8::try_from(index).unwrap_or(0);
+            if !TEST_SELECTORS.contains(&selector) && !VENDOR_TEST_SELECTORS.contains(&selector) {
+                return Err(format!("test selector {index} is not valid"));
+            }
+            Ok((Recipient::Device, FEATURE_TEST_MODE, (selector as u16) << 8))
+        }
+        Feature::U1Enable | Feature::U2Enable => {
+            if usb_version < USB3_VERSION {
+                return Err("U1 and U2 are only for SuperSpeed devices".to_string());
+            }
+            let feature = match feature {
+                Feature::U1Enable => FEATURE_U1_ENABLE,
+                _ => FEATURE_U2_ENABLE,
+            };
+            Ok((Recipient::Device, feature, 0))
+        }
+        Feature::EndpointHalt => {
+            if index & ENDPOINT_NUMBER_MASK as u16 == 0 {
+                return Err("endpoint 0 can't be halted".to_string());
+            }
+            Ok((Recipient::Endpoint, FEATURE_ENDPOINT_HALT, index))
+        }
+    }
+}
+
+pub(crate) async fn change_feature(
+    usb_device: &UsbDevice,
+    method: &'static str,
+    feature: String,
+    index: Option<u16>,
+    set: bool,
+) -> Result<()> {
+    let shared = usb_device.shared().clone();
+    let usb_version = usb_device.device_info().usb_version();
+    run_blocking(move || {
+        let change = || {
+            let feature = Feature::parse(&feature)?;
+            let device = shared.opened_device().ok_or("invalid state")?;
+            let config = device.active_configuration().map_err(|e| e.to_string())?;
+            let (recipient, selector, index) =
+                feature_request(&config, usb_version, &feature, index.unwrap_or(0), set)?;
+            let target = request_target(&shared, recipient, index & 0x00ff)?;
+            match (feature, set) {
+                (Feature::EndpointHalt, false) => {
+                    clear_endpoint_halt(&shared, &target, index as u8)
+                }
+                (_, true) => set_feature(&target, recipient, selector, index),
+                (_, false) => clear_feature(&target, recipient, selector, index),
+            }
+        };
+        change().map_err(|e| format!("{method} error: {e}"))
+    })
+    .await
+}
+
+#[napi]
+impl UsbDevice {
+    /// GET_STATUS for the device, an interface (by number) or an endpoint (by address).
+    #[napi]
+    pub async fn getStatus(
+        &self,
+        #[napi(ts_arg_type = "'device' | 'interface' | 'endpoint'")] recipient: String,
+        index: Option<u16>,
+    ) -> Result<UsbStatus> {
+        let shared = self.shared().clone();
+        let usb3 = self.device_info().usb_version() >= USB3_VERSION;
+        let index = index.unwrap_or(0);
+        run_blocking(move || {
+            let status = || {
+                let recipient = parse_recipient(&recipient)?;
+                let target = request_target(&shared, recipient, index)?;
+                let status = get_status(&target, recipient, index)?;
+                Ok(decode_status(recipient, status, usb3))
+            };
+            status().map_err(|e: String| format!("getStatus error: {e}"))
+        })
+        .await
+    }
+
+    /// SET_FEATURE; `index` is the endpoint address for `endpointHalt` and the test selector for
+    /// `testMode`.
+    #[napi]
+    pub async fn setFeature(
+        &self,
+        #[napi(
+            ts_arg_type = "'remoteWakeup' | 'testMode' | 'u1Enable' | 'u2Enable' | 'endpointHalt'"
+        )]
+        feature: String,
+        index: Option<u16>,
+    ) -> Result<()> {
+        change_feature(self, "setFeature", feature, index, true).await
+    }
+
+    /// CLEAR_FEATURE; `index` is the endpoint address for `endpointHalt`. Test mode is only left by
+    /// a reset.
+    #[napi]
+    pub async fn clearFeature(
+        &self,
+        #[napi(ts_arg_type = "'remoteWakeup' | 'u1Enable' | 'u2Enable' | 'endpointHalt'")]
+        feature: String,
+        index: Option<u16>,
+    ) -> Result<()> {
+        change_feature(self, "clearFeature", feature, index, false).await
+    }
+
+    /// GET_CONFIGURATION, the device's own idea of its configuration value.
+    #[napi]
+    pub async fn getConfiguration(&self) -> Result<u8> {
+        let shared = self.shared().clone();
+        run_blocking(move || {
+            let configuration = || {
+                let target = shared
+                    .control_target(Recipient::Device, 0)
+                    .ok_or("invalid state")?;
+                get_configuration(&target)
+            };
+            configuration().map_err(|e| format!("getConfiguration error: {e}"))
+        })
+        .await
+    }
+
+    /// GET_INTERFACE, the alternate setting the device has selected for an interface.
+    #[napi]
+    pub async fn getInterface(&self, interfaceNumber: u8) -> Result<u8> {
+        let shared = self.shared().clone();
+        run_blocking(move || {
+            let alternate = || {
+                let target = request_target(&shared, Recipient::Interface, interfaceNumber as u16)?;
+                get_interface(&target, interfaceNumber)
+            };
+            alternate().map_err(|e| format!("getInterface error: {e}"))
+        })
+        .await
+    }
+
+    /// SYNCH_FRAME for an isochronous endpoint, by address.
+    #[napi]
+    pub async fn synchFrame(&self, endpointAddress: u8) -> Result<u16> {
+        let shared = self.shared().clone();
+        run_blocking(move || {
+            let frame = || {
+                let device = shared.opened_device().ok_or("invalid state")?;
+                let config = device.active_configuration().map_err(|e| e.to_string())?;
+                let isochronous = config
+                    .interface_alt_settings()
+                    .flat_map(|alt| alt.endpoints().collect::<Vec<_>>())
+                    .any(|endpoint| {
+                        endpoint.address() == endpointAddress
+                            && endpoint.transfer_type() == TransferType::Isochronous
+                    });
+                if !isochronous {
+                    return Err(format!(
+                        "endpoint {endpointAddress:#04x} is not an isochronous endpoint"
+                    ));
+                }
+                let target = request_target(&shared, Recipient::Endpoint, endpointAddress as u16)?;
+                synch_frame(&target, endpointAddress)
+            };
+            frame().map_err(|e| format!("synchFrame error: {e}"))
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One interface with a bulk IN and a bulk OUT endpoint, remote wakeup supported
+    const CONFIGURATION: [u8; 32] = [
+        0x09, 0x02, 0x20, 0x00, 0x01, 0x01, 0x00, 0xa0, 0x32, //
+        0x09, 0x04, 0x00, 0x00, 0x02, 0xff, 0x00, 0x00, 0x00, //
+        0x07, 0x05, 0x81, 0x02, 0x00, 0x02, 0x00, //
+        0x07, 0x05, 0x02, 0x02, 0x00, 0x02, 0x00,
+    ];
+
+    fn configuration(attributes: u8) -> Vec<u8> {
+        let mut data = CONFIGURATION.to_vec();
+        data[7] = attributes;
+        data
+    }
+
+    #[test]
+    fn decodes_device_status() {
+        let status = decode_status(Recipient::Device, 0x001f, true);
+        assert_eq!(status.selfPowered, Some(true));
+        assert_eq!(status.remoteWakeup, Some(true));
+        assert_eq!(status.u1Enabled, Some(true));
+        assert_eq!(status.u2Enabled, Some(true));
+        assert_eq!(status.ltmEnabled, Some(true));
+        assert_eq!(status.halted, None);
+        assert_eq!(status.functionRemoteWakeCapable, None);
+
+        let status = decode_status(Recipient::Device, STATUS_REMOTE_WAKEUP, false);
+        assert_eq!(status.selfPowered, Some(false));
+        assert_eq!(status.remoteWakeup, Some(true));
+        assert_eq!(status.u1Enabled, None);
+        assert_eq!(status.ltmEnabled, None);
+    }
+
+    #[test]
+    fn decodes_interface_and_endpoint_status() {
+        let status = decode_status(Recipient::Interface, STATUS_FUNCTION_REMOTE_WAKEUP, true);
+        assert_eq!(status.functionRemoteWakeCapable, Some(false));
+        assert_eq!(status.functionRemoteWakeup, Some(true));
+        assert_eq!(status.selfPowered, None);
+        assert_eq!(status.halted, None);
+        assert_eq!(
+            decode_status(Recipient::Interface, 0x0003, false).functionRemoteWakeup,
+            None
+        );
+
+        let status = decode_status(Recipient::Endpoint, STATUS_HALT, false);
+        assert_eq!(status.status, STATUS_HALT);
+        assert_eq!(status.halted, Some(true));
+        assert_eq!(status.remoteWakeup, None);
+        assert_eq!(status.functionRemoteWakeup, None);
+    }
+
+    #[test]
+    fn shifts_the_test_selector_into_the_high_byte() {
+        let data = configuration(0x80);
+        let config = ConfigurationDescriptor::new(&data).unwrap();
+        assert_eq!(
+            feature_request(&config, 0x0200, &Feature::TestMode, 4, true),
+            Ok((Recipient::Device, FEATURE_TEST_MODE, 0x0400))
+        );
+        assert_eq!(
+            feature_request(&config, 0x0200, &Feature::TestMode, 0xc0, true),
+            Ok((Recipient::Device, FEATURE_TEST_MODE, 0xc000))
+        );
+        assert!(feature_request(&config, 0x0200, &Feature::TestMode, 6, true).is_err());
+        assert!(feature_request(&config, 0x0200, &Feature::TestMode, 0x0401, true).is_err());
+        assert!(feature_request(&config, 0x0200, &Feature::TestMode, 4, false).is_err());
+    }
+
+    #[test]
+    fn rejects_halting_endpoint_0() {
+        let data = configuration(0x80);
+        let config = ConfigurationDescriptor::new(&data).unwrap();
+        for address in [0x00, 0x80] {
+            assert!(
+                feature_request(&config, 0x0200, &Feature::EndpointHalt, address, true).is_err()
+            );
+        }
+        assert_eq!(
+            feature_request(&config, 0x0200, &Feature::EndpointHalt, 0x81, true),
+            Ok((Recipient::Endpoint, FEATURE_ENDPOINT_HALT, 0x81))
+        );
+    }
+
+    #[test]
+    fn checks_the_feature_against_the_device() {
+        let data = configuration(0x80);
+        let config = ConfigurationDescriptor::new(&data).unwrap();
+        assert!(feature_request(&config, 0x0200, &Feature::RemoteWakeup, 0, true).is_err());
+        assert!(feature_request(&config, 0x0210, &Feature::U1Enable, 0, true).is_err());
+        assert_eq!(
+            feature_request(&config, 0x0300, &Feature::U2Enable, 0, true),
+            Ok((Recipient::Device, FEATURE_U2_ENABLE, 0))
+        );
+
+        let data = configuration(0xa0);
+        let config = ConfigurationDescriptor::new(&data).unwrap();
+        assert_eq!(
+            feature_request(&config, 0x0200, &Feature::RemoteWakeup, 0, false),
+            Ok((Recipient::Device, FEATURE_DEVICE_REMOTE_WAKEUP, 0))
+        );
+    }
+
+    #[test]
+    fn checks_the_recipient_exists() {
+        let config = ConfigurationDescriptor::new(&CONFIGURATION).unwrap();
+        assert!(check_recipient(&config, Recipient::Device, 0).is_ok());
+        assert!(check_recipient(&config, Recipient::Device, 1).is_err());
+        assert!(check_recipient(&config, Recipient::Interface, 0).is_ok());
+        assert!(check_recipient(&config, Recipient::Interface, 1).is_err());
+        for address in [0x00, 0x80, 0x81, 0x02] {
+            assert!(check_recipient(&config, Recipient::Endpoint, address).is_ok());
+        }
+        for address in [0x01, 0x82, 0x03] {
+            assert!(check_recipient(&config, Recipient::Endpoint, address).is_err());
+        }
+    }
+}
@@ -0,0 +1,423 @@
+use crate::webusb_device::{run_blocking, AnyEndpoint, SharedDevice, UsbDevice};
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use nusb::transfer::{Buffer, EndpointDirection, In, Out, TransferError};
+use std::{
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+/// Idle buffers kept per endpoint when none have been reserved.
+const DEFAULT_IDLE_BUFFERS: usize = 4;
+
+/// Endpoints and transfer buffers kept between transfers, so a steady stream
+/// of transfers on the same endpoint reuses them instead of allocating.
+///
+/// Buffers come from `Endpoint::allocate`, which on Linux maps memory the
+/// kernel transfers into directly. Everything is kept per claimed interface:
+/// any change to an interface clears its part of the pool (and bumps its
+/// generation), and anything handed back from an older generation is dropped.
+pub(crate) struct TransferPool {
+    interfaces: HashMap<u8, InterfacePool>,
+}
+
+#[derive(Default)]
+pub(crate) struct InterfacePool {
+    generation: u64,
+    endpoints_in: HashMap<u8, AnyEndpoint<In>>,
+    endpoints_out: HashMap<u8, AnyEndpoint<Out>>,
+    buffers: HashMap<u8, Vec<Buffer>>,
+    reserved: HashMap<u8, usize>,
+}
+
+pub(crate) trait PooledDirection: EndpointDirection + Sized {
+    fn endpoints(pool: &mut InterfacePool) -> &mut HashMap<u8, AnyEndpoint<Self>>;
+}
+
+impl PooledDirection for In {
+    fn endpoints(pool: &mut InterfacePool) -> &mut HashMap<u8, AnyEndpoint<Self>> {
+        &mut pool.endpoints_in
+    }
+}
+
+impl PooledDirection for Out {
+    fn endpoints(pool: &mut InterfacePool) -> &mut HashMap<u8, AnyEndpoint<Self>> {
+        &mut pool.endpoints_out
+    }
+}
+
+/// An endpoint checked out of the pool, see `SharedDevice::put_endpoint`.
+pub(crate) struct PooledEndpoint<DIR: EndpointDirection> {
+    endpoint: AnyEndpoint<DIR>,
+    interface_number: u8,
+    generation: u64,
+}
+
+impl<DIR: EndpointDirection> PooledEndpoint<DIR> {
+    pub(crate) fn new(endpoint: AnyEndpoint<DIR>, interface_number: u8, generation: u64) -> Self {
+        Self {
+            endpoint,
+            interface_number,
+            generation,
+        }
+    }
+
+    pub(crate) fn interface_number(&self) -> u8 {
+        self.interface_number
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+}
+
+impl<DIR: EndpointDirection> Deref for PooledEndpoint<DIR> {
+    type Target = AnyEndpoint<DIR>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.endpoint
+    }
+}
+
+impl<DIR: EndpointDirection> DerefMut for PooledEndpoint<DIR> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.endpoint
+    }
+}
+
+impl InterfacePool {
+    fn clear(&mut self) {
+        *self = Self {
+            generation: self.generation + 1,
+            ..Self::default()
+        };
+    }
+}
+
+impl TransferPool {
+    pub(crate) fn new() -> Self {
+        Self {
+            interfaces: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn generation(&self, interface_number: u8) -> u64 {
+        self.interfaces
+            .get(&interface_number)
+            .map_or(0, |pool| pool.generation)
+    }
+
+    /// The pooled state of a current generation, missing for anything older.
+    fn current(&mut self, interface_number: u8, generation: u64) -> Option<&mut InterfacePool> {
+        let pool = self.interfaces.entry(interface_number).or_default();
+        (pool.generation == generation).then_some(pool)
+    }
+
+    /// Clears every interface, for changes to the whole device.
+    pub(crate) fn clear(&mut self) {
+        self.interfaces.values_mut().for_each(InterfacePool::clear);
+    }
+
+    /// Clears one interface, leaving transfers on the others untouched.
+    pub(crate) fn clear_interface(&mut self, interface_number: u8) {
+        if let Some(pool) = self.interfaces.get_mut(&interface_number) {
+            pool.clear();
+        }
+    }
+
+    pub(crate) fn take_endpoint<DIR: PooledDirection>(
+        &mut self,
+        endpoint_number: u8,
+    ) -> Option<PooledEndpoint<DIR>> {
+        self.interfaces
+            .iter_mut()
+            .find_map(|(&interface_number, pool)| {
+                let endpoint = DIR::endpoints(pool).remove(&endpoint_number)?;
+                Some(PooledEndpoint::new(
+                    endpoint,
+                    interface_number,
+                    pool.generation,
+                ))
+            })
+    }
+
+    pub(crate) fn put_endpoint<DIR: PooledDirection>(&mut self, pooled: PooledEndpoint<DIR>) {
+        if pooled.endpoint.pending() > 0 {
+            return;
+        }
+        if let Some(pool) = self.current(pooled.interface_number, pooled.generation) {
+            let endpoint_number = pooled.endpoint.endpoint_number();
+            DIR::endpoints(pool).insert(endpoint_number, pooled.endpoint);
+        }
+    }
+
+    /// An idle buffer for the endpoint holding at least `len` bytes, emptied and ready to use.
+    pub(crate) fn take_buffer(
+        &mut self,
+        interface_number: u8,
+        address: u8,
+        len: usize,
+    ) -> Option<Buffer> {
+        let idle = self
+            .interfaces
+            .get_mut(&interface_number)?
+            .buffers
+            .get_mut(&address)?;
+        let index = idle.iter().position(|buffer| buffer.capacity() >= len)?;
+        let mut buffer = idle.swap_remove(index);
+        buffer.clear();
+        buffer.set_requested_len(len);
+        Some(buffer)
+    }
+
+    pub(crate) fn put_buffer(
+        &mut self,
+        interface_number: u8,
+        generation: u64,
+        address: u8,
+        buffer: Buffer,
+    ) {
+        let Some(pool) = self.current(interface_number, generation) else {
+            return;
+        };
+        let limit = pool
+            .reserved
+            .get(&address)
+            .copied()
+            .unwrap_or(DEFAULT_IDLE_BUFFERS);
+        let idle = pool.buffers.entry(address).or_default();
+        if idle.len() < limit {
+            idle.push(buffer);
+        }
+    }
+
+    /// Replaces the idle buffers for the endpoint with `buffers` and keeps that many from now on.
+    pub(crate) fn reserve<DIR: PooledDirection>(
+        &mut self,
+        endpoint: &PooledEndpoint<DIR>,
+        buffers: Vec<Buffer>,
+    ) {
+        let address = endpoint.endpoint_address();
+        if let Some(pool) = self.current(endpoint.interface_number, endpoint.generation) {
+            pool.reserved.insert(address, buffers.len());
+            pool.buffers.insert(address, buffers);
+        }
+    }
+}
+
+fn pooled_buffer<DIR: PooledDirection>(
+    shared: &SharedDevice,
+    endpoint: &PooledEndpoint<DIR>,
+    len: usize,
+) -> Buffer {
+    let pooled = shared.pool().take_buffer(
+        endpoint.interface_number(),
+        endpoint.endpoint_address(),
+        len,
+    );
+    pooled.unwrap_or_else(|| endpoint.allocate(len))
+}
+
+/// Reads up to `length` bytes into a pooled buffer, returning it with the number of bytes read.
+/// The buffer is recycled on failure, otherwise the caller passes it on or hands it back.
+pub(crate) fn pooled_transfer_in(
+    shared: &SharedDevice,
+    endpoint: &mut PooledEndpoint<In>,
+    length: usize,
+    timeout: Duration,
+) -> std::result::Result<(Buffer, usize), TransferError> {
+    let packet_size = endpoint.max_packet_size();
+    let req = length.div_ceil(packet_size) * packet_size;
+    let buffer = pooled_buffer(shared, endpoint, req);
+    let completion = endpoint.transfer_blocking(buffer, timeout);
+    if let Err(e) = completion.status {
+        shared.put_buffer(endpoint, completion.buffer);
+        return Err(e);
+    }
+    Ok((completion.buffer, completion.actual_len.min(length)))
+}
+
+/// Writes `data` by copying it into a pooled buffer. nusb only submits memory it allocated itself,
+/// so JS memory can't be handed to the OS in place the way received buffers are handed to JS.
+pub(crate) fn pooled_transfer_out(
+    shared: &SharedDevice,
+    endpoint: &mut PooledEndpoint<Out>,
+    data: &[u8],
+    timeout: Duration,
+) -> std::result::Result<usize, TransferError> {
+    let mut buffer = pooled_buffer(shared, endpoint, data.len());
+    buffer.extend_from_slice(data);
+    let completion = endpoint.transfer_blocking(buffer, timeout);
+    shared.put_buffer(endpoint, completion.buffer);
+    completion.status?;
+    Ok(completion.actual_len)
+}
+
+/// Hands a received buffer to JS as an external ArrayBuffer without copying it.
+/// Once JS collects the array the buffer goes back to the pool.
+pub(crate) fn buffer_to_js(
+    shared: &Arc<SharedDevice>,
+    endpoint: &PooledEndpoint<In>,
+    mut buffer: Buffer,
+    len: usize,
+) -> Uint8Array {
+    let shared: Weak<SharedDevice> = Arc::downgrade(shared);
+    let interface_number = endpoint.interface_number();
+    let generation = endpoint.generation();
+    let address = endpoint.endpoint_address();
+    let data = buffer.as_mut_ptr();
+
+    // SAFETY: the buffer owns `data` and is only dropped or recycled once JS has released the array
+    unsafe {
+        Uint8Array::with_external_data(data, len, move |_, _| {
+            if let Some(shared) = shared.upgrade() {
+                shared
+                    .pool()
+                    .put_buffer(interface_number, generation, address, buffer);
+            }
+        })
+    }
+}
+
+fn reserve_buffers<DIR: PooledDirection>(
+    shared: &SharedDevice,
+    endpoint: PooledEndpoint<DIR>,
+    count: usize,
+    length: usize,
+) {
+    let buffers = (0..count).map(|_| endpoint.allocate(length)).collect();
+    shared.pool().reserve(&endpoint, buffers);
+    shared.put_endpoint(endpoint);
+}
+
+#[napi]
+impl UsbDevice {
+    /// Reads into a pooled buffer and copies the bytes into `target`, so a stream can keep reusing
+    /// the same JS memory. nusb only transfers into buffers it allocated, so this can't skip the copy.
+    #[napi(js_name = "nativeTransferInto")]
+    pub async fn transferInto(
+        &self,
+        endpointNumber: u8,
+        timeout: u32,
+        mut target: Uint8Array,
+    ) -> Result<u32> {
+        let shared = self.shared().clone();
+        let mut endpoint = shared
+            .get_endpoint::<In>(endpointNumber)
+            .ok_or_else(|| napi::Error::from_reason("transferInto error: endpoint not found"))?;
+
+        run_blocking(move || {
+            let result = pooled_transfer_in(
+                &shared,
+                &mut endpoint,
+                target.len(),
+                Duration::from_millis(timeout as u64),
+            )
+            .map(|(buffer, len)| {
+                // SAFETY: JS hands over `target` for the duration of the call and it stays referenced until then
+                let target = unsafe { target.as_mut() };
+                target[..len].copy_from_slice(&buffer[..len]);
+                shared.put_buffer(&endpoint, buffer);
+                len as u32
+            })
+            .map_err(|e| format!("transferInto error: {e:?}"));
+            shared.put_endpoint(endpoint);
+            result
+        })
+        .await
+    }
+
+    /// Allocates `count` transfer buffers of `length` bytes for the endpoint up front and keeps
+    /// that many between transfers, replacing any buffers it already had.
+    #[napi]
+    pub async fn reserveTransferBuffers(
+        &self,
+        #[napi(ts_arg_type = "USBDirection")] direction: String,
+        endpointNumber: u8,
+        count: u32,
+        length: u32,
+    ) -> Result<()> {
+        let shared = self.shared().clone();
+        if direction == "in" {
+            let endpoint = shared.get_endpoint::<In>(endpointNumber).ok_or_else(|| {
+                napi::Error::from_reason("reserveTransferBuffers error: endpoint not found")
+            })?;
+            let packet_size = endpoint.max_packet_size();
+            let length = (length as usize).div_ceil(packet_size) * packet_size;
+            run_blocking(move || {
+                reserve_buffers(&shared, endpoint, count as usize, length);
+                Ok(())
+            })
+            .await
+        } else {
+            let endpoint = shared.get_endpoint::<Out>(endpointNumber).ok_or_else(|| {
+                napi::Error::from_reason("reserveTransferBuffers error: endpoint not found")
+            })?;
+            run_blocking(move || {
+                reserve_buffers(&shared, endpoint, count as usize, length as usize);
+                Ok(())
+            })
+            .await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clearing_an_interface_keeps_the_others() {
+        let mut pool = TransferPool::new();
+        pool.put_buffer(0, 0, 0x81, Buffer::new(64));
+        pool.put_buffer(1, 0, 0x82, Buffer::new(64));
+
+        pool.clear_interface(0);
+        assert_eq!(pool.generation(0), 1);
+        assert_eq!(pool.generation(1), 0);
+        assert!(pool.take_buffer(0, 0x81, 64).is_none());
+        assert!(pool.take_buffer(1, 0x82, 64).is_some());
+
+        // Handed back from before the clear
+        pool.put_buffer(0, 0, 0x81, Buffer::new(64));
+        assert!(pool.take_buffer(0, 0x81, 64).is_none());
+        pool.put_buffer(0, 1, 0x81, Buffer::new(64));
+        assert!(pool.take_buffer(0, 0x81, 64).is_some());
+    }
+
+    #[test]
+    fn releasing_and_reclaiming_drops_older_buffers() {
+        let mut pool = TransferPool::new();
+        pool.put_buffer(0, 0, 0x81, Buffer::new(64));
+
+        // Released, then claimed again: transfers still running hand back generation 0 buffers
+        pool.clear_interface(0);
+        let reclaimed = pool.generation(0);
+        assert_eq!(reclaimed, 1);
+        pool.put_buffer(0, 0, 0x81, Buffer::new(64));
+        assert!(pool.take_buffer(0, 0x81, 64).is_none());
+
+        pool.put_buffer(0, reclaimed, 0x81, Buffer::new(64));
+        pool.clear_interface(0);
+        pool.put_buffer(0, reclaimed, 0x81, Buffer::new(64));
+        assert_eq!(pool.generation(0), 2);
+        assert!(pool.take_buffer(0, 0x81, 64).is_none());
+    }
+
+    #[test]
+    fn clearing_the_device_bumps_every_interface() {
+        let mut pool = TransferPool::new();
+        pool.put_buffer(0, 0, 0x81, Buffer::new(64));
+        pool.put_buffer(1, 0, 0x02, Buffer::new(64));
+
+        pool.clear();
+        assert_eq!((pool.generation(0), pool.generation(1)), (1, 1));
+        assert!(pool.take_buffer(0, 0x81, 64).is_none());
+        assert!(pool.take_buffer(1, 0x02, 64).is_none());
+
+        pool.put_buffer(1, 1, 0x02, Buffer::new(64));
+        assert!(pool.take_buffer(1, 0x02, 64).is_some());
+    }
+}
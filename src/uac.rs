@@ -0,0 +1,937 @@
+use crate::webusb_device::{run_blocking, ControlTarget, SharedDevice, UsbDevice};
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use nusb::{
+    descriptors::{ConfigurationDescriptor, InterfaceDescriptor, TransferType},
+    transfer::{ControlIn, ControlOut, ControlType, Direction, Recipient},
+};
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+const CLASS_AUDIO: u8 = 0x01;
+const SUBCLASS_AUDIO_CONTROL: u8 = 0x01;
+const SUBCLASS_AUDIO_STREAMING: u8 = 0x02;
+const PROTOCOL_UAC2: u8 = 0x20;
+
+const CS_INTERFACE: u8 = 0x24;
+
+const AC_HEADER: u8 = 0x01;
+const AC_INPUT_TERMINAL: u8 = 0x02;
+const AC_OUTPUT_TERMINAL: u8 = 0x03;
+const AC_MIXER_UNIT: u8 = 0x04;
+const AC_SELECTOR_UNIT: u8 = 0x05;
+const AC_FEATURE_UNIT: u8 = 0x06;
+const AC_CLOCK_SOURCE: u8 = 0x0a;
+const AC_CLOCK_SELECTOR: u8 = 0x0b;
+const AC_CLOCK_MULTIPLIER: u8 = 0x0c;
+
+const AS_GENERAL: u8 = 0x01;
+const AS_FORMAT_TYPE: u8 = 0x02;
+const FORMAT_TYPE_I: u8 = 0x01;
+
+/// UAC1 GET requests, UAC2 reads CUR and RANGE with the same request codes as SET
+const SET_CUR: u8 = 0x01;
+const GET_CUR: u8 = 0x81;
+const UAC2_CUR: u8 = 0x01;
+const UAC2_RANGE: u8 = 0x02;
+
+const SAMPLING_FREQ_CONTROL: u16 = 0x01;
+const FU_MUTE_CONTROL: u16 = 0x01;
+const FU_VOLUME_CONTROL: u16 = 0x02;
+
+const USAGE_FEEDBACK: u8 = 0x10;
+
+const CONTROL_TIMEOUT: Duration = Duration::from_millis(1000);
+
+#[napi(object)]
+pub struct UacOptions {
+    /// The AudioControl interface to use, defaults to the first one
+    pub interfaceNumber: Option<u8>,
+    /// Detach kernel drivers (e.g. `snd-usb-audio`) while the interfaces are open, defaults to `true`
+    pub detachKernelDriver: Option<bool>,
+}
+
+/// A terminal, unit or clock entity from the AudioControl interface.
+#[napi(object)]
+#[derive(Clone)]
+pub struct UacEntity {
+    #[napi(writable = false)]
+    pub id: u8,
+    #[napi(
+        js_name = "type",
+        writable = false,
+        ts_type = "'inputTerminal' | 'outputTerminal' | 'mixerUnit' | 'selectorUnit' | 'featureUnit' | 'clockSource' | 'clockSelector' | 'clockMultiplier'"
+    )]
+    pub _type: String,
+    /// wTerminalType of terminals
+    #[napi(writable = false)]
+    pub terminalType: Option<u16>,
+    /// Entities this one takes input from
+    #[napi(writable = false)]
+    pub sources: Vec<u8>,
+    /// The clock entity of UAC2 terminals
+    #[napi(writable = false)]
+    pub clockSource: Option<u8>,
+}
+
+#[napi(object)]
+#[derive(Clone)]
+pub struct UacSampleRateRange {
+    #[napi(writable = false)]
+    pub min: u32,
+    #[napi(writable = false)]
+    pub max: u32,
+    /// `0` for any rate in the range
+    #[napi(writable = false)]
+    pub step: u32,
+}
+
+/// A streaming alternate setting carrying PCM (format type I).
+#[napi(object)]
+#[derive(Clone)]
+pub struct UacAlternateSetting {
+    #[napi(writable = false)]
+    pub interfaceNumber: u8,
+    #[napi(writable = false)]
+    pub alternateSetting: u8,
+    #[napi(writable = false, ts_type = "'capture' | 'playback'")]
+    pub direction: String,
+    /// The terminal the interface connects to
+    #[napi(writable = false)]
+    pub terminalLink: u8,
+    #[napi(writable = false)]
+    pub channels: u8,
+    /// Bytes per sample
+    #[napi(writable = false)]
+    pub subframeSize: u8,
+    #[napi(writable = false)]
+    pub bitResolution: u8,
+    /// Discrete rates from the format descriptor, or for UAC2 from the clock source where it lists them
+    #[napi(writable = false)]
+    pub sampleRates: Vec<u32>,
+    #[napi(writable = false)]
+    pub sampleRateRanges: Vec<UacSampleRateRange>,
+    #[napi(writable = false)]
+    pub endpoint: u8,
+    #[napi(writable = false)]
+    pub maxPacketSize: u32,
+    #[napi(
+        writable = false,
+        ts_type = "'none' | 'asynchronous' | 'adaptive' | 'synchronous'"
+    )]
+    pub syncType: String,
+    /// The explicit feedback endpoint of asynchronous OUT endpoints
+    #[napi(writable = false)]
+    pub feedbackEndpoint: Option<u8>,
+}
+
+#[napi(object)]
+pub struct UacFormatOptions {
+    /// The streaming interface, defaults to any matching the other options
+    pub interfaceNumber: Option<u8>,
+    #[napi(ts_type = "'capture' | 'playback'")]
+    pub direction: Option<String>,
+    pub channels: Option<u8>,
+    pub bitResolution: Option<u8>,
+    /// Also sets the rate once the setting is selected
+    pub sampleRate: Option<u32>,
+}
+
+struct UacLayout {
+    control: u8,
+    uac2: bool,
+    version: u16,
+    entities: Vec<UacEntity>,
+    settings: Vec<UacAlternateSetting>,
+}
+
+fn parse_entity(d: &[u8], uac2: bool) -> Option<UacEntity> {
+    let entity = |_type: &str, terminal_type, sources: &[u8], clock_source| {
+        Some(UacEntity {
+            id: d[3],
+            _type: _type.to_string(),
+            terminalType: terminal_type,
+            sources: sources.to_vec(),
+            clockSource: clock_source,
+        })
+    };
+    let pins = |from: usize| {
+        let count = *d.get(from)? as usize;
+        d.get(from + 1..from + 1 + count)
+    };
+    let terminal_type = (d.len() >= 6).then(|| u16::from_le_bytes([d[4], d[5]]));
+    match (d[2], uac2) {
+        (AC_INPUT_TERMINAL, false) if d.len() >= 12 => {
+            entity("inputTerminal", terminal_type, &[], None)
+        }
+        (AC_INPUT_TERMINAL, true) if d.len() >= 17 => {
+            entity("inputTerminal", terminal_type, &[], Some(d[7]))
+        }
+        (AC_OUTPUT_TERMINAL, false) if d.len() >= 9 => {
+            entity("outputTerminal", terminal_type, &d[7..8], None)
+        }
+        (AC_OUTPUT_TERMINAL, true) if d.len() >= 12 => {
+            entity("outputTerminal", terminal_type, &d[7..8], Some(d[8]))
+        }
+        (AC_MIXER_UNIT, _) => entity("mixerUnit", None, pins(4)?, None),
+        (AC_SELECTOR_UNIT, _) => entity("selectorUnit", None, pins(4)?, None),
+        (AC_FEATURE_UNIT, _) if d.len() >= 5 => entity("featureUnit", None, &d[4..5], None),
+        (AC_CLOCK_SOURCE, true) if d.len() >= 8 => entity("clockSource", None, &[], None),
+        (AC_CLOCK_SELECTOR, true) => entity("clockSelector", None, pins(4)?, None),
+        (AC_CLOCK_MULTIPLIER, true) if d.len() >= 5 => {
+            entity("clockMultiplier", None, &d[4..5], None)
+        }
+        _ => None,
+    }
+}
+
+fn parse_setting(alt: &InterfaceDescriptor, uac2: bool) -> Option<UacAlternateSetting> {
+    let mut terminal_link = None;
+    let mut general_channels = 0;
+    let mut format = None;
+    for d in alt.descriptors() {
+        if d.descriptor_type() != CS_INTERFACE || d.len() < 4 {
+            continue;
+        }
+        match d[2] {
+            AS_GENERAL => {
+                terminal_link = Some(d[3]);
+                if uac2 && d.len() >= 11 {
+                    general_channels = d[10];
+                }
+            }
+            AS_FORMAT_TYPE if d[3] == FORMAT_TYPE_I => format = Some(d.to_vec()),
+            _ => {}
+        }
+    }
+    let format = format?;
+
+    let (channels, subframe_size, bit_resolution, mut sample_rates, mut ranges) = if uac2 {
+        if format.len() < 6 {
+            return None;
+        }
+        (
+            general_channels,
+            format[4],
+            format[5],
+            Vec::new(),
+            Vec::new(),
+        )
+    } else {
+        if format.len() < 8 {
+            return None;
+        }
+        let count = format[7] as usize;
+        let rates = format
+            .get(8..)
+            .unwrap_or(&[])
+            .chunks_exact(3)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], 0]))
+            .collect::<Vec<_>>();
+        // A count of 0 gives a continuous range as lower and upper bounds
+        if count == 0 && rates.len() >= 2 {
+            let range = UacSampleRateRange {
+                min: rates[0],
+                max: rates[1],
+                step: 0,
+            };
+            (format[4], format[5], format[6], Vec::new(), vec![range])
+        } else {
+            let rates = rates.into_iter().take(count).collect();
+            (format[4], format[5], format[6], rates, Vec::new())
+        }
+    };
+    sample_rates.sort_unstable();
+    ranges.sort_unstable_by_key(|range: &UacSampleRateRange| range.min);
+
+    let endpoints = alt
+        .endpoints()
+        .filter(|ep| ep.transfer_type() == TransferType::Isochronous)
+        .collect::<Vec<_>>();
+    let data = endpoints
+        .iter()
+        .find(|ep| ep.attributes() & 0x30 != USAGE_FEEDBACK)?;
+    let feedback = endpoints
+        .iter()
+        .find(|ep| ep.attributes() & 0x30 == USAGE_FEEDBACK)
+        .map(|ep| ep.address());
+
+    Some(UacAlternateSetting {
+        interfaceNumber: alt.interface_number(),
+        alternateSetting: alt.alternate_setting(),
+        direction: match data.direction() {
+            Direction::In => "capture",
+            Direction::Out => "playback",
+        }
+        .to_string(),
+        terminalLink: terminal_link?,
+        channels,
+        subframeSize: subframe_size,
+        bitResolution: bit_resolution,
+        sampleRates: sample_rates,
+        sampleRateRanges: ranges,
+        endpoint: data.address(),
+        maxPacketSize: (data.max_packet_size() * data.packets_per_microframe() as usize) as u32,
+        syncType: match data.attributes() >> 2 & 0x03 {
+            1 => "asynchronous",
+            2 => "adaptive",
+            3 => "synchronous",
+            _ => "none",
+        }
+        .to_string(),
+        feedbackEndpoint: feedback,
+    })
+}
+
+fn find_uac_layout(
+    config: &ConfigurationDescriptor,
+    interface_number: Option<u8>,
+) -> std::result::Result<UacLayout, String> {
+    let control = config
+        .interface_alt_settings()
+        .filter(|alt| alt.alternate_setting() == 0)
+        .filter(|alt| interface_number.is_none_or(|n| alt.interface_number() == n))
+        .find(|alt| alt.class() == CLASS_AUDIO && alt.subclass() == SUBCLASS_AUDIO_CONTROL)
+        .ok_or("no UAC interface found")?;
+    let uac2 = control.protocol() == PROTOCOL_UAC2;
+
+    let mut version = if uac2 { 0x0200 } else { 0x0100 };
+    let mut entities = Vec::new();
+    for d in control.descriptors() {
+        if d.descriptor_type() != CS_INTERFACE || d.len() < 4 {
+            continue;
+        }
+        if d[2] == AC_HEADER && d.len() >= 5 {
+            version = u16::from_le_bytes([d[3], d[4]]);
+        } else if let Some(entity) = parse_entity(&d, uac2) {
+            entities.push(entity);
+        }
+    }
+
+    // The streaming interfaces of a function follow its control interface
+    let mut settings = Vec::new();
+    let mut next = control.interface_number() + 1;
+    loop {
+        let alts = config
+            .interface_alt_settings()
+            .filter(|alt| alt.interface_number() == next)
+            .collect::<Vec<_>>();
+        if alts.is_empty()
+            || !alts
+                .iter()
+                .all(|alt| alt.class() == CLASS_AUDIO && alt.subclass() == SUBCLASS_AUDIO_STREAMING)
+        {
+            break;
+        }
+        settings.extend(alts.iter().filter_map(|alt| parse_setting(alt, uac2)));
+        next += 1;
+    }
+
+    Ok(UacLayout {
+        control: control.interface_number(),
+        uac2,
+        version,
+        entities,
+        settings,
+    })
+}
+
+/// Follows a UAC2 terminal's clock through selectors and multipliers to the clock source.
+fn clock_source(entities: &[UacEntity], terminal: u8) -> Option<u8> {
+    let mut id = entities.iter().find(|e| e.id == terminal)?.clockSource?;
+    for _ in 0..entities.len() {
+        let entity = entities.iter().find(|e| e.id == id)?;
+        match entity._type.as_str() {
+            "clockSource" => return Some(id),
+            // A selector's first input is as good a guess as any without reading its position
+            _ => id = *entity.sources.first()?,
+        }
+    }
+    None
+}
+
+fn parse_ranges(data: &[u8]) -> Vec<UacSampleRateRange> {
+    data.get(2..)
+        .unwrap_or(&[])
+        .chunks_exact(12)
+        .map(|r| {
+            let u32_at = |i: usize| u32::from_le_bytes([r[i], r[i + 1], r[i + 2], r[i + 3]]);
+            UacSampleRateRange {
+                min: u32_at(0),
+                max: u32_at(4),
+                step: u32_at(8),
+            }
+        })
+        .collect()
+}
+
+/// Reads a UAC2 RANGE, asking for the count first as the length depends on it.
+fn read_range(
+    target: &ControlTarget,
+    selector: u16,
+    index: u16,
+) -> std::result::Result<Vec<u8>, String> {
+    let get = |length| {
+        target
+            .control_in_blocking(
+                ControlIn {
+                    control_type: ControlType::Class,
+                    recipient: Recipient::Interface,
+                    request: UAC2_RANGE,
+                    value: selector << 8,
+                    index,
+                    length,
+                },
+                CONTROL_TIMEOUT,
+            )
+            .map_err(|e| e.to_string())
+    };
+    let count = get(2)?;
+    let count = u16::from_le_bytes([
+        count.first().copied().unwrap_or(0),
+        count.get(1).copied().unwrap_or(0),
+    ]);
+    get(2 + count * 12)
+}
+
+fn control_in(
+    target: &ControlTarget,
+    recipient: Recipient,
+    request: u8,
+    value: u16,
+    index: u16,
+    length: u16,
+) -> std::result::Result<Vec<u8>, String> {
+    let request = ControlIn {
+        control_type: ControlType::Class,
+        recipient,
+        request,
+        value,
+        index,
+        length,
+    };
+    target
+        .control_in_blocking(request, CONTROL_TIMEOUT)
+        .map_err(|e| e.to_string())
+}
+
+fn control_out(
+    target: &ControlTarget,
+    recipient: Recipient,
+    value: u16,
+    index: u16,
+    data: &[u8],
+) -> std::result::Result<(), String> {
+    let request = ControlOut {
+        control_type: ControlType::Class,
+        recipient,
+        request: SET_CUR,
+        value,
+        index,
+        data,
+    };
+    target
+        .control_out_blocking(request, CONTROL_TIMEOUT)
+        .map_err(|e| e.to_string())
+}
+
+/// A USB Audio Class 1 or 2 function.
+#[napi]
+pub struct UacDevice {
+    shared: Arc<SharedDevice>,
+    layout: UacLayout,
+    detach: bool,
+    /// Streaming interfaces claimed so far, with the setting each is in
+    selected: Mutex<Vec<(u8, u8)>>,
+
+    #[napi(writable = false)]
+    pub controlInterface: u8,
+    /// bcdADC
+    #[napi(writable = false)]
+    pub version: u16,
+}
+
+impl UacDevice {
+    fn selected(&self) -> MutexGuard<'_, Vec<(u8, u8)>> {
+        self.selected
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn target(&self, method: &'static str) -> Result<ControlTarget> {
+        self.shared
+            .control_target(Recipient::Interface, self.layout.control as u16)
+            .ok_or_else(|| napi::Error::from_reason(format!("{method} error: invalid state")))
+    }
+
+    /// The selected setting of a streaming interface, or the only one claimed when none is named.
+    fn selected_setting(
+        &self,
+        method: &'static str,
+        interface_number: Option<u8>,
+    ) -> Result<UacAlternateSetting> {
+        let selected = self.selected();
+        let (interface, alt) = match interface_number {
+            Some(n) => selected.iter().find(|(i, _)| *i == n).copied(),
+            None if selected.len() == 1 => selected.first().copied(),
+            None => None,
+        }
+        .ok_or_else(|| napi::Error::from_reason(format!("{method} error: no format selected")))?;
+        self.layout
+            .settings
+            .iter()
+            .find(|s| s.interfaceNumber == interface && s.alternateSetting == alt)
+            .cloned()
+            .ok_or_else(|| napi::Error::from_reason(format!("{method} error: no format selected")))
+    }
+
+    /// Where the sampling frequency control of a setting lives: its endpoint for UAC1,
+    /// its clock source for UAC2.
+    fn rate_control(
+        &self,
+        setting: &UacAlternateSetting,
+    ) -> std::result::Result<(Recipient, u16, u16), String> {
+        if self.layout.uac2 {
+            let clock = clock_source(&self.layout.entities, setting.terminalLink)
+                .ok_or("no clock source found")?;
+            Ok((
+                Recipient::Interface,
+                SAMPLING_FREQ_CONTROL << 8,
+                (clock as u16) << 8 | self.layout.control as u16,
+            ))
+        } else {
+            Ok((
+                Recipient::Endpoint,
+                SAMPLING_FREQ_CONTROL << 8,
+                setting.endpoint as u16,
+            ))
+        }
+    }
+
+    async fn feature_get(
+        &self,
+        method: &'static str,
+        unit: u8,
+        selector: u16,
+        channel: u8,
+        length: u16,
+    ) -> Result<Vec<u8>> {
+        let target = self.target(method)?;
+        let request = if self.layout.uac2 { UAC2_CUR } else { GET_CUR };
+        let index = (unit as u16) << 8 | self.layout.control as u16;
+        run_blocking(move || {
+            control_in(
+                &target,
+                Recipient::Interface,
+                request,
+                selector << 8 | channel as u16,
+                index,
+                length,
+            )
+            .map_err(|e| format!("{method} error: {e}"))
+        })
+        .await
+    }
+
+    async fn feature_set(
+        &self,
+        method: &'static str,
+        unit: u8,
+        selector: u16,
+        channel: u8,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        let target = self.target(method)?;
+        let index = (unit as u16) << 8 | self.layout.control as u16;
+        run_blocking(move || {
+            control_out(
+                &target,
+                Recipient::Interface,
+                selector << 8 | channel as u16,
+                index,
+                &data,
+            )
+            .map_err(|e| format!("{method} error: {e}"))
+        })
+        .await
+    }
+}
+
+#[napi]
+impl UacDevice {
+    #[napi(getter)]
+    pub fn entities(&self) -> Vec<UacEntity> {
+        self.layout.entities.clone()
+    }
+
+    /// PCM alternate settings of the streaming interfaces.
+    #[napi(getter)]
+    pub fn alternateSettings(&self) -> Vec<UacAlternateSetting> {
+        self.layout.settings.clone()
+    }
+
+    /// Claims the streaming interface of the first setting matching the options, selects the setting and
+    /// sets the sample rate if given.
+    #[napi]
+    pub async fn selectFormat(&self, options: UacFormatOptions) -> Result<UacAlternateSetting> {
+        let setting = self
+            .layout
+            .settings
+            .iter()
+            .filter(|s| {
+                options
+                    .interfaceNumber
+                    .is_none_or(|n| s.interfaceNumber == n)
+            })
+            .filter(|s| options.direction.as_ref().is_none_or(|d| &s.direction == d))
+            .filter(|s| options.channels.is_none_or(|c| s.channels == c))
+            .filter(|s| options.bitResolution.is_none_or(|b| s.bitResolution == b))
+            .find(|s| {
+                options.sampleRate.is_none_or(|rate| {
+                    (s.sampleRates.is_empty() && s.sampleRateRanges.is_empty())
+                        || s.sampleRates.contains(&rate)
+                        || s.sampleRateRanges.iter().any(|r| {
+                            rate >= r.min
+                                && rate <= r.max
+                                && (r.step == 0 || (rate - r.min) % r.step == 0)
+                        })
+                })
+            })
+            .cloned()
+            .ok_or_else(|| napi::Error::from_reason("selectFormat error: no matching format"))?;
+
+        self.shared
+            .claim_interface("selectFormat", setting.interfaceNumber, self.detach)
+            .await?;
+        self.shared
+            .select_alternate_interface(
+                "selectFormat",
+                setting.interfaceNumber,
+                setting.alternateSetting,
+            )
+            .await?;
+        {
+            let mut selected = self.selected();
+            selected.retain(|(i, _)| *i != setting.interfaceNumber);
+            selected.push((setting.interfaceNumber, setting.alternateSetting));
+        }
+
+        if let Some(rate) = options.sampleRate {
+            self.setSampleRate(rate, Some(setting.interfaceNumber))
+                .await?;
+        }
+        Ok(setting)
+    }
+
+    /// Returns a streaming interface to its zero bandwidth setting 0.
+    #[napi]
+    pub async fn selectIdle(&self, interfaceNumber: u8) -> Result<()> {
+        self.shared
+            .select_alternate_interface("selectIdle", interfaceNumber, 0)
+            .await?;
+        self.selected().retain(|(i, _)| *i != interfaceNumber);
+        Ok(())
+    }
+
+    /// Sets the sample rate of a selected format, through its endpoint for UAC1 or its clock source for UAC2.
+    #[napi]
+    pub async fn setSampleRate(&self, rate: u32, interfaceNumber: Option<u8>) -> Result<()> {
+        let setting = self.selected_setting("setSampleRate", interfaceNumber)?;
+        let (recipient, value, index) = self
+            .rate_control(&setting)
+            .map_err(|e| napi::Error::from_reason(format!("setSampleRate error: {e}")))?;
+        let target = self
+            .shared
+            .control_target(recipient, index)
+            .ok_or_else(|| napi::Error::from_reason("setSampleRate error: invalid state"))?;
+        let data = if self.layout.uac2 {
+            rate.to_le_bytes().to_vec()
+        } else {
+            rate.to_le_bytes()[..3].to_vec()
+        };
+        run_blocking(move || {
+            control_out(&target, recipient, value, index, &data)
+                .map_err(|e| format!("setSampleRate error: {e}"))
+        })
+        .await
+    }
+
+    #[napi]
+    pub async fn getSampleRate(&self, interfaceNumber: Option<u8>) -> Result<u32> {
+        let setting = self.selected_setting("getSampleRate", interfaceNumber)?;
+        let (recipient, value, index) = self
+            .rate_control(&setting)
+            .map_err(|e| napi::Error::from_reason(format!("getSampleRate error: {e}")))?;
+        let target = self
+            .shared
+            .control_target(recipient, index)
+            .ok_or_else(|| napi::Error::from_reason("getSampleRate error: invalid state"))?;
+        let (request, length) = if self.layout.uac2 {
+            (UAC2_CUR, 4)
+        } else {
+            (GET_CUR, 3)
+        };
+        run_blocking(move || {
+            let data = control_in(&target, recipient, request, value, index, length)
+                .map_err(|e| format!("getSampleRate error: {e}"))?;
+            let mut bytes = [0u8; 4];
+            let len = data.len().min(4);
+            bytes[..len].copy_from_slice(&data[..len]);
+            Ok(u32::from_le_bytes(bytes))
+        })
+        .await
+    }
+
+    /// Volume of a feature unit channel in dB, channel 0 being the master channel.
+    #[napi]
+    pub async fn getVolume(&self, unitId: u8, channel: Option<u8>) -> Result<f64> {
+        let data = self
+            .feature_get(
+                "getVolume",
+                unitId,
+                FU_VOLUME_CONTROL,
+                channel.unwrap_or(0),
+                2,
+            )
+            .await?;
+        let [low, high, ..] = data[..] else {
+            return Err(napi::Error::from_reason("getVolume error: short response"));
+        };
+        Ok(i16::from_le_bytes([low, high]) as f64 / 256.0)
+    }
+
+    /// Sets the volume of a feature unit channel in dB, in steps of 1/256 dB.
+    #[napi]
+    pub async fn setVolume(&self, unitId: u8, volume: f64, channel: Option<u8>) -> Result<()> {
+        let value = (volume * 256.0)
+            .round()
+            .clamp(i16::MIN as f64, i16::MAX as f64) as i16;
+        self.feature_set(
+            "setVolume",
+            unitId,
+            FU_VOLUME_CONTROL,
+            channel.unwrap_or(0),
+            value.to_le_bytes().to_vec(),
+        )
+        .await
+    }
+
+    #[napi]
+    pub async fn getMute(&self, unitId: u8, channel: Option<u8>) -> Result<bool> {
+        let data = self
+            .feature_get("getMute", unitId, FU_MUTE_CONTROL, channel.unwrap_or(0), 1)
+            .await?;
+        Ok(data.first().is_some_and(|&mute| mute != 0))
+    }
+
+    #[napi]
+    pub async fn setMute(&self, unitId: u8, mute: bool, channel: Option<u8>) -> Result<()> {
+        self.feature_set(
+            "setMute",
+            unitId,
+            FU_MUTE_CONTROL,
+            channel.unwrap_or(0),
+            vec![mute as u8],
+        )
+        .await
+    }
+
+    /// Returns the streaming interfaces to setting 0 and releases all interfaces, reattaching any kernel driver.
+    #[napi]
+    pub async fn close(&self) -> Result<()> {
+        let selected = std::mem::take(&mut *self.selected());
+        for (interface, _) in selected {
+            // Best effort, the device may already be gone
+            let _ = self
+                .shared
+                .select_alternate_interface("close", interface, 0)
+                .await;
+            let _ = self.shared.release_interface("close", interface).await;
+        }
+        self.shared
+            .release_interface("close", self.layout.control)
+            .await
+    }
+}
+
+#[napi]
+impl UsbDevice {
+    /// Claims the AudioControl interface of a UAC1 or UAC2 function. Streaming interfaces are claimed by `selectFormat()`.
+    #[napi]
+    pub async fn openUac(&self, options: Option<UacOptions>) -> Result<UacDevice> {
+        let interface_number = options.as_ref().and_then(|o| o.interfaceNumber);
+        let detach = options
+            .as_ref()
+            .and_then(|o| o.detachKernelDriver)
+            .unwrap_or(true);
+
+        let shared = self.shared().clone();
+        let device = shared
+            .opened_device()
+            .ok_or_else(|| napi::Error::from_reason("openUac error: invalid state"))?;
+        let mut layout = run_blocking(move || {
+            let config = device
+                .active_configuration()
+                .map_err(|e| format!("openUac error: {e}"))?;
+            find_uac_layout(&config, interface_number).map_err(|e| format!("openUac error: {e}"))
+        })
+        .await?;
+
+        shared
+            .claim_interface("openUac", layout.control, detach)
+            .await?;
+
+        // UAC2 rates come from the clock sources rather than the format descriptors
+        if layout.uac2 {
+            let target = shared
+                .control_target(Recipient::Interface, layout.control as u16)
+                .ok_or_else(|| napi::Error::from_reason("openUac error: invalid state"))?;
+            let control = layout.control;
+            let mut clocks = layout
+                .settings
+                .iter()
+                .filter_map(|s| clock_source(&layout.entities, s.terminalLink))
+                .collect::<Vec<_>>();
+            clocks.sort_unstable();
+            clocks.dedup();
+            let ranges = run_blocking(move || {
+                Ok(clocks
+                    .into_iter()
+                    .map(|clock| {
+                        let index = (clock as u16) << 8 | control as u16;
+                        // Best effort, leaving the rates unknown where the clock won't say
+                        let ranges = read_range(&target, SAMPLING_FREQ_CONTROL, index)
+                            .map(|data| parse_ranges(&data))
+                            .unwrap_or_default();
+                        (clock, ranges)
+                    })
+                    .collect::<Vec<_>>())
+            })
+            .await?;
+
+            for setting in &mut layout.settings {
+                let Some(clock) = clock_source(&layout.entities, setting.terminalLink) else {
+                    continue;
+                };
+                let Some((_, clock_ranges)) = ranges.iter().find(|(c, _)| *c == clock) else {
+                    continue;
+                };
+                for range in clock_ranges {
+                    if range.min == range.max {
+                        setting.sampleRates.push(range.min);
+                    } else {
+                        setting.sampleRateRanges.push(range.clone());
+                    }
+                }
+                setting.sampleRates.sort_unstable();
+                setting.sampleRates.dedup();
+            }
+        }
+
+        Ok(UacDevice {
+            shared,
+            controlInterface: layout.control,
+            version: layout.version,
+            layout,
+            detach,
+            selected: Mutex::new(Vec::new()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(entity: UacEntity) -> (u8, String, Option<u16>, Vec<u8>, Option<u8>) {
+        (
+            entity.id,
+            entity._type,
+            entity.terminalType,
+            entity.sources,
+            entity.clockSource,
+        )
+    }
+
+    /// A UAC2 microphone: input terminal, feature unit, USB streaming output terminal, and a
+    /// clock selector in front of two clock sources.
+    fn uac2_entities() -> Vec<UacEntity> {
+        let descriptors: [&[u8]; 6] = [
+            &[
+                17, 0x24, 0x02, 1, 0x01, 0x02, 0, 6, 2, 3, 0, 0, 0, 0, 0, 0, 0,
+            ],
+            &[14, 0x24, 0x06, 2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            &[12, 0x24, 0x03, 3, 0x01, 0x01, 0, 2, 6, 0, 0, 0],
+            &[8, 0x24, 0x0a, 5, 0x03, 0x07, 0, 0],
+            &[8, 0x24, 0x0a, 7, 0x01, 0x07, 0, 0],
+            &[9, 0x24, 0x0b, 6, 2, 5, 7, 0x03, 0],
+        ];
+        descriptors
+            .iter()
+            .map(|d| parse_entity(d, true).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn parses_uac2_entities() {
+        let entities: Vec<_> = uac2_entities().into_iter().map(summary).collect();
+        assert_eq!(
+            entities,
+            [
+                (1, "inputTerminal".into(), Some(0x0201), vec![], Some(6)),
+                (2, "featureUnit".into(), None, vec![1], None),
+                (3, "outputTerminal".into(), Some(0x0101), vec![2], Some(6)),
+                (5, "clockSource".into(), None, vec![], None),
+                (7, "clockSource".into(), None, vec![], None),
+                (6, "clockSelector".into(), None, vec![5, 7], None),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_uac1_entities() {
+        let input = [12, 0x24, 0x02, 1, 0x01, 0x02, 0, 2, 3, 0, 0, 0];
+        assert_eq!(
+            summary(parse_entity(&input, false).unwrap()),
+            (1, "inputTerminal".into(), Some(0x0201), vec![], None)
+        );
+        let mixer = [13, 0x24, 0x04, 4, 2, 1, 2, 2, 3, 0, 0, 0, 0];
+        assert_eq!(
+            summary(parse_entity(&mixer, false).unwrap()),
+            (4, "mixerUnit".into(), None, vec![1, 2], None)
+        );
+        // Clock entities only exist in UAC2, and short terminals are skipped
+        assert!(parse_entity(&[8, 0x24, 0x0a, 5, 0x03, 0x07, 0, 0], false).is_none());
+        assert!(parse_entity(&input[..11], false).is_none());
+        assert!(parse_entity(&[6, 0x24, 0x05, 4, 3, 1], false).is_none());
+    }
+
+    #[test]
+    fn follows_a_terminal_clock_to_its_source() {
+        let mut entities = uac2_entities();
+        assert_eq!(clock_source(&entities, 1), Some(5));
+        assert_eq!(clock_source(&entities, 3), Some(5));
+        assert_eq!(clock_source(&entities, 2), None);
+
+        // A selector feeding itself never reaches a source
+        entities[5].sources = vec![6];
+        assert_eq!(clock_source(&entities, 1), None);
+    }
+
+    #[test]
+    fn parses_sample_rate_ranges() {
+        let mut data = vec![2, 0];
+        for value in [44100u32, 44100, 0, 8000, 96000, 8000] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        let ranges: Vec<_> = parse_ranges(&data)
+            .iter()
+            .map(|r| (r.min, r.max, r.step))
+            .collect();
+        assert_eq!(ranges, [(44100, 44100, 0), (8000, 96000, 8000)]);
+        assert!(parse_ranges(&data[..13]).is_empty());
+        assert!(parse_ranges(&[]).is_empty());
+    }
+}
@@ -0,0 +1,483 @@
+use crate::webusb_device::{run_blocking, UsbDevice};
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+
+const DEFAULT_MODE: &str = "0660";
+const DEFAULT_GROUP: &str = "plugdev";
+
+#[napi(object)]
+pub struct UdevRuleOptions {
+    /// Permission bits for the device node, e.g. `0660`
+    pub mode: Option<String>,
+    /// Group owning the device node, e.g. `plugdev`
+    pub group: Option<String>,
+    /// Grant access to the logged in user with `TAG+="uaccess"` instead of MODE/GROUP
+    pub uaccess: Option<bool>,
+    /// Match the serial number as well as vendor and product
+    pub serialNumber: Option<bool>,
+}
+
+#[napi(object)]
+pub struct UdevRuleFilter {
+    pub vendorId: u16,
+    pub productId: Option<u16>,
+    pub serialNumber: Option<String>,
+}
+
+#[napi(object)]
+pub struct UdevRule {
+    #[napi(writable = false)]
+    pub rule: String,
+    #[napi(writable = false)]
+    pub fileName: String,
+    #[napi(writable = false)]
+    pub sysfsPath: Option<String>,
+    #[napi(writable = false)]
+    pub matchesDevice: Option<bool>,
+    #[napi(writable = false)]
+    pub mismatches: Vec<String>,
+    #[napi(writable = false)]
+    pub hasAccess: bool,
+    #[napi(writable = false)]
+    pub currentlyHasAccess: Option<bool>,
+    #[napi(writable = false)]
+    pub explanation: String,
+}
+
+enum Permission {
+    Uaccess,
+    Mode { mode: u32, group: Option<String> },
+}
+
+struct RuleSpec {
+    vendor_id: u16,
+    product_id: Option<u16>,
+    serial_number: Option<String>,
+    permission: Permission,
+}
+
+/// Puts udev's glob characters in brackets, so a serial like `ABC*` only matches itself.
+fn escape_glob(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '*' | '?' | '[') {
+            escaped.push('[');
+            escaped.push(c);
+            escaped.push(']');
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+impl RuleSpec {
+    fn new(
+        vendor_id: u16,
+        product_id: Option<u16>,
+        serial_number: Option<String>,
+        options: Option<UdevRuleOptions>,
+    ) -> std::result::Result<Self, String> {
+        let options = options.unwrap_or(UdevRuleOptions {
+            mode: None,
+            group: None,
+            uaccess: None,
+            serialNumber: None,
+        });
+
+        if let Some(serial) = &serial_number {
+            // `|` separates alternative patterns, so it can't be matched literally
+            if serial.is_empty() || serial.contains(['"', '\\', '\n', '|']) {
+                return Err("udevRule error: invalid serial number".to_string());
+            }
+        }
+
+        let permission = if options.uaccess.unwrap_or(false) {
+            if options.mode.is_some() || options.group.is_some() {
+                return Err("udevRule error: uaccess cannot be combined with mode or group".into());
+            }
+            Permission::Uaccess
+        } else {
+            let mode = options.mode.as_deref().unwrap_or(DEFAULT_MODE);
+            let mode = u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+                .ok()
+                .filter(|mode| *mode <= 0o777)
+                .ok_or_else(|| format!("udevRule error: invalid mode {mode}"))?;
+            let group = match options.group {
+                Some(group) if group.is_empty() => None,
+                Some(group) => {
+                    if !group
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+                    {
+                        return Err(format!("udevRule error: invalid group {group}"));
+                    }
+                    Some(group)
+                }
+                None => Some(DEFAULT_GROUP.to_string()),
+            };
+            Permission::Mode { mode, group }
+        };
+
+        Ok(Self {
+            vendor_id,
+            product_id,
+            serial_number,
+            permission,
+        })
+    }
+
+    fn rule(&self) -> String {
+        let mut rule = format!(
+            "SUBSYSTEM==\"usb\", ATTR{{idVendor}}==\"{:04x}\"",
+            self.vendor_id
+        );
+        if let Some(product_id) = self.product_id {
+            rule += &format!(", ATTR{{idProduct}}==\"{product_id:04x}\"");
+        }
+        if let Some(serial) = &self.serial_number {
+            rule += &format!(", ATTR{{serial}}==\"{}\"", escape_glob(serial));
+        }
+        match &self.permission {
+            Permission::Uaccess => rule += ", TAG+=\"uaccess\"",
+            Permission::Mode { mode, group } => {
+                rule += &format!(", MODE=\"{mode:04o}\"");
+                if let Some(group) = group {
+                    rule += &format!(", GROUP=\"{group}\"");
+                }
+            }
+        }
+        rule
+    }
+
+    fn file_name(&self) -> String {
+        // uaccess tags have to be applied before 73-seat-late.rules acts on them
+        let priority = match self.permission {
+            Permission::Uaccess => 70,
+            Permission::Mode { .. } => 50,
+        };
+        let mut name = format!("{priority}-usb-{:04x}", self.vendor_id);
+        if let Some(product_id) = self.product_id {
+            name += &format!("-{product_id:04x}");
+        }
+        // Rules for devices told apart only by serial need files of their own
+        if let Some(serial) = &self.serial_number {
+            name.push('-');
+            name.extend(serial.chars().map(|c| {
+                if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                    c
+                } else {
+                    '_'
+                }
+            }));
+        }
+        name + ".rules"
+    }
+
+    fn build(&self, device: Option<&nusb::DeviceInfo>) -> UdevRule {
+        let mut result = UdevRule {
+            rule: self.rule(),
+            fileName: self.file_name(),
+            sysfsPath: None,
+            matchesDevice: None,
+            mismatches: Vec::new(),
+            hasAccess: false,
+            currentlyHasAccess: None,
+            explanation: String::new(),
+        };
+
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(device_info) = device {
+                self.validate(device_info, &mut result);
+            }
+            let (has_access, explanation) = self.access();
+            result.hasAccess = has_access;
+            result.explanation = explanation;
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = device;
+            result.explanation = "udev rules only apply on Linux".to_string();
+        }
+
+        result
+    }
+
+    #[cfg(target_os = "linux")]
+    fn validate(&self, device_info: &nusb::DeviceInfo, result: &mut UdevRule) {
+        let sysfs_path = device_info.sysfs_path();
+        let attr = |name: &str| {
+            std::fs::read_to_string(sysfs_path.join(name))
+                .ok()
+                .map(|value| value.trim().to_string())
+        };
+
+        let mut expect = |name: &str, expected: String| match attr(name) {
+            Some(actual) if actual == expected => {}
+            Some(actual) => result.mismatches.push(format!(
+                "{name} is \"{actual}\" but the rule expects \"{expected}\""
+            )),
+            None => result
+                .mismatches
+                .push(format!("{name} is missing from {}", sysfs_path.display())),
+        };
+
+        expect("idVendor", format!("{:04x}", self.vendor_id));
+        if let Some(product_id) = self.product_id {
+            expect("idProduct", format!("{product_id:04x}"));
+        }
+        if let Some(serial) = &self.serial_number {
+            expect("serial", serial.clone());
+        }
+
+        result.sysfsPath = Some(sysfs_path.display().to_string());
+        result.matchesDevice = Some(result.mismatches.is_empty());
+
+        let node = format!(
+            "/dev/bus/usb/{:03}/{:03}",
+            device_info.busnum(),
+            device_info.device_address()
+        );
+        result.currentlyHasAccess = std::fs::metadata(node).ok().map(|metadata| {
+            use std::os::unix::fs::MetadataExt;
+            let user = access::User::current();
+            user.can_read_write(metadata.mode(), metadata.uid(), metadata.gid())
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    fn access(&self) -> (bool, String) {
+        let user = access::User::current();
+        if user.uid == Some(0) {
+            return (
+                true,
+                "running as root, which can access every device".to_string(),
+            );
+        }
+
+        match &self.permission {
+            Permission::Uaccess => match access::active_seat_session() {
+                Some(seat) => (
+                    true,
+                    format!("uaccess grants the user of the active session on {seat}, which is this session"),
+                ),
+                None => (
+                    false,
+                    "uaccess only grants the user of an active local seat session and this process is not in one".to_string(),
+                ),
+            },
+            Permission::Mode { mode, group } => {
+                if mode & 0o006 == 0o006 {
+                    return (true, format!("mode {mode:04o} grants read/write to every user"));
+                }
+                let Some(group) = group else {
+                    return (
+                        false,
+                        format!("mode {mode:04o} only grants read/write to root"),
+                    );
+                };
+                if mode & 0o060 != 0o060 {
+                    return (
+                        false,
+                        format!("mode {mode:04o} does not grant read/write to group {group}"),
+                    );
+                }
+                match access::group_id(group) {
+                    None => (
+                        false,
+                        format!("group {group} does not exist, create it and add the user to it"),
+                    ),
+                    Some(gid) if user.gids.contains(&gid) => {
+                        (true, format!("the user is in group {group}"))
+                    }
+                    Some(_) if access::group_lists_user(group, user.name.as_deref()) => (
+                        false,
+                        format!("the user was added to group {group} but has to log in again for it to apply"),
+                    ),
+                    Some(_) => (
+                        false,
+                        format!("the user is not in group {group}, add them with `usermod -aG {group} <user>`"),
+                    ),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod access {
+    pub(super) struct User {
+        pub(super) uid: Option<u32>,
+        pub(super) gids: Vec<u32>,
+        pub(super) name: Option<String>,
+    }
+
+    impl User {
+        pub(super) fn current() -> Self {
+            let status = std::fs::read_to_string("/proc/self/status").unwrap_or_default();
+            let field = |name: &str| {
+                status
+                    .lines()
+                    .find_map(|line| line.strip_prefix(name))
+                    .map(|values| {
+                        values
+                            .split_whitespace()
+                            .filter_map(|value| value.parse::<u32>().ok())
+                            .collect::<Vec<_>>()
+                    })
+                    .unwrap_or_default()
+            };
+
+            // Effective ids decide access, they are the second column
+            let uid = field("Uid:").get(1).copied();
+            let mut gids = field("Groups:");
+            gids.extend(field("Gid:").get(1));
+
+            let name = uid.and_then(|uid| {
+                std::fs::read_to_string("/etc/passwd")
+                    .ok()?
+                    .lines()
+                    .map(|line| line.split(':').collect::<Vec<_>>())
+                    .find(|fields| fields.get(2) == Some(&uid.to_string().as_str()))
+                    .map(|fields| fields[0].to_string())
+            });
+
+            Self { uid, gids, name }
+        }
+
+        pub(super) fn can_read_write(&self, mode: u32, owner: u32, group: u32) -> bool {
+            if self.uid == Some(0) {
+                return true;
+            }
+            if self.uid == Some(owner) {
+                return mode & 0o600 == 0o600;
+            }
+            if self.gids.contains(&group) {
+                return mode & 0o060 == 0o060;
+            }
+            mode & 0o006 == 0o006
+        }
+    }
+
+    fn group_entry(group: &str) -> Option<Vec<String>> {
+        std::fs::read_to_string("/etc/group")
+            .ok()?
+            .lines()
+            .map(|line| line.split(':').map(str::to_string).collect::<Vec<_>>())
+            .find(|fields| fields.first().map(String::as_str) == Some(group))
+    }
+
+    pub(super) fn group_id(group: &str) -> Option<u32> {
+        group_entry(group)?.get(2)?.parse().ok()
+    }
+
+    pub(super) fn group_lists_user(group: &str, user: Option<&str>) -> bool {
+        let (Some(entry), Some(user)) = (group_entry(group), user) else {
+            return false;
+        };
+        entry
+            .get(3)
+            .is_some_and(|members| members.split(',').any(|member| member == user))
+    }
+
+    pub(super) fn active_seat_session() -> Option<String> {
+        let session = std::env::var("XDG_SESSION_ID").ok()?;
+        let state = std::fs::read_to_string(format!("/run/systemd/sessions/{session}")).ok()?;
+        let value = |name: &str| {
+            state
+                .lines()
+                .find_map(|line| line.strip_prefix(name))
+                .map(str::to_string)
+        };
+
+        if value("ACTIVE=").as_deref() != Some("1") {
+            return None;
+        }
+        value("SEAT=").filter(|seat| !seat.is_empty())
+    }
+}
+
+#[napi]
+impl UsbDevice {
+    #[napi]
+    pub async fn udevRule(&self, options: Option<UdevRuleOptions>) -> Result<UdevRule> {
+        let device_info = self.device_info().clone();
+        let serial_number = match &options {
+            Some(UdevRuleOptions {
+                serialNumber: Some(true),
+                ..
+            }) => Some(
+                device_info
+                    .serial_number()
+                    .map(str::to_string)
+                    .ok_or_else(|| {
+                        napi::Error::from_reason("udevRule error: device has no serial number")
+                    })?,
+            ),
+            _ => None,
+        };
+        let spec = RuleSpec::new(
+            device_info.vendor_id(),
+            Some(device_info.product_id()),
+            serial_number,
+            options,
+        )
+        .map_err(napi::Error::from_reason)?;
+
+        run_blocking(move || Ok(spec.build(Some(&device_info)))).await
+    }
+}
+
+pub(crate) fn rule_for_filter(
+    filter: UdevRuleFilter,
+    options: Option<UdevRuleOptions>,
+) -> std::result::Result<UdevRule, String> {
+    let spec = RuleSpec::new(
+        filter.vendorId,
+        filter.productId,
+        filter.serialNumber,
+        options,
+    )?;
+    Ok(spec.build(None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serial_globs_match_literally() {
+        let spec = RuleSpec::new(0x1234, Some(0x5678), Some("A*B?[C]".to_string()), None).unwrap();
+        assert_eq!(
+            spec.rule(),
+            "SUBSYSTEM==\"usb\", ATTR{idVendor}==\"1234\", ATTR{idProduct}==\"5678\", \
+             ATTR{serial}==\"A[*]B[?][[]C]\", MODE=\"0660\", GROUP=\"plugdev\""
+        );
+    }
+
+    #[test]
+    fn serial_alternatives_are_rejected() {
+        assert!(RuleSpec::new(0x1234, None, Some("A|B".to_string()), None).is_err());
+        assert!(RuleSpec::new(0x1234, None, Some("A\"B".to_string()), None).is_err());
+    }
+
+    #[test]
+    fn file_names_include_the_serial() {
+        let file_name = |product_id, serial: Option<&str>| {
+            RuleSpec::new(0x1234, product_id, serial.map(str::to_string), None)
+                .unwrap()
+                .file_name()
+        };
+        assert_eq!(file_name(None, None), "50-usb-1234.rules");
+        assert_eq!(file_name(Some(0x5678), None), "50-usb-1234-5678.rules");
+        assert_eq!(
+            file_name(Some(0x5678), Some("A1/B 2*")),
+            "50-usb-1234-5678-A1_B_2_.rules"
+        );
+        assert_ne!(
+            file_name(Some(0x5678), Some("0001")),
+            file_name(Some(0x5678), Some("0002"))
+        );
+    }
+}
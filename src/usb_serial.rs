@@ -0,0 +1,526 @@
+mod ch34x;
+mod cp210x;
+mod ftdi;
+mod pl2303;
+
+use crate::endpoint_stream::{InStream, StreamSlot};
+use crate::serial::{LineSettings, SerialInputSignals, SerialOptions, SerialOutputSignals};
+use crate::transfer_pool::pooled_transfer_out;
+use crate::webusb_device::{run_blocking, SharedDevice, UsbDevice};
+use napi::{
+    bindgen_prelude::*, threadsafe_function::ThreadsafeFunction,
+    threadsafe_function::ThreadsafeFunctionCallMode,
+};
+use napi_derive::napi;
+use nusb::{
+    descriptors::{ConfigurationDescriptor, TransferType},
+    transfer::{ControlIn, ControlOut, ControlType, Direction, Recipient},
+};
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+const CONTROL_TIMEOUT: Duration = Duration::from_millis(1000);
+const READ_TRANSFERS: usize = 4;
+const DEFAULT_BUFFER_SIZE: u32 = 255;
+const DEFAULT_BAUD_RATE: u32 = 9600;
+
+type DataCallback = ThreadsafeFunction<Uint8Array, (), Uint8Array, napi::Status, false>;
+type EndCallback = ThreadsafeFunction<Option<String>, (), Option<String>, napi::Status, false>;
+
+/// Control requests for a bridge chip, run from blocking threads.
+pub(crate) struct SerialBridge {
+    shared: Arc<SharedDevice>,
+    /// The claimed interface, which is also the port on multi-port chips
+    pub(crate) interface: u8,
+    /// `bcdDevice`, which tells chip generations apart
+    pub(crate) device_version: u16,
+}
+
+impl SerialBridge {
+    pub(crate) fn control_out(
+        &self,
+        control_type: ControlType,
+        recipient: Recipient,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: &[u8],
+    ) -> std::result::Result<(), String> {
+        let target = self
+            .shared
+            .control_target(recipient, self.interface as u16)
+            .ok_or("invalid state")?;
+        target
+            .control_out_blocking(
+                ControlOut {
+                    control_type,
+                    recipient,
+                    request,
+                    value,
+                    index,
+                    data,
+                },
+                CONTROL_TIMEOUT,
+            )
+            .map_err(|e| format!("request {request:#04x} failed: {e}"))
+    }
+
+    pub(crate) fn control_in(
+        &self,
+        control_type: ControlType,
+        recipient: Recipient,
+        request: u8,
+        value: u16,
+        index: u16,
+        length: u16,
+    ) -> std::result::Result<Vec<u8>, String> {
+        let target = self
+            .shared
+            .control_target(recipient, self.interface as u16)
+            .ok_or("invalid state")?;
+        let data = target
+            .control_in_blocking(
+                ControlIn {
+                    control_type,
+                    recipient,
+                    request,
+                    value,
+                    index,
+                    length,
+                },
+                CONTROL_TIMEOUT,
+            )
+            .map_err(|e| format!("request {request:#04x} failed: {e}"))?;
+        if data.len() < length as usize {
+            return Err(format!("short response to request {request:#04x}"));
+        }
+        Ok(data)
+    }
+}
+
+/// A USB-serial bridge chip family. Each call runs on a blocking thread.
+pub(crate) trait SerialDriver: Send + Sync {
+    /// Brings the chip up after the interface is claimed.
+    fn init(&self, _bridge: &SerialBridge) -> std::result::Result<(), String> {
+        Ok(())
+    }
+
+    fn set_line(
+        &self,
+        bridge: &SerialBridge,
+        settings: &LineSettings,
+    ) -> std::result::Result<(), String>;
+
+    fn set_control_lines(
+        &self,
+        bridge: &SerialBridge,
+        dtr: bool,
+        rts: bool,
+    ) -> std::result::Result<(), String>;
+
+    fn set_break(&self, bridge: &SerialBridge, on: bool) -> std::result::Result<(), String>;
+
+    fn read_signals(
+        &self,
+        bridge: &SerialBridge,
+    ) -> std::result::Result<SerialInputSignals, String>;
+
+    /// Handles a packet from the interrupt IN endpoint, for chips reporting modem status there.
+    fn on_notification(&self, _data: &[u8]) {}
+
+    /// Appends the payload of a bulk IN transfer, dropping any chip specific framing.
+    fn received(&self, data: &[u8], _packet_size: usize, out: &mut Vec<u8>) {
+        out.extend_from_slice(data);
+    }
+}
+
+/// Picks the driver for a known bridge from its vendor and product IDs.
+fn driver_name_for(vendor_id: u16, product_id: u16) -> Option<&'static str> {
+    match (vendor_id, product_id) {
+        (ftdi::VENDOR_ID, id) if ftdi::PRODUCT_IDS.contains(&id) => Some("ftdi"),
+        (cp210x::VENDOR_ID, id) if cp210x::PRODUCT_IDS.contains(&id) => Some("cp210x"),
+        (ch34x::VENDOR_ID, id) if ch34x::PRODUCT_IDS.contains(&id) => Some("ch34x"),
+        (pl2303::VENDOR_ID, id) if pl2303::PRODUCT_IDS.contains(&id) => Some("pl2303"),
+        _ => None,
+    }
+}
+
+fn driver(name: &str) -> std::result::Result<Arc<dyn SerialDriver>, String> {
+    match name {
+        "ftdi" => Ok(Arc::new(ftdi::Ftdi::default())),
+        "cp210x" => Ok(Arc::new(cp210x::Cp210x)),
+        "ch34x" => Ok(Arc::new(ch34x::Ch34x)),
+        "pl2303" => Ok(Arc::new(pl2303::Pl2303::default())),
+        name => Err(format!("unknown driver {name}")),
+    }
+}
+
+#[napi(object)]
+pub struct UsbSerialOptions {
+    /// Driver to use instead of picking one from the vendor and product IDs
+    #[napi(ts_type = "'ftdi' | 'cp210x' | 'ch34x' | 'pl2303'")]
+    pub driver: Option<String>,
+    /// The interface to use, defaults to the first with a bulk endpoint pair (the port on multi-port chips)
+    pub interfaceNumber: Option<u8>,
+    /// Detach kernel drivers (e.g. `ftdi_sio`) while the port is open, defaults to `true`
+    pub detachKernelDriver: Option<bool>,
+    /// Defaults to 9600
+    pub baudRate: Option<u32>,
+    #[napi(ts_type = "5 | 6 | 7 | 8")]
+    pub dataBits: Option<u8>,
+    #[napi(ts_type = "1 | 1.5 | 2")]
+    pub stopBits: Option<f64>,
+    #[napi(ts_type = "SerialParity")]
+    pub parity: Option<String>,
+    #[napi(ts_type = "SerialFlowControl")]
+    pub flowControl: Option<String>,
+}
+
+/// Where the port lives in the active configuration.
+struct BridgeLayout {
+    interface: u8,
+    bulk_in: u8,
+    bulk_out: u8,
+    notify: Option<u8>,
+}
+
+fn find_bridge_layout(
+    config: &ConfigurationDescriptor,
+    interface_number: Option<u8>,
+) -> std::result::Result<BridgeLayout, String> {
+    config
+        .interface_alt_settings()
+        .filter(|alt| alt.alternate_setting() == 0)
+        .filter(|alt| interface_number.is_none_or(|n| alt.interface_number() == n))
+        .find_map(|alt| {
+            let endpoint = |direction, transfer_type| {
+                alt.endpoints()
+                    .find(|ep| ep.direction() == direction && ep.transfer_type() == transfer_type)
+                    .map(|ep| ep.address() & 0x7f)
+            };
+            Some(BridgeLayout {
+                interface: alt.interface_number(),
+                bulk_in: endpoint(Direction::In, TransferType::Bulk)?,
+                bulk_out: endpoint(Direction::Out, TransferType::Bulk)?,
+                notify: endpoint(Direction::In, TransferType::Interrupt),
+            })
+        })
+        .ok_or_else(|| "no interface with bulk endpoints found".to_string())
+}
+
+struct PortState {
+    line: LineSettings,
+    dtr: bool,
+    rts: bool,
+    notifications: Option<InStream>,
+}
+
+/// A vendor USB-serial bridge (FTDI, CP210x, CH34x or PL2303) driven directly, without the OS serial driver.
+#[napi]
+pub struct UsbSerialPort {
+    shared: Arc<SharedDevice>,
+    bridge: Arc<SerialBridge>,
+    driver: Arc<dyn SerialDriver>,
+    layout: BridgeLayout,
+    state: Mutex<PortState>,
+    reader: StreamSlot,
+
+    #[napi(
+        writable = false,
+        js_name = "driver",
+        ts_type = "'ftdi' | 'cp210x' | 'ch34x' | 'pl2303'"
+    )]
+    pub driverName: String,
+    #[napi(writable = false)]
+    pub interfaceNumber: u8,
+}
+
+impl UsbSerialPort {
+    fn state(&self) -> MutexGuard<'_, PortState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    async fn run<T, F>(&self, method: &'static str, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn SerialDriver, &SerialBridge) -> std::result::Result<T, String>
+            + Send
+            + 'static,
+    {
+        let driver = self.driver.clone();
+        let bridge = self.bridge.clone();
+        run_blocking(move || {
+            f(driver.as_ref(), &bridge).map_err(|e| format!("{method} error: {e}"))
+        })
+        .await
+    }
+
+    async fn set_control_lines(&self, method: &'static str, dtr: bool, rts: bool) -> Result<()> {
+        self.run(method, move |driver, bridge| {
+            driver.set_control_lines(bridge, dtr, rts)
+        })
+        .await?;
+        let mut state = self.state();
+        state.dtr = dtr;
+        state.rts = rts;
+        Ok(())
+    }
+
+    fn start_notifications(&self) {
+        let Some(notify) = self.layout.notify else {
+            return;
+        };
+        let Some(endpoint) = self.shared.get_endpoint::<nusb::transfer::In>(notify) else {
+            return;
+        };
+        let driver = self.driver.clone();
+        let stream = InStream::start(
+            endpoint,
+            16,
+            1,
+            move |data| driver.on_notification(data),
+            |_| {},
+        );
+        self.state().notifications = Some(stream);
+    }
+}
+
+#[napi]
+impl UsbSerialPort {
+    #[napi]
+    pub async fn setLineCoding(&self, options: SerialOptions) -> Result<()> {
+        let settings = LineSettings::from_options(&options)
+            .map_err(|e| napi::Error::from_reason(format!("setLineCoding error: {e}")))?;
+        self.run("setLineCoding", move |driver, bridge| {
+            driver.set_line(bridge, &settings)
+        })
+        .await?;
+        self.state().line = settings;
+        Ok(())
+    }
+
+    /// The line settings last applied, as the bridges can't report them back.
+    #[napi]
+    pub fn getLineCoding(&self) -> SerialOptions {
+        self.state().line.to_options()
+    }
+
+    /// Sets DTR and RTS, and starts or ends a break. Signals left out are unchanged.
+    #[napi]
+    pub async fn setSignals(&self, signals: SerialOutputSignals) -> Result<()> {
+        if signals.dataTerminalReady.is_some() || signals.requestToSend.is_some() {
+            let (dtr, rts) = {
+                let state = self.state();
+                (
+                    signals.dataTerminalReady.unwrap_or(state.dtr),
+                    signals.requestToSend.unwrap_or(state.rts),
+                )
+            };
+            self.set_control_lines("setSignals", dtr, rts).await?;
+        }
+        if let Some(on) = signals._break {
+            self.run("setSignals", move |driver, bridge| {
+                driver.set_break(bridge, on)
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
+    #[napi]
+    pub async fn getSignals(&self) -> Result<SerialInputSignals> {
+        self.run("getSignals", |driver, bridge| driver.read_signals(bridge))
+            .await
+    }
+
+    #[napi(js_name = "nativeWrite")]
+    pub async fn write(&self, data: Uint8Array, timeout: u32) -> Result<u32> {
+        let mut endpoint = self
+            .shared
+            .get_endpoint::<nusb::transfer::Out>(self.layout.bulk_out)
+            .ok_or_else(|| napi::Error::from_reason("write error: invalid state"))?;
+        let shared = self.shared.clone();
+        run_blocking(move || {
+            let result = pooled_transfer_out(
+                &shared,
+                &mut endpoint,
+                &data,
+                Duration::from_millis(timeout as u64),
+            )
+            .map(|len| len as u32)
+            .map_err(|e| format!("write error: {e:?}"));
+            shared.put_endpoint(endpoint);
+            result
+        })
+        .await
+    }
+
+    #[napi(js_name = "nativeStartReading")]
+    pub fn startReading(
+        &self,
+        bufferSize: Option<u32>,
+        onData: DataCallback,
+        onEnd: EndCallback,
+    ) -> Result<()> {
+        let driver = self.driver.clone();
+        self.reader.start(
+            "startReading",
+            &self.shared,
+            self.layout.bulk_in,
+            |endpoint| {
+                let packet_size = endpoint.max_packet_size();
+                InStream::start(
+                    endpoint,
+                    bufferSize.unwrap_or(DEFAULT_BUFFER_SIZE) as usize,
+                    READ_TRANSFERS,
+                    move |data| {
+                        let mut payload = Vec::with_capacity(data.len());
+                        driver.received(data, packet_size, &mut payload);
+                        if !payload.is_empty() {
+                            onData.call(
+                                Uint8Array::new(payload),
+                                ThreadsafeFunctionCallMode::NonBlocking,
+                            );
+                        }
+                    },
+                    move |error| {
+                        onEnd.call(error, ThreadsafeFunctionCallMode::NonBlocking);
+                    },
+                )
+            },
+        )
+    }
+
+    #[napi]
+    pub fn stopReading(&self) {
+        self.reader.stop();
+    }
+
+    /// Stops reading from the device until `resumeReading()`, leaving it to hold on to the data.
+    #[napi]
+    pub fn pauseReading(&self) {
+        self.reader.pause();
+    }
+
+    #[napi]
+    pub fn resumeReading(&self) {
+        self.reader.resume();
+    }
+
+    /// Stops reading, drops DTR/RTS and releases the interface, reattaching any kernel driver.
+    #[napi]
+    pub async fn close(&self) -> Result<()> {
+        // Their endpoints keep the interface claimed until the streams have ended
+        self.reader.finish().await;
+        let notifications = self.state().notifications.take();
+        if let Some(stream) = notifications {
+            stream.finish().await;
+        }
+
+        // Best effort, the device may already be gone
+        let _ = self.set_control_lines("close", false, false).await;
+
+        self.shared
+            .release_interface("close", self.layout.interface)
+            .await
+    }
+}
+
+#[napi]
+impl UsbDevice {
+    /// The vendor serial driver `openUsbSerial()` would pick for this device, if any.
+    #[napi(
+        getter,
+        ts_return_type = "'ftdi' | 'cp210x' | 'ch34x' | 'pl2303' | undefined"
+    )]
+    pub fn usbSerialDriver(&self) -> Option<String> {
+        driver_name_for(self.vendorId, self.productId).map(str::to_string)
+    }
+
+    /// Claims a vendor USB-serial bridge and returns a port for it, with DTR and RTS raised.
+    #[napi]
+    pub async fn openUsbSerial(&self, options: Option<UsbSerialOptions>) -> Result<UsbSerialPort> {
+        let options = options.unwrap_or(UsbSerialOptions {
+            driver: None,
+            interfaceNumber: None,
+            detachKernelDriver: None,
+            baudRate: None,
+            dataBits: None,
+            stopBits: None,
+            parity: None,
+            flowControl: None,
+        });
+        let line = LineSettings::from_options(&SerialOptions {
+            baudRate: options.baudRate.unwrap_or(DEFAULT_BAUD_RATE),
+            dataBits: options.dataBits,
+            stopBits: options.stopBits,
+            parity: options.parity.clone(),
+            flowControl: options.flowControl.clone(),
+        })
+        .map_err(|e| napi::Error::from_reason(format!("openUsbSerial error: {e}")))?;
+
+        let driver_name = match options.driver.as_deref() {
+            Some(name) => name,
+            None => driver_name_for(self.vendorId, self.productId).ok_or_else(|| {
+                napi::Error::from_reason("openUsbSerial error: no driver for this device")
+            })?,
+        }
+        .to_string();
+        let driver = driver(&driver_name)
+            .map_err(|e| napi::Error::from_reason(format!("openUsbSerial error: {e}")))?;
+
+        let shared = self.shared().clone();
+        let device = shared
+            .opened_device()
+            .ok_or_else(|| napi::Error::from_reason("openUsbSerial error: invalid state"))?;
+        let interface_number = options.interfaceNumber;
+        let layout = run_blocking(move || {
+            let config = device
+                .active_configuration()
+                .map_err(|e| format!("openUsbSerial error: {e}"))?;
+            find_bridge_layout(&config, interface_number)
+                .map_err(|e| format!("openUsbSerial error: {e}"))
+        })
+        .await?;
+
+        let detach = options.detachKernelDriver.unwrap_or(true);
+        shared
+            .claim_interface("openUsbSerial", layout.interface, detach)
+            .await?;
+
+        let port = UsbSerialPort {
+            bridge: Arc::new(SerialBridge {
+                shared: shared.clone(),
+                interface: layout.interface,
+                device_version: self.device_info().device_version(),
+            }),
+            shared,
+            driver,
+            state: Mutex::new(PortState {
+                line,
+                dtr: false,
+                rts: false,
+                notifications: None,
+            }),
+            reader: StreamSlot::default(),
+            driverName: driver_name,
+            interfaceNumber: layout.interface,
+            layout,
+        };
+
+        port.run("openUsbSerial", move |driver, bridge| {
+            driver.init(bridge)?;
+            driver.set_line(bridge, &line)
+        })
+        .await?;
+        port.start_notifications();
+
+        // Best effort, as some adapters don't wire up DTR and RTS
+        let _ = port.set_control_lines("openUsbSerial", true, true).await;
+        Ok(port)
+    }
+}
@@ -0,0 +1,197 @@
+use super::{SerialBridge, SerialDriver};
+use crate::serial::{FlowControl, LineSettings, Parity, SerialInputSignals, StopBits};
+use nusb::transfer::{ControlType, Recipient};
+
+pub(super) const VENDOR_ID: u16 = 0x1a86;
+/// CH340/CH341 and CH341 in serial mode
+pub(super) const PRODUCT_IDS: &[u16] = &[0x7523, 0x5523, 0x7522];
+
+const REQ_READ_REG: u8 = 0x95;
+const REQ_WRITE_REG: u8 = 0x9a;
+const REQ_SERIAL_INIT: u8 = 0xa1;
+const REQ_MODEM_CTRL: u8 = 0xa4;
+
+/// Register pairs, read and written two at a time
+const REG_PRESCALER_DIVISOR: u16 = 0x1312;
+const REG_DIVISOR_LOW: u16 = 0x0f2c;
+const REG_LCR: u16 = 0x2518;
+const REG_BREAK: u16 = 0x1805;
+const REG_STATUS: u16 = 0x0706;
+
+const LCR_ENABLE_RX: u16 = 0x80;
+const LCR_ENABLE_TX: u16 = 0x40;
+const LCR_MARK_SPACE: u16 = 0x20;
+const LCR_PARITY_EVEN: u16 = 0x10;
+const LCR_ENABLE_PARITY: u16 = 0x08;
+const LCR_STOP_BITS_2: u16 = 0x04;
+
+/// Break is active low in the first register, and transmit is paused in the LCR meanwhile
+const BREAK_NOT: u8 = 0x01;
+
+const MODEM_DTR: u8 = 0x20;
+const MODEM_RTS: u8 = 0x40;
+
+const STATUS_CTS: u8 = 0x01;
+const STATUS_DSR: u8 = 0x02;
+const STATUS_RI: u8 = 0x04;
+const STATUS_DCD: u8 = 0x08;
+
+const BAUD_BASE_FACTOR: u32 = 1_532_620_800;
+const BAUD_MAX_PRESCALER: u16 = 3;
+
+pub(super) struct Ch34x;
+
+impl Ch34x {
+    fn write(
+        bridge: &SerialBridge,
+        request: u8,
+        value: u16,
+        index: u16,
+    ) -> std::result::Result<(), String> {
+        bridge.control_out(
+            ControlType::Vendor,
+            Recipient::Device,
+            request,
+            value,
+            index,
+            &[],
+        )
+    }
+
+    fn read_registers(
+        bridge: &SerialBridge,
+        registers: u16,
+    ) -> std::result::Result<[u8; 2], String> {
+        let data = bridge.control_in(
+            ControlType::Vendor,
+            Recipient::Device,
+            REQ_READ_REG,
+            registers,
+            0,
+            2,
+        )?;
+        Ok([data[0], data[1]])
+    }
+
+    fn set_baud_rate(bridge: &SerialBridge, baud_rate: u32) -> std::result::Result<(), String> {
+        let (prescaler_divisor, divisor_low) = encode_baud_rate(baud_rate)?;
+        Self::write(
+            bridge,
+            REQ_WRITE_REG,
+            REG_PRESCALER_DIVISOR,
+            prescaler_divisor,
+        )?;
+        Self::write(bridge, REQ_WRITE_REG, REG_DIVISOR_LOW, divisor_low)
+    }
+}
+
+/// The prescaler and divisor register values for a rate, as the Linux driver computes them.
+fn encode_baud_rate(baud_rate: u32) -> std::result::Result<(u16, u16), String> {
+    let mut factor = BAUD_BASE_FACTOR / baud_rate;
+    let mut prescaler = BAUD_MAX_PRESCALER;
+    while factor > 0xfff0 && prescaler > 0 {
+        factor >>= 3;
+        prescaler -= 1;
+    }
+    if factor > 0xfff0 {
+        return Err(format!("baud rate {baud_rate} unsupported"));
+    }
+    let factor = (0x10000 - factor) as u16;
+    Ok(((factor & 0xff00) | prescaler | 0x80, factor & 0xff))
+}
+
+impl SerialDriver for Ch34x {
+    fn init(&self, bridge: &SerialBridge) -> std::result::Result<(), String> {
+        Self::write(bridge, REQ_SERIAL_INIT, 0, 0)
+    }
+
+    fn set_line(
+        &self,
+        bridge: &SerialBridge,
+        settings: &LineSettings,
+    ) -> std::result::Result<(), String> {
+        if settings.data_bits > 8 {
+            return Err(format!("{} data bits unsupported", settings.data_bits));
+        }
+        if settings.flow_control == FlowControl::Hardware {
+            return Err("hardware flow control unsupported".to_string());
+        }
+        let mut lcr = LCR_ENABLE_RX | LCR_ENABLE_TX | (settings.data_bits as u16 - 5);
+        lcr |= match settings.parity {
+            Parity::None => 0,
+            Parity::Odd => LCR_ENABLE_PARITY,
+            Parity::Even => LCR_ENABLE_PARITY | LCR_PARITY_EVEN,
+            Parity::Mark => LCR_ENABLE_PARITY | LCR_MARK_SPACE,
+            Parity::Space => LCR_ENABLE_PARITY | LCR_MARK_SPACE | LCR_PARITY_EVEN,
+        };
+        lcr |= match settings.stop_bits {
+            StopBits::One => 0,
+            StopBits::Two => LCR_STOP_BITS_2,
+            StopBits::OnePointFive => return Err("1.5 stop bits unsupported".to_string()),
+        };
+
+        Self::set_baud_rate(bridge, settings.baud_rate)?;
+        Self::write(bridge, REQ_WRITE_REG, REG_LCR, lcr)
+    }
+
+    fn set_control_lines(
+        &self,
+        bridge: &SerialBridge,
+        dtr: bool,
+        rts: bool,
+    ) -> std::result::Result<(), String> {
+        let mut lines = 0;
+        if dtr {
+            lines |= MODEM_DTR;
+        }
+        if rts {
+            lines |= MODEM_RTS;
+        }
+        // The lines are active low
+        Self::write(bridge, REQ_MODEM_CTRL, !lines as u16, 0)
+    }
+
+    fn set_break(&self, bridge: &SerialBridge, on: bool) -> std::result::Result<(), String> {
+        let [mut control, mut lcr] = Self::read_registers(bridge, REG_BREAK)?;
+        if on {
+            control &= !BREAK_NOT;
+            lcr &= !(LCR_ENABLE_TX as u8);
+        } else {
+            control |= BREAK_NOT;
+            lcr |= LCR_ENABLE_TX as u8;
+        }
+        Self::write(
+            bridge,
+            REQ_WRITE_REG,
+            REG_BREAK,
+            u16::from_le_bytes([control, lcr]),
+        )
+    }
+
+    fn read_signals(
+        &self,
+        bridge: &SerialBridge,
+    ) -> std::result::Result<SerialInputSignals, String> {
+        // Also active low
+        let status = !Self::read_registers(bridge, REG_STATUS)?[0];
+        Ok(SerialInputSignals {
+            dataCarrierDetect: status & STATUS_DCD != 0,
+            clearToSend: status & STATUS_CTS != 0,
+            ringIndicator: status & STATUS_RI != 0,
+            dataSetReady: status & STATUS_DSR != 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_baud_rates() {
+        assert_eq!(encode_baud_rate(9600), Ok((0xb282, 0x0c)));
+        assert_eq!(encode_baud_rate(115_200), Ok((0xcc83, 0x08)));
+        assert_eq!(encode_baud_rate(2_000_000), Ok((0xfd83, 0x02)));
+        assert!(encode_baud_rate(10).is_err());
+    }
+}
@@ -0,0 +1,141 @@
+use super::{SerialBridge, SerialDriver};
+use crate::serial::{FlowControl, LineSettings, Parity, SerialInputSignals, StopBits};
+use nusb::transfer::{ControlType, Recipient};
+
+pub(super) const VENDOR_ID: u16 = 0x10c4;
+/// CP2102/CP2104 and friends, CP2105 and CP2108
+pub(super) const PRODUCT_IDS: &[u16] = &[0xea60, 0xea70, 0xea71];
+
+const IFC_ENABLE: u8 = 0x00;
+const SET_LINE_CTL: u8 = 0x03;
+const SET_BREAK: u8 = 0x05;
+const SET_MHS: u8 = 0x07;
+const GET_MDMSTS: u8 = 0x08;
+const SET_FLOW: u8 = 0x13;
+const SET_BAUDRATE: u8 = 0x1e;
+
+const MHS_DTR: u16 = 0x0001;
+const MHS_RTS: u16 = 0x0002;
+const MHS_DTR_MASK: u16 = 0x0100;
+const MHS_RTS_MASK: u16 = 0x0200;
+
+const STATUS_CTS: u8 = 0x10;
+const STATUS_DSR: u8 = 0x20;
+const STATUS_RI: u8 = 0x40;
+const STATUS_DCD: u8 = 0x80;
+
+/// `ulControlHandshake` bits: DTR held active, CTS gating transmit
+const CONTROL_DTR_ACTIVE: u32 = 0x01;
+const CONTROL_CTS_HANDSHAKE: u32 = 0x08;
+/// `ulFlowReplace` bits: RTS held active, or RTS driven by the receive buffer
+const FLOW_RTS_ACTIVE: u32 = 0x40;
+const FLOW_RTS_HANDSHAKE: u32 = 0x80;
+const XON_XOFF_LIMIT: u32 = 128;
+
+pub(super) struct Cp210x;
+
+impl Cp210x {
+    fn request(
+        bridge: &SerialBridge,
+        request: u8,
+        value: u16,
+        data: &[u8],
+    ) -> std::result::Result<(), String> {
+        bridge.control_out(
+            ControlType::Vendor,
+            Recipient::Interface,
+            request,
+            value,
+            bridge.interface as u16,
+            data,
+        )
+    }
+}
+
+impl SerialDriver for Cp210x {
+    fn init(&self, bridge: &SerialBridge) -> std::result::Result<(), String> {
+        Self::request(bridge, IFC_ENABLE, 1, &[])
+    }
+
+    fn set_line(
+        &self,
+        bridge: &SerialBridge,
+        settings: &LineSettings,
+    ) -> std::result::Result<(), String> {
+        if settings.data_bits > 8 {
+            return Err(format!("{} data bits unsupported", settings.data_bits));
+        }
+        let stop_bits = match settings.stop_bits {
+            StopBits::One => 0,
+            StopBits::OnePointFive => 1,
+            StopBits::Two => 2,
+        };
+        let parity = match settings.parity {
+            Parity::None => 0,
+            Parity::Odd => 1,
+            Parity::Even => 2,
+            Parity::Mark => 3,
+            Parity::Space => 4,
+        };
+        Self::request(bridge, SET_BAUDRATE, 0, &settings.baud_rate.to_le_bytes())?;
+        Self::request(
+            bridge,
+            SET_LINE_CTL,
+            stop_bits | (parity << 4) | ((settings.data_bits as u16) << 8),
+            &[],
+        )?;
+
+        let (control, flow) = match settings.flow_control {
+            FlowControl::None => (CONTROL_DTR_ACTIVE, FLOW_RTS_ACTIVE),
+            FlowControl::Hardware => (
+                CONTROL_DTR_ACTIVE | CONTROL_CTS_HANDSHAKE,
+                FLOW_RTS_HANDSHAKE,
+            ),
+        };
+        let mut data = Vec::with_capacity(16);
+        for field in [control, flow, XON_XOFF_LIMIT, XON_XOFF_LIMIT] {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+        Self::request(bridge, SET_FLOW, 0, &data)
+    }
+
+    fn set_control_lines(
+        &self,
+        bridge: &SerialBridge,
+        dtr: bool,
+        rts: bool,
+    ) -> std::result::Result<(), String> {
+        let mut value = MHS_DTR_MASK | MHS_RTS_MASK;
+        if dtr {
+            value |= MHS_DTR;
+        }
+        if rts {
+            value |= MHS_RTS;
+        }
+        Self::request(bridge, SET_MHS, value, &[])
+    }
+
+    fn set_break(&self, bridge: &SerialBridge, on: bool) -> std::result::Result<(), String> {
+        Self::request(bridge, SET_BREAK, on as u16, &[])
+    }
+
+    fn read_signals(
+        &self,
+        bridge: &SerialBridge,
+    ) -> std::result::Result<SerialInputSignals, String> {
+        let status = bridge.control_in(
+            ControlType::Vendor,
+            Recipient::Interface,
+            GET_MDMSTS,
+            0,
+            bridge.interface as u16,
+            1,
+        )?[0];
+        Ok(SerialInputSignals {
+            dataCarrierDetect: status & STATUS_DCD != 0,
+            clearToSend: status & STATUS_CTS != 0,
+            ringIndicator: status & STATUS_RI != 0,
+            dataSetReady: status & STATUS_DSR != 0,
+        })
+    }
+}
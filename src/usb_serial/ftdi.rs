@@ -0,0 +1,210 @@
+use super::{SerialBridge, SerialDriver};
+use crate::serial::{FlowControl, LineSettings, Parity, SerialInputSignals, StopBits};
+use nusb::transfer::{ControlType, Recipient};
+use std::sync::atomic::{AtomicU16, Ordering};
+
+pub(super) const VENDOR_ID: u16 = 0x0403;
+/// FT232R/FT232BM, FT2232C/D/H, FT4232H, FT232H and FT-X
+pub(super) const PRODUCT_IDS: &[u16] = &[0x6001, 0x6010, 0x6011, 0x6014, 0x6015];
+
+const SIO_RESET: u8 = 0x00;
+const SIO_MODEM_CTRL: u8 = 0x01;
+const SIO_SET_FLOW_CTRL: u8 = 0x02;
+const SIO_SET_BAUD_RATE: u8 = 0x03;
+const SIO_SET_DATA: u8 = 0x04;
+const SIO_GET_MODEM_STATUS: u8 = 0x05;
+
+const MODEM_DTR: u16 = 0x0001;
+const MODEM_RTS: u16 = 0x0002;
+const MODEM_DTR_MASK: u16 = 0x0100;
+const MODEM_RTS_MASK: u16 = 0x0200;
+const FLOW_RTS_CTS: u16 = 0x0100;
+const DATA_BREAK: u16 = 1 << 14;
+
+const STATUS_CTS: u8 = 0x10;
+const STATUS_DSR: u8 = 0x20;
+const STATUS_RI: u8 = 0x40;
+const STATUS_DCD: u8 = 0x80;
+
+/// Bytes of modem and line status at the start of every IN packet
+const STATUS_HEADER_LEN: usize = 2;
+
+/// Sub-integer divisor codes, indexed by eighths
+const DIVISOR_FRACTIONS: [u32; 8] = [0, 3, 2, 4, 1, 5, 6, 7];
+
+#[derive(Default)]
+pub(super) struct Ftdi {
+    /// The last `SIO_SET_DATA` value, which break is sent as a bit of
+    data: AtomicU16,
+}
+
+/// H-series chips (FT2232H, FT4232H, FT232H) clock at 12 MHz and put the port in the baud rate index
+fn is_h_series(device_version: u16) -> bool {
+    matches!(device_version, 0x0700 | 0x0800 | 0x0900)
+}
+
+/// FT2232C and the H-series have more than one port, or share the multi-port request layout
+fn uses_port_index(device_version: u16) -> bool {
+    device_version == 0x0500 || is_h_series(device_version)
+}
+
+/// Encodes `clock / baud` as a 14-bit integer part and a 3-bit eighths code.
+fn baud_divisor(baud_rate: u32, clock: u32) -> u32 {
+    let divisor3 = (8 * clock as u64 + baud_rate as u64 / 2) / baud_rate as u64;
+    let divisor3 = divisor3.min(0x1ffff) as u32;
+    let divisor = (divisor3 >> 3) | (DIVISOR_FRACTIONS[(divisor3 & 7) as usize] << 14);
+    // 1 and 1.5 have their own codes, for the two highest rates
+    match divisor {
+        1 => 0,
+        0x4001 => 1,
+        divisor => divisor,
+    }
+}
+
+impl Ftdi {
+    fn port(bridge: &SerialBridge) -> u16 {
+        bridge.interface as u16 + 1
+    }
+
+    fn request(
+        bridge: &SerialBridge,
+        request: u8,
+        value: u16,
+        index: u16,
+    ) -> std::result::Result<(), String> {
+        bridge.control_out(
+            ControlType::Vendor,
+            Recipient::Device,
+            request,
+            value,
+            index,
+            &[],
+        )
+    }
+
+    fn set_baud_rate(bridge: &SerialBridge, baud_rate: u32) -> std::result::Result<(), String> {
+        let h_series = is_h_series(bridge.device_version);
+        let divisor = if h_series && baud_rate >= 1200 {
+            // Turns off the divide by 2.5, for rates up to 12 Mbaud
+            baud_divisor(baud_rate, 12_000_000) | 0x20000
+        } else {
+            baud_divisor(baud_rate, 3_000_000)
+        };
+        let index = if uses_port_index(bridge.device_version) {
+            ((divisor >> 8) as u16 & 0xff00) | Self::port(bridge)
+        } else {
+            (divisor >> 16) as u16
+        };
+        Self::request(bridge, SIO_SET_BAUD_RATE, divisor as u16, index)
+    }
+}
+
+impl SerialDriver for Ftdi {
+    fn init(&self, bridge: &SerialBridge) -> std::result::Result<(), String> {
+        Self::request(bridge, SIO_RESET, 0, Self::port(bridge))
+    }
+
+    fn set_line(
+        &self,
+        bridge: &SerialBridge,
+        settings: &LineSettings,
+    ) -> std::result::Result<(), String> {
+        if !matches!(settings.data_bits, 7 | 8) {
+            return Err(format!("{} data bits unsupported", settings.data_bits));
+        }
+        let parity = match settings.parity {
+            Parity::None => 0,
+            Parity::Odd => 1,
+            Parity::Even => 2,
+            Parity::Mark => 3,
+            Parity::Space => 4,
+        };
+        let stop_bits = match settings.stop_bits {
+            StopBits::One => 0,
+            StopBits::OnePointFive => 1,
+            StopBits::Two => 2,
+        };
+        let data = settings.data_bits as u16 | (parity << 8) | (stop_bits << 11);
+
+        Self::set_baud_rate(bridge, settings.baud_rate)?;
+        Self::request(bridge, SIO_SET_DATA, data, Self::port(bridge))?;
+        self.data.store(data, Ordering::Relaxed);
+
+        let flow = match settings.flow_control {
+            FlowControl::None => 0,
+            FlowControl::Hardware => FLOW_RTS_CTS,
+        };
+        Self::request(bridge, SIO_SET_FLOW_CTRL, 0, flow | Self::port(bridge))
+    }
+
+    fn set_control_lines(
+        &self,
+        bridge: &SerialBridge,
+        dtr: bool,
+        rts: bool,
+    ) -> std::result::Result<(), String> {
+        let mut value = MODEM_DTR_MASK | MODEM_RTS_MASK;
+        if dtr {
+            value |= MODEM_DTR;
+        }
+        if rts {
+            value |= MODEM_RTS;
+        }
+        Self::request(bridge, SIO_MODEM_CTRL, value, Self::port(bridge))
+    }
+
+    fn set_break(&self, bridge: &SerialBridge, on: bool) -> std::result::Result<(), String> {
+        let data = self.data.load(Ordering::Relaxed);
+        let data = if on {
+            data | DATA_BREAK
+        } else {
+            data & !DATA_BREAK
+        };
+        Self::request(bridge, SIO_SET_DATA, data, Self::port(bridge))
+    }
+
+    fn read_signals(
+        &self,
+        bridge: &SerialBridge,
+    ) -> std::result::Result<SerialInputSignals, String> {
+        let status = bridge.control_in(
+            ControlType::Vendor,
+            Recipient::Device,
+            SIO_GET_MODEM_STATUS,
+            0,
+            Self::port(bridge),
+            1,
+        )?[0];
+        Ok(SerialInputSignals {
+            dataCarrierDetect: status & STATUS_DCD != 0,
+            clearToSend: status & STATUS_CTS != 0,
+            ringIndicator: status & STATUS_RI != 0,
+            dataSetReady: status & STATUS_DSR != 0,
+        })
+    }
+
+    fn received(&self, data: &[u8], packet_size: usize, out: &mut Vec<u8>) {
+        for packet in data.chunks(packet_size) {
+            out.extend_from_slice(packet.get(STATUS_HEADER_LEN..).unwrap_or_default());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn baud_divisors_match_the_datasheet() {
+        // From FTDI's AN232B-05 baud rate table
+        assert_eq!(baud_divisor(300, 3_000_000), 0x2710);
+        assert_eq!(baud_divisor(9600, 3_000_000), 0x4138);
+        assert_eq!(baud_divisor(115_200, 3_000_000), 0x001a);
+        assert_eq!(baud_divisor(2_000_000, 3_000_000), 1);
+        assert_eq!(baud_divisor(3_000_000, 3_000_000), 0);
+        // H series at 12 MHz, before the divide by 2.5 bit is added
+        assert_eq!(baud_divisor(921_600, 12_000_000), 0x000d);
+        // Clamped to the largest divisor
+        assert_eq!(baud_divisor(10, 3_000_000), 0x1_ffff);
+    }
+}
@@ -0,0 +1,221 @@
+use super::{SerialBridge, SerialDriver};
+use crate::serial::{FlowControl, LineSettings, Parity, SerialInputSignals, StopBits};
+use nusb::transfer::{ControlType, Recipient};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+pub(super) const VENDOR_ID: u16 = 0x067b;
+pub(super) const PRODUCT_IDS: &[u16] = &[0x2303];
+
+const VENDOR_REQUEST: u8 = 0x01;
+const SET_LINE_REQUEST: u8 = 0x20;
+const SET_CONTROL_REQUEST: u8 = 0x22;
+const BREAK_REQUEST: u8 = 0x23;
+
+const CONTROL_DTR: u16 = 0x01;
+const CONTROL_RTS: u16 = 0x02;
+const BREAK_ON: u16 = 0xffff;
+
+/// Offset of the line state in an interrupt notification
+const UART_STATE_INDEX: usize = 8;
+const STATE_DCD: u8 = 0x01;
+const STATE_DSR: u8 = 0x02;
+const STATE_RI: u8 = 0x08;
+const STATE_CTS: u8 = 0x80;
+
+/// Rates the chip takes as-is. Others are sent as a divisor.
+const STANDARD_BAUD_RATES: &[u32] = &[
+    75, 150, 300, 600, 1200, 1800, 2400, 3600, 4800, 7200, 9600, 14400, 19200, 28800, 38400, 57600,
+    115200, 230400, 460800, 614400, 921600, 1228800, 2457600, 3000000, 6000000,
+];
+
+/// The PL2303 reports modem status on its interrupt endpoint rather than on request,
+/// so the last reported state is kept.
+#[derive(Default)]
+pub(super) struct Pl2303 {
+    state: AtomicU8,
+}
+
+/// Encodes a non-standard rate as a mantissa and power of four dividing 384 MHz.
+fn encode_baud_rate(baud_rate: u32) -> [u8; 4] {
+    if STANDARD_BAUD_RATES.contains(&baud_rate) {
+        return baud_rate.to_le_bytes();
+    }
+    let mut mantissa = (384_000_000 / baud_rate).max(1);
+    let mut exponent = 0;
+    while mantissa >= 512 {
+        if exponent < 7 {
+            mantissa >>= 2;
+            exponent += 1;
+        } else {
+            mantissa = 511;
+            break;
+        }
+    }
+    [
+        mantissa as u8,
+        (exponent << 1 | mantissa >> 8) as u8,
+        0,
+        0x80,
+    ]
+}
+
+impl Pl2303 {
+    fn vendor_read(bridge: &SerialBridge, value: u16) -> std::result::Result<(), String> {
+        bridge
+            .control_in(
+                ControlType::Vendor,
+                Recipient::Device,
+                VENDOR_REQUEST,
+                value,
+                0,
+                1,
+            )
+            .map(|_| ())
+    }
+
+    fn vendor_write(
+        bridge: &SerialBridge,
+        value: u16,
+        index: u16,
+    ) -> std::result::Result<(), String> {
+        bridge.control_out(
+            ControlType::Vendor,
+            Recipient::Device,
+            VENDOR_REQUEST,
+            value,
+            index,
+            &[],
+        )
+    }
+
+    fn class_request(
+        bridge: &SerialBridge,
+        request: u8,
+        value: u16,
+        data: &[u8],
+    ) -> std::result::Result<(), String> {
+        bridge.control_out(
+            ControlType::Class,
+            Recipient::Interface,
+            request,
+            value,
+            bridge.interface as u16,
+            data,
+        )
+    }
+}
+
+impl SerialDriver for Pl2303 {
+    /// The vendor's start-up sequence. The reads' results are unused but the chip expects them.
+    fn init(&self, bridge: &SerialBridge) -> std::result::Result<(), String> {
+        Self::vendor_read(bridge, 0x8484)?;
+        Self::vendor_write(bridge, 0x0404, 0)?;
+        Self::vendor_read(bridge, 0x8484)?;
+        Self::vendor_read(bridge, 0x8383)?;
+        Self::vendor_read(bridge, 0x8484)?;
+        Self::vendor_write(bridge, 0x0404, 1)?;
+        Self::vendor_read(bridge, 0x8484)?;
+        Self::vendor_read(bridge, 0x8383)?;
+        Self::vendor_write(bridge, 0, 1)?;
+        Self::vendor_write(bridge, 1, 0)?;
+        // HX and later, against the original PL2303
+        let mode = if bridge.device_version >= 0x0300 {
+            0x44
+        } else {
+            0x24
+        };
+        Self::vendor_write(bridge, 2, mode)
+    }
+
+    fn set_line(
+        &self,
+        bridge: &SerialBridge,
+        settings: &LineSettings,
+    ) -> std::result::Result<(), String> {
+        if settings.data_bits > 8 {
+            return Err(format!("{} data bits unsupported", settings.data_bits));
+        }
+        let mut line = [0; 7];
+        line[..4].copy_from_slice(&encode_baud_rate(settings.baud_rate));
+        line[4] = match settings.stop_bits {
+            StopBits::One => 0,
+            StopBits::OnePointFive => 1,
+            StopBits::Two => 2,
+        };
+        line[5] = match settings.parity {
+            Parity::None => 0,
+            Parity::Odd => 1,
+            Parity::Even => 2,
+            Parity::Mark => 3,
+            Parity::Space => 4,
+        };
+        line[6] = settings.data_bits;
+        Self::class_request(bridge, SET_LINE_REQUEST, 0, &line)?;
+
+        let flow = match settings.flow_control {
+            FlowControl::None => 0,
+            FlowControl::Hardware => 0x61,
+        };
+        Self::vendor_write(bridge, 0, flow)
+    }
+
+    fn set_control_lines(
+        &self,
+        bridge: &SerialBridge,
+        dtr: bool,
+        rts: bool,
+    ) -> std::result::Result<(), String> {
+        let mut value = 0;
+        if dtr {
+            value |= CONTROL_DTR;
+        }
+        if rts {
+            value |= CONTROL_RTS;
+        }
+        Self::class_request(bridge, SET_CONTROL_REQUEST, value, &[])
+    }
+
+    fn set_break(&self, bridge: &SerialBridge, on: bool) -> std::result::Result<(), String> {
+        Self::class_request(bridge, BREAK_REQUEST, if on { BREAK_ON } else { 0 }, &[])
+    }
+
+    fn read_signals(
+        &self,
+        _bridge: &SerialBridge,
+    ) -> std::result::Result<SerialInputSignals, String> {
+        let state = self.state.load(Ordering::Relaxed);
+        Ok(SerialInputSignals {
+            dataCarrierDetect: state & STATE_DCD != 0,
+            clearToSend: state & STATE_CTS != 0,
+            ringIndicator: state & STATE_RI != 0,
+            dataSetReady: state & STATE_DSR != 0,
+        })
+    }
+
+    fn on_notification(&self, data: &[u8]) {
+        if let Some(state) = data.get(UART_STATE_INDEX) {
+            self.state.store(*state, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standard_rates_are_sent_directly() {
+        assert_eq!(encode_baud_rate(9600), [0x80, 0x25, 0x00, 0x00]);
+        assert_eq!(encode_baud_rate(115_200), [0x00, 0xc2, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn other_rates_divide_384_mhz() {
+        // 384 MHz / 311 = 1234726 baud
+        assert_eq!(encode_baud_rate(1_234_567), [0x37, 0x01, 0x00, 0x80]);
+        // 384 MHz / (468 * 4^7) = 50.08 baud
+        assert_eq!(encode_baud_rate(50), [0xd4, 0x0f, 0x00, 0x80]);
+        // Too slow for the largest divisor
+        assert_eq!(encode_baud_rate(1), [0xff, 0x0f, 0x00, 0x80]);
+    }
+}
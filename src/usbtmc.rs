@@ -0,0 +1,861 @@
+use crate::endpoint_stream::InStream;
+use crate::transfer_pool::{pooled_transfer_in, pooled_transfer_out};
+use crate::webusb_device::{run_blocking, SharedDevice, UsbDevice};
+use napi::{
+    bindgen_prelude::*, threadsafe_function::ThreadsafeFunction,
+    threadsafe_function::ThreadsafeFunctionCallMode,
+};
+use napi_derive::napi;
+use nusb::{
+    descriptors::{ConfigurationDescriptor, TransferType},
+    transfer::{ControlIn, ControlType, Direction, In, Out, Recipient},
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    thread::sleep,
+    time::Duration,
+};
+use tokio::sync::oneshot;
+
+const CLASS_APPLICATION_SPECIFIC: u8 = 0xfe;
+const SUBCLASS_USBTMC: u8 = 0x03;
+const PROTOCOL_USB488: u8 = 0x01;
+
+const DEV_DEP_MSG_OUT: u8 = 1;
+const REQUEST_DEV_DEP_MSG_IN: u8 = 2;
+const DEV_DEP_MSG_IN: u8 = 2;
+const USB488_TRIGGER: u8 = 128;
+
+const INITIATE_ABORT_BULK_OUT: u8 = 1;
+const CHECK_ABORT_BULK_OUT_STATUS: u8 = 2;
+const INITIATE_ABORT_BULK_IN: u8 = 3;
+const CHECK_ABORT_BULK_IN_STATUS: u8 = 4;
+const INITIATE_CLEAR: u8 = 5;
+const CHECK_CLEAR_STATUS: u8 = 6;
+const GET_CAPABILITIES: u8 = 7;
+const INDICATOR_PULSE: u8 = 64;
+const READ_STATUS_BYTE: u8 = 128;
+
+const STATUS_SUCCESS: u8 = 0x01;
+const STATUS_PENDING: u8 = 0x02;
+
+const HEADER_LENGTH: usize = 12;
+const NOTIFY_SRQ: u8 = 0x81;
+
+const CONTROL_TIMEOUT: Duration = Duration::from_millis(1000);
+const DEFAULT_TIMEOUT: u32 = 5000;
+const DEFAULT_READ_LENGTH: u32 = 1024 * 1024;
+const STATUS_POLL_INTERVAL: Duration = Duration::from_millis(10);
+const STATUS_POLL_LIMIT: usize = 100;
+const NOTIFY_TRANSFERS: usize = 2;
+
+type ServiceRequestCallback = ThreadsafeFunction<u8, (), u8, napi::Status, false>;
+
+#[napi(object)]
+pub struct UsbtmcOptions {
+    /// The USBTMC interface to use, defaults to the first one
+    pub interfaceNumber: Option<u8>,
+    /// Detach kernel drivers (e.g. `usbtmc`) while the interface is open, defaults to `true`
+    pub detachKernelDriver: Option<bool>,
+}
+
+/// The result of GET_CAPABILITIES, with the USB488 fields when the interface has them.
+#[napi(object)]
+#[derive(Clone, Copy)]
+pub struct UsbtmcCapabilities {
+    #[napi(writable = false)]
+    pub usbtmcVersion: u16,
+    #[napi(writable = false)]
+    pub indicatorPulse: bool,
+    #[napi(writable = false)]
+    pub talkOnly: bool,
+    #[napi(writable = false)]
+    pub listenOnly: bool,
+    #[napi(writable = false)]
+    pub termChar: bool,
+    #[napi(writable = false)]
+    pub usb488Version: Option<u16>,
+    /// USB488.2 interface
+    #[napi(writable = false)]
+    pub ieee4882: bool,
+    /// REN_CONTROL, GO_TO_LOCAL and LOCAL_LOCKOUT
+    #[napi(writable = false)]
+    pub remoteLocal: bool,
+    #[napi(writable = false)]
+    pub trigger: bool,
+    #[napi(writable = false)]
+    pub scpi: bool,
+    #[napi(writable = false)]
+    pub serviceRequest: bool,
+}
+
+#[napi(object)]
+pub struct UsbtmcReadOptions {
+    /// Most bytes to read, defaults to 1 MiB
+    pub maxLength: Option<u32>,
+    /// Ask the device to end the message at this byte, if it supports a term char
+    pub termChar: Option<u8>,
+    /// Milliseconds to wait for each transfer, defaults to 5000
+    pub timeout: Option<u32>,
+}
+
+/// Where the USBTMC function lives in the active configuration.
+struct UsbtmcLayout {
+    interface: u8,
+    usb488: bool,
+    bulk_in: u8,
+    bulk_out: u8,
+    interrupt_in: Option<u8>,
+}
+
+fn find_usbtmc_layout(
+    config: &ConfigurationDescriptor,
+    interface_number: Option<u8>,
+) -> std::result::Result<UsbtmcLayout, String> {
+    let alt = config
+        .interface_alt_settings()
+        .filter(|alt| alt.alternate_setting() == 0)
+        .filter(|alt| interface_number.is_none_or(|n| alt.interface_number() == n))
+        .find(|alt| alt.class() == CLASS_APPLICATION_SPECIFIC && alt.subclass() == SUBCLASS_USBTMC)
+        .ok_or("no USBTMC interface found")?;
+
+    let endpoint = |direction, transfer_type| {
+        alt.endpoints()
+            .find(|ep| ep.direction() == direction && ep.transfer_type() == transfer_type)
+            .map(|ep| ep.address() & 0x7f)
+    };
+
+    Ok(UsbtmcLayout {
+        interface: alt.interface_number(),
+        usb488: alt.protocol() == PROTOCOL_USB488,
+        bulk_in: endpoint(Direction::In, TransferType::Bulk).ok_or("no bulk IN endpoint")?,
+        bulk_out: endpoint(Direction::Out, TransferType::Bulk).ok_or("no bulk OUT endpoint")?,
+        interrupt_in: endpoint(Direction::In, TransferType::Interrupt),
+    })
+}
+
+fn parse_capabilities(
+    data: &[u8],
+    usb488: bool,
+) -> std::result::Result<UsbtmcCapabilities, String> {
+    if data.len() < 0x18 || data[0] != STATUS_SUCCESS {
+        return Err("GET_CAPABILITIES failed".to_string());
+    }
+    let usb488 = usb488.then(|| (u16::from_le_bytes([data[12], data[13]]), data[14], data[15]));
+    let (interface_488, device_488) = usb488.map(|(_, i, d)| (i, d)).unwrap_or((0, 0));
+    Ok(UsbtmcCapabilities {
+        usbtmcVersion: u16::from_le_bytes([data[2], data[3]]),
+        indicatorPulse: data[4] & 0x04 != 0,
+        talkOnly: data[4] & 0x02 != 0,
+        listenOnly: data[4] & 0x01 != 0,
+        termChar: data[5] & 0x01 != 0,
+        usb488Version: usb488.map(|(version, ..)| version),
+        ieee4882: interface_488 & 0x04 != 0,
+        remoteLocal: interface_488 & 0x02 != 0,
+        trigger: interface_488 & 0x01 != 0,
+        scpi: device_488 & 0x08 != 0,
+        serviceRequest: device_488 & 0x04 != 0,
+    })
+}
+
+/// The 12 byte Bulk-OUT header shared by every message.
+fn header(msg_id: u8, tag: u8, transfer_size: u32, attributes: u8, term_char: u8) -> Vec<u8> {
+    let mut header = vec![msg_id, tag, !tag, 0];
+    header.extend_from_slice(&transfer_size.to_le_bytes());
+    header.extend_from_slice(&[attributes, term_char, 0, 0]);
+    header
+}
+
+/// Checks a DEV_DEP_MSG_IN header against its request, returning the transfer size and whether
+/// the message ends with it.
+fn parse_in_header(
+    response: &[u8],
+    tag: u8,
+    wanted: usize,
+) -> std::result::Result<(usize, bool), String> {
+    if response.len() < HEADER_LENGTH {
+        return Err("short DEV_DEP_MSG_IN header".to_string());
+    }
+    if response[0] != DEV_DEP_MSG_IN || response[1] != tag || response[2] != !tag {
+        return Err("DEV_DEP_MSG_IN tag mismatch".to_string());
+    }
+    let size = u32::from_le_bytes([response[4], response[5], response[6], response[7]]) as usize;
+    if size > wanted {
+        return Err("DEV_DEP_MSG_IN larger than requested".to_string());
+    }
+    Ok((size, response[8] & 0x01 != 0))
+}
+
+/// Bulk message exchange, behind a mutex so a write and its read aren't interleaved with another.
+struct UsbtmcTransport {
+    shared: Arc<SharedDevice>,
+    interface: u8,
+    bulk_in: u8,
+    bulk_out: u8,
+    tag: u8,
+}
+
+impl UsbtmcTransport {
+    fn next_tag(&mut self) -> u8 {
+        // bTag runs 1 to 255, skipping 0
+        self.tag = self.tag.checked_add(1).unwrap_or(1);
+        self.tag
+    }
+
+    fn control_in(
+        &self,
+        recipient: Recipient,
+        request: u8,
+        value: u16,
+        index: u16,
+        length: u16,
+    ) -> std::result::Result<Vec<u8>, String> {
+        let target = self
+            .shared
+            .control_target(recipient, index)
+            .ok_or("invalid state")?;
+        target
+            .control_in_blocking(
+                ControlIn {
+                    control_type: ControlType::Class,
+                    recipient,
+                    request,
+                    value,
+                    index,
+                    length,
+                },
+                CONTROL_TIMEOUT,
+            )
+            .map_err(|e| e.to_string())
+    }
+
+    fn interface_request(
+        &self,
+        request: u8,
+        value: u16,
+        length: u16,
+    ) -> std::result::Result<Vec<u8>, String> {
+        self.control_in(
+            Recipient::Interface,
+            request,
+            value,
+            self.interface as u16,
+            length,
+        )
+    }
+
+    fn send(&self, message: &[u8], timeout: Duration) -> std::result::Result<(), String> {
+        let mut endpoint = self
+            .shared
+            .get_endpoint::<Out>(self.bulk_out)
+            .ok_or("invalid state")?;
+        let result = pooled_transfer_out(&self.shared, &mut endpoint, message, timeout)
+            .map(|_| ())
+            .map_err(|e| format!("bulk OUT failed: {e:?}"));
+        self.shared.put_endpoint(endpoint);
+        result
+    }
+
+    fn receive(&self, length: usize, timeout: Duration) -> std::result::Result<Vec<u8>, String> {
+        let mut endpoint = self
+            .shared
+            .get_endpoint::<In>(self.bulk_in)
+            .ok_or("invalid state")?;
+        let result = pooled_transfer_in(&self.shared, &mut endpoint, length, timeout)
+            .map(|(buffer, len)| {
+                let data = buffer[..len].to_vec();
+                self.shared.put_buffer(&endpoint, buffer);
+                data
+            })
+            .map_err(|e| format!("bulk IN failed: {e:?}"));
+        self.shared.put_endpoint(endpoint);
+        result
+    }
+
+    fn clear_halt(
+        &self,
+        endpoint_number: u8,
+        direction: Direction,
+    ) -> std::result::Result<(), String> {
+        let result = match direction {
+            Direction::Out => {
+                let mut endpoint = self
+                    .shared
+                    .get_endpoint::<Out>(endpoint_number)
+                    .ok_or("invalid state")?;
+                let result = endpoint.clear_halt_blocking();
+                self.shared.put_endpoint(endpoint);
+                result
+            }
+            Direction::In => {
+                let mut endpoint = self
+                    .shared
+                    .get_endpoint::<In>(endpoint_number)
+                    .ok_or("invalid state")?;
+                let result = endpoint.clear_halt_blocking();
+                self.shared.put_endpoint(endpoint);
+                result
+            }
+        };
+        result.map_err(|e| format!("clear halt failed: {e}"))
+    }
+
+    /// Polls a CHECK_*_STATUS request while the device reports STATUS_PENDING.
+    fn poll_status<F>(&self, mut check: F) -> std::result::Result<Vec<u8>, String>
+    where
+        F: FnMut() -> std::result::Result<Vec<u8>, String>,
+    {
+        for _ in 0..STATUS_POLL_LIMIT {
+            let status = check()?;
+            if status.first() != Some(&STATUS_PENDING) {
+                return Ok(status);
+            }
+            sleep(STATUS_POLL_INTERVAL);
+        }
+        Err("device stayed pending".to_string())
+    }
+
+    fn abort_bulk_out(&self, tag: u8) -> std::result::Result<(), String> {
+        let index = self.bulk_out as u16;
+        let status = self.control_in(
+            Recipient::Endpoint,
+            INITIATE_ABORT_BULK_OUT,
+            tag as u16,
+            index,
+            2,
+        )?;
+        if status.first() == Some(&STATUS_SUCCESS) {
+            self.poll_status(|| {
+                self.control_in(
+                    Recipient::Endpoint,
+                    CHECK_ABORT_BULK_OUT_STATUS,
+                    0,
+                    index,
+                    8,
+                )
+            })?;
+        }
+        self.clear_halt(self.bulk_out, Direction::Out)
+    }
+
+    fn abort_bulk_in(&self, tag: u8, timeout: Duration) -> std::result::Result<(), String> {
+        let index = (self.bulk_in | 0x80) as u16;
+        let status = self.control_in(
+            Recipient::Endpoint,
+            INITIATE_ABORT_BULK_IN,
+            tag as u16,
+            index,
+            2,
+        )?;
+        if status.first() != Some(&STATUS_SUCCESS) {
+            return Ok(());
+        }
+        self.poll_status(|| {
+            let status =
+                self.control_in(Recipient::Endpoint, CHECK_ABORT_BULK_IN_STATUS, 0, index, 8)?;
+            // bmAbortBulkIn asks us to keep reading until a short packet
+            if status.first() == Some(&STATUS_PENDING)
+                && status.get(1).is_some_and(|b| b & 0x01 != 0)
+            {
+                let _ = self.receive(DEFAULT_READ_LENGTH as usize, timeout);
+            }
+            Ok(status)
+        })?;
+        Ok(())
+    }
+
+    fn clear(&self, timeout: Duration) -> std::result::Result<(), String> {
+        let status = self.interface_request(INITIATE_CLEAR, 0, 1)?;
+        if status.first() != Some(&STATUS_SUCCESS) {
+            return Err(format!(
+                "INITIATE_CLEAR failed with status {:#04x}",
+                status.first().unwrap_or(&0)
+            ));
+        }
+        self.poll_status(|| {
+            let status = self.interface_request(CHECK_CLEAR_STATUS, 0, 2)?;
+            // bmClear asks us to read and discard Bulk-IN data
+            if status.first() == Some(&STATUS_PENDING)
+                && status.get(1).is_some_and(|b| b & 0x01 != 0)
+            {
+                let _ = self.receive(DEFAULT_READ_LENGTH as usize, timeout);
+            }
+            Ok(status)
+        })?;
+        self.clear_halt(self.bulk_out, Direction::Out)
+    }
+
+    fn write(&mut self, data: &[u8], timeout: Duration) -> std::result::Result<(), String> {
+        let tag = self.next_tag();
+        let mut message = header(DEV_DEP_MSG_OUT, tag, data.len() as u32, 0x01, 0);
+        message.extend_from_slice(data);
+        message.resize(message.len().next_multiple_of(4), 0);
+        self.send(&message, timeout).inspect_err(|_| {
+            // Best effort, the original error is the one worth reporting
+            let _ = self.abort_bulk_out(tag);
+        })
+    }
+
+    fn read(
+        &mut self,
+        max_length: usize,
+        term_char: Option<u8>,
+        timeout: Duration,
+    ) -> std::result::Result<Vec<u8>, String> {
+        let mut data = Vec::new();
+        loop {
+            let tag = self.next_tag();
+            let wanted = max_length - data.len();
+            let attributes = if term_char.is_some() { 0x02 } else { 0x00 };
+            let request = header(
+                REQUEST_DEV_DEP_MSG_IN,
+                tag,
+                wanted as u32,
+                attributes,
+                term_char.unwrap_or(0),
+            );
+            if let Err(e) = self.send(&request, timeout) {
+                let _ = self.abort_bulk_out(tag);
+                return Err(e);
+            }
+
+            match self.read_message(tag, wanted, timeout) {
+                Ok((chunk, end_of_message)) => {
+                    data.extend_from_slice(&chunk);
+                    if end_of_message || data.len() >= max_length {
+                        return Ok(data);
+                    }
+                }
+                Err(e) => {
+                    let _ = self.abort_bulk_in(tag, timeout);
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Reads one DEV_DEP_MSG_IN transfer, which may take several bulk transfers.
+    fn read_message(
+        &self,
+        tag: u8,
+        wanted: usize,
+        timeout: Duration,
+    ) -> std::result::Result<(Vec<u8>, bool), String> {
+        let response = self.receive(HEADER_LENGTH + wanted + 3, timeout)?;
+        let (size, end_of_message) = parse_in_header(&response, tag, wanted)?;
+
+        let mut chunk = response[HEADER_LENGTH..].to_vec();
+        while chunk.len() < size {
+            let more = self.receive(size - chunk.len() + 3, timeout)?;
+            if more.is_empty() {
+                return Err("DEV_DEP_MSG_IN ended early".to_string());
+            }
+            chunk.extend_from_slice(&more);
+        }
+        chunk.truncate(size);
+        Ok((chunk, end_of_message))
+    }
+
+    fn trigger(&mut self, timeout: Duration) -> std::result::Result<(), String> {
+        let tag = self.next_tag();
+        let message = header(USB488_TRIGGER, tag, 0, 0, 0);
+        self.send(&message, timeout).inspect_err(|_| {
+            let _ = self.abort_bulk_out(tag);
+        })
+    }
+}
+
+struct Notifications {
+    status_bytes: HashMap<u8, oneshot::Sender<u8>>,
+    callback: Option<ServiceRequestCallback>,
+}
+
+fn notifications_guard(notifications: &Mutex<Notifications>) -> MutexGuard<'_, Notifications> {
+    notifications
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// A USBTMC (and USB488) instrument interface.
+#[napi]
+pub struct UsbtmcDevice {
+    shared: Arc<SharedDevice>,
+    transport: Arc<Mutex<UsbtmcTransport>>,
+    capabilities: UsbtmcCapabilities,
+    interrupt_in: Option<u8>,
+    notifications: Arc<Mutex<Notifications>>,
+    notify_stream: Mutex<Option<InStream>>,
+    status_tag: AtomicU8,
+
+    #[napi(writable = false)]
+    pub interfaceNumber: u8,
+    #[napi(writable = false)]
+    pub isUsb488: bool,
+}
+
+impl UsbtmcDevice {
+    async fn run<T, F>(&self, method: &'static str, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut UsbtmcTransport) -> std::result::Result<T, String> + Send + 'static,
+    {
+        let transport = self.transport.clone();
+        run_blocking(move || {
+            let mut transport = transport
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            f(&mut transport).map_err(|e| format!("{method} error: {e}"))
+        })
+        .await
+    }
+
+    /// Routes interrupt IN notifications: READ_STATUS_BYTE replies and service requests.
+    fn start_notifications(&self) {
+        let Some(interrupt_in) = self.interrupt_in else {
+            return;
+        };
+        let Some(endpoint) = self.shared.get_endpoint::<In>(interrupt_in) else {
+            return;
+        };
+        let notifications = self.notifications.clone();
+        let stream = InStream::start(
+            endpoint,
+            2,
+            NOTIFY_TRANSFERS,
+            move |data| {
+                let [notify, status_byte, ..] = *data else {
+                    return;
+                };
+                let mut notifications = notifications_guard(&notifications);
+                if notify == NOTIFY_SRQ {
+                    if let Some(callback) = notifications.callback.as_ref() {
+                        callback.call(status_byte, ThreadsafeFunctionCallMode::NonBlocking);
+                    }
+                } else if notify & 0x80 != 0 {
+                    if let Some(sender) = notifications.status_bytes.remove(&(notify & 0x7f)) {
+                        let _ = sender.send(status_byte);
+                    }
+                }
+            },
+            |_| {},
+        );
+        *self
+            .notify_stream
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(stream);
+    }
+}
+
+#[napi]
+impl UsbtmcDevice {
+    #[napi(getter)]
+    pub fn capabilities(&self) -> UsbtmcCapabilities {
+        self.capabilities
+    }
+
+    /// Sends a DEV_DEP_MSG_OUT message, aborting the transfer if it fails.
+    #[napi]
+    pub async fn write(&self, data: Uint8Array, timeout: Option<u32>) -> Result<()> {
+        let timeout = Duration::from_millis(timeout.unwrap_or(DEFAULT_TIMEOUT) as u64);
+        self.run("write", move |transport| transport.write(&data, timeout))
+            .await
+    }
+
+    /// Reads a response with REQUEST_DEV_DEP_MSG_IN until the device marks its end, aborting the transfer if it fails.
+    #[napi]
+    pub async fn read(&self, options: Option<UsbtmcReadOptions>) -> Result<Uint8Array> {
+        let max_length = options
+            .as_ref()
+            .and_then(|o| o.maxLength)
+            .unwrap_or(DEFAULT_READ_LENGTH);
+        let term_char = options.as_ref().and_then(|o| o.termChar);
+        let timeout = Duration::from_millis(
+            options
+                .as_ref()
+                .and_then(|o| o.timeout)
+                .unwrap_or(DEFAULT_TIMEOUT) as u64,
+        );
+        if term_char.is_some() && !self.capabilities.termChar {
+            return Err(napi::Error::from_reason(
+                "read error: device doesn't support a term char",
+            ));
+        }
+        let data = self
+            .run("read", move |transport| {
+                transport.read(max_length as usize, term_char, timeout)
+            })
+            .await?;
+        Ok(Uint8Array::from(data))
+    }
+
+    /// Writes a command, adding a newline if it has none, and reads back the response as text
+    /// without its trailing newline, e.g. `query("*IDN?")`.
+    #[napi]
+    pub async fn query(&self, command: String, timeout: Option<u32>) -> Result<String> {
+        let timeout = Duration::from_millis(timeout.unwrap_or(DEFAULT_TIMEOUT) as u64);
+        let mut command = command.into_bytes();
+        if command.last() != Some(&b'\n') {
+            command.push(b'\n');
+        }
+        let response = self
+            .run("query", move |transport| {
+                transport.write(&command, timeout)?;
+                transport.read(DEFAULT_READ_LENGTH as usize, None, timeout)
+            })
+            .await?;
+        Ok(String::from_utf8_lossy(&response)
+            .trim_end_matches(['\r', '\n'])
+            .to_string())
+    }
+
+    /// Clears the device's input and output buffers with INITIATE_CLEAR.
+    #[napi]
+    pub async fn clear(&self, timeout: Option<u32>) -> Result<()> {
+        let timeout = Duration::from_millis(timeout.unwrap_or(DEFAULT_TIMEOUT) as u64);
+        self.run("clear", move |transport| transport.clear(timeout))
+            .await
+    }
+
+    #[napi]
+    pub async fn indicatorPulse(&self) -> Result<()> {
+        if !self.capabilities.indicatorPulse {
+            return Err(napi::Error::from_reason(
+                "indicatorPulse error: not supported by device",
+            ));
+        }
+        self.run("indicatorPulse", |transport| {
+            let status = transport.interface_request(INDICATOR_PULSE, 0, 1)?;
+            match status.first() {
+                Some(&STATUS_SUCCESS) => Ok(()),
+                status => Err(format!("failed with status {:#04x}", status.unwrap_or(&0))),
+            }
+        })
+        .await
+    }
+
+    /// USB488 TRIGGER, the equivalent of GPIB Group Execute Trigger.
+    #[napi]
+    pub async fn trigger(&self, timeout: Option<u32>) -> Result<()> {
+        if !self.capabilities.trigger {
+            return Err(napi::Error::from_reason(
+                "trigger error: not supported by device",
+            ));
+        }
+        let timeout = Duration::from_millis(timeout.unwrap_or(DEFAULT_TIMEOUT) as u64);
+        self.run("trigger", move |transport| transport.trigger(timeout))
+            .await
+    }
+
+    /// USB488 READ_STATUS_BYTE. With an interrupt endpoint the status byte arrives there, as the spec requires.
+    #[napi]
+    pub async fn readStatusByte(&self) -> Result<u8> {
+        if !self.isUsb488 {
+            return Err(napi::Error::from_reason(
+                "readStatusByte error: not a USB488 interface",
+            ));
+        }
+        // bTag for READ_STATUS_BYTE runs 2 to 127
+        let tag = self
+            .status_tag
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |tag| {
+                Some(if tag >= 127 { 2 } else { tag + 1 })
+            })
+            .map_or(2, |tag| if tag >= 127 { 2 } else { tag + 1 });
+
+        let receiver = self.interrupt_in.map(|_| {
+            let (sender, receiver) = oneshot::channel();
+            notifications_guard(&self.notifications)
+                .status_bytes
+                .insert(tag, sender);
+            receiver
+        });
+
+        let response = self
+            .run("readStatusByte", move |transport| {
+                transport.interface_request(READ_STATUS_BYTE, tag as u16, 3)
+            })
+            .await;
+        let response = match response {
+            Ok(response) if response.len() >= 3 && response[0] == STATUS_SUCCESS => response,
+            other => {
+                notifications_guard(&self.notifications)
+                    .status_bytes
+                    .remove(&tag);
+                other?;
+                return Err(napi::Error::from_reason(
+                    "readStatusByte error: request failed",
+                ));
+            }
+        };
+
+        match receiver {
+            Some(receiver) => {
+                let status_byte = tokio::time::timeout(CONTROL_TIMEOUT, receiver).await;
+                notifications_guard(&self.notifications)
+                    .status_bytes
+                    .remove(&tag);
+                status_byte
+                    .ok()
+                    .and_then(|status_byte| status_byte.ok())
+                    .ok_or_else(|| {
+                        napi::Error::from_reason("readStatusByte error: no notification")
+                    })
+            }
+            None => Ok(response[2]),
+        }
+    }
+
+    /// Calls back with the status byte of each USB488 service request (SRQ) notification.
+    #[napi]
+    pub fn onServiceRequest(&self, callback: ServiceRequestCallback) -> Result<()> {
+        if self.interrupt_in.is_none() {
+            return Err(napi::Error::from_reason(
+                "onServiceRequest error: no interrupt IN endpoint",
+            ));
+        }
+        notifications_guard(&self.notifications).callback = Some(callback);
+        Ok(())
+    }
+
+    /// Stops notifications and releases the interface, reattaching any kernel driver.
+    #[napi]
+    pub async fn close(&self) -> Result<()> {
+        let stream = self
+            .notify_stream
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .take();
+        // Its endpoint keeps the interface claimed until the stream has ended
+        if let Some(stream) = stream {
+            stream.finish().await;
+        }
+        notifications_guard(&self.notifications).callback = None;
+        self.shared
+            .release_interface("close", self.interfaceNumber)
+            .await
+    }
+}
+
+#[napi]
+impl UsbDevice {
+    /// Claims a USBTMC interface, reads its capabilities and returns an instrument for it.
+    #[napi]
+    pub async fn openUsbtmc(&self, options: Option<UsbtmcOptions>) -> Result<UsbtmcDevice> {
+        let interface_number = options.as_ref().and_then(|o| o.interfaceNumber);
+        let detach = options
+            .as_ref()
+            .and_then(|o| o.detachKernelDriver)
+            .unwrap_or(true);
+
+        let shared = self.shared().clone();
+        let device = shared
+            .opened_device()
+            .ok_or_else(|| napi::Error::from_reason("openUsbtmc error: invalid state"))?;
+        let layout = run_blocking(move || {
+            let config = device
+                .active_configuration()
+                .map_err(|e| format!("openUsbtmc error: {e}"))?;
+            find_usbtmc_layout(&config, interface_number)
+                .map_err(|e| format!("openUsbtmc error: {e}"))
+        })
+        .await?;
+
+        shared
+            .claim_interface("openUsbtmc", layout.interface, detach)
+            .await?;
+
+        let transport = UsbtmcTransport {
+            shared: shared.clone(),
+            interface: layout.interface,
+            bulk_in: layout.bulk_in,
+            bulk_out: layout.bulk_out,
+            tag: 0,
+        };
+        let usb488 = layout.usb488;
+        let (transport, capabilities) = run_blocking(move || {
+            let capabilities = transport
+                .interface_request(GET_CAPABILITIES, 0, 0x18)
+                .and_then(|data| parse_capabilities(&data, usb488))
+                .map_err(|e| format!("openUsbtmc error: {e}"))?;
+            Ok((transport, capabilities))
+        })
+        .await?;
+
+        let device = UsbtmcDevice {
+            shared,
+            transport: Arc::new(Mutex::new(transport)),
+            capabilities,
+            interrupt_in: layout.interrupt_in,
+            notifications: Arc::new(Mutex::new(Notifications {
+                status_bytes: HashMap::new(),
+                callback: None,
+            })),
+            notify_stream: Mutex::new(None),
+            status_tag: AtomicU8::new(1),
+            interfaceNumber: layout.interface,
+            isUsb488: layout.usb488,
+        };
+        device.start_notifications();
+        Ok(device)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_carries_inverted_tag() {
+        assert_eq!(
+            header(DEV_DEP_MSG_OUT, 0x05, 6, 0x01, 0),
+            [0x01, 0x05, 0xfa, 0x00, 0x06, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(
+            header(REQUEST_DEV_DEP_MSG_IN, 0xff, 0x0001_0000, 0x02, b'\n'),
+            [0x02, 0xff, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x02, 0x0a, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn parses_dev_dep_msg_in_header() {
+        let mut response = header(DEV_DEP_MSG_IN, 0x07, 5, 0x01, 0);
+        response.extend_from_slice(b"1.0\n\0\0\0");
+        assert_eq!(parse_in_header(&response, 0x07, 16), Ok((5, true)));
+
+        response[8] = 0;
+        assert_eq!(parse_in_header(&response, 0x07, 16), Ok((5, false)));
+        assert!(parse_in_header(&response, 0x08, 16).is_err());
+        assert!(parse_in_header(&response, 0x07, 4).is_err());
+        assert!(parse_in_header(&response[..8], 0x07, 16).is_err());
+    }
+
+    #[test]
+    fn parses_usb488_capabilities() {
+        let mut data = vec![0u8; 0x18];
+        data[0] = STATUS_SUCCESS;
+        data[2..4].copy_from_slice(&0x0100u16.to_le_bytes());
+        data[4] = 0x04;
+        data[5] = 0x01;
+        data[12..14].copy_from_slice(&0x0100u16.to_le_bytes());
+        data[14] = 0x07;
+        data[15] = 0x0c;
+
+        let capabilities = parse_capabilities(&data, true).unwrap();
+        assert_eq!(capabilities.usbtmcVersion, 0x0100);
+        assert!(capabilities.indicatorPulse && capabilities.termChar);
+        assert!(!capabilities.talkOnly && !capabilities.listenOnly);
+        assert_eq!(capabilities.usb488Version, Some(0x0100));
+        assert!(capabilities.ieee4882 && capabilities.trigger && capabilities.scpi);
+
+        let plain = parse_capabilities(&data, false).unwrap();
+        assert_eq!(plain.usb488Version, None);
+        assert!(!plain.trigger);
+
+        data[0] = STATUS_PENDING;
+        assert!(parse_capabilities(&data, true).is_err());
+    }
+}
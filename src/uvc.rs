@@ -0,0 +1,945 @@
+mod payload;
+
+use crate::endpoint_stream::InStream;
+use crate::webusb_device::{run_blocking, ControlTarget, SharedDevice, UsbDevice};
+use napi::{
+    bindgen_prelude::*, threadsafe_function::ThreadsafeFunction,
+    threadsafe_function::ThreadsafeFunctionCallMode,
+};
+use napi_derive::napi;
+use nusb::{
+    descriptors::{ConfigurationDescriptor, InterfaceDescriptor, TransferType},
+    transfer::{ControlIn, ControlOut, ControlType, Direction, In, Recipient},
+};
+use payload::FrameAssembler;
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+const CLASS_VIDEO: u8 = 0x0e;
+const SUBCLASS_VIDEO_CONTROL: u8 = 0x01;
+const SUBCLASS_VIDEO_STREAMING: u8 = 0x02;
+
+const CS_INTERFACE: u8 = 0x24;
+const SS_ENDPOINT_COMPANION: u8 = 0x30;
+
+const VC_HEADER: u8 = 0x01;
+const VC_INPUT_TERMINAL: u8 = 0x02;
+const VC_OUTPUT_TERMINAL: u8 = 0x03;
+const VC_SELECTOR_UNIT: u8 = 0x04;
+const VC_PROCESSING_UNIT: u8 = 0x05;
+const VC_EXTENSION_UNIT: u8 = 0x06;
+const VC_ENCODING_UNIT: u8 = 0x07;
+
+const VS_FORMAT_UNCOMPRESSED: u8 = 0x04;
+const VS_FRAME_UNCOMPRESSED: u8 = 0x05;
+const VS_FORMAT_MJPEG: u8 = 0x06;
+const VS_FRAME_MJPEG: u8 = 0x07;
+const VS_FORMAT_FRAME_BASED: u8 = 0x10;
+const VS_FRAME_FRAME_BASED: u8 = 0x11;
+
+const TERMINAL_CAMERA: u16 = 0x0201;
+
+const SET_CUR: u8 = 0x01;
+const GET_CUR: u8 = 0x81;
+const GET_MIN: u8 = 0x82;
+const GET_MAX: u8 = 0x83;
+const GET_RES: u8 = 0x84;
+const GET_LEN: u8 = 0x85;
+const GET_INFO: u8 = 0x86;
+const GET_DEF: u8 = 0x87;
+
+const VS_PROBE_CONTROL: u16 = 0x01;
+const VS_COMMIT_CONTROL: u16 = 0x02;
+
+/// bmHint: keep dwFrameInterval as asked
+const HINT_FRAME_INTERVAL: u16 = 0x0001;
+
+const CLEAR_FEATURE: u8 = 0x01;
+const ENDPOINT_HALT: u16 = 0x00;
+
+const CONTROL_TIMEOUT: Duration = Duration::from_millis(1000);
+const READ_TRANSFERS: usize = 4;
+
+type FrameCallback = ThreadsafeFunction<UvcVideoFrame, (), UvcVideoFrame, napi::Status, false>;
+
+#[napi(object)]
+pub struct UvcOptions {
+    /// The VideoControl interface to use, defaults to the first one
+    pub interfaceNumber: Option<u8>,
+    /// The VideoStreaming interface to use, defaults to the first one in the VideoControl interface's collection
+    pub streamingInterface: Option<u8>,
+    /// Detach kernel drivers (e.g. `uvcvideo`) while the interfaces are open, defaults to `true`
+    pub detachKernelDriver: Option<bool>,
+}
+
+/// A terminal or unit from the VideoControl interface.
+#[napi(object)]
+#[derive(Clone)]
+pub struct UvcEntity {
+    #[napi(writable = false)]
+    pub id: u8,
+    #[napi(
+        js_name = "type",
+        writable = false,
+        ts_type = "'inputTerminal' | 'cameraTerminal' | 'outputTerminal' | 'selectorUnit' | 'processingUnit' | 'extensionUnit' | 'encodingUnit'"
+    )]
+    pub _type: String,
+    /// wTerminalType of terminals
+    #[napi(writable = false)]
+    pub terminalType: Option<u16>,
+    /// Entities this one takes input from
+    #[napi(writable = false)]
+    pub sources: Vec<u8>,
+    /// bmControls, up to its first 32 bits
+    #[napi(writable = false)]
+    pub controls: u32,
+    /// guidExtensionCode of extension units
+    #[napi(writable = false)]
+    pub guid: Option<String>,
+}
+
+#[napi(object)]
+#[derive(Clone)]
+pub struct UvcIntervalRange {
+    #[napi(writable = false)]
+    pub min: u32,
+    #[napi(writable = false)]
+    pub max: u32,
+    #[napi(writable = false)]
+    pub step: u32,
+}
+
+#[napi(object)]
+#[derive(Clone)]
+pub struct UvcFrameSize {
+    #[napi(writable = false)]
+    pub index: u8,
+    #[napi(writable = false)]
+    pub width: u16,
+    #[napi(writable = false)]
+    pub height: u16,
+    #[napi(writable = false)]
+    pub minBitRate: u32,
+    #[napi(writable = false)]
+    pub maxBitRate: u32,
+    /// `0` for frame based formats, which don't give it
+    #[napi(writable = false)]
+    pub maxFrameBufferSize: u32,
+    /// In 100 ns units
+    #[napi(writable = false)]
+    pub defaultInterval: u32,
+    /// Discrete frame intervals in 100 ns units, empty where `intervalRange` is given
+    #[napi(writable = false)]
+    pub intervals: Vec<u32>,
+    /// Continuous frame intervals in 100 ns units
+    #[napi(writable = false)]
+    pub intervalRange: Option<UvcIntervalRange>,
+}
+
+#[napi(object)]
+#[derive(Clone)]
+pub struct UvcFormat {
+    #[napi(writable = false)]
+    pub index: u8,
+    #[napi(
+        js_name = "type",
+        writable = false,
+        ts_type = "'uncompressed' | 'mjpeg' | 'frameBased'"
+    )]
+    pub _type: String,
+    /// The first four bytes of the format GUID, e.g. `YUY2` or `NV12`, and `MJPG` for MJPEG
+    #[napi(writable = false)]
+    pub fourcc: String,
+    #[napi(writable = false)]
+    pub guid: Option<String>,
+    /// `0` for MJPEG
+    #[napi(writable = false)]
+    pub bitsPerPixel: u8,
+    #[napi(writable = false)]
+    pub defaultFrameIndex: u8,
+    #[napi(writable = false)]
+    pub frames: Vec<UvcFrameSize>,
+}
+
+#[napi(object)]
+pub struct UvcStreamOptions {
+    pub formatIndex: u8,
+    pub frameIndex: u8,
+    /// In 100 ns units, defaults to the frame's default interval
+    pub frameInterval: Option<u32>,
+}
+
+/// The negotiated fields of the probe and commit controls.
+#[napi(object)]
+#[derive(Clone)]
+pub struct UvcStreamParameters {
+    #[napi(writable = false)]
+    pub formatIndex: u8,
+    #[napi(writable = false)]
+    pub frameIndex: u8,
+    #[napi(writable = false)]
+    pub frameInterval: u32,
+    #[napi(writable = false)]
+    pub maxVideoFrameSize: u32,
+    #[napi(writable = false)]
+    pub maxPayloadTransferSize: u32,
+    /// From the control where the device gives it (UVC 1.1 and later), otherwise the VideoControl header's
+    #[napi(writable = false)]
+    pub clockFrequency: u32,
+    /// The streaming alternate setting with the bandwidth for `maxPayloadTransferSize`
+    #[napi(writable = false)]
+    pub alternateSetting: u8,
+}
+
+/// A frame reassembled from payloads.
+#[napi(object)]
+pub struct UvcVideoFrame {
+    #[napi(writable = false)]
+    pub data: Uint8Array,
+    /// Presentation time stamp from the payload headers, in device clock ticks
+    #[napi(writable = false)]
+    pub pts: Option<u32>,
+    /// Source time clock from the last payload header carrying one
+    #[napi(writable = false)]
+    pub stc: Option<u32>,
+    /// USB frame number sampled with `stc`
+    #[napi(writable = false)]
+    pub sof: Option<u16>,
+    /// Whether a payload reported an error, a payload header was bad, or an uncompressed frame came up short
+    #[napi(writable = false)]
+    pub error: bool,
+}
+
+/// A VideoStreaming alternate setting with an IN endpoint.
+#[derive(Clone, Copy)]
+struct StreamingSetting {
+    alternate_setting: u8,
+    endpoint: u8,
+    bulk: bool,
+    /// Bytes per (micro)frame for isochronous endpoints
+    bandwidth: usize,
+}
+
+struct UvcLayout {
+    control: u8,
+    streaming: u8,
+    version: u16,
+    clock_frequency: u32,
+    entities: Vec<UvcEntity>,
+    formats: Vec<UvcFormat>,
+    settings: Vec<StreamingSetting>,
+}
+
+fn format_guid(guid: &[u8]) -> String {
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{}",
+        u32::from_le_bytes([guid[0], guid[1], guid[2], guid[3]]),
+        u16::from_le_bytes([guid[4], guid[5]]),
+        u16::from_le_bytes([guid[6], guid[7]]),
+        guid[8],
+        guid[9],
+        guid[10..16]
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>()
+    )
+}
+
+fn bitmap(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .take(4)
+        .enumerate()
+        .fold(0, |bits, (i, &b)| bits | (b as u32) << (i * 8))
+}
+
+fn parse_entity(d: &[u8]) -> Option<UvcEntity> {
+    let entity = |_type: &str, terminal_type, sources: &[u8], controls, guid| {
+        Some(UvcEntity {
+            id: d[3],
+            _type: _type.to_string(),
+            terminalType: terminal_type,
+            sources: sources.to_vec(),
+            controls,
+            guid,
+        })
+    };
+    match d[2] {
+        VC_INPUT_TERMINAL if d.len() >= 8 => {
+            let terminal_type = u16::from_le_bytes([d[4], d[5]]);
+            if terminal_type == TERMINAL_CAMERA && d.len() >= 15 {
+                let size = d[14] as usize;
+                let controls = bitmap(d.get(15..15 + size).unwrap_or(&[]));
+                entity("cameraTerminal", Some(terminal_type), &[], controls, None)
+            } else {
+                entity("inputTerminal", Some(terminal_type), &[], 0, None)
+            }
+        }
+        VC_OUTPUT_TERMINAL if d.len() >= 9 => entity(
+            "outputTerminal",
+            Some(u16::from_le_bytes([d[4], d[5]])),
+            &d[7..8],
+            0,
+            None,
+        ),
+        VC_SELECTOR_UNIT if d.len() >= 5 => {
+            let pins = d[4] as usize;
+            entity("selectorUnit", None, d.get(5..5 + pins)?, 0, None)
+        }
+        VC_PROCESSING_UNIT if d.len() >= 8 => {
+            let size = d[7] as usize;
+            let controls = bitmap(d.get(8..8 + size).unwrap_or(&[]));
+            entity("processingUnit", None, &d[4..5], controls, None)
+        }
+        VC_EXTENSION_UNIT if d.len() >= 22 => {
+            let pins = d[21] as usize;
+            let sources = d.get(22..22 + pins)?;
+            let size = *d.get(22 + pins)? as usize;
+            let controls = bitmap(d.get(23 + pins..23 + pins + size).unwrap_or(&[]));
+            let guid = Some(format_guid(&d[4..20]));
+            entity("extensionUnit", None, sources, controls, guid)
+        }
+        VC_ENCODING_UNIT if d.len() >= 10 => {
+            let size = d[6] as usize;
+            let controls = bitmap(d.get(7..7 + size).unwrap_or(&[]));
+            entity("encodingUnit", None, &d[4..5], controls, None)
+        }
+        _ => None,
+    }
+}
+
+fn parse_frame(d: &[u8]) -> Option<UvcFrameSize> {
+    if d.len() < 26 {
+        return None;
+    }
+    let u16_at = |i: usize| u16::from_le_bytes([d[i], d[i + 1]]);
+    let u32_at = |i: usize| u32::from_le_bytes([d[i], d[i + 1], d[i + 2], d[i + 3]]);
+    // Frame based descriptors drop dwMaxVideoFrameBufferSize and add dwBytesPerLine after the interval type
+    let frame_based = d[2] == VS_FRAME_FRAME_BASED;
+    let (buffer_size, default_interval, interval_type) = if frame_based {
+        (0, 17, 21)
+    } else {
+        (u32_at(17), 21, 25)
+    };
+    // A count of 0 means a continuous range given as minimum, maximum and step
+    let count = d[interval_type] as usize;
+    let intervals = d.get(26..26 + if count == 0 { 3 } else { count } * 4)?;
+    let interval = |i: usize| {
+        u32::from_le_bytes([
+            intervals[i * 4],
+            intervals[i * 4 + 1],
+            intervals[i * 4 + 2],
+            intervals[i * 4 + 3],
+        ])
+    };
+    Some(UvcFrameSize {
+        index: d[3],
+        width: u16_at(5),
+        height: u16_at(7),
+        minBitRate: u32_at(9),
+        maxBitRate: u32_at(13),
+        maxFrameBufferSize: buffer_size,
+        defaultInterval: u32_at(default_interval),
+        intervals: (0..count).map(interval).collect(),
+        intervalRange: (count == 0).then(|| UvcIntervalRange {
+            min: interval(0),
+            max: interval(1),
+            step: interval(2),
+        }),
+    })
+}
+
+fn parse_formats(alt: &InterfaceDescriptor) -> Vec<UvcFormat> {
+    let mut formats: Vec<UvcFormat> = Vec::new();
+    for d in alt.descriptors() {
+        if d.descriptor_type() != CS_INTERFACE || d.len() < 5 {
+            continue;
+        }
+        match d[2] {
+            VS_FORMAT_UNCOMPRESSED | VS_FORMAT_FRAME_BASED if d.len() >= 23 => {
+                let guid = &d[5..21];
+                formats.push(UvcFormat {
+                    index: d[3],
+                    _type: if d[2] == VS_FORMAT_UNCOMPRESSED {
+                        "uncompressed"
+                    } else {
+                        "frameBased"
+                    }
+                    .to_string(),
+                    fourcc: String::from_utf8_lossy(&guid[..4])
+                        .trim_end_matches(['\0', ' '])
+                        .to_string(),
+                    guid: Some(format_guid(guid)),
+                    bitsPerPixel: d[21],
+                    defaultFrameIndex: d[22],
+                    frames: Vec::new(),
+                });
+            }
+            VS_FORMAT_MJPEG if d.len() >= 7 => formats.push(UvcFormat {
+                index: d[3],
+                _type: "mjpeg".to_string(),
+                fourcc: "MJPG".to_string(),
+                guid: None,
+                bitsPerPixel: 0,
+                defaultFrameIndex: d[6],
+                frames: Vec::new(),
+            }),
+            // Frame descriptors follow the format they belong to
+            VS_FRAME_UNCOMPRESSED | VS_FRAME_MJPEG | VS_FRAME_FRAME_BASED => {
+                if let (Some(format), Some(frame)) = (formats.last_mut(), parse_frame(&d)) {
+                    format.frames.push(frame);
+                }
+            }
+            _ => {}
+        }
+    }
+    formats
+}
+
+fn find_uvc_layout(
+    config: &ConfigurationDescriptor,
+    interface_number: Option<u8>,
+    streaming_interface: Option<u8>,
+) -> std::result::Result<UvcLayout, String> {
+    let control = config
+        .interface_alt_settings()
+        .filter(|alt| alt.alternate_setting() == 0)
+        .filter(|alt| interface_number.is_none_or(|n| alt.interface_number() == n))
+        .find(|alt| alt.class() == CLASS_VIDEO && alt.subclass() == SUBCLASS_VIDEO_CONTROL)
+        .ok_or("no UVC interface found")?;
+
+    let mut version = 0x0100;
+    let mut clock_frequency = 0;
+    let mut collection = Vec::new();
+    let mut entities = Vec::new();
+    for d in control.descriptors() {
+        if d.descriptor_type() != CS_INTERFACE || d.len() < 4 {
+            continue;
+        }
+        if d[2] == VC_HEADER && d.len() >= 12 {
+            version = u16::from_le_bytes([d[3], d[4]]);
+            clock_frequency = u32::from_le_bytes([d[7], d[8], d[9], d[10]]);
+            collection = d.get(12..12 + d[11] as usize).unwrap_or(&[]).to_vec();
+        } else if let Some(entity) = parse_entity(&d) {
+            entities.push(entity);
+        }
+    }
+
+    let streaming = match streaming_interface {
+        Some(n) if collection.contains(&n) => n,
+        Some(n) => return Err(format!("interface {n} is not a streaming interface")),
+        None => *collection.first().ok_or("no streaming interface found")?,
+    };
+    let streaming_alts = config
+        .interface_alt_settings()
+        .filter(|alt| {
+            alt.interface_number() == streaming
+                && alt.class() == CLASS_VIDEO
+                && alt.subclass() == SUBCLASS_VIDEO_STREAMING
+        })
+        .collect::<Vec<_>>();
+    let formats = streaming_alts
+        .iter()
+        .find(|alt| alt.alternate_setting() == 0)
+        .map(parse_formats)
+        .ok_or("no streaming interface found")?;
+
+    let settings = streaming_alts
+        .iter()
+        .filter_map(|alt| {
+            let ep = alt.endpoints().find(|ep| {
+                ep.direction() == Direction::In
+                    && matches!(
+                        ep.transfer_type(),
+                        TransferType::Bulk | TransferType::Isochronous
+                    )
+            })?;
+            // SuperSpeed endpoints give their bandwidth in the companion descriptor
+            let companion = ep
+                .descriptors()
+                .find(|d| d.descriptor_type() == SS_ENDPOINT_COMPANION && d.len() >= 6)
+                .map(|d| u16::from_le_bytes([d[4], d[5]]) as usize);
+            Some(StreamingSetting {
+                alternate_setting: alt.alternate_setting(),
+                endpoint: ep.address() & 0x7f,
+                bulk: ep.transfer_type() == TransferType::Bulk,
+                bandwidth: companion
+                    .unwrap_or(ep.max_packet_size() * ep.packets_per_microframe() as usize),
+            })
+        })
+        .collect::<Vec<_>>();
+    if settings.is_empty() {
+        return Err(format!("no video endpoint found for interface {streaming}"));
+    }
+
+    Ok(UvcLayout {
+        control: control.interface_number(),
+        streaming,
+        version,
+        clock_frequency,
+        entities,
+        formats,
+        settings,
+    })
+}
+
+/// Picks the bulk setting, or the isochronous one with the least bandwidth that carries `payload_size`.
+fn select_setting(settings: &[StreamingSetting], payload_size: usize) -> Option<StreamingSetting> {
+    if let Some(bulk) = settings.iter().find(|setting| setting.bulk) {
+        return Some(*bulk);
+    }
+    settings
+        .iter()
+        .filter(|setting| setting.bandwidth >= payload_size)
+        .min_by_key(|setting| setting.bandwidth)
+        .or_else(|| settings.iter().max_by_key(|setting| setting.bandwidth))
+        .copied()
+}
+
+/// Length of the probe and commit controls by UVC version.
+fn probe_len(version: u16) -> u16 {
+    match version {
+        0..=0x010f => 26,
+        0x0110..=0x014f => 34,
+        _ => 48,
+    }
+}
+
+fn class_get(
+    target: &ControlTarget,
+    request: u8,
+    selector: u16,
+    index: u16,
+    length: u16,
+) -> std::result::Result<Vec<u8>, String> {
+    let request = ControlIn {
+        control_type: ControlType::Class,
+        recipient: Recipient::Interface,
+        request,
+        value: selector << 8,
+        index,
+        length,
+    };
+    target
+        .control_in_blocking(request, CONTROL_TIMEOUT)
+        .map_err(|e| e.to_string())
+}
+
+fn class_set(
+    target: &ControlTarget,
+    selector: u16,
+    index: u16,
+    data: &[u8],
+) -> std::result::Result<(), String> {
+    let request = ControlOut {
+        control_type: ControlType::Class,
+        recipient: Recipient::Interface,
+        request: SET_CUR,
+        value: selector << 8,
+        index,
+        data,
+    };
+    target
+        .control_out_blocking(request, CONTROL_TIMEOUT)
+        .map_err(|e| e.to_string())
+}
+
+struct StreamState {
+    /// The device's last answer to PROBE, committed as it is
+    probe: Option<Vec<u8>>,
+    committed: Option<UvcStreamParameters>,
+    stream: Option<InStream>,
+    /// The setting streamed from, to stop it the way its transfer type needs
+    setting: Option<StreamingSetting>,
+}
+
+/// A USB Video Class camera.
+#[napi]
+pub struct UvcCamera {
+    shared: Arc<SharedDevice>,
+    layout: UvcLayout,
+    state: Mutex<StreamState>,
+
+    #[napi(writable = false)]
+    pub controlInterface: u8,
+    #[napi(writable = false)]
+    pub streamingInterface: u8,
+    /// bcdUVC
+    #[napi(writable = false)]
+    pub version: u16,
+    /// dwClockFrequency from the VideoControl header, in Hz
+    #[napi(writable = false)]
+    pub clockFrequency: u32,
+}
+
+impl UvcCamera {
+    fn state(&self) -> MutexGuard<'_, StreamState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn target(&self, method: &'static str) -> Result<ControlTarget> {
+        self.shared
+            .control_target(Recipient::Interface, self.layout.control as u16)
+            .ok_or_else(|| napi::Error::from_reason(format!("{method} error: invalid state")))
+    }
+
+    fn parameters(&self, probe: &[u8]) -> UvcStreamParameters {
+        let u32_at = |i: usize| {
+            probe
+                .get(i..i + 4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        };
+        let max_payload = u32_at(22).unwrap_or(0);
+        UvcStreamParameters {
+            formatIndex: probe.get(2).copied().unwrap_or(0),
+            frameIndex: probe.get(3).copied().unwrap_or(0),
+            frameInterval: u32_at(4).unwrap_or(0),
+            maxVideoFrameSize: u32_at(18).unwrap_or(0),
+            maxPayloadTransferSize: max_payload,
+            clockFrequency: u32_at(26)
+                .filter(|&frequency| frequency != 0)
+                .unwrap_or(self.layout.clock_frequency),
+            alternateSetting: select_setting(&self.layout.settings, max_payload as usize)
+                .map(|setting| setting.alternate_setting)
+                .unwrap_or(0),
+        }
+    }
+
+    async fn run_probe(&self, options: UvcStreamOptions) -> Result<UvcStreamParameters> {
+        let format = self
+            .layout
+            .formats
+            .iter()
+            .find(|format| format.index == options.formatIndex)
+            .ok_or_else(|| {
+                napi::Error::from_reason(format!("probe error: no format {}", options.formatIndex))
+            })?;
+        let frame = format
+            .frames
+            .iter()
+            .find(|frame| frame.index == options.frameIndex)
+            .ok_or_else(|| {
+                napi::Error::from_reason(format!(
+                    "probe error: no frame {} in format {}",
+                    options.frameIndex, options.formatIndex
+                ))
+            })?;
+
+        let len = probe_len(self.layout.version);
+        let mut probe = vec![0u8; len as usize];
+        probe[0..2].copy_from_slice(&HINT_FRAME_INTERVAL.to_le_bytes());
+        probe[2] = options.formatIndex;
+        probe[3] = options.frameIndex;
+        probe[4..8].copy_from_slice(
+            &options
+                .frameInterval
+                .unwrap_or(frame.defaultInterval)
+                .to_le_bytes(),
+        );
+
+        let target = self.target("probe")?;
+        let streaming = self.layout.streaming as u16;
+        let probe = run_blocking(move || {
+            class_set(&target, VS_PROBE_CONTROL, streaming, &probe)
+                .and_then(|_| class_get(&target, GET_CUR, VS_PROBE_CONTROL, streaming, len))
+                .map_err(|e| format!("probe error: {e}"))
+        })
+        .await?;
+
+        let parameters = self.parameters(&probe);
+        self.state().probe = Some(probe);
+        Ok(parameters)
+    }
+
+    async fn run_commit(&self) -> Result<UvcStreamParameters> {
+        let probe = self
+            .state()
+            .probe
+            .clone()
+            .ok_or_else(|| napi::Error::from_reason("commit error: nothing probed"))?;
+        let target = self.target("commit")?;
+        let streaming = self.layout.streaming as u16;
+        let committed = probe.clone();
+        run_blocking(move || {
+            class_set(&target, VS_COMMIT_CONTROL, streaming, &committed)
+                .map_err(|e| format!("commit error: {e}"))
+        })
+        .await?;
+
+        let parameters = self.parameters(&probe);
+        self.state().committed = Some(parameters.clone());
+        Ok(parameters)
+    }
+}
+
+#[napi]
+impl UvcCamera {
+    #[napi(getter)]
+    pub fn entities(&self) -> Vec<UvcEntity> {
+        self.layout.entities.clone()
+    }
+
+    #[napi(getter)]
+    pub fn formats(&self) -> Vec<UvcFormat> {
+        self.layout.formats.clone()
+    }
+
+    /// SET_CUR then GET_CUR on the probe control, resolving with what the device offers for the format,
+    /// frame size and interval asked for.
+    #[napi]
+    pub async fn probe(&self, options: UvcStreamOptions) -> Result<UvcStreamParameters> {
+        self.run_probe(options).await
+    }
+
+    /// SET_CUR on the commit control with the device's last probe answer.
+    #[napi]
+    pub async fn commit(&self) -> Result<UvcStreamParameters> {
+        self.run_commit().await
+    }
+
+    /// Starts streaming, calling back with each reassembled frame. With `options` the stream is probed
+    /// and committed first, otherwise the last commit is used. Only bulk endpoints can be streamed from.
+    #[napi]
+    pub async fn startStreaming(
+        &self,
+        onFrame: FrameCallback,
+        options: Option<UvcStreamOptions>,
+    ) -> Result<UvcStreamParameters> {
+        if self.state().stream.is_some() {
+            return Err(napi::Error::from_reason(
+                "startStreaming error: already streaming",
+            ));
+        }
+        let parameters = match options {
+            Some(options) => {
+                self.run_probe(options).await?;
+                self.run_commit().await?
+            }
+            None => self.state().committed.clone().ok_or_else(|| {
+                napi::Error::from_reason("startStreaming error: nothing committed")
+            })?,
+        };
+
+        let setting = select_setting(
+            &self.layout.settings,
+            parameters.maxPayloadTransferSize as usize,
+        )
+        .ok_or_else(|| napi::Error::from_reason("startStreaming error: no video endpoint"))?;
+        if !setting.bulk {
+            return Err(napi::Error::from_reason(format!(
+                "startStreaming error: isochronous streaming (alternate setting {}) is not supported",
+                setting.alternate_setting
+            )));
+        }
+        if setting.alternate_setting != 0 {
+            self.shared
+                .select_alternate_interface(
+                    "startStreaming",
+                    self.layout.streaming,
+                    setting.alternate_setting,
+                )
+                .await?;
+        }
+
+        let endpoint = self
+            .shared
+            .get_endpoint::<In>(setting.endpoint)
+            .ok_or_else(|| napi::Error::from_reason("startStreaming error: invalid state"))?;
+
+        // Uncompressed frames have a known size, anything shorter lost payloads
+        let expected_len = self
+            .layout
+            .formats
+            .iter()
+            .find(|format| format.index == parameters.formatIndex && format._type == "uncompressed")
+            .and_then(|format| {
+                let frame = format
+                    .frames
+                    .iter()
+                    .find(|frame| frame.index == parameters.frameIndex)?;
+                Some(
+                    frame.width as usize * frame.height as usize * format.bitsPerPixel as usize / 8,
+                )
+            });
+        let mut assembler = FrameAssembler::new();
+        let stream = InStream::start(
+            endpoint,
+            parameters.maxPayloadTransferSize as usize,
+            READ_TRANSFERS,
+            move |payload| {
+                for frame in assembler.payload(payload) {
+                    let short = expected_len.is_some_and(|len| frame.data.len() < len);
+                    onFrame.call(
+                        UvcVideoFrame {
+                            pts: frame.pts,
+                            stc: frame.scr.map(|(stc, _)| stc),
+                            sof: frame.scr.map(|(_, sof)| sof),
+                            error: frame.error || short,
+                            data: Uint8Array::new(frame.data),
+                        },
+                        ThreadsafeFunctionCallMode::NonBlocking,
+                    );
+                }
+            },
+            |_| {},
+        );
+
+        let mut state = self.state();
+        state.stream = Some(stream);
+        state.setting = Some(setting);
+        Ok(parameters)
+    }
+
+    /// Stops streaming. Bulk streams are stopped with CLEAR_FEATURE(ENDPOINT_HALT) on their endpoint.
+    #[napi]
+    pub async fn stopStreaming(&self) -> Result<()> {
+        let (stream, setting) = {
+            let mut state = self.state();
+            (state.stream.take(), state.setting.take())
+        };
+        let (Some(stream), Some(setting)) = (stream, setting) else {
+            return Ok(());
+        };
+        // Its endpoint keeps the interface claimed until the stream has ended
+        stream.finish().await;
+
+        let address = setting.endpoint | 0x80;
+        let target = self
+            .shared
+            .control_target(Recipient::Endpoint, address as u16)
+            .ok_or_else(|| napi::Error::from_reason("stopStreaming error: invalid state"))?;
+        run_blocking(move || {
+            let request = ControlOut {
+                control_type: ControlType::Standard,
+                recipient: Recipient::Endpoint,
+                request: CLEAR_FEATURE,
+                value: ENDPOINT_HALT,
+                index: address as u16,
+                data: &[],
+            };
+            target
+                .control_out_blocking(request, CONTROL_TIMEOUT)
+                .map_err(|e| format!("stopStreaming error: {e}"))
+        })
+        .await?;
+        if setting.alternate_setting != 0 {
+            self.shared
+                .select_alternate_interface("stopStreaming", self.layout.streaming, 0)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Reads a control of a terminal or unit. `request` is one of `'cur'`, `'min'`, `'max'`, `'res'`,
+    /// `'len'`, `'info'` or `'def'`, defaulting to `'cur'`.
+    #[napi]
+    pub async fn getControl(
+        &self,
+        entityId: u8,
+        selector: u8,
+        length: u16,
+        #[napi(ts_arg_type = "'cur' | 'min' | 'max' | 'res' | 'len' | 'info' | 'def'")]
+        request: Option<String>,
+    ) -> Result<Uint8Array> {
+        let request = match request.as_deref().unwrap_or("cur") {
+            "cur" => GET_CUR,
+            "min" => GET_MIN,
+            "max" => GET_MAX,
+            "res" => GET_RES,
+            "len" => GET_LEN,
+            "info" => GET_INFO,
+            "def" => GET_DEF,
+            request => {
+                return Err(napi::Error::from_reason(format!(
+                    "getControl error: invalid request {request}"
+                )))
+            }
+        };
+        let target = self.target("getControl")?;
+        let index = (entityId as u16) << 8 | self.layout.control as u16;
+        let data = run_blocking(move || {
+            class_get(&target, request, selector as u16, index, length)
+                .map_err(|e| format!("getControl error: {e}"))
+        })
+        .await?;
+        Ok(Uint8Array::new(data))
+    }
+
+    /// SET_CUR on a control of a terminal or unit.
+    #[napi]
+    pub async fn setControl(&self, entityId: u8, selector: u8, data: Uint8Array) -> Result<()> {
+        let target = self.target("setControl")?;
+        let index = (entityId as u16) << 8 | self.layout.control as u16;
+        run_blocking(move || {
+            class_set(&target, selector as u16, index, &data)
+                .map_err(|e| format!("setControl error: {e}"))
+        })
+        .await
+    }
+
+    /// Stops streaming and releases the interfaces, reattaching any kernel driver.
+    #[napi]
+    pub async fn close(&self) -> Result<()> {
+        // Best effort, the device may already be gone
+        let _ = self.stopStreaming().await;
+        self.shared
+            .release_interface("close", self.layout.streaming)
+            .await?;
+        self.shared
+            .release_interface("close", self.layout.control)
+            .await
+    }
+}
+
+#[napi]
+impl UsbDevice {
+    /// Claims a UVC camera's VideoControl interface and one of its VideoStreaming interfaces.
+    #[napi]
+    pub async fn openUvc(&self, options: Option<UvcOptions>) -> Result<UvcCamera> {
+        let interface_number = options.as_ref().and_then(|o| o.interfaceNumber);
+        let streaming_interface = options.as_ref().and_then(|o| o.streamingInterface);
+        let detach = options
+            .as_ref()
+            .and_then(|o| o.detachKernelDriver)
+            .unwrap_or(true);
+
+        let shared = self.shared().clone();
+        let device = shared
+            .opened_device()
+            .ok_or_else(|| napi::Error::from_reason("openUvc error: invalid state"))?;
+        let layout = run_blocking(move || {
+            let config = device
+                .active_configuration()
+                .map_err(|e| format!("openUvc error: {e}"))?;
+            find_uvc_layout(&config, interface_number, streaming_interface)
+                .map_err(|e| format!("openUvc error: {e}"))
+        })
+        .await?;
+
+        shared
+            .claim_interface("openUvc", layout.control, detach)
+            .await?;
+        shared
+            .claim_interface("openUvc", layout.streaming, detach)
+            .await?;
+
+        Ok(UvcCamera {
+            shared,
+            controlInterface: layout.control,
+            streamingInterface: layout.streaming,
+            version: layout.version,
+            clockFrequency: layout.clock_frequency,
+            layout,
+            state: Mutex::new(StreamState {
+                probe: None,
+                committed: None,
+                stream: None,
+                setting: None,
+            }),
+        })
+    }
+}
@@ -0,0 +1,213 @@
+const HEADER_FID: u8 = 0x01;
+const HEADER_EOF: u8 = 0x02;
+const HEADER_PTS: u8 = 0x04;
+const HEADER_SCR: u8 = 0x08;
+const HEADER_ERR: u8 = 0x40;
+
+/// The fields of a payload header that frame assembly needs.
+pub(super) struct PayloadHeader {
+    pub(super) len: usize,
+    pub(super) fid: bool,
+    pub(super) eof: bool,
+    pub(super) error: bool,
+    pub(super) pts: Option<u32>,
+    /// Source clock: the STC value and the 11 bit SOF counter
+    pub(super) scr: Option<(u32, u16)>,
+}
+
+pub(super) fn parse_header(payload: &[u8]) -> Option<PayloadHeader> {
+    let (&len, rest) = payload.split_first()?;
+    let len = len as usize;
+    if len < 2 || len > payload.len() {
+        return None;
+    }
+    let info = rest[0];
+    let mut fields = &payload[2..len];
+    let mut take = |n: usize| {
+        let (field, rest) = fields.split_at_checked(n)?;
+        fields = rest;
+        Some(field)
+    };
+    let pts = if info & HEADER_PTS != 0 {
+        Some(u32::from_le_bytes(take(4)?.try_into().ok()?))
+    } else {
+        None
+    };
+    let scr = if info & HEADER_SCR != 0 {
+        let scr = take(6)?;
+        Some((
+            u32::from_le_bytes([scr[0], scr[1], scr[2], scr[3]]),
+            u16::from_le_bytes([scr[4], scr[5]]) & 0x07ff,
+        ))
+    } else {
+        None
+    };
+    Some(PayloadHeader {
+        len,
+        fid: info & HEADER_FID != 0,
+        eof: info & HEADER_EOF != 0,
+        error: info & HEADER_ERR != 0,
+        pts,
+        scr,
+    })
+}
+
+/// A reassembled video frame.
+pub(super) struct Frame {
+    pub(super) data: Vec<u8>,
+    pub(super) pts: Option<u32>,
+    pub(super) scr: Option<(u32, u16)>,
+    pub(super) error: bool,
+}
+
+/// Joins payloads into frames. A frame ends at a payload with EOF set, or when the frame ID
+/// toggles for devices that never set EOF.
+pub(super) struct FrameAssembler {
+    fid: Option<bool>,
+    frame: Option<Frame>,
+}
+
+impl FrameAssembler {
+    pub(super) fn new() -> Self {
+        Self {
+            fid: None,
+            frame: None,
+        }
+    }
+
+    pub(super) fn payload(&mut self, payload: &[u8]) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        let Some(header) = parse_header(payload) else {
+            // Without a header the payload can't be placed, so the frame it belonged to is damaged
+            if let Some(frame) = self.frame.as_mut() {
+                frame.error = true;
+            }
+            return frames;
+        };
+
+        if self.fid.is_some_and(|fid| fid != header.fid) {
+            frames.extend(self.take_frame());
+        }
+        self.fid = Some(header.fid);
+
+        let frame = self.frame.get_or_insert_with(|| Frame {
+            data: Vec::new(),
+            pts: None,
+            scr: None,
+            error: false,
+        });
+        frame.data.extend_from_slice(&payload[header.len..]);
+        frame.pts = frame.pts.or(header.pts);
+        frame.scr = header.scr.or(frame.scr);
+        frame.error |= header.error;
+
+        if header.eof {
+            frames.extend(self.take_frame());
+        }
+        frames
+    }
+
+    /// Header-only payloads between frames leave nothing worth passing on.
+    fn take_frame(&mut self) -> Option<Frame> {
+        self.frame.take().filter(|frame| !frame.data.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(payloads: &[&[u8]]) -> Vec<Frame> {
+        let mut assembler = FrameAssembler::new();
+        payloads
+            .iter()
+            .flat_map(|payload| assembler.payload(payload))
+            .collect()
+    }
+
+    #[test]
+    fn parses_a_full_header() {
+        let header = parse_header(&[
+            0x0c, 0x8f, 0x78, 0x56, 0x34, 0x12, 0x01, 0x00, 0x00, 0x00, 0xff, 0xff, 0xaa,
+        ])
+        .unwrap();
+        assert_eq!(header.len, 12);
+        assert!(header.fid && header.eof && !header.error);
+        assert_eq!(header.pts, Some(0x1234_5678));
+        // Only 11 bits of the SOF counter are defined
+        assert_eq!(header.scr, Some((1, 0x07ff)));
+    }
+
+    #[test]
+    fn parses_a_minimal_header() {
+        let header = parse_header(&[0x02, 0xc0]).unwrap();
+        assert_eq!(header.len, 2);
+        assert!(!header.fid && !header.eof && header.error);
+        assert_eq!(header.pts, None);
+        assert_eq!(header.scr, None);
+    }
+
+    #[test]
+    fn rejects_short_headers() {
+        assert!(parse_header(&[]).is_none());
+        assert!(parse_header(&[0x01, 0x80]).is_none());
+        assert!(parse_header(&[0x04, 0x80, 0x00]).is_none());
+        // PTS flagged without room for it
+        assert!(parse_header(&[0x05, 0x84, 0x01, 0x02, 0x03]).is_none());
+    }
+
+    #[test]
+    fn ends_frames_at_eof() {
+        let frames = frames(&[
+            &[0x06, 0x84, 0x01, 0x00, 0x00, 0x00, 0xa0, 0xa1],
+            &[0x06, 0x84, 0x02, 0x00, 0x00, 0x00, 0xa2],
+            &[0x02, 0x82, 0xa3],
+        ]);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].data, [0xa0, 0xa1, 0xa2, 0xa3]);
+        // The first PTS belongs to the frame
+        assert_eq!(frames[0].pts, Some(1));
+        assert!(!frames[0].error);
+    }
+
+    #[test]
+    fn ends_frames_when_the_frame_id_toggles() {
+        let frames = frames(&[
+            &[0x02, 0x80, 1],
+            &[0x02, 0x80, 2],
+            &[0x02, 0x81, 3],
+            &[0x02, 0x80, 4],
+        ]);
+        let data: Vec<_> = frames.iter().map(|frame| frame.data.clone()).collect();
+        assert_eq!(data, [vec![1, 2], vec![3]]);
+    }
+
+    #[test]
+    fn keeps_the_latest_source_clock() {
+        let frames = frames(&[
+            &[0x08, 0x88, 0x10, 0x00, 0x00, 0x00, 0x01, 0x00, 1],
+            &[0x08, 0x8a, 0x20, 0x00, 0x00, 0x00, 0x02, 0x00, 2],
+        ]);
+        assert_eq!(frames[0].scr, Some((0x20, 2)));
+    }
+
+    #[test]
+    fn skips_header_only_payloads() {
+        assert!(frames(&[&[0x02, 0x82], &[0x02, 0x83]]).is_empty());
+    }
+
+    #[test]
+    fn marks_damaged_frames() {
+        let frames = frames(&[
+            &[0x02, 0x80, 1],
+            &[0x00],
+            &[0x02, 0x82, 2],
+            &[0x02, 0xc0, 3],
+            &[0x02, 0x82, 4],
+            &[0x02, 0x80, 5],
+            &[0x02, 0x82, 6],
+        ]);
+        let errors: Vec<_> = frames.iter().map(|frame| frame.error).collect();
+        assert_eq!(errors, [true, true, false]);
+    }
+}
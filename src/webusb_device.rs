@@ -1,10 +1,19 @@
+use crate::sequence::OperationQueue;
+use crate::transfer_pool::{
+    buffer_to_js, pooled_transfer_in, pooled_transfer_out, PooledDirection, PooledEndpoint,
+    TransferPool,
+};
 use napi::bindgen_prelude::*;
 use napi_derive::napi;
 use nusb::{
     descriptors::language_id::US_ENGLISH, descriptors::TransferType, transfer::Buffer,
     transfer::Bulk, transfer::Interrupt, MaybeFuture,
 };
-use std::time::Duration;
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock, Mutex, MutexGuard, OnceLock, Weak},
+    time::Duration,
+};
 
 const ENDPOINT_NUMBER_MASK: u8 = 0x7f;
 const DESC_TIMEOUT: Duration = Duration::from_millis(100);
@@ -27,7 +36,7 @@ fn get_string(device: &nusb::Device, index: Option<std::num::NonZeroU8>) -> Resu
     }
 }
 
-fn control_type_from_request_type(request_type: &str) -> nusb::transfer::ControlType {
+pub(crate) fn control_type_from_request_type(request_type: &str) -> nusb::transfer::ControlType {
     match request_type {
         "standard" => nusb::transfer::ControlType::Standard,
         "class" => nusb::transfer::ControlType::Class,
@@ -36,7 +45,7 @@ fn control_type_from_request_type(request_type: &str) -> nusb::transfer::Control
     }
 }
 
-fn recipient_from_request_recipient(recipient: &str) -> nusb::transfer::Recipient {
+pub(crate) fn recipient_from_request_recipient(recipient: &str) -> nusb::transfer::Recipient {
     match recipient {
         "device" => nusb::transfer::Recipient::Device,
         "interface" => nusb::transfer::Recipient::Interface,
@@ -46,7 +55,7 @@ fn recipient_from_request_recipient(recipient: &str) -> nusb::transfer::Recipien
     }
 }
 
-fn control_in_setup(
+pub(crate) fn control_in_setup(
     setup: &UsbControlTransferParameters,
     control_type: nusb::transfer::ControlType,
     recipient: nusb::transfer::Recipient,
@@ -63,7 +72,7 @@ fn control_in_setup(
     }
 }
 
-fn control_out_setup<'a>(
+pub(crate) fn control_out_setup<'a>(
     setup: &UsbControlTransferParameters,
     control_type: nusb::transfer::ControlType,
     recipient: nusb::transfer::Recipient,
@@ -94,20 +103,70 @@ where
 /// Enum wrapping either a Bulk or Interrupt endpoint.
 /// Both implement BulkOrInterrupt and share identical method signatures,
 /// but are different concrete types in Rust's type system.
-enum AnyEndpoint<DIR: nusb::transfer::EndpointDirection> {
+pub(crate) enum AnyEndpoint<DIR: nusb::transfer::EndpointDirection> {
     Bulk(nusb::Endpoint<nusb::transfer::Bulk, DIR>),
     Interrupt(nusb::Endpoint<nusb::transfer::Interrupt, DIR>),
 }
 
 impl<DIR: nusb::transfer::EndpointDirection> AnyEndpoint<DIR> {
-    fn max_packet_size(&self) -> usize {
+    pub(crate) fn max_packet_size(&self) -> usize {
         match self {
             AnyEndpoint::Bulk(ep) => ep.max_packet_size(),
             AnyEndpoint::Interrupt(ep) => ep.max_packet_size(),
         }
     }
 
-    fn transfer_blocking(
+    pub(crate) fn endpoint_address(&self) -> u8 {
+        match self {
+            AnyEndpoint::Bulk(ep) => ep.endpoint_address(),
+            AnyEndpoint::Interrupt(ep) => ep.endpoint_address(),
+        }
+    }
+
+    pub(crate) fn endpoint_number(&self) -> u8 {
+        self.endpoint_address() & ENDPOINT_NUMBER_MASK
+    }
+
+    pub(crate) fn pending(&self) -> usize {
+        match self {
+            AnyEndpoint::Bulk(ep) => ep.pending(),
+            AnyEndpoint::Interrupt(ep) => ep.pending(),
+        }
+    }
+
+    /// Allocates a buffer the OS can transfer into directly where supported.
+    pub(crate) fn allocate(&self, len: usize) -> Buffer {
+        match self {
+            AnyEndpoint::Bulk(ep) => ep.allocate(len),
+            AnyEndpoint::Interrupt(ep) => ep.allocate(len),
+        }
+    }
+
+    pub(crate) fn submit(&mut self, buf: Buffer) {
+        match self {
+            AnyEndpoint::Bulk(ep) => ep.submit(buf),
+            AnyEndpoint::Interrupt(ep) => ep.submit(buf),
+        }
+    }
+
+    pub(crate) fn poll_next_complete(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<nusb::transfer::Completion> {
+        match self {
+            AnyEndpoint::Bulk(ep) => ep.poll_next_complete(cx),
+            AnyEndpoint::Interrupt(ep) => ep.poll_next_complete(cx),
+        }
+    }
+
+    pub(crate) fn cancel_all(&mut self) {
+        match self {
+            AnyEndpoint::Bulk(ep) => ep.cancel_all(),
+            AnyEndpoint::Interrupt(ep) => ep.cancel_all(),
+        }
+    }
+
+    pub(crate) fn transfer_blocking(
         &mut self,
         buf: nusb::transfer::Buffer,
         timeout: Duration,
@@ -118,7 +177,7 @@ impl<DIR: nusb::transfer::EndpointDirection> AnyEndpoint<DIR> {
         }
     }
 
-    fn clear_halt_blocking(&mut self) -> std::result::Result<(), nusb::Error> {
+    pub(crate) fn clear_halt_blocking(&mut self) -> std::result::Result<(), nusb::Error> {
         match self {
             AnyEndpoint::Bulk(ep) => ep.clear_halt().wait(),
             AnyEndpoint::Interrupt(ep) => ep.clear_halt().wait(),
@@ -126,6 +185,35 @@ impl<DIR: nusb::transfer::EndpointDirection> AnyEndpoint<DIR> {
     }
 }
 
+pub(crate) enum ControlTarget {
+    Device(nusb::Device),
+    Interface(nusb::Interface),
+}
+
+impl ControlTarget {
+    pub(crate) fn control_in_blocking(
+        &self,
+        request: nusb::transfer::ControlIn,
+        timeout: Duration,
+    ) -> std::result::Result<Vec<u8>, nusb::transfer::TransferError> {
+        match self {
+            ControlTarget::Device(device) => device.control_in(request, timeout).wait(),
+            ControlTarget::Interface(interface) => interface.control_in(request, timeout).wait(),
+        }
+    }
+
+    pub(crate) fn control_out_blocking(
+        &self,
+        request: nusb::transfer::ControlOut,
+        timeout: Duration,
+    ) -> std::result::Result<(), nusb::transfer::TransferError> {
+        match self {
+            ControlTarget::Device(device) => device.control_out(request, timeout).wait(),
+            ControlTarget::Interface(interface) => interface.control_out(request, timeout).wait(),
+        }
+    }
+}
+
 #[napi(object)]
 pub struct UsbEndpoint {
     #[napi(writable = false)]
@@ -213,11 +301,11 @@ impl UsbInterface {
     ) -> Result<Self> {
         Ok(Self {
             interfaceNumber: iface.interface_number(),
-            claimed: usb_device.interfaces[iface.interface_number() as usize].is_some(),
-            alternate: UsbAlternateInterface::new(&device, iface.first_alt_setting())?,
+            claimed: usb_device.handles().interfaces[iface.interface_number() as usize].is_some(),
+            alternate: UsbAlternateInterface::new(device, iface.first_alt_setting())?,
             alternates: iface
                 .alt_settings()
-                .map(|iface| UsbAlternateInterface::new(&device, iface))
+                .map(|iface| UsbAlternateInterface::new(device, iface))
                 .collect::<Result<Vec<_>>>()?,
         })
     }
@@ -241,7 +329,7 @@ impl UsbConfiguration {
     ) -> Result<Self> {
         let interfaces = config
             .interfaces()
-            .map(|iface| UsbInterface::new(&usb_device, &device, iface))
+            .map(|iface| UsbInterface::new(usb_device, device, iface))
             .collect::<Result<Vec<_>>>()?;
 
         Ok(Self {
@@ -263,11 +351,342 @@ pub struct UsbControlTransferParameters {
     pub index: u16,
 }
 
-#[napi]
-pub struct UsbDevice {
-    device_info: nusb::DeviceInfo,
+pub(crate) struct DeviceHandles {
     device: Option<nusb::Device>,
     interfaces: Vec<Option<nusb::Interface>>,
+}
+
+/// Open and claim state of one physical device, shared by every `UsbDevice`
+/// created for it so they don't fight over handles and interfaces.
+///
+/// `handles` is only ever held briefly to read or swap handles, never across
+/// an await. Calls that change state are serialised by the async locks:
+/// open, close, reset and selectConfiguration take `lifecycle` exclusively,
+/// the interface calls share it and queue on their interface's lock.
+pub(crate) struct SharedDevice {
+    device_info: nusb::DeviceInfo,
+    handles: Mutex<DeviceHandles>,
+    lifecycle: tokio::sync::RwLock<()>,
+    interface_locks: Vec<tokio::sync::Mutex<()>>,
+    queue: OnceLock<OperationQueue>,
+    pool: Mutex<TransferPool>,
+}
+
+static SHARED_DEVICES: LazyLock<Mutex<HashMap<nusb::DeviceId, Weak<SharedDevice>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn shared_devices_guard() -> MutexGuard<'static, HashMap<nusb::DeviceId, Weak<SharedDevice>>> {
+    SHARED_DEVICES
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// Device ids can be reused once a device is unplugged (e.g. Linux bus addresses),
+// so only hand out the existing state if it still describes the same device.
+fn is_same_device(a: &nusb::DeviceInfo, b: &nusb::DeviceInfo) -> bool {
+    a.vendor_id() == b.vendor_id()
+        && a.product_id() == b.product_id()
+        && a.device_version() == b.device_version()
+        && a.port_chain() == b.port_chain()
+        && a.serial_number() == b.serial_number()
+}
+
+fn shared_device(device_info: nusb::DeviceInfo) -> Arc<SharedDevice> {
+    let mut devices = shared_devices_guard();
+    devices.retain(|_, shared| shared.strong_count() > 0);
+
+    let id = device_info.id();
+    if let Some(shared) = devices.get(&id).and_then(Weak::upgrade) {
+        if is_same_device(&shared.device_info, &device_info) {
+            return shared;
+        }
+    }
+
+    let shared = Arc::new(SharedDevice {
+        device_info,
+        handles: Mutex::new(DeviceHandles {
+            device: None,
+            interfaces: vec![None; 256],
+        }),
+        lifecycle: tokio::sync::RwLock::new(()),
+        interface_locks: (0..256).map(|_| tokio::sync::Mutex::new(())).collect(),
+        queue: OnceLock::new(),
+        pool: Mutex::new(TransferPool::new()),
+    });
+    devices.insert(id, Arc::downgrade(&shared));
+    shared
+}
+
+impl SharedDevice {
+    pub(crate) fn handles(&self) -> MutexGuard<'_, DeviceHandles> {
+        self.handles
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub(crate) fn opened_device(&self) -> Option<nusb::Device> {
+        self.handles().device.clone()
+    }
+
+    /// The ordered operation queue, started on first use.
+    pub(crate) fn queue(&self) -> &OperationQueue {
+        self.queue.get_or_init(OperationQueue::new)
+    }
+
+    pub(crate) fn pool(&self) -> MutexGuard<'_, TransferPool> {
+        self.pool
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Drops every pooled endpoint and buffer, for changes to the whole device.
+    pub(crate) fn clear_pool(&self) {
+        self.pool().clear();
+    }
+
+    /// Drops the interface's pooled endpoints and buffers, which must happen
+    /// before it is released or changes alternate setting.
+    pub(crate) fn clear_interface_pool(&self, interface_number: u8) {
+        self.pool().clear_interface(interface_number);
+    }
+
+    /// Claims an interface unless it already is, detaching any kernel driver
+    /// first if asked (it is reattached when the interface is released).
+    pub(crate) async fn claim_interface(
+        &self,
+        method: &'static str,
+        interface_number: u8,
+        detach: bool,
+    ) -> Result<()> {
+        let _lifecycle = self.lifecycle.read().await;
+        let _interface = self.interface_locks[interface_number as usize].lock().await;
+
+        let device = {
+            let handles = self.handles();
+            if handles.interfaces[interface_number as usize].is_some() {
+                return Ok(());
+            }
+            handles.device.clone()
+        };
+
+        match device {
+            Some(device) => {
+                let interface = run_blocking(move || {
+                    let claim = if detach {
+                        device.detach_and_claim_interface(interface_number).wait()
+                    } else {
+                        device.claim_interface(interface_number).wait()
+                    };
+                    claim.map_err(|e| format!("{method} error: {e}"))
+                })
+                .await?;
+                self.handles().interfaces[interface_number as usize] = Some(interface);
+                Ok(())
+            }
+            None => Err(napi::Error::from_reason(format!(
+                "{method} error: invalid state"
+            ))),
+        }
+    }
+
+    pub(crate) async fn release_interface(
+        &self,
+        method: &'static str,
+        interface_number: u8,
+    ) -> Result<()> {
+        let _lifecycle = self.lifecycle.read().await;
+        let _interface = self.interface_locks[interface_number as usize].lock().await;
+
+        let interface = {
+            let mut handles = self.handles();
+            if handles.device.is_none() {
+                return Err(napi::Error::from_reason(format!(
+                    "{method} error: invalid state"
+                )));
+            }
+            handles.interfaces[interface_number as usize].take()
+        };
+        self.clear_interface_pool(interface_number);
+
+        match interface {
+            Some(interface) => {
+                run_blocking(move || {
+                    drop(interface);
+                    Ok(())
+                })
+                .await
+            }
+            None => Err(napi::Error::from_reason(format!(
+                "{method} error: not claimed"
+            ))),
+        }
+    }
+
+    pub(crate) async fn select_alternate_interface(
+        &self,
+        method: &'static str,
+        interface_number: u8,
+        alternate_setting: u8,
+    ) -> Result<()> {
+        let _lifecycle = self.lifecycle.read().await;
+        let _interface = self.interface_locks[interface_number as usize].lock().await;
+
+        let interface = self.handles().interfaces[interface_number as usize].clone();
+        match interface {
+            Some(interface) => {
+                // Pooled endpoints would keep the current setting busy
+                self.clear_interface_pool(interface_number);
+                run_blocking(move || {
+                    interface
+                        .set_alt_setting(alternate_setting)
+                        .wait()
+                        .map_err(|e| format!("{method} error: {e}"))
+                })
+                .await
+            }
+            None => Err(napi::Error::from_reason(format!(
+                "{method} error: invalid state"
+            ))),
+        }
+    }
+
+    /// Picks what a control transfer is sent through: the device where the OS
+    /// allows it, otherwise the claimed interface owning the recipient.
+    pub(crate) fn control_target(
+        &self,
+        recipient: nusb::transfer::Recipient,
+        index: u16,
+    ) -> Option<ControlTarget> {
+        #[cfg(not(windows))]
+        if recipient == nusb::transfer::Recipient::Device {
+            return self.opened_device().map(ControlTarget::Device);
+        }
+
+        self.get_interface(recipient, index)
+            .map(ControlTarget::Interface)
+    }
+
+    pub(crate) fn get_interface(
+        &self,
+        recipient: nusb::transfer::Recipient,
+        index: u16,
+    ) -> Option<nusb::Interface> {
+        let handles = self.handles();
+        if recipient == nusb::transfer::Recipient::Interface {
+            // If recipient is interface and index matches a claimed interface number use that interface
+            if let Some(interface) = handles
+                .interfaces
+                .get(index as usize)
+                .and_then(|interface| interface.clone())
+            {
+                return Some(interface);
+            }
+        }
+        if recipient == nusb::transfer::Recipient::Endpoint {
+            // If recipient is endpoint and index matches an endpoint address use the interface that owns that endpoint
+            for maybe_iface in &handles.interfaces {
+                let iface = match maybe_iface {
+                    Some(i) => i,
+                    None => continue,
+                };
+
+                let Some(descriptor) = iface.descriptor() else {
+                    continue;
+                };
+
+                for endpoint in descriptor.endpoints() {
+                    if endpoint.address() == index as u8 {
+                        return Some(iface.clone());
+                    }
+                }
+            }
+        }
+
+        // Return any claimed interface (e.g. for device control transfers on Windows)
+        let maybe_iface = handles.interfaces.iter().find_map(|x| x.clone());
+        if maybe_iface.is_some() {
+            return maybe_iface;
+        }
+        None
+    }
+
+    /// Checks out an endpoint, reusing the pooled one if it is idle.
+    /// Hand it back with `put_endpoint` once the transfer is done.
+    pub(crate) fn get_endpoint<DIR: PooledDirection>(
+        &self,
+        endpoint_number: u8,
+    ) -> Option<PooledEndpoint<DIR>> {
+        // Held while looking the endpoint up, so a release can't clear its interface in between
+        let mut pool = self.pool();
+        if let Some(endpoint) = pool.take_endpoint(endpoint_number) {
+            return Some(endpoint);
+        }
+
+        for maybe_iface in &self.handles().interfaces {
+            let iface = match maybe_iface {
+                Some(i) => i,
+                None => continue,
+            };
+
+            let Some(descriptor) = iface.descriptor() else {
+                continue;
+            };
+
+            for ep_desc in descriptor.endpoints() {
+                if ep_desc.direction() == DIR::DIR
+                    && (ep_desc.address() & ENDPOINT_NUMBER_MASK) == endpoint_number
+                {
+                    let addr = ep_desc.address();
+                    let endpoint = match ep_desc.transfer_type() {
+                        TransferType::Bulk => iface
+                            .endpoint::<Bulk, DIR>(addr)
+                            .ok()
+                            .map(AnyEndpoint::Bulk),
+                        TransferType::Interrupt => iface
+                            .endpoint::<Interrupt, DIR>(addr)
+                            .ok()
+                            .map(AnyEndpoint::Interrupt),
+                        _ => None,
+                    };
+                    let interface_number = iface.interface_number();
+                    let generation = pool.generation(interface_number);
+                    return endpoint.map(|endpoint| {
+                        PooledEndpoint::new(endpoint, interface_number, generation)
+                    });
+                }
+            }
+        }
+
+        None
+    }
+
+    pub(crate) fn put_endpoint<DIR: PooledDirection>(&self, endpoint: PooledEndpoint<DIR>) {
+        self.pool().put_endpoint(endpoint);
+    }
+
+    pub(crate) fn put_buffer<DIR: PooledDirection>(
+        &self,
+        endpoint: &PooledEndpoint<DIR>,
+        buffer: Buffer,
+    ) {
+        self.pool().put_buffer(
+            endpoint.interface_number(),
+            endpoint.generation(),
+            endpoint.endpoint_address(),
+            buffer,
+        );
+    }
+}
+
+/// Drops the shared state of a disconnected device so a new device reusing its id starts afresh.
+pub(crate) fn forget_shared_device(id: nusb::DeviceId) {
+    shared_devices_guard().remove(&id);
+}
+
+#[napi]
+pub struct UsbDevice {
+    shared: Arc<SharedDevice>,
+    resume_watch: Mutex<Option<tokio::task::JoinHandle<()>>>,
 
     #[napi(writable = false)]
     pub vendorId: u16,
@@ -312,9 +731,8 @@ impl UsbDevice {
             decode_version(device_info.usb_version());
 
         Self {
-            device_info: device_info.clone(),
-            device: None,
-            interfaces: vec![None; 256],
+            shared: shared_device(device_info.clone()),
+            resume_watch: Mutex::new(None),
             vendorId: device_info.vendor_id(),
             productId: device_info.product_id(),
             deviceVersionMajor,
@@ -343,18 +761,40 @@ impl UsbDevice {
         }
     }
 
+    pub(crate) fn device_info(&self) -> &nusb::DeviceInfo {
+        &self.shared.device_info
+    }
+
+    pub(crate) fn shared(&self) -> &Arc<SharedDevice> {
+        &self.shared
+    }
+
+    pub(crate) fn resume_watch(&self) -> MutexGuard<'_, Option<tokio::task::JoinHandle<()>>> {
+        self.resume_watch
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn handles(&self) -> MutexGuard<'_, DeviceHandles> {
+        self.shared.handles()
+    }
+
+    fn opened_device(&self) -> Option<nusb::Device> {
+        self.shared.opened_device()
+    }
+
     #[napi(getter)]
     pub fn handle(&self) -> String {
-        format!("{:?}", self.device_info.id())
+        format!("{:?}", self.device_info().id())
     }
 
     #[napi(getter)]
-    pub unsafe fn manufacturerName(&mut self) -> Result<Option<String>> {
-        match &self.device_info.manufacturer_string() {
+    pub fn manufacturerName(&self) -> Result<Option<String>> {
+        match &self.device_info().manufacturer_string() {
             Some(str) => Ok(Some(str.to_string())),
             None => {
-                let device = match self.device.as_ref() {
-                    Some(device) => device.clone(),
+                let device = match self.opened_device() {
+                    Some(device) => device,
                     None => self._open()?,
                 };
 
@@ -367,12 +807,12 @@ impl UsbDevice {
     }
 
     #[napi(getter)]
-    pub unsafe fn productName(&mut self) -> Result<Option<String>> {
-        match &self.device_info.product_string() {
+    pub fn productName(&self) -> Result<Option<String>> {
+        match &self.device_info().product_string() {
             Some(str) => Ok(Some(str.to_string())),
             None => {
-                let device = match self.device.as_ref() {
-                    Some(device) => device.clone(),
+                let device = match self.opened_device() {
+                    Some(device) => device,
                     None => self._open()?,
                 };
 
@@ -382,12 +822,12 @@ impl UsbDevice {
     }
 
     #[napi(getter)]
-    pub unsafe fn serialNumber(&mut self) -> Result<Option<String>> {
-        match &self.device_info.serial_number() {
+    pub fn serialNumber(&self) -> Result<Option<String>> {
+        match &self.device_info().serial_number() {
             Some(str) => Ok(Some(str.to_string())),
             None => {
-                let device = match self.device.as_ref() {
-                    Some(device) => device.clone(),
+                let device = match self.opened_device() {
+                    Some(device) => device,
                     None => self._open()?,
                 };
 
@@ -401,13 +841,13 @@ impl UsbDevice {
 
     #[napi(getter)]
     pub fn opened(&self) -> bool {
-        self.device.is_some()
+        self.handles().device.is_some()
     }
 
     #[napi(getter, ts_return_type = "USBConfiguration")]
-    pub unsafe fn configuration(&mut self) -> Result<Option<UsbConfiguration>> {
-        let device = match self.device.as_ref() {
-            Some(device) => device.clone(),
+    pub fn configuration(&self) -> Result<Option<UsbConfiguration>> {
+        let device = match self.opened_device() {
+            Some(device) => device,
             None => self._open()?,
         };
 
@@ -415,32 +855,37 @@ impl UsbDevice {
             .active_configuration()
             .map_err(|e| napi::Error::from_reason(format!("configuration error: {e}")))?;
 
-        Ok(Some(UsbConfiguration::new(&self, &device, config)?))
+        Ok(Some(UsbConfiguration::new(self, &device, config)?))
     }
 
     #[napi(getter, ts_return_type = "Array<USBConfiguration>")]
-    pub unsafe fn configurations(&mut self) -> Result<Vec<UsbConfiguration>> {
-        let device = match self.device.as_ref() {
-            Some(device) => device.clone(),
+    pub fn configurations(&self) -> Result<Vec<UsbConfiguration>> {
+        let device = match self.opened_device() {
+            Some(device) => device,
             None => self._open()?,
         };
 
         device
             .configurations()
-            .map(|config| UsbConfiguration::new(&self, &device, config))
+            .map(|config| UsbConfiguration::new(self, &device, config))
             .collect::<Result<Vec<_>>>()
     }
 
-    unsafe fn _open(&mut self) -> Result<nusb::Device> {
-        self.device_info
+    fn _open(&self) -> Result<nusb::Device> {
+        self.device_info()
             .open()
             .wait()
             .map_err(|e| napi::Error::from_reason(format!("open error: {e}")))
     }
 
     #[napi]
-    pub async unsafe fn open(&mut self) -> Result<()> {
-        let device_info = self.device_info.clone();
+    pub async fn open(&self) -> Result<()> {
+        let _lifecycle = self.shared.lifecycle.write().await;
+        if self.opened() {
+            return Ok(());
+        }
+
+        let device_info = self.device_info().clone();
         let device = run_blocking(move || {
             device_info
                 .open()
@@ -448,26 +893,40 @@ impl UsbDevice {
                 .map_err(|e| format!("open error: {e}"))
         })
         .await?;
-        self.device = Some(device);
+        self.handles().device = Some(device);
         Ok(())
     }
 
     #[napi]
-    pub async unsafe fn close(&mut self) -> Result<()> {
-        self.device = None;
-        Ok(())
+    pub async fn close(&self) -> Result<()> {
+        let _lifecycle = self.shared.lifecycle.write().await;
+        let (device, interfaces) = {
+            let mut handles = self.handles();
+            let interfaces = std::mem::replace(&mut handles.interfaces, vec![None; 256]);
+            (handles.device.take(), interfaces)
+        };
+        self.shared.clear_pool();
+
+        // Releasing interfaces blocks on the OS, keep it off the JS thread
+        run_blocking(move || {
+            drop(interfaces);
+            drop(device);
+            Ok(())
+        })
+        .await
     }
 
     #[napi]
-    pub async unsafe fn forget(&mut self) -> Result<()> {
+    pub async fn forget(&self) -> Result<()> {
         self.close().await
     }
 
     #[napi]
     pub async fn reset(&self) -> Result<()> {
-        match &self.device {
+        let _lifecycle = self.shared.lifecycle.write().await;
+        self.shared.clear_pool();
+        match self.opened_device() {
             Some(device) => {
-                let device = device.clone();
                 run_blocking(move || {
                     device
                         .reset()
@@ -482,7 +941,8 @@ impl UsbDevice {
 
     #[napi]
     pub async fn selectConfiguration(&self, configurationValue: u8) -> Result<()> {
-        match &self.device {
+        let _lifecycle = self.shared.lifecycle.write().await;
+        match self.opened_device() {
             Some(device) => {
                 let found = device
                     .configurations()
@@ -500,7 +960,7 @@ impl UsbDevice {
                 }
                 #[cfg(not(windows))]
                 {
-                    let device = device.clone();
+                    self.shared.clear_pool();
                     run_blocking(move || {
                         device
                             .set_configuration(configurationValue)
@@ -517,66 +977,32 @@ impl UsbDevice {
     }
 
     #[napi]
-    pub async unsafe fn claimInterface(&mut self, interfaceNumber: u8) -> Result<()> {
-        match &self.device {
-            Some(device) => {
-                let device = device.clone();
-                let interface = run_blocking(move || {
-                    device
-                        .claim_interface(interfaceNumber)
-                        .wait()
-                        .map_err(|e| format!("claimInterface error: {e}"))
-                })
-                .await?;
-                self.interfaces[interfaceNumber as usize] = Some(interface);
-                Ok(())
-            }
-            None => Err(napi::Error::from_reason(
-                "claimInterface error: invalid state",
-            )),
-        }
+    pub async fn claimInterface(&self, interfaceNumber: u8) -> Result<()> {
+        self.shared
+            .claim_interface("claimInterface", interfaceNumber, false)
+            .await
     }
 
     #[napi]
-    pub async unsafe fn releaseInterface(&mut self, interfaceNumber: u8) -> Result<()> {
-        match &self.device {
-            Some(_device) => match &self.interfaces[interfaceNumber as usize] {
-                Some(_interface) => {
-                    self.interfaces[interfaceNumber as usize] = None;
-                    Ok(())
-                }
-                None => Err(napi::Error::from_reason(
-                    "releaseInterface error: not claimed",
-                )),
-            },
-            None => Err(napi::Error::from_reason(
-                "releaseInterface error: invalid state",
-            )),
-        }
+    pub async fn releaseInterface(&self, interfaceNumber: u8) -> Result<()> {
+        self.shared
+            .release_interface("releaseInterface", interfaceNumber)
+            .await
     }
 
     #[napi]
-    pub async unsafe fn selectAlternateInterface(
-        &mut self,
+    pub async fn selectAlternateInterface(
+        &self,
         interfaceNumber: u8,
         alternateSetting: u8,
     ) -> Result<()> {
-        match &self.interfaces[interfaceNumber as usize] {
-            Some(interface) => {
-                let interface = interface.clone();
-                run_blocking(move || {
-                    interface
-                        .set_alt_setting(alternateSetting)
-                        .wait()
-                        .map_err(|e| format!("selectAlternateInterface error: {e}"))
-                })
-                .await?;
-                Ok(())
-            }
-            None => Err(napi::Error::from_reason(
-                "selectAlternateInterface error: invalid state",
-            )),
-        }
+        self.shared
+            .select_alternate_interface(
+                "selectAlternateInterface",
+                interfaceNumber,
+                alternateSetting,
+            )
+            .await
     }
 
     #[napi(js_name = "nativeControlTransferIn")]
@@ -589,30 +1015,14 @@ impl UsbDevice {
         let control_type = control_type_from_request_type(&setup.requestType);
         let recipient = recipient_from_request_recipient(&setup.recipient);
 
-        #[cfg(not(windows))]
-        if recipient == nusb::transfer::Recipient::Device {
-            let device = self.device.as_ref().cloned().ok_or_else(|| {
-                napi::Error::from_reason(format!("controlTransferIn error: invalid state"))
-            })?;
-            let request = control_in_setup(&setup, control_type, recipient, setup.index, length);
-            let result = run_blocking(move || {
-                device
-                    .control_in(request, Duration::from_millis(timeout as u64))
-                    .wait()
-                    .map_err(|e| format!("controlTransferIn error: {e}"))
-            })
-            .await?;
-            return Ok(Some(Uint8Array::from(result)));
-        }
-
-        let interface = self.get_interface(recipient, setup.index).ok_or_else(|| {
-            napi::Error::from_reason(format!("controlTransferIn error: invalid state"))
-        })?;
+        let target = self
+            .shared
+            .control_target(recipient, setup.index)
+            .ok_or_else(|| napi::Error::from_reason("controlTransferIn error: invalid state"))?;
         let request = control_in_setup(&setup, control_type, recipient, setup.index, length);
         let result = run_blocking(move || {
-            interface
-                .control_in(request, Duration::from_millis(timeout as u64))
-                .wait()
+            target
+                .control_in_blocking(request, Duration::from_millis(timeout as u64))
                 .map_err(|e| format!("controlTransferIn error: {e}"))
         })
         .await?;
@@ -631,31 +1041,14 @@ impl UsbDevice {
         let bytes = data.map(|b| b.to_vec()).unwrap_or_default();
         let bytes_len = bytes.len();
 
-        #[cfg(not(windows))]
-        if recipient == nusb::transfer::Recipient::Device {
-            let device = self.device.as_ref().cloned().ok_or_else(|| {
-                napi::Error::from_reason(format!("controlTransferOut error: invalid state"))
-            })?;
-            run_blocking(move || {
-                let request =
-                    control_out_setup(&setup, control_type, recipient, setup.index, &bytes);
-                device
-                    .control_out(request, Duration::from_millis(timeout as u64))
-                    .wait()
-                    .map_err(|e| format!("controlTransferOut error: {e}"))
-            })
-            .await?;
-            return Ok(bytes_len as u32);
-        }
-
-        let interface = self.get_interface(recipient, setup.index).ok_or_else(|| {
-            napi::Error::from_reason(format!("controlTransferOut error: invalid state"))
-        })?;
+        let target = self
+            .shared
+            .control_target(recipient, setup.index)
+            .ok_or_else(|| napi::Error::from_reason("controlTransferOut error: invalid state"))?;
         run_blocking(move || {
             let request = control_out_setup(&setup, control_type, recipient, setup.index, &bytes);
-            interface
-                .control_out(request, Duration::from_millis(timeout as u64))
-                .wait()
+            target
+                .control_out_blocking(request, Duration::from_millis(timeout as u64))
                 .map_err(|e| format!("controlTransferOut error: {e}"))
         })
         .await?;
@@ -669,29 +1062,29 @@ impl UsbDevice {
         timeout: u32,
         length: u32,
     ) -> Result<Option<Uint8Array>> {
-        match self.get_endpoint::<nusb::transfer::In>(endpointNumber) {
+        match self
+            .shared
+            .get_endpoint::<nusb::transfer::In>(endpointNumber)
+        {
             Some(mut endpoint) => {
-                let v = run_blocking(move || {
-                    let packet_size = endpoint.max_packet_size();
-                    let req = (((length as usize) + packet_size - 1) / packet_size) * packet_size;
-                    let buf = Buffer::new(req);
-                    let completion =
-                        endpoint.transfer_blocking(buf, Duration::from_millis(timeout as u64));
-                    completion
-                        .status
-                        .map_err(|e| format!("transferIn error: {e:?}"))?;
-                    let mut v = completion.buffer.into_vec();
-                    v.truncate(completion.actual_len.min(length as usize));
-                    Ok(v)
+                let shared = self.shared.clone();
+                run_blocking(move || {
+                    let result = pooled_transfer_in(
+                        &shared,
+                        &mut endpoint,
+                        length as usize,
+                        Duration::from_millis(timeout as u64),
+                    )
+                    .map(|(buffer, len)| Some(buffer_to_js(&shared, &endpoint, buffer, len)))
+                    .map_err(|e| format!("transferIn error: {e:?}"));
+                    shared.put_endpoint(endpoint);
+                    result
                 })
-                .await?;
-                Ok(Some(Uint8Array::from(v)))
-            }
-            None => {
-                return Err(napi::Error::from_reason(
-                    "transferIn error: endpoint not found",
-                ));
+                .await
             }
+            None => Err(napi::Error::from_reason(
+                "transferIn error: endpoint not found",
+            )),
         }
     }
 
@@ -702,26 +1095,30 @@ impl UsbDevice {
         timeout: u32,
         data: Uint8Array,
     ) -> Result<u32> {
-        match self.get_endpoint::<nusb::transfer::Out>(endpointNumber) {
+        match self
+            .shared
+            .get_endpoint::<nusb::transfer::Out>(endpointNumber)
+        {
             Some(mut endpoint) => {
-                let data = data.to_vec();
+                let shared = self.shared.clone();
+                // `data` stays referenced until it has been copied into the transfer buffer
                 run_blocking(move || {
-                    let mut buf = Buffer::new(data.len());
-                    buf.extend_from_slice(&data);
-                    let completion =
-                        endpoint.transfer_blocking(buf, Duration::from_millis(timeout as u64));
-                    completion
-                        .status
-                        .map_err(|e| format!("transferOut error: {e:?}"))?;
-                    Ok(completion.actual_len as u32)
+                    let result = pooled_transfer_out(
+                        &shared,
+                        &mut endpoint,
+                        &data,
+                        Duration::from_millis(timeout as u64),
+                    )
+                    .map(|len| len as u32)
+                    .map_err(|e| format!("transferOut error: {e:?}"));
+                    shared.put_endpoint(endpoint);
+                    result
                 })
                 .await
             }
-            None => {
-                return Err(napi::Error::from_reason(
-                    "transferOut error: endpoint not found",
-                ));
-            }
+            None => Err(napi::Error::from_reason(
+                "transferOut error: endpoint not found",
+            )),
         }
     }
 
@@ -763,12 +1160,18 @@ impl UsbDevice {
         endpointNumber: u8,
     ) -> Result<()> {
         if direction == "in" {
-            match self.get_endpoint::<nusb::transfer::In>(endpointNumber) {
+            match self
+                .shared
+                .get_endpoint::<nusb::transfer::In>(endpointNumber)
+            {
                 Some(mut endpoint) => {
+                    let shared = self.shared.clone();
                     run_blocking(move || {
-                        endpoint
+                        let result = endpoint
                             .clear_halt_blocking()
-                            .map_err(|e| format!("clearHalt error: {e}"))
+                            .map_err(|e| format!("clearHalt error: {e}"));
+                        shared.put_endpoint(endpoint);
+                        result
                     })
                     .await?;
                 }
@@ -779,12 +1182,18 @@ impl UsbDevice {
                 }
             }
         } else {
-            match self.get_endpoint::<nusb::transfer::Out>(endpointNumber) {
+            match self
+                .shared
+                .get_endpoint::<nusb::transfer::Out>(endpointNumber)
+            {
                 Some(mut endpoint) => {
+                    let shared = self.shared.clone();
                     run_blocking(move || {
-                        endpoint
+                        let result = endpoint
                             .clear_halt_blocking()
-                            .map_err(|e| format!("clearHalt error: {e}"))
+                            .map_err(|e| format!("clearHalt error: {e}"));
+                        shared.put_endpoint(endpoint);
+                        result
                     })
                     .await?;
                 }
@@ -801,9 +1210,8 @@ impl UsbDevice {
 
     #[napi]
     pub async fn detachKernelDriver(&self, interfaceNumber: u8) -> Result<()> {
-        match &self.device {
+        match self.opened_device() {
             Some(device) => {
-                let device = device.clone();
                 run_blocking(move || {
                     device
                         .detach_kernel_driver(interfaceNumber)
@@ -819,9 +1227,8 @@ impl UsbDevice {
 
     #[napi]
     pub async fn attachKernelDriver(&self, interfaceNumber: u8) -> Result<()> {
-        match &self.device {
+        match self.opened_device() {
             Some(device) => {
-                let device = device.clone();
                 run_blocking(move || {
                     device
                         .attach_kernel_driver(interfaceNumber)
@@ -834,84 +1241,12 @@ impl UsbDevice {
             )),
         }
     }
+}
 
-    fn get_interface(
-        &self,
-        recipient: nusb::transfer::Recipient,
-        index: u16,
-    ) -> Option<nusb::Interface> {
-        if recipient == nusb::transfer::Recipient::Interface {
-            // If recipient is interface and index matches a claimed interface number use that interface
-            if let Some(interface) = self
-                .interfaces
-                .get(index as usize)
-                .and_then(|interface| interface.clone())
-            {
-                return Some(interface);
-            }
-        }
-        if recipient == nusb::transfer::Recipient::Endpoint {
-            // If recipient is endpoint and index matches an endpoint address use the interface that owns that endpoint
-            for maybe_iface in &self.interfaces {
-                let iface = match maybe_iface {
-                    Some(i) => i,
-                    None => continue,
-                };
-
-                let Some(descriptor) = iface.descriptor() else {
-                    continue;
-                };
-
-                for endpoint in descriptor.endpoints() {
-                    if endpoint.address() == index as u8 {
-                        return Some(iface.clone());
-                    }
-                }
-            }
-        }
-
-        // Return any claimed interface (e.g. for device control transfers on Windows)
-        let maybe_iface = self.interfaces.iter().find_map(|x| x.clone());
-        if maybe_iface.is_some() {
-            return maybe_iface;
-        }
-        None
-    }
-
-    fn get_endpoint<DIR: nusb::transfer::EndpointDirection>(
-        &self,
-        endpointNumber: u8,
-    ) -> Option<AnyEndpoint<DIR>> {
-        for maybe_iface in &self.interfaces {
-            let iface = match maybe_iface {
-                Some(i) => i,
-                None => continue,
-            };
-
-            let Some(descriptor) = iface.descriptor() else {
-                continue;
-            };
-
-            for ep_desc in descriptor.endpoints() {
-                if ep_desc.direction() == DIR::DIR
-                    && (ep_desc.address() & ENDPOINT_NUMBER_MASK) == endpointNumber
-                {
-                    let addr = ep_desc.address();
-                    return match ep_desc.transfer_type() {
-                        TransferType::Bulk => iface
-                            .endpoint::<Bulk, DIR>(addr)
-                            .ok()
-                            .map(AnyEndpoint::Bulk),
-                        TransferType::Interrupt => iface
-                            .endpoint::<Interrupt, DIR>(addr)
-                            .ok()
-                            .map(AnyEndpoint::Interrupt),
-                        _ => None,
-                    };
-                }
-            }
+impl Drop for UsbDevice {
+    fn drop(&mut self) {
+        if let Some(task) = self.resume_watch().take() {
+            task.abort();
         }
-
-        None
     }
 }
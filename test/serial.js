@@ -0,0 +1,62 @@
+const assert = require('assert');
+const { defineSerialStreams } = require('../dist/serial');
+
+// Behaves like the native ports: one reader at a time, free again once it has ended
+class FakePort {
+    constructor() {
+        this.reading = false;
+        this.starts = 0;
+    }
+
+    nativeStartReading(_bufferSize, onData, onEnd) {
+        if (this.reading) {
+            throw new Error('startReading error: already reading');
+        }
+        this.reading = true;
+        this.starts++;
+        this.onData = onData;
+        this.onEnd = error => {
+            this.reading = false;
+            onEnd(error);
+        };
+    }
+
+    stopReading() {
+        this.reading = false;
+    }
+
+    pauseReading() {}
+
+    resumeReading() {}
+
+    async nativeWrite(data) {
+        return data.length;
+    }
+}
+defineSerialStreams(FakePort.prototype);
+
+describe('Serial streams', () => {
+    it('should hand out the same readable until it ends', () => {
+        const port = new FakePort();
+        assert.strictEqual(port.readable, port.readable);
+        assert.equal(port.starts, 1);
+    });
+
+    it('should reopen readable after a read error', async () => {
+        const port = new FakePort();
+        const first = port.readable;
+        const reader = first.getReader();
+        port.onData(new Uint8Array([1]));
+        assert.deepEqual((await reader.read()).value, new Uint8Array([1]));
+
+        port.onEnd('Stall');
+        await assert.rejects(reader.read(), /read error: Stall/);
+
+        const second = port.readable;
+        assert.notStrictEqual(second, first);
+        assert.equal(port.starts, 2);
+        const again = second.getReader();
+        port.onData(new Uint8Array([2]));
+        assert.deepEqual((await again.read()).value, new Uint8Array([2]));
+    });
+});
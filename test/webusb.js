@@ -23,6 +23,21 @@ describe('usb Module', () => {
         const dev = await usb.findDeviceBySerial('TEST_DEVICE');
         assert.ok(dev, 'Demo device is not attached');
     });
+
+    it('should generate a udev rule', async () => {
+        const rule = await usb.udevRule({ vendorId: 0x59e3, productId: 0x0a23 }, { group: 'plugdev' });
+        assert.equal(rule.rule, 'SUBSYSTEM=="usb", ATTR{idVendor}=="59e3", ATTR{idProduct}=="0a23", MODE="0660", GROUP="plugdev"');
+        assert.equal(rule.fileName, '50-usb-59e3-0a23.rules');
+    });
+
+    if (process.platform === 'linux') {
+        it('should validate a udev rule against the device', async () => {
+            const dev = await usb.findDeviceByIds(0x59e3, 0x0a23);
+            const rule = await dev.udevRule({ uaccess: true, serialNumber: true });
+            assert.equal(rule.matchesDevice, true);
+            assert.deepEqual(rule.mismatches, []);
+        });
+    }
 });
 
 describe('WebUSB Module', () => {
@@ -160,6 +175,20 @@ describe('Device access', () => {
         await device.close();
         assert.equal(device.opened, false);
     });
+
+    it('returns the same instance for a known device', async () => {
+        const dev = await usb.findDeviceByIds(0x59e3, 0x0a23);
+        const devs = await usb.getDevices();
+        assert.ok(devs.includes(dev));
+    });
+
+    it('shares open state between instances', async () => {
+        const [other] = await require('../index.js').nativeGetDevices().then(devs => devs.filter(dev => dev.vendorId === 0x59e3));
+        await device.open();
+        assert.equal(other.opened, true);
+        await other.close();
+        assert.equal(device.opened, false);
+    });
 });
 
 describe('Configurations', () => {
@@ -245,6 +274,35 @@ describe('Alternates', () => {
     });
 });
 
+describe('Concurrent calls', () => {
+    let device = null;
+
+    before(async () => {
+        device = await webusb.requestDevice({ filters: [{ vendorId: 0x59e3 }] });
+    });
+
+    it('serialises random concurrent calls', async () => {
+        const calls = [
+            () => device.open(),
+            () => device.close(),
+            () => device.claimInterface(0),
+            () => device.releaseInterface(0),
+            () => device.selectAlternateInterface(0, 0),
+        ];
+
+        for (let round = 0; round < 20; round++) {
+            await Promise.allSettled(Array.from({ length: 16 }, () => calls[Math.floor(Math.random() * calls.length)]()));
+        }
+
+        await device.open();
+        await device.claimInterface(0);
+        assert.equal(device.configuration.interfaces[0].claimed, true);
+        await device.close();
+        assert.equal(device.opened, false);
+        assert.equal(device.configuration.interfaces[0].claimed, false);
+    });
+});
+
 describe('Control Transfers', () => {
     let device = null;
     const b1 = Uint8Array.from(Array.from({ length: 0x40 - 0x30 }, (_, i) => i + 0x30)).buffer;
@@ -322,6 +380,75 @@ describe('Transfers', () => {
         assert(resultBuffer.equals(expectedBuffer));
     });
 
+    it('should transfer IN into a reused buffer', async () => {
+        await device.reserveTransferBuffers('in', 3, 2, 64);
+        const target = new Uint8Array(64);
+
+        for (let i = 0; i < 2; i++) {
+            await device.transferOut(4, b2);
+            const transferResult = await device.transferInto(3, target);
+
+            assert.equal(transferResult.status, 'ok');
+            assert.equal(transferResult.data.buffer, target.buffer);
+            assert.equal(transferResult.data.byteLength, b2.byteLength);
+            assert(Buffer.from(target.buffer, 0, b2.byteLength).equals(Buffer.from(b2)));
+        }
+    });
+
+    after(async () => {
+        await device.releaseInterface(0);
+        await device.close();
+    });
+});
+
+describe('Sequences', () => {
+    let device = null;
+    let blocker = null;
+    const b3 = Uint8Array.from(Array.from({ length: 0x10 }, (_, i) => i + 0x60)).buffer;
+
+    before(async () => {
+        device = await webusb.requestDevice({ filters: [{ vendorId: 0x59e3 }] });
+        await device.open();
+        await device.claimInterface(0);
+    });
+
+    it('should run transfers in issue order', async () => {
+        const [outResult, inResult] = await device.sequence(tx => Promise.all([
+            tx.transferOut(4, b3),
+            tx.transferIn(3, b3.byteLength),
+        ]));
+
+        assert.equal(outResult.bytesWritten, b3.byteLength);
+        const resultBuffer = Buffer.from(inResult.data.buffer, inResult.data.byteOffset, inResult.data.byteLength);
+        assert(resultBuffer.equals(Buffer.from(b3)));
+    });
+
+    it('should run waiting sequences by priority', async () => {
+        const order = [];
+        let release;
+        await new Promise(started => blocker = device.sequence(() => new Promise(resolve => {
+            release = resolve;
+            started();
+        })));
+        const low = device.sequence(async () => order.push('low'), { priority: 0 });
+        const high = device.sequence(async () => order.push('high'), { priority: 10 });
+        await new Promise(resolve => setTimeout(resolve, 10));
+        release();
+        await Promise.all([blocker, low, high]);
+        assert.deepEqual(order, ['high', 'low']);
+    });
+
+    it('should time out waiting for the device', async () => {
+        let release;
+        await new Promise(started => blocker = device.sequence(() => new Promise(resolve => {
+            release = resolve;
+            started();
+        })));
+        await assert.rejects(device.sequence(async () => {}, { timeout: 10 }));
+        release();
+        await blocker;
+    });
+
     after(async () => {
         await device.releaseInterface(0);
         await device.close();
@@ -359,6 +486,35 @@ describe('Throwing Transfers', () => {
     });
 });
 
+describe('Class drivers', () => {
+    let device = null;
+
+    before(async () => {
+        device = await webusb.requestDevice({ filters: [{ vendorId: 0x59e3 }] });
+        await device.open();
+    });
+
+    it('should report chapter 9 checks', async () => {
+        const report = await device.checkChapter9();
+        assert.equal(report.passed, report.violations.length === 0);
+    });
+
+    it('should reject standard requests for a missing interface', async () => {
+        await assert.rejects(device.getInterface(99), /interface 99 not found/);
+    });
+
+    if (process.platform === 'linux') {
+        it('should read the power state', async () => {
+            const state = await device.getPowerState();
+            assert.ok(['auto', 'on'].includes(state.control));
+        });
+    }
+
+    after(async () => {
+        await device.close();
+    });
+});
+
 describe('WebUSB Hotplug', () => {
     it('should detect disconnect', done => {
         const fn = e => {
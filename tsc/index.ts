@@ -1,4 +1,5 @@
-import { nativeGetDevices, nativeFindDeviceByIds, nativeFindDeviceBySerial, UsbDevice, Emitter } from '../index.js'
+import { nativeGetDevices, nativeFindDeviceByIds, nativeFindDeviceBySerial, nativeUdevRule, UsbDevice, UsbSequence, UsbSequenceOptions, Emitter, UdevRule, UdevRuleOptions, CdcAcmPort, CdcAcmOptions, CdcSerialState, SerialOptions, SerialInputSignals, SerialOutputSignals, HidInterface, HidOptions, HidDescriptor, HidCollectionInfo, HidReportInfo, HidReportItem, HidInputReport, MassStorageDevice, MassStorageOptions, ScsiInquiry, ScsiSense, BlockCapacity, DfuDevice, DfuOptions, DfuInterface, DfuFunctionalDescriptor, DfuStatus, DfuProgress, DfuseMemory, DfuseSegment, UsbtmcDevice, UsbtmcOptions, UsbtmcCapabilities, UsbtmcReadOptions, UsbSerialPort, UsbSerialOptions, CmsisDapProbe, CmsisDapOptions, DapTransferRequest, AccessoryConnection, AccessoryInfo, AccessoryOptions, AdbConnection, AdbStream, AdbSync, AdbOptions, AdbFileStat, AdbDirectoryEntry, PtpSession, PtpOptions, PtpDeviceInfo, PtpStorageInfo, PtpObjectInfo, PtpSendObjectOptions, PtpResponse, PtpEvent, PtpDeviceStatus, UsbPrinter, PrinterOptions, PrinterDeviceId, PrinterPortStatus, MidiInterface, MidiOptions, MidiJack, MidiCable, MidiGroupTerminalBlock, MidiAlternateSetting, MidiMessage, CcidReader, CcidOptions, CcidDescriptor, CcidSlotStatus, CcidSlotChange, CdcEthernet, CdcEthernetOptions, CdcPacketFilter, CdcNtbParameters, CdcLinkState, UvcCamera, UvcOptions, UvcEntity, UvcIntervalRange, UvcFrameSize, UvcFormat, UvcStreamOptions, UvcStreamParameters, UvcVideoFrame, UacDevice, UacOptions, UacEntity, UacSampleRateRange, UacAlternateSetting, UacFormatOptions, FirmwareOptions, Chapter9Options, Chapter9Violation, Chapter9Report, UsbStatus, UsbPowerState } from '../index.js'
+import { defineSerialStreams, NativeSerialPort } from './serial'
 
 /**
  * Hidden
@@ -21,6 +22,49 @@ const toUint8Array = (data: BufferSource): Uint8Array => {
     );
 };
 
+/**
+ * Hidden
+ */
+const deviceInstances = new Map<string, UsbDevice>();
+
+/**
+ * Hidden
+ */
+const isSameDevice = (a: UsbDevice, b: UsbDevice): boolean =>
+    a.vendorId === b.vendorId
+    && a.productId === b.productId
+    && a.bus === b.bus
+    && a.ports.join('.') === b.ports.join('.');
+
+/**
+ * Hidden
+ */
+const knownInstance = (device: UsbDevice): UsbDevice => {
+    const existing = deviceInstances.get(device.handle);
+    if (existing && isSameDevice(existing, device)) {
+        return existing;
+    }
+
+    deviceInstances.set(device.handle, device);
+    return device;
+};
+
+/**
+ * Hidden
+ */
+const getDeviceInstances = async (): Promise<UsbDevice[]> => {
+    const devices = await nativeGetDevices();
+    const handles = new Set(devices.map(device => device.handle));
+
+    for (const handle of deviceInstances.keys()) {
+        if (!handles.has(handle)) {
+            deviceInstances.delete(handle);
+        }
+    }
+
+    return devices.map(knownInstance);
+};
+
 /**
  * Hidden
  */
@@ -65,6 +109,18 @@ UsbDevice.prototype.transferOut = async function (endpointNumber: number, data:
     };
 };
 
+/**
+ * Hidden
+ */
+UsbDevice.prototype.transferInto = async function (endpointNumber: number, buffer: BufferSource, timeout = DEFAULT_TIMEOUT): Promise<USBInTransferResult> {
+    const target = toUint8Array(buffer);
+    const res = await this.nativeTransferInto(endpointNumber, timeout, target);
+    return {
+        data: new DataView(target.buffer, target.byteOffset, res),
+        status: 'ok',
+    };
+};
+
 /**
  * Hidden
  */
@@ -81,6 +137,67 @@ UsbDevice.prototype.isochronousTransferOut = async function (endpointNumber: num
     return res;
 }
 
+/**
+ * Hidden
+ */
+UsbDevice.prototype.sequence = async function <T>(callback: (sequence: UsbSequence) => Promise<T>, options: UsbSequenceOptions = {}): Promise<T> {
+    const sequence = await this.nativeSequence(options.priority, options.timeout);
+    try {
+        return await callback(sequence);
+    } finally {
+        sequence.end();
+    }
+};
+
+/**
+ * Hidden
+ */
+UsbSequence.prototype.controlTransferIn = async function (setup: USBControlTransferParameters, length: number, timeout = DEFAULT_TIMEOUT): Promise<USBInTransferResult> {
+    const res = await this.nativeControlTransferIn(setup, timeout, length);
+    return {
+        data: res ? new DataView(res.buffer) : undefined,
+        status: res ? 'ok' : 'stall',
+    };
+};
+
+/**
+ * Hidden
+ */
+UsbSequence.prototype.controlTransferOut = async function (setup: USBControlTransferParameters, data?: BufferSource, timeout = DEFAULT_TIMEOUT): Promise<USBOutTransferResult> {
+    const res = await this.nativeControlTransferOut(setup, timeout, data ? toUint8Array(data) : undefined);
+    return {
+        bytesWritten: res,
+        status: res >= 0 ? 'ok' : 'stall',
+    };
+};
+
+/**
+ * Hidden
+ */
+UsbSequence.prototype.transferIn = async function (endpointNumber: number, length: number, timeout = DEFAULT_TIMEOUT): Promise<USBInTransferResult> {
+    const res = await this.nativeTransferIn(endpointNumber, timeout, length);
+    return {
+        data: res ? new DataView(res.buffer) : undefined,
+        status: res ? 'ok' : 'stall',
+    };
+};
+
+/**
+ * Hidden
+ */
+UsbSequence.prototype.transferOut = async function (endpointNumber: number, data: BufferSource, timeout = DEFAULT_TIMEOUT): Promise<USBOutTransferResult> {
+    const res = await this.nativeTransferOut(endpointNumber, timeout, toUint8Array(data));
+    return {
+        bytesWritten: res,
+        status: res >= 0 ? 'ok' : 'stall',
+    };
+};
+
+defineSerialStreams(CdcAcmPort.prototype as unknown as NativeSerialPort);
+defineSerialStreams(UsbSerialPort.prototype as unknown as NativeSerialPort);
+defineSerialStreams(AccessoryConnection.prototype as unknown as NativeSerialPort);
+defineSerialStreams(AdbStream.prototype as unknown as NativeSerialPort);
+
 /**
  * USB Options
  */
@@ -152,6 +269,7 @@ class WebUSB extends EventTarget implements USB {
     }
 
     private deviceConnectCallback = async (device: UsbDevice) => {
+        device = knownInstance(device);
         this.knownDevices.set(device.handle, device);
 
         // When connected, emit an event if it is an allowed device
@@ -169,6 +287,7 @@ class WebUSB extends EventTarget implements USB {
             }
             this.knownDevices.delete(handle);
         }
+        deviceInstances.delete(handle);
     };
 
     public addEventListener(type: 'connect' | 'disconnect', listener: (this: this, ev: USBConnectionEvent) => void): void;
@@ -194,7 +313,7 @@ class WebUSB extends EventTarget implements USB {
                 await this.nativeEmitter.addAttach(this.deviceConnectCallback);
                 await this.nativeEmitter.addDetach(this.deviceDisconnectCallback);
             });
-            nativeGetDevices().then(devices => devices.forEach(device => this.knownDevices.set(device.handle, device)));
+            getDeviceInstances().then(devices => devices.forEach(device => this.knownDevices.set(device.handle, device)));
         }
     }
 
@@ -337,7 +456,7 @@ class WebUSB extends EventTarget implements USB {
      */
     public async findDeviceByIds(vid: number, pid: number): Promise<UsbDevice | undefined> {
         const device = await nativeFindDeviceByIds(vid, pid);
-        return device || undefined;
+        return device ? knownInstance(device) : undefined;
     }
 
     /**
@@ -346,11 +465,33 @@ class WebUSB extends EventTarget implements USB {
      */
     public async findDeviceBySerial(serialNumber: string): Promise<UsbDevice | undefined> {
         const device = await nativeFindDeviceBySerial(serialNumber);
-        return device || undefined;
+        return device ? knownInstance(device) : undefined;
+    }
+
+    /**
+     * Generates a udev rule granting access to a device, or to all devices matching a filter (Linux only).
+     * When passed a device, the rule is also validated against the device's sysfs attributes.
+     * @param target The device or filter to match, filters require a `vendorId`
+     * @param options The permissions to grant
+     */
+    public async udevRule(target: UsbDevice | USBDeviceFilter, options?: UdevRuleOptions): Promise<UdevRule> {
+        if (target instanceof UsbDevice) {
+            return target.udevRule(options);
+        }
+
+        if (target.vendorId === undefined) {
+            throw new TypeError('udevRule error: vendorId is required');
+        }
+
+        return nativeUdevRule({
+            vendorId: target.vendorId,
+            productId: target.productId,
+            serialNumber: target.serialNumber
+        }, options);
     }
 
     private async loadDevices(preFilters?: USBDeviceFilter[]): Promise<UsbDevice[]> {
-        let devices = await nativeGetDevices();
+        let devices = await getDeviceInstances();
 
         // Pre-filter devices
         devices = this.quickFilter(devices, preFilters);
@@ -478,6 +619,107 @@ export {
     // Main object class
     WebUSB,
 
+    // Class drivers
+    CdcAcmPort,
+    HidInterface,
+    MassStorageDevice,
+    DfuDevice,
+    UsbtmcDevice,
+    UsbSerialPort,
+    CmsisDapProbe,
+    AccessoryConnection,
+    AdbConnection,
+    AdbStream,
+    AdbSync,
+    PtpSession,
+    UsbPrinter,
+    MidiInterface,
+    CcidReader,
+    CdcEthernet,
+    UvcCamera,
+    UacDevice,
+
     // Types
     USBOptions,
+    UdevRule,
+    UdevRuleOptions,
+    UsbSequence,
+    UsbSequenceOptions,
+    CdcAcmOptions,
+    CdcSerialState,
+    SerialOptions,
+    SerialInputSignals,
+    SerialOutputSignals,
+    HidOptions,
+    HidDescriptor,
+    HidCollectionInfo,
+    HidReportInfo,
+    HidReportItem,
+    HidInputReport,
+    MassStorageOptions,
+    ScsiInquiry,
+    ScsiSense,
+    BlockCapacity,
+    DfuOptions,
+    DfuInterface,
+    DfuFunctionalDescriptor,
+    DfuStatus,
+    DfuProgress,
+    DfuseMemory,
+    DfuseSegment,
+    UsbtmcOptions,
+    UsbtmcCapabilities,
+    UsbtmcReadOptions,
+    UsbSerialOptions,
+    CmsisDapOptions,
+    DapTransferRequest,
+    AccessoryInfo,
+    AccessoryOptions,
+    AdbOptions,
+    AdbFileStat,
+    AdbDirectoryEntry,
+    PtpOptions,
+    PtpDeviceInfo,
+    PtpStorageInfo,
+    PtpObjectInfo,
+    PtpSendObjectOptions,
+    PtpResponse,
+    PtpEvent,
+    PtpDeviceStatus,
+    PrinterOptions,
+    PrinterDeviceId,
+    PrinterPortStatus,
+    MidiOptions,
+    MidiJack,
+    MidiCable,
+    MidiGroupTerminalBlock,
+    MidiAlternateSetting,
+    MidiMessage,
+    CcidOptions,
+    CcidDescriptor,
+    CcidSlotStatus,
+    CcidSlotChange,
+    CdcEthernetOptions,
+    CdcPacketFilter,
+    CdcNtbParameters,
+    CdcLinkState,
+    UvcOptions,
+    UvcEntity,
+    UvcIntervalRange,
+    UvcFrameSize,
+    UvcFormat,
+    UvcStreamOptions,
+    UvcStreamParameters,
+    UvcVideoFrame,
+    UacOptions,
+    UacEntity,
+    UacSampleRateRange,
+    UacAlternateSetting,
+    UacFormatOptions,
+    FirmwareOptions,
+    Chapter9Options,
+    Chapter9Violation,
+    Chapter9Report,
+    UsbStatus,
+    UsbPowerState,
 };
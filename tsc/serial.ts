@@ -0,0 +1,97 @@
+/**
+ * Native half of the serial port drivers, which the streams below are built on
+ */
+export interface NativeSerialPort {
+    nativeStartReading(bufferSize: number | undefined | null, onData: (data: Uint8Array) => void, onEnd: (error?: string | null) => void): void;
+    stopReading(): void;
+    pauseReading(): void;
+    resumeReading(): void | Promise<void>;
+    nativeWrite(data: Uint8Array, timeout: number): Promise<number>;
+}
+
+/**
+ * Time (in milliseconds) a write may wait for the device, e.g. while it is flow controlled
+ */
+const WRITE_TIMEOUT = 5000;
+
+/**
+ * Bytes a readable stream queues before reading from the device is paused
+ */
+const READ_HIGH_WATER_MARK = 64 * 1024;
+
+interface SerialStreams {
+    readable?: ReadableStream<Uint8Array>;
+    writable?: WritableStream<Uint8Array>;
+}
+
+const portStreams = new WeakMap<NativeSerialPort, SerialStreams>();
+
+const streamsFor = (port: NativeSerialPort): SerialStreams => {
+    let streams = portStreams.get(port);
+    if (!streams) {
+        streams = {};
+        portStreams.set(port, streams);
+    }
+    return streams;
+};
+
+const createReadable = (port: NativeSerialPort, streams: SerialStreams): ReadableStream<Uint8Array> => {
+    const readable = new ReadableStream<Uint8Array>({
+        start: controller => {
+            port.nativeStartReading(undefined, data => {
+                controller.enqueue(data);
+                // Leave further data with the device until the reader catches up
+                if (controller.desiredSize !== null && controller.desiredSize <= 0) {
+                    port.pauseReading();
+                }
+            }, error => {
+                // A new stream is handed out next time, as with Web Serial after an error
+                if (streams.readable === readable) {
+                    streams.readable = undefined;
+                }
+                if (error) {
+                    controller.error(new Error(`read error: ${error}`));
+                } else {
+                    controller.close();
+                }
+            });
+        },
+        pull: () => port.resumeReading(),
+        cancel: () => {
+            port.stopReading();
+        }
+    }, new ByteLengthQueuingStrategy({ highWaterMark: READ_HIGH_WATER_MARK }));
+    return readable;
+};
+
+const createWritable = (port: NativeSerialPort): WritableStream<Uint8Array> => new WritableStream<Uint8Array>({
+    write: async chunk => {
+        await port.nativeWrite(chunk, WRITE_TIMEOUT);
+    }
+});
+
+/**
+ * Adds Web Serial style `readable` and `writable` streams to a driver's prototype
+ * @param prototype The prototype of the native port class
+ */
+export const defineSerialStreams = (prototype: NativeSerialPort): void => {
+    Object.defineProperty(prototype, 'readable', {
+        get(this: NativeSerialPort) {
+            const streams = streamsFor(this);
+            if (!streams.readable) {
+                streams.readable = createReadable(this, streams);
+            }
+            return streams.readable;
+        }
+    });
+
+    Object.defineProperty(prototype, 'writable', {
+        get(this: NativeSerialPort) {
+            const streams = streamsFor(this);
+            if (!streams.writable) {
+                streams.writable = createWritable(this);
+            }
+            return streams.writable;
+        }
+    });
+};